@@ -1,24 +1,66 @@
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::Instant;
 
-use cargo_subcommand::{Profile, Subcommand};
+use cargo_subcommand::{Artifact, ArtifactType, Profile, Subcommand};
+use ndk_build::apk::{deny_warnings, InstallOptions};
+use sha2::{Digest, Sha256};
 use ndk_build::error::NdkError;
 
-use ndk_build::ndk::{KeystoreMeta, Ndk};
+use ndk_build::ndk::Ndk;
+use ndk_build::partial::PartialFile;
+use ndk_build::target::Target;
 
-use crate::Error;
-use crate::manifest::Manifest;
+use crate::apk::{android_jar, apk_dir, check_64_bit, ndk_from_env, profile_dir, profile_name, ApkBuilder};
+use crate::env::Env;
+use crate::error::Error;
+use crate::keystore::{apply_signing_config, check_release_signing, is_debug_key, jarsigner_algorithms, read_keystore_meta};
+use crate::manifest::{compute_version_code, compute_version_name, ensure_android_package, expand_artifact_name, Manifest, Root};
 
 pub struct AabBuilder {
     pub cmd: Subcommand,
     pub ndk: Ndk,
+    /// The environment that `JAVA_HOME`, `ANDROID_HOME` and the signing variables are read from
+    pub env: Env,
     pub crate_path: PathBuf,
     pub manifest: Manifest,
+    pub version_code: u32,
+    pub version_name: String,
+    /// The `target_sdk_version` of the bundle, resolved like `ApkBuilder` does
+    pub target_sdk_version: u32,
     pub apk_dir: PathBuf,
+    /// The artifact whose APK the bundle is created from, see [`bundled_artifact()`]
+    pub artifact: Artifact,
+    /// The APK the bundle is created from
+    pub apk_path: PathBuf,
     pub aab_dir: PathBuf,
+    /// Directory the intermediate files are created in, `aab_dir` unless configured otherwise
+    pub scratch_dir: PathBuf,
+    /// Name of the signed `.aab` file, without extension
+    pub aab_name: String,
+    /// Directory the signed `.aab` file is written to
+    pub out_dir: PathBuf,
     pub java: PathBuf,
     pub jarsigner: PathBuf,
     pub aapt2: PathBuf,
+    /// The `android.jar` of the `compile_sdk_version`, which resources are linked against
     pub android: PathBuf,
+    /// Also link the resources into a binary-format APK (regular `resources.arsc` instead of
+    /// the proto format bundles use), written next to the signed `.aab` for older tooling
+    pub binary_resources_apk: bool,
+    /// Create bundles of non-`dev` profiles even when they are signed with the debug keystore
+    /// or `debuggable`
+    pub allow_debug_signing: bool,
+    /// Keep the intermediate files of a successful build in `scratch_dir` and print their paths,
+    /// instead of removing them
+    pub keep_intermediates: bool,
+    /// Fail when apktool or aapt2 print warnings about the manifest or resources
+    pub deny_warnings: bool,
+    /// Fail for bundles of release profiles that ship a 32-bit ABI without its 64-bit
+    /// counterpart
+    pub strict: bool,
 }
 
 impl AabBuilder {
@@ -26,101 +68,196 @@ impl AabBuilder {
     const BUNDLE_TOOL: &'static [u8; 29_069_641] = include_bytes!("../tools/bundletool-1.15.4.jar");
 
     pub fn from_subcommand(cmd: Subcommand) -> anyhow::Result<Self> {
-        let ndk = Ndk::from_env()?;
-        let manifest = Manifest::parse_from_toml(cmd.manifest())?;
+        Self::from_subcommand_with_env(cmd, Env::default())
+    }
+
+    /// Like [`AabBuilder::from_subcommand()`], but with the settings and variables of `env`.
+    pub fn from_subcommand_with_env(cmd: Subcommand, env: Env) -> anyhow::Result<Self> {
+        let ndk = ndk_from_env(cmd.target_dir(), &env)?;
+        Self::from_subcommand_with_ndk(cmd, ndk, env)
+    }
+
+    /// Like [`AabBuilder::from_subcommand()`], but with an `ndk` that the caller already located
+    /// and with `JAVA_HOME`, `ANDROID_HOME` and the signing variables read from `env`.
+    pub fn from_subcommand_with_ndk(cmd: Subcommand, ndk: Ndk, env: Env) -> anyhow::Result<Self> {
+        ensure_android_package(cmd.package(), cmd.manifest(), cmd.workspace_manifest())?;
+        // `keytool` from the same JDK as `jarsigner`
+        let ndk = ndk.with_java_home(env.var_os("JAVA_HOME").map(PathBuf::from));
+        let mut manifest = Manifest::parse_from_toml(cmd.manifest())?;
+        apply_signing_config(&mut manifest, &env)?;
+        let workspace_manifest: Option<Root> = cmd
+            .workspace_manifest()
+            .map(Root::parse_from_toml)
+            .transpose()?;
+        let version_code = compute_version_code(&manifest, workspace_manifest.as_ref())?;
+        let version_name = compute_version_name(&manifest, workspace_manifest.as_ref())?;
         let crate_path = PathBuf::from(dunce::simplified(cmd.manifest()).parent().ok_or(NdkError::PathNotFound(PathBuf::from(cmd.manifest())))?);
 
-        let base_dir = dunce::simplified(cmd.target_dir()).join(cmd.profile());
-        let apk_dir = base_dir.join("apk");
-        let aab_dir = base_dir.join("aab");
+        let apk_dir = apk_dir(cmd.target_dir(), cmd.profile());
+        let aab_dir = dunce::simplified(cmd.target_dir()).join(profile_dir(cmd.profile())).join("aab");
+
+        // Locate the APK exactly like `ApkBuilder` names it
+        let artifact = bundled_artifact(cmd.artifacts())?.clone();
+        let apk_name_of = |artifact: &Artifact| expand_artifact_name(manifest.apk_name.as_deref().unwrap_or(&artifact.name), &artifact.name, &version_name);
+        let mut apk_name = apk_name_of(&artifact);
+        // Mirror `ApkBuilder::resolve_apk_names()`, which disambiguates colliding APK names
+        if cmd.artifacts().any(|other| *other != artifact && other.build_dir() == artifact.build_dir() && apk_name_of(other) == apk_name) {
+            apk_name = format!("{apk_name}-{}", artifact.name);
+        }
+        let apk_path = apk_dir.join(artifact.build_dir()).join(format!("{apk_name}.apk"));
+        let aab_name = match &manifest.aab_name {
+            Some(aab_name) => expand_artifact_name(aab_name, &artifact.name, &version_name),
+            None => apk_name,
+        };
+        let scratch_dir = match &manifest.aab_scratch_dir {
+            Some(dir) => crate_path.join(dir),
+            None => aab_dir.clone(),
+        };
+        let out_dir = match &manifest.aab_dir {
+            Some(dir) => crate_path.join(dir),
+            None => aab_dir.clone(),
+        };
 
         // Get java and jarsigner from JAVA_HOME
-        let java = dunce::simplified(std::env::var("JAVA_HOME")?.as_ref()).join("bin").join("java");
-        let jarsigner = dunce::simplified(std::env::var("JAVA_HOME")?.as_ref()).join("bin").join("jarsigner");
-        let aapt2 = dunce::simplified(std::env::var("ANDROID_HOME")?.as_ref()).join("build-tools").join("35.0.0").join("aapt2");
-        let android = dunce::simplified(std::env::var("ANDROID_HOME")?.as_ref()).join("platforms").join("android-35").join("android.jar");
+        let java = dunce::simplified(env.var("JAVA_HOME")?.as_ref()).join("bin").join("java");
+        let jarsigner = dunce::simplified(env.var("JAVA_HOME")?.as_ref()).join("bin").join("jarsigner");
+        let aapt2 = dunce::simplified(env.var("ANDROID_HOME")?.as_ref()).join("build-tools").join("35.0.0").join("aapt2");
+        let target_sdk_version = aab_target_sdk_version(&manifest);
+        let android = android_jar(&ndk, manifest.compile_sdk_version(target_sdk_version)?)?;
 
-        Ok(Self { cmd, ndk, crate_path, manifest, apk_dir, aab_dir, java, jarsigner, aapt2, android })
+        Ok(Self { cmd, ndk, env, crate_path, manifest, version_code, version_name, target_sdk_version, apk_dir, artifact, apk_path, aab_dir, scratch_dir, aab_name, out_dir, java, jarsigner, aapt2, android, binary_resources_apk: false, allow_debug_signing: false, keep_intermediates: false, deny_warnings: false, strict: false })
     }
 
-    pub fn create_from_apk(&self) -> anyhow::Result<()> {
-        let Self { aab_dir, apk_dir, java, jarsigner, aapt2, android, .. } = self;
-
-        std::fs::create_dir_all(&aab_dir)?;
-        for entry in std::fs::read_dir(&aab_dir)? {
-            let entry = entry?;
-            if entry.file_name() != "tools" {
-                if entry.file_type()?.is_dir() {
-                    std::fs::remove_dir_all(entry.path())?;
-                } else {
-                    std::fs::remove_file(entry.path())?;
-                }
-            }
+    /// Converts the last built APK into a signed `.aab` and returns the path to it.
+    ///
+    /// Every tool's output is streamed as it runs, prefixed with its step, followed by the
+    /// duration of the step. The steps, after resolving and validating the signing key:
+    ///
+    /// 1. apktool decodes the APK, while bundletool is extracted and `keytool` is asked for
+    ///    the signing algorithms
+    /// 2. aapt2 compiles the resources that changed since the previous build
+    /// 3. aapt2 links the proto-format resources, and the binary-format ones at the same time
+    ///    when [`Self::binary_resources_apk`] is set
+    /// 4. unzip, jar and bundletool assemble the bundle, which jarsigner signs
+    pub fn create_from_apk(&self) -> anyhow::Result<PathBuf> {
+        let Self { scratch_dir, apk_path, aab_name, out_dir, java, jarsigner, aapt2, android, .. } = self;
+
+        // Resolve and validate the signing key before the (possibly lengthy) conversion, falling
+        // back to the debug keystore for `dev` bundles like `ApkBuilder` does
+        let is_debug_profile = *self.cmd.profile() == Profile::Dev;
+        let key = read_keystore_meta(
+            &self.ndk,
+            &self.manifest,
+            self.cmd.profile(),
+            &self.crate_path,
+            is_debug_profile,
+            &self.env,
+        )?;
+        let debuggable = self.manifest.android_manifest.application.debuggable.unwrap_or(is_debug_profile);
+        check_release_signing(self.cmd.profile(), is_debug_key(&self.ndk, &key, &self.env)?, debuggable, self.allow_debug_signing || self.manifest.allow_debuggable_release)?;
+        ensure_not_test_only(self.cmd.profile(), &self.manifest)?;
+        if self.manifest.signing.get(profile_name(self.cmd.profile())).is_some_and(|signing| signing.rotation.is_some()) {
+            eprintln!("Warning: signing key rotation only applies to APKs, the aab is signed with the current key only");
         }
 
-        let tools_dir = aab_dir.join("tools");
+        std::fs::create_dir_all(scratch_dir)?;
+        remove_scratch_files(scratch_dir, aab_name)?;
+
+        let tools_dir = scratch_dir.join("tools");
         std::fs::create_dir_all(&tools_dir)?;
 
         let apk_tool = tools_dir.join("apktool-2.8.1.jar");
         let bundle_tool = tools_dir.join("bundletool-1.15.4.jar");
 
         std::fs::write(&apk_tool, Self::APK_TOOL)?;
-        std::fs::write(&bundle_tool, Self::BUNDLE_TOOL)?;
-
-        let unpacked_apk = aab_dir.join("unpacked-apk");
-        let res_zip = aab_dir.join("res.zip");
-        let base_zip = aab_dir.join("base.zip");
-
-        let output = std::process::Command::new(&java)
-            .arg("-jar").arg(&apk_tool)
-            .arg("d")
-            .arg(apk_dir.join(match &self.manifest.apk_name {
-                Some(name) => format!("{name}.apk"),
-                None => "app.apk".to_string(),
-            }))
-            .arg("-s")
-            .arg("-o").arg(&unpacked_apk)
-            .arg("-f")
-            .output()?;
 
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("Failed to unpack apk: {}", String::from_utf8_lossy(&output.stderr)));
-        } else {
-            println!("Unpacked apk to {:?}", &unpacked_apk);
+        let unpacked_apk = scratch_dir.join("unpacked-apk");
+        let res_list = scratch_dir.join("compiled-resources.txt");
+        let base_zip = scratch_dir.join("base.zip");
+
+        // Extracting bundletool and asking `keytool` for the signing algorithms don't depend
+        // on the unpacked apk, so they happen while apktool runs
+        let profile = self.cmd.profile();
+        let (extracted, (sig_alg, digest_alg), unpacked) = std::thread::scope(|scope| {
+            let extract = scope.spawn(|| std::fs::write(&bundle_tool, Self::BUNDLE_TOOL));
+            let algorithms = scope.spawn(|| jarsigner_algorithms(&self.ndk, &self.manifest, profile, &key));
+            let unpacked = run_step("apktool", Command::new(java)
+                .arg("-jar").arg(&apk_tool)
+                .arg("d")
+                .arg(apk_path)
+                .arg("-s")
+                .arg("-o").arg(&unpacked_apk)
+                .arg("-f"));
+            (extract.join().expect("extracting bundletool panicked"), algorithms.join().expect("keytool lookup panicked"), unpacked)
+        });
+        extracted?;
+        let stderr = step_succeeded("unpack apk", "apktool", unpacked?)?;
+        self.check_warnings("apktool", &stderr)?;
+        println!("Unpacked apk to {:?}", &unpacked_apk);
+        let targets = std::fs::read_dir(unpacked_apk.join("lib")).into_iter().flatten()
+            .filter_map(|entry| Target::from_android_abi(entry.ok()?.file_name().to_str()?).ok())
+            .collect::<Vec<_>>();
+        // Building the apk of a release profile warned already
+        if !is_debug_profile && self.strict {
+            check_64_bit(&targets, true)?;
         }
 
-        let output = std::process::Command::new(&aapt2)
-            .arg("compile")
-            .arg("--dir").arg(unpacked_apk.join("res"))
-            .arg("-o").arg(&res_zip)
-            .output()?;
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("Failed to compile resources: {}", String::from_utf8_lossy(&output.stderr)));
-        } else {
-            println!("Compiled resources to {:?}", &res_zip);
-        }
-
-        let output = std::process::Command::new(&aapt2)
-            .arg("link")
-            .arg("-o").arg(&base_zip)
-            .arg("-R").arg(&res_zip)
-            .arg("-I").arg(android)
-            .arg("--manifest").arg(unpacked_apk.join("AndroidManifest.xml"))
-            .arg("--min-sdk-version").arg(self.manifest.android_manifest.sdk.min_sdk_version.unwrap_or(21).to_string())
-            .arg("--target-sdk-version").arg(self.manifest.android_manifest.sdk.target_sdk_version.unwrap_or(35).to_string())
-            .arg("--version-code").arg(self.manifest.version_code.unwrap_or(1).to_string())
-            .arg("--version-name").arg(self.manifest.version_name.as_deref().unwrap_or("1.0"))
-            .arg("--auto-add-overlay")
-            .arg("--proto-format")
-            .output()?;
+        let res_cache = scratch_dir.join(format!("{aab_name}-res-cache"));
+        let compiled = timed("aapt2 compile", || compile_resources(&unpacked_apk.join("res"), &res_cache, |file, out_dir| {
+            let compiled = stream_output("aapt2 compile", Command::new(aapt2)
+                .arg("compile")
+                .arg(file)
+                .arg("-o").arg(out_dir))?;
+            let stderr = step_succeeded(&format!("compile `{}`", file.display()), "aapt2", compiled)?;
+            self.check_warnings("aapt2", &stderr)?;
+            Ok(())
+        }))?;
+        let mut list = String::new();
+        for flat in compiled {
+            list.push_str(&flat.to_string_lossy());
+            list.push('\n');
+        }
+        std::fs::write(&res_list, list)?;
+
+        let link = |output: &Path, proto_format: bool| {
+            let mut cmd = Command::new(aapt2);
+            cmd.arg("link")
+                .arg("-o").arg(output)
+                .arg("-R").arg(format!("@{}", res_list.display()))
+                .arg("-I").arg(android)
+                .arg("--manifest").arg(unpacked_apk.join("AndroidManifest.xml"))
+                .args(sdk_version_args(self.manifest.android_manifest.sdk.min_sdk_version.unwrap_or(21), self.target_sdk_version))
+                .arg("--version-code").arg(self.version_code.to_string())
+                .arg("--version-name").arg(&self.version_name)
+                .arg("--auto-add-overlay");
+            if proto_format {
+                cmd.arg("--proto-format");
+            }
+            cmd
+        };
 
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("Failed to link resources: {}", String::from_utf8_lossy(&output.stderr)));
-        } else {
-            println!("Linked resources to {:?}", &base_zip);
+        // Both formats are linked from the same compiled resources, at the same time
+        let binary_apk = out_dir.join(format!("{aab_name}-binary-resources.apk"));
+        if self.binary_resources_apk {
+            std::fs::create_dir_all(out_dir)?;
+        }
+        let (linked, binary_linked) = std::thread::scope(|scope| {
+            let binary = self.binary_resources_apk.then(|| scope.spawn(|| run_step("aapt2 link binary", &mut link(&binary_apk, false))));
+            let linked = run_step("aapt2 link", &mut link(&base_zip, true));
+            (linked, binary.map(|binary| binary.join().expect("linking binary resources panicked")))
+        });
+
+        let stderr = step_succeeded("link resources", "aapt2", linked?)?;
+        self.check_warnings("aapt2", &stderr)?;
+        println!("Linked resources to {:?}", &base_zip);
+
+        if let Some(binary_linked) = binary_linked {
+            let stderr = step_succeeded("link binary resources", "aapt2", binary_linked?)?;
+            self.check_warnings("aapt2", &stderr)?;
+            println!("Linked binary-format resources to {:?}", &binary_apk);
         }
 
-        let bundle_dir = aab_dir.join("bundle");
+        let bundle_dir = scratch_dir.join("bundle");
         let dex_dir = bundle_dir.join("dex");
         let manifest_dir = bundle_dir.join("manifest");
         let root_dir = bundle_dir.join("root");
@@ -129,16 +266,10 @@ impl AabBuilder {
         std::fs::create_dir(&manifest_dir)?;
         std::fs::create_dir(&root_dir)?;
 
-        let output = std::process::Command::new("unzip")
+        step_succeeded("unzip base.zip", "unzip", run_step("unzip", Command::new("unzip")
             .arg("-d").arg(&bundle_dir)
-            .arg(&base_zip)
-            .output()?;
-
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("Failed to unzip base.zip: {}", String::from_utf8_lossy(&output.stderr)));
-        } else {
-            println!("Unzipped base.zip to {:?}", &bundle_dir);
-        }
+            .arg(&base_zip))?)?;
+        println!("Unzipped base.zip to {:?}", &bundle_dir);
 
         std::fs::rename(bundle_dir.join("AndroidManifest.xml"), manifest_dir.join("AndroidManifest.xml"))?;
         std::fs::rename(unpacked_apk.join("lib"), bundle_dir.join("lib"))?;
@@ -148,19 +279,17 @@ impl AabBuilder {
                 return Err(err.into());
             }
         }
-        if let Err(err) = std::fs::rename(unpacked_apk.join("unknown"), &root_dir) {
-            if err.kind() != std::io::ErrorKind::NotFound {
-                return Err(err.into());
-            }
-        }
-        if let Err(err) = std::fs::rename(unpacked_apk.join("kotlin"), &root_dir) {
-            if err.kind() != std::io::ErrorKind::NotFound {
-                return Err(err.into());
+        // `unknown/` holds files relative to the root of the APK, while the Kotlin runtime
+        // looks up its builtins below `kotlin/`
+        for (dir, dest) in [("unknown", root_dir.clone()), ("kotlin", root_dir.join("kotlin"))] {
+            let dir = unpacked_apk.join(dir);
+            if dir.exists() {
+                merge_into_root(&dir, &dest)?;
             }
         }
 
         let bundle_zip = bundle_dir.join("bundle.zip");
-        let output = std::process::Command::new("jar")
+        let jarred = run_step("jar", Command::new("jar")
             .arg("cMf").arg(&bundle_zip)
             .arg("-C").arg(&bundle_dir).arg("assets")
             .arg("-C").arg(&bundle_dir).arg("dex")
@@ -168,133 +297,700 @@ impl AabBuilder {
             .arg("-C").arg(&bundle_dir).arg("manifest")
             .arg("-C").arg(&bundle_dir).arg("res")
             .arg("-C").arg(&bundle_dir).arg("root")
-            .arg("-C").arg(&bundle_dir).arg("resources.pb")
-            .output()?;
+            .arg("-C").arg(&bundle_dir).arg("resources.pb"))?;
+        step_succeeded("create bundle.zip", "jar", jarred)?;
+        println!("Created bundle.zip at {:?}", &bundle_zip);
 
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("Failed to create bundle.zip: {}", String::from_utf8_lossy(&output.stderr)));
-        } else {
-            println!("Created bundle.zip at {:?}", &bundle_zip);
-        }
-
-        let bundle = match &self.manifest.apk_name {
-            Some(bundle) => format!("{bundle}-unsigned.aab"),
-            None => "bundle-unsigned.aab".to_string(),
-        };
-        let output = std::process::Command::new(&java)
+        let bundle = format!("{aab_name}-unsigned.aab");
+        let built = run_step("bundletool", Command::new(java)
             .arg("-jar").arg(&bundle_tool)
             .arg("build-bundle")
             .arg("--modules").arg(&bundle_zip)
-            .arg("--output").arg(aab_dir.join(&bundle))
+            .arg("--output").arg(scratch_dir.join(&bundle)))?;
+        step_succeeded("build bundle", "bundletool", built)?;
+        println!("Built bundle at {:?}", scratch_dir.join(&bundle));
+
+        std::fs::create_dir_all(out_dir)?;
+        let signed = PartialFile::new(out_dir.join(format!("{aab_name}.aab")));
+
+        let mut cmd = Command::new(jarsigner);
+        cmd.arg("-verbose");
+        // Options precede the jar and alias operands
+        if let Some(sig_alg) = sig_alg {
+            cmd.arg("-sigalg").arg(sig_alg);
+        }
+        if let Some(digest_alg) = digest_alg {
+            cmd.arg("-digestalg").arg(digest_alg);
+        }
+        cmd.arg("-keystore").arg(&key.path)
+           .arg("-storepass").arg(key.store_pass.expose())
+           .arg("-keypass").arg(key.key_pass.unwrap_or_default().expose())
+           .arg("-signedjar").arg(signed.temp_path())
+           .arg(scratch_dir.join(bundle))
+           .arg(&key.alias.unwrap_or_default());
+
+        step_succeeded("sign aab", "jarsigner", run_step("jarsigner", &mut cmd)?)?;
+        self.verify_signature(signed.temp_path())?;
+
+        let signed = signed.commit()?;
+        println!("Signed aab at {:?}", &signed);
+        if self.keep_intermediates {
+            println!("Kept intermediate files:");
+            for name in scratch_files(aab_name) {
+                let path = scratch_dir.join(name);
+                if path.exists() {
+                    println!("    {}", path.display());
+                }
+            }
+        } else {
+            remove_scratch_files(scratch_dir, aab_name)?;
+        }
+        Ok(signed)
+    }
+
+    /// Extracts a single installable APK from `aab`, as created by
+    /// [`AabBuilder::create_from_apk()`], with `bundletool build-apks --mode=universal` and
+    /// returns the path to it.
+    ///
+    /// Unlike the APK the bundle was created from, its resources went through the bundle's
+    /// proto pipeline, which allows comparing the behavior of both on the same device.
+    pub fn build_apks(&self, aab: &Path) -> anyhow::Result<PathBuf> {
+        let Self { scratch_dir, aab_name, out_dir, java, .. } = self;
+
+        let key = read_keystore_meta(
+            &self.ndk,
+            &self.manifest,
+            self.cmd.profile(),
+            &self.crate_path,
+            *self.cmd.profile() == Profile::Dev,
+            &self.env,
+        )?;
+
+        let tools_dir = scratch_dir.join("tools");
+        std::fs::create_dir_all(&tools_dir)?;
+        let bundle_tool = tools_dir.join("bundletool-1.15.4.jar");
+        if !bundle_tool.exists() {
+            std::fs::write(&bundle_tool, Self::BUNDLE_TOOL)?;
+        }
+
+        let apks = scratch_dir.join(format!("{aab_name}.apks"));
+        let mut cmd = Command::new(java);
+        cmd.arg("-jar").arg(&bundle_tool)
+           .arg("build-apks")
+           .arg("--bundle").arg(aab)
+           .arg("--output").arg(&apks)
+           .arg("--mode=universal")
+           .arg("--overwrite")
+           .arg("--ks").arg(&key.path)
+           .arg(format!("--ks-pass=pass:{}", key.store_pass.expose()));
+        if let Some(alias) = &key.alias {
+            cmd.arg("--ks-key-alias").arg(alias);
+        }
+        if let Some(key_pass) = &key.key_pass {
+            cmd.arg(format!("--key-pass=pass:{}", key_pass.expose()));
+        }
+        step_succeeded("build apks", "bundletool", run_step("bundletool", &mut cmd)?)?;
+        println!("Built apks at {:?}", &apks);
+
+        std::fs::create_dir_all(out_dir)?;
+        let universal = PartialFile::new(out_dir.join(format!("{aab_name}-universal.apk")));
+        let output = Command::new("unzip")
+            .arg("-p").arg(&apks)
+            .arg("universal.apk")
+            .stdout(std::fs::File::create(universal.temp_path())?)
             .output()?;
 
         if !output.status.success() {
-            return Err(anyhow::anyhow!("Failed to build bundle: {}", String::from_utf8_lossy(&output.stderr)));
+            return Err(anyhow::anyhow!("Failed to extract universal.apk: {}", String::from_utf8_lossy(&output.stderr)));
+        }
+
+        let universal = universal.commit()?;
+        println!("Extracted universal apk to {:?}", &universal);
+        if self.keep_intermediates {
+            println!("Kept intermediate file:\n    {}", apks.display());
         } else {
-            println!("Built bundle at {:?}", aab_dir.join(&bundle));
+            std::fs::remove_file(&apks)?;
         }
+        Ok(universal)
+    }
 
-        let signed = match &self.manifest.apk_name {
-            Some(signed) => format!("{signed}.aab"),
-            None => "bundle.aab".to_string(),
+    /// Extracts the universal APK of `aab` like [`AabBuilder::build_apks()`] does, and installs
+    /// and runs it with `apk_builder` exactly like `cargo android apk run` runs the regular APK:
+    /// same device, package name, reverse port forwards and `logcat` following.
+    pub fn run_universal_apk(&self, aab: &Path, apk_builder: &ApkBuilder<'_>, no_logcat: bool, options: &InstallOptions) -> anyhow::Result<()> {
+        let universal = self.build_apks(aab)?;
+        apk_builder.run_prebuilt(&self.artifact, &universal, no_logcat, options)?;
+        Ok(())
+    }
+
+    /// Verifies the signature of the signed bundle at `aab` with `jarsigner -verify`, so that a
+    /// bundle Google Play would reject fails the build, and prints the owner and fingerprint of
+    /// its certificate.
+    fn verify_signature(&self, aab: &Path) -> anyhow::Result<()> {
+        let mut cmd = Command::new(&self.jarsigner);
+        // `jar verified.` is translated, so the JVM runs with an English locale whatever the
+        // locale of the host is
+        cmd.arg("-J-Duser.language=en").arg("-J-Duser.country=US").arg("-verify").arg(aab);
+        let output = cmd.stdin(Stdio::null()).output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() || !stdout.contains("jar verified.") {
+            return Err(anyhow::anyhow!("Verifying the signature of {aab:?} failed, jarsigner exited with {}:\n{}{}", output.status, stdout.trim_end(), String::from_utf8_lossy(&output.stderr).trim_end()));
+        }
+
+        let certificate = match self.ndk.keytool() {
+            Ok(mut keytool) => {
+                let output = keytool.arg("-printcert").arg("-jarfile").arg(aab).stdin(Stdio::null()).output()?;
+                certificate_summary(&String::from_utf8_lossy(&output.stdout))
+            }
+            Err(e) => {
+                log::debug!("Not printing the certificate of the aab: {e}");
+                Vec::new()
+            }
         };
-        let key = self.read_keystore_meta(&self.crate_path, false)?;
-
-        let mut cmd = std::process::Command::new(&jarsigner);
-        cmd.arg("-verbose")
-           .arg("-sigalg").arg("SHA256withRSA")
-           .arg("-digestalg").arg("SHA-256")
-           .arg("-keystore").arg(&key.path)
-           .arg("-storepass").arg(&key.store_pass)
-           .arg("-keypass").arg(&key.key_pass.unwrap_or_default())
-           .arg("-signedjar").arg(aab_dir.join(&signed))
-           .arg(aab_dir.join(bundle))
-           .arg(&key.alias.unwrap_or_default());
+        println!("Verified the signature of the aab");
+        for line in certificate {
+            println!("    {line}");
+        }
+        Ok(())
+    }
 
-        cmd.stdin(std::process::Stdio::null())
-           .stdout(std::process::Stdio::inherit())
-           .stderr(std::process::Stdio::inherit());
-        
-        let output = cmd.output()?;
+    /// Fails with the warnings in the `stderr` of `tool` when [`Self::deny_warnings`] is set.
+    fn check_warnings(&self, tool: &str, stderr: &str) -> Result<(), NdkError> {
+        if self.deny_warnings {
+            deny_warnings(tool, stderr)?;
+        }
+        Ok(())
+    }
+}
 
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("Failed to sign aab: {}", String::from_utf8_lossy(&output.stderr)));
-        } else {
-            println!("Signed aab at {:?}", aab_dir.join(signed));
+/// The artifact that the bundle is created from: the only one selected on the command line, or
+/// the library when the selection wasn't narrowed down with `--bin` or `--example`.
+fn bundled_artifact<'c>(artifacts: impl Iterator<Item = &'c Artifact>) -> anyhow::Result<&'c Artifact> {
+    let artifacts = artifacts.collect::<Vec<_>>();
+    if let [artifact] = artifacts[..] {
+        return Ok(artifact);
+    }
+    if let Some(lib) = artifacts.iter().find(|artifact| artifact.r#type == ArtifactType::Lib) {
+        return Ok(lib);
+    }
+    if artifacts.is_empty() {
+        return Err(anyhow::anyhow!("No artifact to create an aab from"));
+    }
+    let names = artifacts.iter().map(|artifact| artifact.name.as_str()).collect::<Vec<_>>();
+    Err(anyhow::anyhow!("An aab is created from a single artifact, select one of {names:?} with `--bin` or `--example`"))
+}
+
+/// The owner and SHA-256 fingerprint lines of the certificates in `keytool -printcert` output.
+fn certificate_summary(printcert_output: &str) -> Vec<String> {
+    printcert_output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("Owner:") || line.starts_with("SHA256:"))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Runs `cmd` as the pipeline step `step` like [`stream_output()`] does, and prints how long
+/// it took.
+fn run_step(step: &str, cmd: &mut Command) -> anyhow::Result<(ExitStatus, String)> {
+    timed(step, || stream_output(step, cmd))
+}
+
+/// Runs `cmd`, printing its stdout and stderr line by line as they are written, prefixed
+/// with `[step]`, instead of buffering them until it exits. Returns the exit status and the
+/// stderr, which is also captured for [`AabBuilder::deny_warnings`].
+fn stream_output(step: &str, cmd: &mut Command) -> anyhow::Result<(ExitStatus, String)> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to start {step} ({:?}): {e}", cmd.get_program()))?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = std::thread::scope(|scope| {
+        // Read concurrently, so that a tool blocked on writing a full pipe cannot deadlock
+        scope.spawn(|| {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                println!("[{step}] {line}");
+            }
+        });
+        let mut captured = String::new();
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            eprintln!("[{step}] {line}");
+            captured.push_str(&line);
+            captured.push('\n');
+        }
+        captured
+    });
+    Ok((child.wait()?, stderr))
+}
+
+/// The stderr of a step that `tool` ran for `action`, or an error with it when `tool` failed, so
+/// that the cause is part of the error rather than only scrolled past.
+fn step_succeeded(action: &str, tool: &str, (status, stderr): (ExitStatus, String)) -> anyhow::Result<String> {
+    if status.success() {
+        return Ok(stderr);
+    }
+    let stderr = stderr.trim_end();
+    if stderr.is_empty() {
+        return Err(anyhow::anyhow!("Failed to {action}, {tool} exited with {status}"));
+    }
+    Err(anyhow::anyhow!("Failed to {action}, {tool} exited with {status}:\n{stderr}"))
+}
+
+/// Runs `f` and prints how long the pipeline step `step` took.
+fn timed<T>(step: &str, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    println!("[{step}] took {:.1}s", started.elapsed().as_secs_f64());
+    result
+}
+
+/// Fails for bundles of profiles other than `dev` that are `android:testOnly`, as those are
+/// meant for the store, which rejects them.
+fn ensure_not_test_only(profile: &Profile, manifest: &Manifest) -> Result<(), Error> {
+    if *profile != Profile::Dev && manifest.android_manifest.application.test_only == Some(true) {
+        return Err(Error::TestOnlyRelease(profile_name(profile).to_owned()));
+    }
+    Ok(())
+}
+
+/// The files and directories in the scratch directory that are created for a bundle named
+/// `aab_name`, other than the `tools` and the resource cache that are reused.
+fn scratch_files(aab_name: &str) -> [String; 6] {
+    [
+        "unpacked-apk".to_owned(),
+        "compiled-resources.txt".to_owned(),
+        "base.zip".to_owned(),
+        "bundle".to_owned(),
+        format!("{aab_name}-unsigned.aab"),
+        format!("{aab_name}.apks"),
+    ]
+}
+
+/// Removes the [`scratch_files()`] left behind by a previous run from `dir`, which may
+/// contain files of others.
+fn remove_scratch_files(dir: &Path, aab_name: &str) -> std::io::Result<()> {
+    for name in scratch_files(aab_name) {
+        let path = dir.join(name);
+        let result = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(&path),
+            Ok(_) => std::fs::remove_file(&path),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
         }
+    }
+    Ok(())
+}
 
+/// Compiles every file in `res_dir` into `cache_dir` with `compile`, which is given the file and
+/// the directory to write its `.flat` files to, and returns all compiled files. Files whose
+/// path and contents did not change since a previous build are not compiled again, and the
+/// compiled files of resources that no longer exist are removed.
+fn compile_resources(
+    res_dir: &Path,
+    cache_dir: &Path,
+    mut compile: impl FnMut(&Path, &Path) -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(cache_dir)?;
+    let mut files = Vec::new();
+    for type_dir in std::fs::read_dir(res_dir)? {
+        let type_dir = type_dir?;
+        if type_dir.file_type()?.is_dir() {
+            for file in std::fs::read_dir(type_dir.path())? {
+                files.push(file?.path());
+            }
+        }
+    }
+    files.sort();
+
+    let mut keys = HashSet::new();
+    let mut compiled = Vec::new();
+    let mut compiled_count = 0;
+    for file in &files {
+        let relative = file.strip_prefix(res_dir)?;
+        let key = resource_cache_key(relative, &std::fs::read(file)?);
+        let entry = cache_dir.join(&key);
+        if !entry.is_dir() {
+            // Compile next to the entry, so that a failed or interrupted compilation is not
+            // mistaken for a cached one
+            let partial = cache_dir.join(format!("{key}.partial"));
+            let _ = std::fs::remove_dir_all(&partial);
+            std::fs::create_dir_all(&partial)?;
+            compile(file, &partial)?;
+            std::fs::rename(&partial, &entry)?;
+            compiled_count += 1;
+        }
+        for flat in std::fs::read_dir(&entry)? {
+            compiled.push(flat?.path());
+        }
+        keys.insert(key);
+    }
+
+    for entry in std::fs::read_dir(cache_dir)? {
+        let entry = entry?;
+        if !keys.contains(&*entry.file_name().to_string_lossy()) {
+            log::debug!("Removing stale compiled resource {:?}", entry.path());
+            std::fs::remove_dir_all(entry.path())?;
+        }
+    }
+    println!("Compiled {compiled_count} of {} resources into {:?}", files.len(), cache_dir);
+    Ok(compiled)
+}
+
+/// The name of the cache entry of the resource at `relative` in the `res` directory, a SHA-256
+/// that stays valid across Rust versions. Includes the path, as the names of the compiled files
+/// derive from it.
+fn resource_cache_key(relative: &Path, contents: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(relative.to_string_lossy().as_bytes());
+    // Terminates the path, which the contents would otherwise continue
+    hasher.update([0]);
+    hasher.update(contents);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Moves the contents of `dir`, an apktool output directory of files that end up in the root
+/// of the APK, into `root`, the bundle's `root` or a subdirectory of it, merging
+/// subdirectories with those of other directories. Files that exist in both are a conflict.
+///
+/// Signature files in `META-INF` are left out, as the input APK's signature does not apply to
+/// the bundle.
+fn merge_into_root(dir: &Path, root: &Path) -> anyhow::Result<()> {
+    fn merge(from: &Path, to: &Path, relative: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            let relative = relative.join(entry.file_name());
+            let dest = to.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                merge(&entry.path(), &dest, &relative)?;
+            } else if is_signature_file(&relative) {
+                log::debug!("Stripping signature file `{}` from the bundle", relative.display());
+            } else if dest.exists() {
+                return Err(anyhow::anyhow!("`{}` exists in more than one apktool output directory", relative.display()));
+            } else {
+                std::fs::rename(entry.path(), dest)?;
+            }
+        }
         Ok(())
     }
+    merge(dir, root, Path::new(""))
+}
 
-    fn read_keystore_meta(&self, crate_path: &Path, is_debug_profile: bool) -> Result<KeystoreMeta, Error> {
-        let profile_name = match self.cmd.profile() {
-            Profile::Dev => "dev",
-            Profile::Release => "release",
-            Profile::Custom(c) => c.as_str(),
+/// Whether `path`, relative to the root of an APK, is part of a JAR signature.
+fn is_signature_file(path: &Path) -> bool {
+    let Ok(file) = path.strip_prefix("META-INF") else {
+        return false;
+    };
+    let Some(name) = file.to_str().filter(|_| file.components().count() == 1) else {
+        return false;
+    };
+    let name = name.to_ascii_uppercase();
+    name == "MANIFEST.MF" || [".SF", ".RSA", ".DSA", ".EC"].iter().any(|ext| name.ends_with(ext))
+}
+
+/// The `target_sdk_version` of bundles that don't configure one. Unlike APKs, which fall back
+/// to the NDK's default platform, bundles are uploaded to Google Play, which rejects those
+/// targeting an older platform than it requires.
+const DEFAULT_AAB_TARGET_SDK_VERSION: u32 = 35;
+
+/// The configured `target_sdk_version`, or [`DEFAULT_AAB_TARGET_SDK_VERSION`].
+fn aab_target_sdk_version(manifest: &Manifest) -> u32 {
+    manifest.target_sdk_version(|| DEFAULT_AAB_TARGET_SDK_VERSION)
+}
+
+/// The `aapt2 link` arguments selecting the SDK versions of the bundle.
+fn sdk_version_args(min_sdk_version: u32, target_sdk_version: u32) -> [String; 4] {
+    [
+        "--min-sdk-version".to_owned(),
+        min_sdk_version.to_string(),
+        "--target-sdk-version".to_owned(),
+        target_sdk_version.to_string(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certificate_from_printcert() {
+        let output = "Signer #1:\n\nSignature:\n\nOwner: CN=Release, O=Example\n\
+            Issuer: CN=Release, O=Example\nSerial number: 1a2b\n\
+            Certificate fingerprints:\n\t SHA1: 00:11\n\t SHA256: AA:BB:CC\n\
+            Signature algorithm name: SHA256withRSA\n";
+        assert_eq!(
+            certificate_summary(output),
+            ["Owner: CN=Release, O=Example", "SHA256: AA:BB:CC"]
+        );
+    }
+
+    #[test]
+    fn configured_target_sdk_version() {
+        let dir = std::env::temp_dir().join("cargo-android-tests").join("aab_target_sdk");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Cargo.toml");
+        std::fs::write(
+            &path,
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android.sdk]\ntarget_sdk_version = 33\n",
+        )
+        .unwrap();
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+
+        let target_sdk_version = aab_target_sdk_version(&manifest);
+        assert_eq!(target_sdk_version, 33);
+        assert_eq!(
+            sdk_version_args(21, target_sdk_version),
+            ["--min-sdk-version", "21", "--target-sdk-version", "33"]
+        );
+
+        // Not the NDK's default platform, which may be older than Google Play accepts
+        std::fs::write(&path, "[package]\nversion = \"1.0.0\"\n").unwrap();
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        assert_eq!(aab_target_sdk_version(&manifest), DEFAULT_AAB_TARGET_SDK_VERSION);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apk_of_custom_target_dir() {
+        use clap::Parser;
+
+        let sdk = crate::test_support::FakeSdk::new("aab_target_dir");
+        let manifest = sdk.fixture_crate("aab-target-dir", "build_targets = [\"aarch64-linux-android\"]\ncompile_sdk_version = 34\n\n[package.metadata.android.sdk]\ntarget_sdk_version = 34\n");
+        let target_dir = sdk.root().join("ci-target");
+        let cmd = || Subcommand::new(cargo_subcommand::Args::parse_from(["cargo".as_ref(), "--manifest-path".as_ref(), manifest.as_os_str(), "--target-dir".as_ref(), target_dir.as_os_str(), "--release".as_ref()])).unwrap();
+        let env = Env::hermetic(
+            [("JAVA_HOME", "/opt/java".to_owned()), ("ANDROID_HOME", sdk.root().join("sdk").display().to_string())]
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value))
+                .collect(),
+        );
+
+        let apk_cmd = cmd();
+        let apk_builder = ApkBuilder::from_subcommand_with_ndk(&apk_cmd, None, sdk.ndk.clone(), env.clone()).unwrap();
+        let apk_path = apk_builder.apk_path(apk_cmd.artifacts().next().unwrap());
+        assert!(apk_path.starts_with(target_dir.join("release").join("apk")), "{apk_path:?}");
+        // The aab is created from the apk that `apk build` wrote
+        let builder = AabBuilder::from_subcommand_with_ndk(cmd(), sdk.ndk.clone(), env).unwrap();
+        assert_eq!(builder.apk_path, apk_path);
+    }
+
+    #[test]
+    fn merge_root_dirs() {
+        let dir = std::env::temp_dir().join("cargo-android-tests").join("aab_merge_root");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |path: &str| {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
         };
+        write("unknown/META-INF/CERT.SF");
+        write("unknown/META-INF/CERT.RSA");
+        write("unknown/META-INF/MANIFEST.MF");
+        write("unknown/META-INF/services/com.example.Service");
+        write("unknown/okhttp3/publicsuffixes.gz");
+        write("kotlin/kotlin.kotlin_builtins");
+        write("kotlin/collections/collections.kotlin_builtins");
+        write("kotlin-conflict/okhttp3/publicsuffixes.gz");
+        let root = dir.join("root");
+
+        merge_into_root(&dir.join("unknown"), &root).unwrap();
+        merge_into_root(&dir.join("kotlin"), &root.join("kotlin")).unwrap();
+        for file in [
+            "META-INF/services/com.example.Service",
+            "okhttp3/publicsuffixes.gz",
+            "kotlin/kotlin.kotlin_builtins",
+            "kotlin/collections/collections.kotlin_builtins",
+        ] {
+            assert!(root.join(file).is_file(), "{file} was not merged");
+        }
+        assert!(!root.join("kotlin.kotlin_builtins").exists());
+        for file in ["META-INF/CERT.SF", "META-INF/CERT.RSA", "META-INF/MANIFEST.MF"] {
+            assert!(!root.join(file).exists(), "{file} was not stripped");
+        }
+        assert!(merge_into_root(&dir.join("kotlin-conflict"), &root).is_err());
+    }
 
-        let manifest = self.manifest.signing.get(profile_name);
-
-        let profile_name = profile_name.to_uppercase().replace('-', "_");
-
-        // TODO: Add documentation for environment variables and signing section
-
-        let env_store_path = format!("CARGO_ANDROID_{profile_name}_STORE_PATH");
-        let env_store_password = format!("CARGO_ANDROID_{profile_name}_STORE_PASSWORD");
-        let env_key_alias = format!("CARGO_ANDROID_{profile_name}_KEY_ALIAS");
-        let env_key_password = format!("CARGO_ANDROID_{profile_name}_KEY_PASSWORD");
-
-        let store_path = std::env::var_os(&env_store_path).map(PathBuf::from);
-        let store_password = std::env::var(&env_store_password).ok();
-        let key_alias = std::env::var(&env_key_alias).ok();
-        let key_password = std::env::var(&env_key_password).ok();
-
-        if let Some(store_path) = store_path {
-            let signing_key = match store_password {
-                Some(store_password) => KeystoreMeta::single(store_path, store_password),
-                None => if is_debug_profile {
-                    println!("{env_store_password} not specified, falling back to default password");
-                    KeystoreMeta::single(store_path, ndk_build::ndk::DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned())
-                } else {
-                    eprintln!("`{}` was specified via `{env_store_path}`, but `{env_store_password}` was not specified, both or neither must be present for profiles other than `dev`", store_path.to_string_lossy());
-                    return Err(Error::MissingReleaseKey(profile_name));
-                },
-            };
-
-            return match key_alias {
-                Some(key_alias) => if let Some(key_password) = key_password {
-                    Ok(signing_key.alias(key_alias).key_pass(key_password))
-                } else {
-                    eprintln!("`{key_alias}` was specified via `{env_key_alias}`, but `{env_key_password}` was not specified");
-                    Err(Error::MissingReleaseKey(profile_name))
-                },
-                None => Ok(signing_key),
-            };
-        }
-
-        if let Some(signing) = manifest {
-            let store_path = crate_path.join(&signing.store_path);
-            let store_password = signing.store_password.clone();
-            let key_alias = signing.key_alias.clone();
-            let key_password = signing.key_password.clone();
-
-            let signing_key = KeystoreMeta::single(store_path, store_password);
-
-            return match key_alias {
-                Some(key_alias) => if let Some(key_password) = key_password {
-                    Ok(signing_key.alias(key_alias).key_pass(key_password))
-                } else {
-                    eprintln!("`{key_alias}` was specified via `{env_key_alias}`, but `{env_key_password}` was not specified");
-                    Err(Error::MissingReleaseKey(profile_name))
-                },
-                None => Ok(signing_key),
-            };
-        }
-
-        if is_debug_profile {
-            Ok(self.ndk.debug_key()?)
-        } else {
-            Err(Error::MissingReleaseKey(profile_name))
+    #[test]
+    fn remove_only_scratch_files() {
+        let dir = std::env::temp_dir().join("cargo-android-tests").join("aab_scratch");
+        let _ = std::fs::remove_dir_all(&dir);
+        for path in [
+            "unpacked-apk/res/values/strings.xml",
+            "bundle/root/file",
+            "tools/apktool-2.8.1.jar",
+            "notes/todo.txt",
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
         }
+        for file in ["base.zip", "app-unsigned.aab", "other-unsigned.aab", "app.aab"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        remove_scratch_files(&dir, "app").unwrap();
+        for removed in ["unpacked-apk", "bundle", "base.zip", "app-unsigned.aab"] {
+            assert!(!dir.join(removed).exists(), "{removed} was not removed");
+        }
+        for kept in ["tools/apktool-2.8.1.jar", "notes/todo.txt", "other-unsigned.aab", "app.aab"] {
+            assert!(dir.join(kept).exists(), "{kept} was removed");
+        }
+        // Nothing left to remove
+        remove_scratch_files(&dir, "app").unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn incremental_resources() {
+        let dir = std::env::temp_dir().join("cargo-android-tests").join("aab_res_cache");
+        let _ = std::fs::remove_dir_all(&dir);
+        let res_dir = dir.join("res");
+        let cache_dir = dir.join("cache");
+        for (path, contents) in [("values/strings.xml", "a"), ("drawable/icon.png", "b"), ("drawable/logo.png", "c")] {
+            let path = res_dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        // Returns the contents of the compiled files, and the files that were compiled
+        let build = || {
+            let mut compiled = Vec::new();
+            let flats = compile_resources(&res_dir, &cache_dir, |file, out_dir| {
+                let name = file.file_name().unwrap().to_string_lossy();
+                std::fs::write(out_dir.join(format!("{name}.flat")), std::fs::read(file)?)?;
+                compiled.push(name.into_owned());
+                Ok(())
+            })
+            .unwrap();
+            let mut flats = flats.iter().map(|flat| std::fs::read_to_string(flat).unwrap()).collect::<Vec<_>>();
+            flats.sort();
+            (flats, compiled)
+        };
+
+        assert_eq!(build(), (vec!["a".to_owned(), "b".to_owned(), "c".to_owned()], vec!["icon.png".to_owned(), "logo.png".to_owned(), "strings.xml".to_owned()]));
+        // Nothing changed
+        assert!(build().1.is_empty());
+
+        // Only the changed file is compiled, and the deleted one is dropped
+        std::fs::write(res_dir.join("values/strings.xml"), "d").unwrap();
+        std::fs::remove_file(res_dir.join("drawable/logo.png")).unwrap();
+        assert_eq!(build(), (vec!["b".to_owned(), "d".to_owned()], vec!["strings.xml".to_owned()]));
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn resource_cache_keys() {
+        let key = resource_cache_key(Path::new("values/strings.xml"), b"a");
+        // The same in every build of cargo-android, which reuse the cache
+        assert_eq!(key, "eaaefb1eeb34f5fab132b9f2aaaf787fc670e390909f1333aa349c0603e3fd5c");
+        assert_ne!(key, resource_cache_key(Path::new("values/strings.xm"), b"la"));
+        assert_ne!(key, resource_cache_key(Path::new("values/names.xml"), b"a"));
+    }
+
+    #[test]
+    fn test_only_release() {
+        let dir = std::env::temp_dir().join("cargo-android-tests").join("aab_test_only");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Cargo.toml");
+        std::fs::write(
+            &path,
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android.application]\ntest_only = true\n",
+        )
+        .unwrap();
+        let mut manifest = Manifest::parse_from_toml(&path).unwrap();
+
+        ensure_not_test_only(&Profile::Dev, &manifest).unwrap();
+        assert!(matches!(
+            ensure_not_test_only(&Profile::Release, &manifest),
+            Err(Error::TestOnlyRelease(profile)) if profile == "release"
+        ));
+        manifest.android_manifest.application.test_only = Some(false);
+        ensure_not_test_only(&Profile::Release, &manifest).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn streamed_step_output() {
+        let (status, stderr) = stream_output("sh", Command::new("sh").arg("-c").arg("echo out; echo first >&2; echo second >&2; exit 3")).unwrap();
+        assert_eq!(status.code(), Some(3));
+        // Only stderr is kept, for error messages and warnings
+        assert_eq!(stderr, "first\nsecond\n");
+
+        let (status, stderr) = run_step("true", &mut Command::new("true")).unwrap();
+        assert!(status.success());
+        assert!(stderr.is_empty());
+        assert!(run_step("missing", &mut Command::new("cargo-android-missing-tool")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn step_output_order() {
+        // More stdout than a pipe buffers before any stderr, which only completes when both are
+        // read at the same time
+        let script = "i=0; while [ $i -lt 2000 ]; do echo 'stdout line that fills the pipe buffer'; i=$((i+1)); done; \
+            for i in 1 2 3 4 5; do echo \"stderr $i\" >&2; done";
+        let (status, stderr) = stream_output("sh", Command::new("sh").arg("-c").arg(script)).unwrap();
+        assert!(status.success());
+        assert_eq!(stderr, "stderr 1\nstderr 2\nstderr 3\nstderr 4\nstderr 5\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_step_error() {
+        let failed = stream_output("sh", Command::new("sh").arg("-c").arg("echo progress; echo 'E: res/values/strings.xml: invalid' >&2; exit 1")).unwrap();
+        let error = step_succeeded("link resources", "aapt2", failed).unwrap_err().to_string();
+        assert!(error.starts_with("Failed to link resources, aapt2 exited with "), "{error}");
+        assert!(error.ends_with(":\nE: res/values/strings.xml: invalid"), "{error}");
+        assert!(!error.contains("progress"), "{error}");
+
+        let succeeded = stream_output("sh", Command::new("sh").arg("-c").arg("echo warning >&2")).unwrap();
+        assert_eq!(step_succeeded("link resources", "aapt2", succeeded).unwrap(), "warning\n");
+        let silent = stream_output("sh", Command::new("sh").arg("-c").arg("exit 2")).unwrap();
+        assert!(step_succeeded("unzip base.zip", "unzip", silent).unwrap_err().to_string().ends_with("exit status: 2"));
+    }
+
+    #[test]
+    fn bundled_artifact_selection() {
+        let artifact = |name: &str, r#type| Artifact { name: name.to_owned(), path: PathBuf::from(format!("{name}.rs")), r#type };
+        let lib = artifact("app", ArtifactType::Lib);
+        let demo = artifact("demo", ArtifactType::Example);
+        let bench = artifact("bench", ArtifactType::Example);
+
+        // `--example demo`
+        assert_eq!(bundled_artifact([&demo].into_iter()).unwrap(), &demo);
+        // Nothing selected, which keeps all artifacts of the package
+        assert_eq!(bundled_artifact([&lib, &demo, &bench].into_iter()).unwrap(), &lib);
+        let error = bundled_artifact([&demo, &bench].into_iter()).unwrap_err().to_string();
+        assert!(error.contains(r#"["demo", "bench"]"#), "{error}");
+        assert!(bundled_artifact(std::iter::empty()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn aab_of_selected_example() {
+        use clap::Parser;
+
+        let sdk = crate::test_support::FakeSdk::new("aab_example");
+        let manifest = sdk.fixture_crate("aab-example", "compile_sdk_version = 34\n\n[package.metadata.android.sdk]\ntarget_sdk_version = 34\n");
+        let examples = manifest.with_file_name("examples");
+        std::fs::create_dir_all(&examples).unwrap();
+        std::fs::write(examples.join("demo.rs"), "").unwrap();
+        let cmd = Subcommand::new(cargo_subcommand::Args::parse_from(["cargo".as_ref(), "--manifest-path".as_ref(), manifest.as_os_str(), "--example".as_ref(), "demo".as_ref()])).unwrap();
+        let env = Env::hermetic(
+            [("JAVA_HOME", "/opt/java".to_owned()), ("ANDROID_HOME", sdk.root().join("sdk").display().to_string())]
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value))
+                .collect(),
+        );
+        let builder = AabBuilder::from_subcommand_with_ndk(cmd, sdk.ndk.clone(), env).unwrap();
+        // Also the artifact that `aab run` runs the universal apk of
+        assert_eq!(builder.artifact.name, "demo");
+        assert_eq!(builder.artifact.r#type, ArtifactType::Example);
+        assert!(builder.apk_path.ends_with("examples/demo.apk"), "{:?}", builder.apk_path);
+    }
+}
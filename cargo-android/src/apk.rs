@@ -1,83 +1,509 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
 use cargo_subcommand::{Artifact, ArtifactType, CrateType, Profile, Subcommand};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use ndk_build::apk::{Apk, ApkConfig};
-use ndk_build::cargo::{cargo_ndk, VersionCode};
+use ndk_build::apk::{Apk, ApkConfig, InstallOptions, StripConfig};
+use ndk_build::cargo::cargo_ndk_with_vars;
 use ndk_build::dylibs::get_libs_search_paths;
 use ndk_build::error::NdkError;
-use ndk_build::manifest::{IntentFilter, MetaData};
+use ndk_build::manifest::{AndroidManifest, IntentFilter, MetaData, Permission};
 use ndk_build::ndk::{KeystoreMeta, Ndk};
+use ndk_build::secret::redacted_shell_command;
+use ndk_build::shell;
 use ndk_build::target::Target;
+use ndk_build::wsl;
 
+use crate::artifact::{apply_args, ArtifactSelector};
+use crate::collisions::{case_collisions, stage_without};
+use crate::diagnostics::{has_message_format, Warnings};
+use crate::env::Env;
 use crate::error::Error;
-use crate::manifest::{Inheritable, Manifest, Root};
+use crate::keystore::{
+    apply_signing_config, check_release_signing, is_debug_key, keystore_fingerprint,
+    read_key_rotation, read_keystore_meta, InstalledDebugKeys,
+};
+use crate::lint::{lint_manifest, Finding};
+use crate::manifest::{
+    compute_version_code, compute_version_name, ensure_android_package, example_features,
+    expand_artifact_name, IntentExtra, Manifest, Root,
+};
+use crate::manifest_diff::{dump_manifest, ManifestDiff};
+use crate::network_security;
+use crate::splash;
+use crate::watch::ChangeWatcher;
 
+/// Flags that `cargo-subcommand` does not capture but which must reach every inner `cargo`
+/// invocation, e.g. for reproducible builds or `-Zbuild-std`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, clap::Args)]
+#[group(skip)]
+pub struct CargoFlags {
+    /// Require `Cargo.lock` to be up to date
+    #[clap(long)]
+    pub locked: bool,
+    /// Require `Cargo.lock` and cache to be up to date
+    #[clap(long)]
+    pub frozen: bool,
+    /// Run without accessing the network
+    #[clap(long)]
+    pub offline: bool,
+    /// Number of parallel jobs of every inner cargo invocation. The targets are built one
+    /// after another, so this is also the limit of the whole build
+    #[clap(short, long, value_name = "N")]
+    pub jobs: Option<u32>,
+    /// Override a cargo configuration value (`KEY=VALUE` or a path to a TOML file)
+    #[clap(long, value_name = "CONFIG")]
+    pub config: Vec<String>,
+    /// Unstable (nightly-only) flag passed to cargo as `-Z <FLAG>`, e.g. `build-std=std,panic_abort`
+    #[clap(short = 'Z', value_name = "FLAG")]
+    pub unstable_flags: Vec<String>,
+    /// Additional raw argument for every inner cargo invocation, may be given multiple times
+    #[clap(long = "cargo-flag", value_name = "FLAG", allow_hyphen_values = true)]
+    pub cargo_flags: Vec<String>,
+}
+
+impl CargoFlags {
+    pub fn apply(&self, cmd: &mut Command) {
+        if self.locked {
+            cmd.arg("--locked");
+        }
+        if self.frozen {
+            cmd.arg("--frozen");
+        }
+        if self.offline {
+            cmd.arg("--offline");
+        }
+        if let Some(jobs) = self.jobs {
+            cmd.arg("--jobs").arg(jobs.to_string());
+        }
+        for config in &self.config {
+            cmd.arg("--config").arg(config);
+        }
+        for flag in &self.unstable_flags {
+            cmd.arg("-Z").arg(flag);
+        }
+        cmd.args(&self.cargo_flags);
+    }
+}
+
+/// The environment of the inner `cargo` invocation for one target, written to
+/// `<target dir>/<profile>/apk/.env/<triple>.json` on every build so that other tools, such as
+/// fuzzing or benchmarking harnesses, can invoke `cargo` exactly like cargo-android does.
+///
+/// This is a stable machine interface: fields are only added, never renamed or removed.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TargetEnv {
+    /// Rust target triple
+    pub triple: String,
+    /// API level that C/C++ dependencies and the final link target, which is below the
+    /// `min_sdk_version` of the manifest if the NDK has no platform libraries that new
+    pub min_sdk_version: u32,
+    /// All environment variables set on `cargo`, see [`ApkBuilder::target_env()`]
+    pub env: BTreeMap<String, String>,
+    /// `CARGO_ENCODED_RUSTFLAGS`, split into individual flags
+    pub rustflags: Vec<String>,
+    /// Path of the linker set through `CARGO_TARGET_<TRIPLE>_LINKER`
+    pub linker: Option<String>,
+}
+
+impl TargetEnv {
+    fn new(target: Target, min_sdk_version: u32, env: BTreeMap<String, String>) -> Self {
+        let triple = target.rust_triple();
+        let rustflags = env
+            .get("CARGO_ENCODED_RUSTFLAGS")
+            .map(|flags| {
+                flags
+                    .split('\x1f')
+                    .filter(|flag| !flag.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let linker_var = format!("CARGO_TARGET_{}_LINKER", triple.replace('-', "_")).to_uppercase();
+        let linker = env.get(&linker_var).cloned();
+        Self {
+            triple: triple.to_owned(),
+            min_sdk_version,
+            env,
+            rustflags,
+            linker,
+        }
+    }
+}
+
+/// Builds, installs and runs the APKs of a package, configured from its
+/// `[package.metadata.android]`.
+///
+/// The SDK versions, targets and package names it resolved are available before building
+/// through accessors that are kept stable, e.g. for an xtask that only runs instrumentation for
+/// new enough SDKs:
+///
+/// ```no_run
+/// use cargo_android::ApkBuilder;
+/// use cargo_subcommand::{Args, Subcommand};
+/// use clap::Parser;
+/// use ndk_build::target::Target;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let cmd = Subcommand::new(Args::parse_from(["xtask", "--release"]))?;
+/// let builder = ApkBuilder::from_subcommand(&cmd, None)?;
+/// println!(
+///     "Building API levels {} to {} for {:?} into {}",
+///     builder.min_sdk_version(),
+///     builder.target_sdk_version(),
+///     builder.build_targets(),
+///     builder.build_dir().display()
+/// );
+/// for artifact in cmd.artifacts() {
+///     let package = builder.package_id(artifact);
+///     builder.build(artifact)?;
+///     let arm64 = builder.build_targets().contains(&Target::Arm64V8a);
+///     if builder.target_sdk_version() >= 33 && arm64 {
+///         println!("Running the instrumentation of `{package}`");
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
 pub struct ApkBuilder<'a> {
     cmd: &'a Subcommand,
     ndk: Ndk,
+    /// The environment that the `CARGO_ANDROID_*` signing variables are read from
+    env: Env,
     manifest: Manifest,
     build_dir: PathBuf,
     build_targets: Vec<Target>,
+    compile_sdk_version: u32,
     device_serial: Option<String>,
+    cargo_flags: CargoFlags,
+    /// Build non-`dev` profiles that are signed with the debug keystore or `debuggable`
+    allow_debug_signing: bool,
+    /// Don't strip debug symbols, regardless of the `strip` configuration
+    no_strip: bool,
+    /// Strip debug symbols to `.debug` files even when the `strip` configuration doesn't strip
+    strip_debug: bool,
+    /// Reuse the libraries of the previous build when only `assets` changed since
+    assets_only_rebuild: bool,
+    /// Fail when `aapt` prints warnings
+    deny_warnings: bool,
+    /// Fail release builds that ship a 32-bit ABI without its 64-bit counterpart
+    strict: bool,
+    /// Warn about missing `assets`, `resources` and `runtime_libs` directories instead of
+    /// failing
+    allow_missing_dirs: bool,
+    /// Package the first of the `assets` and `resources` paths that only differ in case
+    /// instead of failing
+    allow_case_collisions: bool,
+    /// Extras of the intent that launches the application, from the device profile
+    launch_extras: BTreeMap<String, IntentExtra>,
+    /// Print a [`LaunchInfo`] once the application started
+    print_launch_info: bool,
+    /// Print each warning of the per-target `cargo` builds once, with the targets it occurred on
+    dedup_warnings: bool,
+    /// Print a failed inner `cargo` invocation as a shell command that reproduces it
+    print_cargo_command: bool,
+    /// Stop following `logcat` after this long
+    logcat_duration: Option<Duration>,
+    /// Stop following `logcat` at the first line matching this pattern
+    logcat_until: Option<Regex>,
+    /// Clear the log of the device before starting the application
+    clear_logcat: bool,
+    /// Artifacts whose APK name collides with another artifact's, see
+    /// [`ApkBuilder::resolve_apk_names()`]
+    disambiguated: Vec<Artifact>,
+}
+
+/// The APK that [`ApkBuilder::run()`] installed, or that [`ApkBuilder::build()`] created when
+/// converted from the returned [`Apk`], for the follow-up steps of library users.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuiltApk {
+    /// Path of the signed APK
+    pub path: PathBuf,
+    pub package_name: String,
+    pub version_code: Option<u32>,
+}
+
+impl From<&Apk> for BuiltApk {
+    fn from(apk: &Apk) -> Self {
+        Self {
+            path: apk.path().to_owned(),
+            package_name: apk.package_name().to_owned(),
+            version_code: apk.version_code(),
+        }
+    }
+}
+
+/// The started application, printed by `run` as a single line of JSON with
+/// [`ApkBuilder::print_launch_info()`] for tools that attach to its process, such as profilers.
+#[derive(Clone, Debug, Serialize)]
+pub struct LaunchInfo {
+    pub package: String,
+    /// [`None`] when it could not be looked up, see `uid_retries`
+    pub uid: Option<u32>,
+    /// [`None`] when the process didn't appear within [`PID_TIMEOUT`]
+    pub pid: Option<u32>,
+    /// [`None`] when neither given nor exactly one device is connected
+    pub device_serial: Option<String>,
+    pub apk_path: PathBuf,
+}
+
+/// A step of starting the installed application in [`ApkBuilder::run()`], see
+/// [`launch_steps()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LaunchStep {
+    /// `adb logcat -c`, with [`ApkBuilder::clear_logcat()`]
+    ClearLogcat,
+    /// Looks up the UID that `logcat` is filtered by
+    LookUpUid,
+    /// Spawns `adb logcat`, whose lines are held back until [`LaunchStep::FollowLogcat`]
+    SpawnLogcat,
+    /// `am start` and waiting for the application to reach the foreground
+    Start,
+    /// With [`ApkBuilder::print_launch_info()`]
+    PrintLaunchInfo,
+    /// Echoes the spawned `logcat`
+    FollowLogcat,
+}
+
+/// The order in which `run` starts the installed application. `logcat` is spawned before the
+/// application starts, so that the log of its startup, e.g. a crash right away, is captured
+/// rather than lost when the ring buffer rotated by the time the application was found in the
+/// foreground. Its lines are only echoed after the [`LaunchInfo`], which comes first.
+fn launch_steps(no_logcat: bool, clear_logcat: bool, print_launch_info: bool) -> Vec<LaunchStep> {
+    let mut steps = Vec::new();
+    if clear_logcat {
+        steps.push(LaunchStep::ClearLogcat);
+    }
+    // The application is installed already, so its UID is known before it starts
+    steps.push(LaunchStep::LookUpUid);
+    if !no_logcat {
+        steps.push(LaunchStep::SpawnLogcat);
+    }
+    steps.push(LaunchStep::Start);
+    if print_launch_info {
+        steps.push(LaunchStep::PrintLaunchInfo);
+    }
+    if !no_logcat {
+        steps.push(LaunchStep::FollowLogcat);
+    }
+    steps
+}
+
+/// The arguments of `adb logcat` for the processes of `uid`, or for the whole device from the
+/// `logcat -T` time `since` on.
+fn logcat_args(uid: Option<u32>, since: Option<&str>, color: bool) -> Vec<String> {
+    let mut args = vec!["logcat".to_owned()];
+    if color {
+        args.extend(["-v".to_owned(), "color".to_owned()]);
+    }
+    if let Some(uid) = uid {
+        args.extend(["--uid".to_owned(), uid.to_string()]);
+    } else if let Some(since) = since {
+        args.extend(["-T".to_owned(), since.to_owned()]);
+    }
+    args
+}
+
+/// A running `adb logcat` whose lines are read on a thread, so that it can be spawned before
+/// the application starts and its lines echoed later.
+struct Logcat {
+    child: Child,
+    lines: mpsc::Receiver<String>,
+}
+
+impl Logcat {
+    fn spawn(mut logcat: Command) -> Result<Self, Error> {
+        let mut child = logcat.stdout(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (send, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if send.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self { child, lines })
+    }
+
+    /// Ends `logcat` after echoing the lines it printed so far, e.g. the crash of an
+    /// application that failed to start.
+    fn flush_and_stop(self) -> Result<(), Error> {
+        let deadline = Instant::now() + LOGCAT_FLUSH_TIMEOUT;
+        while let Ok(line) = self
+            .lines
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            println!("{line}");
+        }
+        self.stop()
+    }
+
+    /// Ends `logcat`, which may have ended on its own already.
+    fn stop(mut self) -> Result<(), Error> {
+        let _ = self.child.kill();
+        self.child.wait()?;
+        Ok(())
+    }
+}
+
+/// How [`ApkBuilder::watch()`] deploys rebuilt APKs.
+#[derive(Clone, Debug)]
+pub struct WatchOptions {
+    /// Restart the application after installing a changed APK, instead of only installing it
+    pub restart: bool,
+    /// Time without further changes before rebuilding, so that saving several files at once
+    /// triggers a single build
+    pub debounce: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            restart: true,
+            debounce: Duration::from_millis(300),
+        }
+    }
+}
+
+/// How often [`ApkBuilder::watch()`] checks that `logcat` is still following the log while
+/// no file changes
+const LOGCAT_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Times that `run` retries looking up the UID of the application, unless configured with
+/// `[package.metadata.android.run] uid_retries`.
+const DEFAULT_UID_RETRIES: u32 = 5;
+
+/// Wait before the first retry of looking up the UID, doubled for every following retry.
+const UID_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Time that `run` waits for the process of the started application to appear for its
+/// [`LaunchInfo`].
+const PID_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often `run` checks whether the application exited while following `logcat` with
+/// `post_run_shell` commands pending.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time that `run` keeps echoing `logcat` after the application failed to start, for its crash.
+const LOGCAT_FLUSH_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Set while `run` follows `logcat` with `post_run_shell` commands pending, see
+/// [`is_following_logcat()`].
+static FOLLOWING_LOGCAT: AtomicBool = AtomicBool::new(false);
+
+/// Whether [`ApkBuilder::run`] is following `logcat` with `post_run_shell` commands pending.
+///
+/// An interrupt handler should not exit the process while this is set: `logcat` is ended by
+/// the interrupt as well, after which `run` executes the `post_run_shell` commands.
+pub fn is_following_logcat() -> bool {
+    FOLLOWING_LOGCAT.load(Ordering::SeqCst)
+}
+
+/// Locates the SDK and NDK through the variables of `env`, reusing the probe results that are
+/// cached in `target_dir` unless disabled with [`Env::ndk_cache()`].
+pub(crate) fn ndk_from_env(target_dir: &Path, env: &Env) -> Result<Ndk, NdkError> {
+    let var = |name: &str| env.var(name);
+    if env.uses_ndk_cache() {
+        Ndk::from_vars_cached(
+            &dunce::simplified(target_dir).join("cargo-android-ndk.cache"),
+            var,
+        )
+    } else {
+        Ndk::from_vars(var)
+    }
+}
+
+/// Like [`ApkBuilder::device_abi()`], but only locates the SDK for `adb` (with the NDK cache
+/// in `target_dir` and the settings of `env`) instead of resolving a package, for querying the
+/// device without building anything.
+pub fn device_abi(
+    target_dir: &Path,
+    device_serial: Option<&str>,
+    env: &Env,
+) -> Result<Target, Error> {
+    let ndk = ndk_from_env(target_dir, env)?;
+    detect_device_abi(&ndk, device_serial)
+}
+
+/// Fails with guidance when no `device_serial` is given and `adb` sees no device at all, which
+/// inside WSL typically means that the device is attached to the Windows host.
+fn ensure_device(ndk: &Ndk, device_serial: Option<&str>) -> Result<(), Error> {
+    if device_serial.is_some() || !ndk.devices()?.is_empty() {
+        return Ok(());
+    }
+    Err(if wsl::is_wsl() {
+        Error::NoDeviceInWsl
+    } else {
+        Error::NoDevice
+    })
+}
+
+/// The ABI of the device, from its `ro.product.cpu.abi` property.
+pub(crate) fn detect_device_abi(ndk: &Ndk, device_serial: Option<&str>) -> Result<Target, Error> {
+    ensure_device(ndk, device_serial)?;
+    Ok(ndk.detect_abi(device_serial)?)
 }
 
 impl<'a> ApkBuilder<'a> {
     pub fn from_subcommand(cmd: &'a Subcommand, device_serial: Option<String>) -> Result<Self, Error> {
+        Self::from_subcommand_with_env(cmd, device_serial, Env::default())
+    }
+
+    /// Like [`ApkBuilder::from_subcommand()`], but with the settings and variables of `env`.
+    pub fn from_subcommand_with_env(
+        cmd: &'a Subcommand,
+        device_serial: Option<String>,
+        env: Env,
+    ) -> Result<Self, Error> {
+        let ndk = ndk_from_env(cmd.target_dir(), &env)?;
+        Self::from_subcommand_with_ndk(cmd, device_serial, ndk, env)
+    }
+
+    /// Like [`ApkBuilder::from_subcommand()`], but with an `ndk` that the caller already
+    /// located instead of probing the SDK and NDK, and with the signing variables read from
+    /// `env`, for build systems that run hermetically.
+    pub fn from_subcommand_with_ndk(
+        cmd: &'a Subcommand,
+        device_serial: Option<String>,
+        ndk: Ndk,
+        env: Env,
+    ) -> Result<Self, Error> {
         println!(
             "Using package `{}` in `{}`",
             cmd.package(),
             cmd.manifest().display()
         );
-        let ndk = Ndk::from_env()?;
+        ensure_android_package(cmd.package(), cmd.manifest(), cmd.workspace_manifest())?;
+        // `keytool` from the same JDK as `JAVA_HOME` in `env`
+        let ndk = ndk.with_java_home(env.var_os("JAVA_HOME").map(PathBuf::from));
         let mut manifest = Manifest::parse_from_toml(cmd.manifest())?;
+        apply_signing_config(&mut manifest, &env)?;
         let workspace_manifest: Option<Root> = cmd
             .workspace_manifest()
             .map(Root::parse_from_toml)
             .transpose()?;
-        let build_targets = if let Some(target) = cmd.target() {
-            vec![Target::from_rust_triple(target)?]
-        } else if !manifest.build_targets.is_empty() {
-            manifest.build_targets.clone()
-        } else {
-            vec![ndk
-                .detect_abi(device_serial.as_deref())
-                .unwrap_or(Target::Arm64V8a)]
-        };
-        let build_dir = dunce::simplified(cmd.target_dir())
-            .join(cmd.profile())
-            .join("apk");
-
-        let package_version = match &manifest.version {
-            Inheritable::Value(v) => v.clone(),
-            Inheritable::Inherited { workspace: true } => {
-                let workspace = workspace_manifest
-                    .ok_or(Error::InheritanceMissingWorkspace)?
-                    .workspace
-                    .unwrap_or_else(|| {
-                        // Unlikely to fail as cargo-subcommand should give us
-                        // a `Cargo.toml` containing a `[workspace]` table
-                        panic!(
-                            "Manifest `{:?}` must contain a `[workspace]` table",
-                            cmd.workspace_manifest().unwrap()
-                        )
-                    });
-
-                workspace
-                    .package
-                    .ok_or(Error::WorkspaceMissingInheritedField("package"))?
-                    .version
-                    .ok_or(Error::WorkspaceMissingInheritedField("package.version"))?
-            }
-            Inheritable::Inherited { workspace: false } => return Err(Error::InheritedFalse),
-        };
-        let version_code = VersionCode::from_semver(&package_version)?.to_code(1);
+        let build_targets = resolve_build_targets(cmd.target(), &manifest.build_targets, || {
+            ndk.detect_abi(device_serial.as_deref()).ok()
+        })?;
+        let build_dir = apk_dir(cmd.target_dir(), cmd.profile());
+
+        let version_name = compute_version_name(&manifest, workspace_manifest.as_ref())?;
+        let version_code = compute_version_code(&manifest, workspace_manifest.as_ref())?;
 
         // Set default Android manifest values
         if manifest
             .android_manifest
             .version_name
-            .replace(package_version)
+            .replace(version_name)
             .is_some()
         {
             panic!("version_name should not be set in TOML");
@@ -92,11 +518,21 @@ impl<'a> ApkBuilder<'a> {
             panic!("version_code should not be set in TOML");
         }
 
-        let target_sdk_version = *manifest
-            .android_manifest
-            .sdk
-            .target_sdk_version
-            .get_or_insert_with(|| ndk.default_target_platform());
+        let target_sdk_version = manifest.target_sdk_version(|| ndk.default_target_platform());
+        manifest.android_manifest.sdk.target_sdk_version = Some(target_sdk_version);
+        let compile_sdk_version = manifest.compile_sdk_version(target_sdk_version)?;
+        // Fail before building when the platform is missing
+        android_jar(&ndk, compile_sdk_version)?;
+        if let Some(min_sdk_version) = manifest.android_manifest.sdk.min_sdk_version {
+            for &target in &build_targets {
+                match ndk.sysroot_platforms(target) {
+                    Ok(platforms) => check_min_sdk_floor(min_sdk_version, &platforms, target)?,
+                    Err(e) => {
+                        log::debug!("Not checking `min_sdk_version` against the NDK sysroot: {e}")
+                    }
+                }
+            }
+        }
 
         manifest
             .android_manifest
@@ -104,311 +540,2927 @@ impl<'a> ApkBuilder<'a> {
             .debuggable
             .get_or_insert_with(|| *cmd.profile() == Profile::Dev);
 
-        let activity = &mut manifest.android_manifest.application.activity;
-
-        // Add a default `MAIN` action to launch the activity, if the user didn't supply it by hand.
-        if activity
-            .intent_filter
-            .iter()
-            .all(|i| i.actions.iter().all(|f| f != "android.intent.action.MAIN"))
-        {
-            activity.intent_filter.push(IntentFilter {
-                actions: vec!["android.intent.action.MAIN".to_string()],
-                categories: vec!["android.intent.category.LAUNCHER".to_string()],
-                data: vec![],
-            });
+        add_launcher_defaults(&mut manifest, target_sdk_version);
+        if let Some(warning) = check_internet_permission(&mut manifest) {
+            eprintln!("Warning: {warning}");
         }
-
-        // Export the sole Rust activity on Android S and up, if the user didn't explicitly do so.
-        // Without this, apps won't start on S+.
-        // https://developer.android.com/about/versions/12/behavior-changes-12#exported
-        if target_sdk_version >= 31 {
-            activity.exported.get_or_insert(true);
+        if let Some(warning) = check_profileable(&manifest.android_manifest) {
+            eprintln!("Warning: {warning}");
         }
 
         Ok(Self {
             cmd,
             ndk,
+            env,
             manifest,
             build_dir,
             build_targets,
+            compile_sdk_version,
             device_serial,
+            cargo_flags: CargoFlags::default(),
+            allow_debug_signing: false,
+            no_strip: false,
+            strip_debug: false,
+            assets_only_rebuild: false,
+            deny_warnings: false,
+            strict: false,
+            allow_missing_dirs: false,
+            allow_case_collisions: false,
+            launch_extras: BTreeMap::new(),
+            print_launch_info: false,
+            dedup_warnings: false,
+            print_cargo_command: false,
+            logcat_duration: None,
+            logcat_until: None,
+            clear_logcat: false,
+            disambiguated: Vec::new(),
         })
     }
 
-    pub fn check(&self) -> Result<(), Error> {
-        for target in &self.build_targets {
-            let mut cargo = cargo_ndk(
-                &self.ndk,
-                *target,
-                self.min_sdk_version(),
-                self.cmd.target_dir(),
-            )?;
-            cargo.arg("check");
-            if self.cmd.target().is_none() {
-                let triple = target.rust_triple();
-                cargo.arg("--target").arg(triple);
-            }
-            self.cmd.args().apply(&mut cargo);
-            if !cargo.status()?.success() {
-                return Err(NdkError::CmdFailed(cargo).into());
-            }
+    /// Applies the device profile `name` from `[package.metadata.android.devices]`: its serial
+    /// unless a device was given already, its ABI as the only build target unless `--target`
+    /// was given, and its intent extras when launching the application.
+    pub fn device_profile(mut self, name: &str) -> Result<Self, Error> {
+        let profile = self.manifest.device_profile(name)?.clone();
+        if self.device_serial.is_none() {
+            self.device_serial = profile.serial.clone();
         }
-        Ok(())
+        let device_serial = self.device_serial.as_deref();
+        self.build_targets = match profile.target() {
+            Some(target) if self.cmd.target().is_none() => vec![target],
+            // Detected again, now on the device of the profile
+            _ => resolve_build_targets(self.cmd.target(), &self.manifest.build_targets, || {
+                self.ndk.detect_abi(device_serial).ok()
+            })?,
+        };
+        self.launch_extras = profile.extras;
+        Ok(self)
     }
 
-    pub fn build(&self, artifact: &Artifact) -> Result<Apk, Error> {
-        // Set artifact specific manifest default values.
-        let mut manifest = self.manifest.android_manifest.clone();
-
-        if manifest.package.is_empty() {
-            let name = artifact.name.replace('-', "_");
-            manifest.package = match artifact.r#type {
-                ArtifactType::Lib | ArtifactType::Bin => format!("rust.{name}"),
-                ArtifactType::Example => format!("rust.example.{name}"),
-            };
-        }
+    /// Forwards `flags` to every inner `cargo` invocation.
+    #[must_use]
+    pub fn cargo_flags(mut self, flags: CargoFlags) -> Self {
+        self.cargo_flags = flags;
+        self
+    }
 
-        if manifest.application.label.is_empty() {
-            manifest.application.label = artifact.name.to_string();
-        }
+    /// Builds non-`dev` profiles even when they are signed with the debug keystore or
+    /// `debuggable`, like the `allow_debuggable_release` metadata key does. A warning is
+    /// printed regardless.
+    #[must_use]
+    pub fn allow_debug_signing(mut self, allow: bool) -> Self {
+        self.allow_debug_signing = allow;
+        self
+    }
 
-        manifest.application.activity.meta_data.push(MetaData {
-            name: "android.app.lib_name".to_string(),
-            value: artifact.name.replace('-', "_"),
-        });
+    /// Copies libraries into the APK as they are, overriding the `strip` configuration of
+    /// every ABI.
+    #[must_use]
+    pub fn no_strip(mut self, no_strip: bool) -> Self {
+        self.no_strip = no_strip;
+        self
+    }
 
-        let crate_path = self.cmd.manifest().parent().expect("invalid manifest path");
+    /// Writes the debug symbols of every library to a `.debug` file next to the stripped
+    /// copy in the build directory, for symbolicating crashes later. Libraries of ABIs that
+    /// the `strip` configuration doesn't strip have their debug symbols stripped.
+    #[must_use]
+    pub fn strip_debug(mut self, strip_debug: bool) -> Self {
+        self.strip_debug = strip_debug;
+        self
+    }
 
-        let is_debug_profile = *self.cmd.profile() == Profile::Dev;
+    /// Repackages the APK with the libraries of the previous build, without running `cargo`,
+    /// when nothing in the workspace but the `assets` changed since it was built.
+    #[must_use]
+    pub fn assets_only_rebuild(mut self, enabled: bool) -> Self {
+        self.assets_only_rebuild = enabled;
+        self
+    }
 
-        let assets = self
-            .manifest
-            .assets
-            .as_ref()
-            .map(|assets| dunce::simplified(&crate_path.join(assets)).to_owned());
-        let resources = self
-            .manifest
-            .resources
-            .as_ref()
-            .map(|res| dunce::simplified(&crate_path.join(res)).to_owned());
-        let runtime_libs = self
-            .manifest
-            .runtime_libs
-            .as_ref()
-            .map(|libs| dunce::simplified(&crate_path.join(libs)).to_owned());
-        let apk_name = self
-            .manifest
-            .apk_name
-            .clone()
-            .unwrap_or_else(|| artifact.name.to_string());
+    /// Fails the build when `aapt` prints warnings about the manifest or resources, which are
+    /// otherwise only shown.
+    #[must_use]
+    pub fn deny_warnings(mut self, deny_warnings: bool) -> Self {
+        self.deny_warnings = deny_warnings;
+        self
+    }
 
-        let config = ApkConfig {
-            ndk: self.ndk.clone(),
-            build_dir: self.build_dir.join(artifact.build_dir()),
-            apk_name,
-            assets,
-            resources,
-            manifest,
-            disable_aapt_compression: is_debug_profile,
-            strip: self.manifest.strip,
-            reverse_port_forward: self.manifest.reverse_port_forward.clone(),
-        };
-        let mut apk = config.create_apk()?;
+    /// Fails the build when validating the packaged libraries finds problems, and release
+    /// builds whose build targets contain a 32-bit ABI without its 64-bit counterpart, which
+    /// Google Play rejects, with [`Error::Missing64Bit`], instead of warning.
+    #[must_use]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
 
-        for target in &self.build_targets {
-            let triple = target.rust_triple();
-            let build_dir = self.cmd.build_dir(Some(triple));
-            let artifact = self.cmd.artifact(artifact, Some(triple), CrateType::Cdylib);
+    /// Builds without the configured `assets`, `resources` or `runtime_libs` directories that
+    /// don't exist, e.g. a vendor drop that wasn't fetched yet, printing a warning instead of
+    /// failing with [`Error::MissingMetadataDir`].
+    #[must_use]
+    pub fn allow_missing_dirs(mut self, allow_missing_dirs: bool) -> Self {
+        self.allow_missing_dirs = allow_missing_dirs;
+        self
+    }
 
-            let mut cargo = cargo_ndk(
-                &self.ndk,
-                *target,
-                self.min_sdk_version(),
-                self.cmd.target_dir(),
-            )?;
-            cargo.arg("build");
-            if self.cmd.target().is_none() {
-                cargo.arg("--target").arg(triple);
-            }
-            self.cmd.args().apply(&mut cargo);
+    /// Packages only the first in byte order of the `assets` and `resources` paths that only
+    /// differ in case, printing a warning, instead of failing with [`Error::CaseCollisions`].
+    /// The APK then has the same contents on case-insensitive file systems.
+    #[must_use]
+    pub fn allow_case_collisions(mut self, allow_case_collisions: bool) -> Self {
+        self.allow_case_collisions = allow_case_collisions;
+        self
+    }
 
-            if !cargo.status()?.success() {
-                return Err(NdkError::CmdFailed(cargo).into());
+    /// Whether the APK of `config` can be repackaged with the libraries of the previous build:
+    /// it exists, there are libraries for every target, and the `fingerprint` of the build
+    /// inputs matches the one the libraries were built from.
+    fn only_assets_changed(&self, config: &ApkConfig, fingerprint: &str) -> bool {
+        if !config.apk().exists() {
+            println!("No APK was built before, building everything");
+            return false;
+        }
+        let lib_dir = config.build_dir.join("lib");
+        if let Some(target) = self.build_targets.iter().find(|target| {
+            std::fs::read_dir(lib_dir.join(target.android_abi()))
+                .map_or(true, |mut entries| entries.next().is_none())
+        }) {
+            println!(
+                "No `{}` libraries were built before, building everything",
+                target.android_abi()
+            );
+            return false;
+        }
+        match std::fs::read_to_string(fingerprint_path(config)) {
+            Ok(previous) if previous == fingerprint => true,
+            Ok(_) => {
+                println!(
+                    "Files outside of `assets` or the build flags changed, building everything"
+                );
+                false
             }
+            Err(_) => {
+                println!("The libraries were not built with `--assets-only-rebuild` before, building everything");
+                false
+            }
+        }
+    }
 
-            let mut libs_search_paths =
-                get_libs_search_paths(self.cmd.target_dir(), triple, self.cmd.profile().as_ref())?;
-            libs_search_paths.push(build_dir.join("deps"));
-
-            let libs_search_paths = libs_search_paths
-                .iter()
-                .map(PathBuf::as_path)
-                .collect::<Vec<_>>();
+    /// A SHA-256 of everything the libraries of an APK are built from: the files in the
+    /// workspace (or the crate outside of one) except for `assets`, hidden directories and the
+    /// target directory, and the cargo arguments, `features` and targets of this build.
+    fn input_fingerprint(&self, crate_path: &Path, features: &[String]) -> Result<String, Error> {
+        let root = self
+            .cmd
+            .workspace_manifest()
+            .and_then(Path::parent)
+            .unwrap_or(crate_path);
+        let root = dunce::canonicalize(root)?;
+        // The configured `assets`, not the copy that `resolve_case_collisions()` may stage
+        let assets = self
+            .manifest
+            .assets
+            .as_ref()
+            .map(|assets| crate_path.join(assets));
+        let skip = [Some(self.cmd.target_dir().to_owned()), assets]
+            .into_iter()
+            .flatten()
+            .filter_map(|path| dunce::canonicalize(path).ok())
+            .collect::<Vec<_>>();
+        let mut hasher = Sha256::new();
+        hasher.update(
+            format!(
+                "{:?}\n{:?}\n{features:?}\n{:?}\n{}\n",
+                self.cmd.args(),
+                self.cargo_flags,
+                self.build_targets,
+                profile_name(self.cmd.profile()),
+            )
+            .as_bytes(),
+        );
+        hash_tree(&mut hasher, &root, &root, &skip)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
 
-            apk.add_lib_recursively(&artifact, *target, libs_search_paths.as_slice())?;
+    /// Creates a `cargo <subcommand>` invocation for `target` with all cargo arguments applied,
+    /// ahead of the `--target` argument. Only the artifacts in `selection` are built, unless it
+    /// is empty, see [`apply_args()`].
+    fn cargo(
+        &self,
+        target: Target,
+        subcommand: &str,
+        selection: &[ArtifactSelector],
+    ) -> Result<Command, Error> {
+        self.cargo_for_api_level(
+            target,
+            self.native_api_level(target)?,
+            subcommand,
+            selection,
+        )
+    }
 
-            if let Some(runtime_libs) = &runtime_libs {
-                apk.add_runtime_libs(runtime_libs, *target, libs_search_paths.as_slice())?;
+    /// [`Self::cargo()`] with an already resolved [`Self::native_api_level()`].
+    fn cargo_for_api_level(
+        &self,
+        target: Target,
+        api_level: u32,
+        subcommand: &str,
+        selection: &[ArtifactSelector],
+    ) -> Result<Command, Error> {
+        let mut cargo = cargo_ndk_with_vars(
+            &self.ndk,
+            target,
+            api_level,
+            self.cmd.target_dir(),
+            |name| self.env.var(name),
+        )?;
+        let rustflags = config_rustflags(&self.cargo_flags.config, target.rust_triple());
+        if !rustflags.is_empty() {
+            let mut encoded = command_env(&cargo)
+                .remove("CARGO_ENCODED_RUSTFLAGS")
+                .unwrap_or_default();
+            for flag in rustflags {
+                if !encoded.is_empty() {
+                    encoded.push('\x1f');
+                }
+                encoded.push_str(&flag);
             }
+            cargo.env("CARGO_ENCODED_RUSTFLAGS", encoded);
         }
+        let triple = self.cmd.target().is_none().then(|| target.rust_triple());
+        append_cargo_args(
+            &mut cargo,
+            subcommand,
+            |cargo| self.apply_cargo_args(cargo, selection),
+            triple,
+        );
+        Ok(cargo)
+    }
 
-        let signing_key = self.read_keystore_meta(crate_path, is_debug_profile)?;
+    fn apply_cargo_args(&self, cargo: &mut Command, selection: &[ArtifactSelector]) {
+        apply_args(cargo, self.cmd.args(), selection);
+        cargo.args(&self.manifest.cargo_flags);
+        for flag in &self.manifest.cargo_unstable_flags {
+            cargo.arg("-Z").arg(flag);
+        }
+        self.cargo_flags.apply(cargo);
+    }
 
-        let unsigned = apk.add_pending_libs_and_align()?;
+    /// The environment variables that cargo-android sets on the inner `cargo` invocation for
+    /// `target`: the NDK compilers, archivers and linker, and the resulting
+    /// `CARGO_ENCODED_RUSTFLAGS`. Variables inherited from this process are not included.
+    pub fn target_env(&self, target: Target) -> Result<BTreeMap<String, String>, Error> {
+        Ok(command_env(&self.cargo(target, "build", &[])?))
+    }
 
-        println!(
-            "Signing `{}` with keystore `{}`",
-            config.apk().display(),
-            signing_key.path.display()
-        );
-        Ok(unsigned.sign(signing_key)?)
+    /// Writes the [`TargetEnv`] of `cargo`, which builds `target`.
+    fn write_target_env(
+        &self,
+        target: Target,
+        api_level: u32,
+        cargo: &Command,
+    ) -> Result<(), Error> {
+        let env = TargetEnv::new(target, api_level, command_env(cargo));
+        let dir = self.build_dir.join(".env");
+        std::fs::create_dir_all(&dir)?;
+        let json = serde_json::to_vec_pretty(&env).map_err(std::io::Error::from)?;
+        std::fs::write(dir.join(format!("{}.json", env.triple)), json)?;
+        Ok(())
     }
 
-    fn read_keystore_meta(&self, crate_path: &Path, is_debug_profile: bool) -> Result<KeystoreMeta, Error> {
-        let profile_name = match self.cmd.profile() {
-            Profile::Dev => "dev",
-            Profile::Release => "release",
-            Profile::Custom(c) => c.as_str(),
-        };
+    /// Checks the `AndroidManifest` that [`ApkBuilder::build()`] writes for `artifact` against
+    /// the rules of [`crate::lint`], configured through `[package.metadata.android.lint]`.
+    pub fn lint(&self, artifact: &Artifact) -> Vec<Finding> {
+        let crate_path = self.cmd.manifest().parent().expect("invalid manifest path");
+        let mut manifest = self.packaged_manifest(artifact, crate_path);
+        if self.manifest.network_security.is_some() {
+            // Generated into the resources by `launcher_resources()`
+            manifest.application.network_security_config =
+                Some(network_security::CONFIG.to_owned());
+        }
+        lint_manifest(
+            &manifest,
+            *self.cmd.profile() != Profile::Dev,
+            &self.manifest.lint,
+        )
+    }
 
-        let manifest = self.manifest.signing.get(profile_name);
+    /// Compares the manifests of the APKs or AABs at `old` and `new`, see [`crate::manifest_diff`].
+    pub fn manifest_diff(&self, old: &Path, new: &Path) -> Result<ManifestDiff, Error> {
+        let old = dump_manifest(&self.ndk, old, &self.build_dir)?;
+        let new = dump_manifest(&self.ndk, new, &self.build_dir)?;
+        Ok(ManifestDiff::between(&old, &new))
+    }
 
-        let profile_name = profile_name.to_uppercase().replace('-', "_");
+    /// Fails with a hint to `rustup target add` when the standard library for one of `targets`
+    /// is not installed, instead of letting `cargo` fail with "can't find crate for `std`".
+    fn ensure_rust_targets_installed(&self, targets: &[Target]) -> Result<(), Error> {
+        // `-Zbuild-std` compiles the standard library from source
+        let build_std = self
+            .manifest
+            .cargo_unstable_flags
+            .iter()
+            .chain(&self.cargo_flags.unstable_flags)
+            .any(|flag| flag.starts_with("build-std"));
+        if build_std || self.env.var_os("CARGO_UNSTABLE_BUILD_STD").is_some() {
+            return Ok(());
+        }
+        let rustc = self.env.var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let sysroot = match Command::new(&rustc).arg("--print").arg("sysroot").output() {
+            Ok(output) if output.status.success() => {
+                PathBuf::from(String::from_utf8_lossy(&output.stdout).trim())
+            }
+            _ => {
+                log::debug!("Failed to query the `rustc` sysroot, not checking installed targets");
+                return Ok(());
+            }
+        };
 
-        // TODO: Add documentation for environment variables and signing section
+        for target in targets {
+            let triple = target.rust_triple();
+            let target_libs = sysroot.join("lib").join("rustlib").join(triple).join("lib");
+            if target_libs.is_dir() {
+                continue;
+            }
+            let supported = Command::new(&rustc)
+                .arg("--print")
+                .arg("target-list")
+                .output()
+                .map(|output| {
+                    String::from_utf8_lossy(&output.stdout)
+                        .lines()
+                        .any(|t| t == triple)
+                })
+                .unwrap_or(true);
+            return Err(if supported {
+                Error::RustTargetNotInstalled(triple.to_owned())
+            } else {
+                Error::RustTargetUnsupported(triple.to_owned())
+            });
+        }
+        Ok(())
+    }
 
-        let env_store_path = format!("CARGO_ANDROID_{profile_name}_STORE_PATH");
-        let env_store_password = format!("CARGO_ANDROID_{profile_name}_STORE_PASSWORD");
-        let env_key_alias = format!("CARGO_ANDROID_{profile_name}_KEY_ALIAS");
-        let env_key_password = format!("CARGO_ANDROID_{profile_name}_KEY_PASSWORD");
+    /// Prints how parallel the build of the targets is, which are built by one `cargo`
+    /// invocation each.
+    fn print_parallelism(&self) {
+        let jobs = self.cargo_flags.jobs.map_or_else(
+            || "cargo's default number of".to_owned(),
+            |jobs| jobs.to_string(),
+        );
+        println!(
+            "Building {} target(s) one after another, each with {jobs} jobs",
+            self.build_targets.len()
+        );
+    }
 
-        let store_path = std::env::var_os(&env_store_path).map(PathBuf::from);
-        let store_password = std::env::var(&env_store_password).ok();
-        let key_alias = std::env::var(&env_key_alias).ok();
-        let key_password = std::env::var(&env_key_password).ok();
+    pub fn check(&self) -> Result<(), Error> {
+        self.ensure_rust_targets_installed(&self.build_targets)?;
+        self.print_parallelism();
+        let selection = self
+            .cmd
+            .artifacts()
+            .map(ArtifactSelector::from_artifact)
+            .collect::<Vec<_>>();
+        let mut warnings = Warnings::default();
+        for target in &self.build_targets {
+            let cargo = self.cargo(*target, "check", &selection)?;
+            self.run_cargo(cargo, *target, &mut warnings)?;
+        }
+        warnings.print();
+        Ok(())
+    }
 
-        if let Some(store_path) = store_path {
-            let signing_key = match store_password {
-                Some(store_password) => KeystoreMeta::single(store_path, store_password),
-                None => if is_debug_profile {
-                    println!("{env_store_password} not specified, falling back to default password");
-                    KeystoreMeta::single(store_path, ndk_build::ndk::DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned())
-                } else {
-                    eprintln!("`{}` was specified via `{env_store_path}`, but `{env_store_password}` was not specified, both or neither must be present for profiles other than `dev`", store_path.to_string_lossy());
-                    return Err(Error::MissingReleaseKey(profile_name));
-                },
-            };
+    /// The error of the inner `cargo` invocation that failed, printing a command line to
+    /// reproduce it with first when [`ApkBuilder::print_cargo_command()`] is set.
+    fn cargo_failed(&self, cargo: Command) -> Error {
+        if self.print_cargo_command {
+            eprintln!(
+                "To reproduce the failed cargo invocation, run:\n\n{}\n",
+                redacted_shell_command(&cargo)
+            );
+        }
+        NdkError::CmdFailed(cargo).into()
+    }
 
-            return match key_alias {
-                Some(key_alias) => if let Some(key_password) = key_password {
-                    Ok(signing_key.alias(key_alias).key_pass(key_password))
-                } else {
-                    eprintln!("`{key_alias}` was specified via `{env_key_alias}`, but `{env_key_password}` was not specified");
-                    Err(Error::MissingReleaseKey(profile_name))
-                },
-                None => Ok(signing_key),
+    /// Runs `cargo` for `target`, collecting its warnings into `warnings` when they are
+    /// deduplicated across the targets. The warnings collected so far are printed when it
+    /// fails.
+    fn run_cargo(
+        &self,
+        mut cargo: Command,
+        target: Target,
+        warnings: &mut Warnings,
+    ) -> Result<(), Error> {
+        let status =
+            if self.dedup_warnings && self.build_targets.len() > 1 && !has_message_format(&cargo) {
+                warnings.run(&cargo, target)?
+            } else {
+                cargo.status()?
             };
+        if !status.success() {
+            warnings.print();
+            return Err(self.cargo_failed(cargo));
         }
+        Ok(())
+    }
 
-        if let Some(signing) = manifest {
-            let store_path = crate_path.join(&signing.store_path);
-            let store_password = signing.store_password.clone();
-            let key_alias = signing.key_alias.clone();
-            let key_password = signing.key_password.clone();
-
-            let signing_key = KeystoreMeta::single(store_path, store_password);
-
-            return match key_alias {
-                Some(key_alias) => if let Some(key_password) = key_password {
-                    Ok(signing_key.alias(key_alias).key_pass(key_password))
-                } else {
-                    eprintln!("`{key_alias}` was specified via `{env_key_alias}`, but `{env_key_password}` was not specified");
-                    Err(Error::MissingReleaseKey(profile_name))
-                },
-                None => Ok(signing_key),
-            };
+    /// Resolves `selector` to an artifact of the package. Artifacts selected on the command
+    /// line are preferred, as they carry their configured source path.
+    pub fn artifact(&self, selector: &ArtifactSelector) -> Artifact {
+        if let Some(artifact) = self.cmd.artifacts().find(|a| selector.matches(a)) {
+            return artifact.clone();
         }
+        let lib_name = match &self.manifest.lib_name {
+            Some(lib_name) => lib_name.clone(),
+            None => self.cmd.package().replace('-', "_"),
+        };
+        selector.to_artifact(&lib_name)
+    }
 
-        if is_debug_profile {
-            Ok(self.ndk.debug_key()?)
+    /// The name of the APK built for `artifact`, from `apk_name` with its placeholders
+    /// expanded.
+    fn apk_name(&self, artifact: &Artifact) -> String {
+        let android_manifest = &self.manifest.android_manifest;
+        let apk_name = expand_artifact_name(
+            self.manifest.apk_name.as_deref().unwrap_or(&artifact.name),
+            &artifact.name,
+            android_manifest.version_name.as_deref().unwrap_or_default(),
+        );
+        if self.disambiguated.contains(artifact) {
+            format!("{apk_name}-{}", artifact.name)
         } else {
-            Err(Error::MissingReleaseKey(profile_name))
+            apk_name
         }
     }
 
-    pub fn run(&self, artifact: &Artifact, no_logcat: bool) -> Result<(), Error> {
-        let apk = self.build(artifact)?;
-        apk.reverse_port_forwarding(self.device_serial.as_deref())?;
-        apk.install(self.device_serial.as_deref())?;
-        apk.start(self.device_serial.as_deref())?;
-        let uid = apk.uidof(self.device_serial.as_deref())?;
+    /// The path of the APK that [`ApkBuilder::build()`] writes for `artifact`.
+    pub fn apk_path(&self, artifact: &Artifact) -> PathBuf {
+        self.build_dir
+            .join(artifact.build_dir())
+            .join(format!("{}.apk", self.apk_name(artifact)))
+    }
 
-        if !no_logcat {
-            self.ndk
-                .adb(self.device_serial.as_deref())?
-                .arg("logcat")
-                .arg("-v")
-                .arg("color")
-                .arg("--uid")
-                .arg(uid.to_string())
-                .status()?;
+    /// Checks that `artifacts`, which are built in the same invocation, are written to
+    /// distinct APK paths, as a shared `apk_name` would otherwise silently overwrite earlier
+    /// outputs.
+    ///
+    /// Colliding artifacts get their name appended to the APK name, unless `strict` is set in
+    /// which case an error naming the colliding artifacts is returned.
+    pub fn resolve_apk_names<'b>(
+        &mut self,
+        artifacts: impl IntoIterator<Item = &'b Artifact>,
+        strict: bool,
+    ) -> Result<(), Error> {
+        let mut paths = HashMap::<PathBuf, Vec<&Artifact>>::new();
+        for artifact in artifacts {
+            paths
+                .entry(self.apk_path(artifact))
+                .or_default()
+                .push(artifact);
+        }
+        let mut collisions = paths
+            .into_iter()
+            .filter(|(_, artifacts)| artifacts.len() > 1)
+            .collect::<Vec<_>>();
+        collisions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        if let Some((path, artifacts)) = collisions.first().filter(|_| strict) {
+            return Err(Error::ApkNameCollision {
+                path: path.clone(),
+                artifacts: artifacts.iter().map(|a| a.name.clone()).collect(),
+            });
+        }
+        for (path, artifacts) in collisions {
+            let names = artifacts
+                .iter()
+                .map(|a| a.name.as_str())
+                .collect::<Vec<_>>();
+            eprintln!(
+                "Warning: artifacts {names:?} all resolve to `{}`, appending the artifact name to their APK name",
+                path.display()
+            );
+            self.disambiguated.extend(artifacts.into_iter().cloned());
         }
-
         Ok(())
     }
 
-    pub fn gdb(&self, artifact: &Artifact) -> Result<(), Error> {
-        let apk = self.build(artifact)?;
-        apk.install(self.device_serial.as_deref())?;
+    /// Copies the `round_icon` and `banner` images into a generated resource directory and
+    /// points the corresponding `<application>` attributes at them, unless those were set
+    /// explicitly. Also generates the `splash` screen theme of the main activity and the
+    /// `network_security` config.
+    fn launcher_resources(
+        &self,
+        crate_path: &Path,
+        build_dir: &Path,
+        manifest: &mut AndroidManifest,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let res_dir = build_dir.join("generated-res");
+        if res_dir.exists() {
+            std::fs::remove_dir_all(&res_dir)?;
+        }
 
-        let target_dir = self.build_dir.join(artifact.build_dir());
-        self.ndk.ndk_gdb(
-            target_dir,
-            "android.app.NativeActivity",
-            self.device_serial.as_deref(),
-        )?;
-        Ok(())
-    }
+        let application = &mut manifest.application;
+        let images = [
+            (
+                &self.manifest.round_icon,
+                "mipmap",
+                "mipmap",
+                "ic_launcher_round",
+                &mut application.round_icon,
+            ),
+            (
+                &self.manifest.banner,
+                "drawable-xhdpi",
+                "drawable",
+                "banner",
+                &mut application.banner,
+            ),
+        ];
+        let mut generated = false;
+        for (image, dir, res_type, name, attribute) in images {
+            let Some(image) = image else {
+                continue;
+            };
+            let image = crate_path.join(image);
+            let extension = image
+                .extension()
+                .map_or("png".into(), |e| e.to_string_lossy());
+            let dir = res_dir.join(dir);
+            std::fs::create_dir_all(&dir)?;
+            std::fs::copy(&image, dir.join(format!("{name}.{extension}")))
+                .map_err(|e| NdkError::IoPathError(image, e))?;
+            attribute.get_or_insert_with(|| format!("@{res_type}/{name}"));
+            generated = true;
+        }
 
-    pub fn default(&self, cargo_cmd: &str, cargo_args: &[String]) -> Result<(), Error> {
-        for target in &self.build_targets {
-            let mut cargo = cargo_ndk(
-                &self.ndk,
-                *target,
-                self.min_sdk_version(),
-                self.cmd.target_dir(),
+        if let Some(splash) = &self.manifest.splash {
+            // Inherit the look of the activity once it is drawn
+            let activity = &mut manifest.application.activity;
+            let parent = activity
+                .theme
+                .as_ref()
+                .or(manifest.application.theme.as_ref())
+                .map_or(
+                    "@android:style/Theme.DeviceDefault.NoActionBar",
+                    String::as_str,
+                );
+            let icon = splash.icon.as_ref().map(|icon| crate_path.join(icon));
+            splash::write_resources(
+                &res_dir,
+                splash,
+                icon.as_deref(),
+                parent,
+                self.compile_sdk_version,
             )?;
-            cargo.arg(cargo_cmd);
-            self.cmd.args().apply(&mut cargo);
+            activity.theme = Some(splash::THEME.to_owned());
+            generated = true;
+        }
 
-            if self.cmd.target().is_none() {
-                let triple = target.rust_triple();
-                cargo.arg("--target").arg(triple);
+        if let Some(network_security) = &self.manifest.network_security {
+            let application = &mut manifest.application;
+            if let Some(config) = &application.network_security_config {
+                return Err(Error::NetworkSecurityConfigConflict(config.clone()));
             }
+            network_security::write_resources(
+                &res_dir,
+                network_security,
+                crate_path,
+                *self.cmd.profile() == Profile::Dev,
+            )?;
+            application.network_security_config = Some(network_security::CONFIG.to_owned());
+            generated = true;
+        }
 
-            for additional_arg in cargo_args {
-                cargo.arg(additional_arg);
-            }
+        Ok(if generated { vec![res_dir] } else { vec![] })
+    }
 
-            if !cargo.status()?.success() {
-                return Err(NdkError::CmdFailed(cargo).into());
-            }
-        }
-        Ok(())
+    /// The Android manifest of `artifact`, see [`artifact_manifest()`].
+    pub(crate) fn artifact_manifest(&self, artifact: &Artifact) -> AndroidManifest {
+        artifact_manifest(&self.manifest.android_manifest, artifact)
     }
 
-    /// Returns `minSdkVersion` for use in compiler target selection:
-    /// <https://developer.android.com/ndk/guides/sdk-versions#minsdkversion>
-    ///
-    /// Has a lower bound of `23` to retain backwards compatibility with
-    /// the previous default.
-    fn min_sdk_version(&self) -> u32 {
+    /// The [`ApkBuilder::artifact_manifest()`] with the `embed_build_info` meta-data of the
+    /// crate at `crate_path`, as packaged by [`ApkBuilder::build()`] before the launcher
+    /// resources are generated.
+    fn packaged_manifest(&self, artifact: &Artifact, crate_path: &Path) -> AndroidManifest {
+        let mut manifest = self.artifact_manifest(artifact);
+        if self.manifest.embed_build_info {
+            manifest.application.meta_data.extend(build_info(
+                self.cmd.profile(),
+                crate_path,
+                &self.env,
+            ));
+        }
+        manifest
+    }
+
+    /// The [`ApkConfig`] that packages `artifact` with `manifest`, with the `assets`,
+    /// `resources` and launcher resources of the crate at `crate_path`. Its `strip` is left
+    /// at [`StripConfig::Default`], which [`ApkBuilder::build()`] resolves per target.
+    pub(crate) fn apk_config(
+        &self,
+        artifact: &Artifact,
+        mut manifest: AndroidManifest,
+        crate_path: &Path,
+    ) -> Result<ApkConfig, Error> {
+        let build_dir = self.build_dir.join(artifact.build_dir());
+        let assets = self.metadata_dir("assets", &self.manifest.assets, crate_path)?;
+        let assets = self.resolve_case_collisions("assets", assets, &build_dir)?;
+        let resources = self.metadata_dir("resources", &self.manifest.resources, crate_path)?;
+        let resources = self.resolve_case_collisions("resources", resources, &build_dir)?;
+        let extra_resources = self.launcher_resources(crate_path, &build_dir, &mut manifest)?;
+        Ok(ApkConfig {
+            ndk: self.ndk.clone(),
+            build_dir,
+            apk_name: self.apk_name(artifact),
+            assets,
+            resources,
+            extra_resources,
+            manifest,
+            disable_aapt_compression: *self.cmd.profile() == Profile::Dev,
+            strip: StripConfig::Default,
+            debug_sidecars: self.strip_debug,
+            reverse_port_forward: self.manifest.reverse_port_forward.clone(),
+            shrink_resources: self.manifest.shrink_resources,
+            shrink_keep: self.manifest.shrink_keep.clone(),
+            compile_sdk_version: Some(self.compile_sdk_version),
+            deny_warnings: self.deny_warnings,
+            strict_libraries: self.strict,
+        })
+    }
+
+    pub fn build(&self, artifact: &Artifact) -> Result<Apk, Error> {
+        self.build_apk(artifact, None)
+    }
+
+    /// Builds an APK per build target instead of one containing all of them, for uploading
+    /// them to Google Play separately. Each gets the `versionCode` that
+    /// `version_code_abi_scheme` derives for its ABI and is written next to the one of
+    /// [`ApkBuilder::build()`], with the ABI appended to its name.
+    pub fn build_split_per_abi(&self, artifact: &Artifact) -> Result<Vec<Apk>, Error> {
+        if self.manifest.version_code_abi_scheme.is_none() {
+            return Err(Error::MissingVersionCodeAbiScheme);
+        }
+        self.build_targets
+            .iter()
+            .map(|target| self.build_apk(artifact, Some(*target)))
+            .collect()
+    }
+
+    /// The APK of [`ApkBuilder::build()`], or the one of [`ApkBuilder::build_split_per_abi()`]
+    /// for the ABI of `split`.
+    fn build_apk(&self, artifact: &Artifact, split: Option<Target>) -> Result<Apk, Error> {
+        self.ensure_rust_targets_installed(&self.build_targets)?;
+        let features = match artifact.r#type {
+            ArtifactType::Example => {
+                example_features(&self.manifest, &artifact.name, self.cmd.args())?
+            }
+            _ => Vec::new(),
+        };
+
+        let crate_path = self.cmd.manifest().parent().expect("invalid manifest path");
+        let mut manifest = self.packaged_manifest(artifact, crate_path);
+        if let Some(target) = split {
+            apply_abi_version_code(&mut manifest, &self.manifest, target)?;
+        }
+        let targets = split
+            .as_ref()
+            .map_or(&*self.build_targets, std::slice::from_ref);
+
+        let is_debug_profile = *self.cmd.profile() == Profile::Dev;
+        if !is_debug_profile {
+            check_64_bit(&self.build_targets, self.strict)?;
+        }
+
+        // Resolve and validate the signing key before the (possibly lengthy) build
+        let signing_key = read_keystore_meta(
+            &self.ndk,
+            &self.manifest,
+            self.cmd.profile(),
+            crate_path,
+            is_debug_profile,
+            &self.env,
+        )?;
+        check_release_signing(
+            self.cmd.profile(),
+            is_debug_key(&self.ndk, &signing_key, &self.env)?,
+            manifest.application.debuggable == Some(true),
+            self.allow_debug_signing || self.manifest.allow_debuggable_release,
+        )?;
+        if !is_debug_profile && manifest.application.test_only == Some(true) {
+            eprintln!(
+                "Warning: the `{}` build is `test_only`, which Google Play rejects and which can only be installed with `adb install -t`",
+                profile_name(self.cmd.profile())
+            );
+        }
+        let rotation = read_key_rotation(
+            &self.ndk,
+            &self.manifest,
+            self.cmd.profile(),
+            crate_path,
+            &self.env,
+        )?;
+
+        let runtime_libs =
+            self.metadata_dir("runtime_libs", &self.manifest.runtime_libs, crate_path)?;
+        let mut config = self.apk_config(artifact, manifest, crate_path)?;
+        if let Some(target) = split {
+            config.apk_name = format!("{}-{}", config.apk_name, target.android_abi());
+        }
+        let fingerprint = if self.assets_only_rebuild {
+            Some(self.input_fingerprint(crate_path, &features)?)
+        } else {
+            // Built from unknown inputs, which must never be reused
+            let _ = std::fs::remove_file(fingerprint_path(&config));
+            None
+        };
+        let reuse_libs = fingerprint
+            .as_deref()
+            .is_some_and(|fingerprint| self.only_assets_changed(&config, fingerprint));
+        let mut apk = config.create_apk()?;
+        let selection = [ArtifactSelector::from_artifact(artifact)];
+        if reuse_libs {
+            println!("Only `assets` changed, reusing the libraries of the previous build");
+        } else {
+            self.print_parallelism();
+        }
+
+        let mut warnings = Warnings::default();
+        for target in targets {
+            let triple = target.rust_triple();
+            let build_dir = self.target_build_dir(triple);
+            let artifact = self.artifact_path(artifact, triple, CrateType::Cdylib);
+            if reuse_libs {
+                apk.add_built_libs(*target)?;
+                continue;
+            }
+            apk.set_strip(match self.manifest.strip.for_target(*target) {
+                _ if self.no_strip => StripConfig::Default,
+                StripConfig::Default if self.strip_debug => StripConfig::Strip,
+                strip => strip,
+            });
+
+            let api_level = self.native_api_level(*target)?;
+            let mut cargo = self.cargo_for_api_level(*target, api_level, "build", &selection)?;
+            if !features.is_empty() {
+                cargo.arg("--features").arg(features.join(","));
+            }
+            self.write_target_env(*target, api_level, &cargo)?;
+            self.run_cargo(cargo, *target, &mut warnings)?;
+
+            let mut libs_search_paths = get_libs_search_paths(
+                self.cmd.target_dir(),
+                triple,
+                profile_dir(self.cmd.profile()),
+            )?;
+            libs_search_paths.push(build_dir.join("deps"));
+
+            let libs_search_paths = libs_search_paths
+                .iter()
+                .map(PathBuf::as_path)
+                .collect::<Vec<_>>();
+
+            apk.add_lib_recursively(&artifact, *target, libs_search_paths.as_slice())?;
+
+            if let Some(runtime_libs) = &runtime_libs {
+                apk.add_runtime_libs(runtime_libs, *target, libs_search_paths.as_slice())?;
+            }
+        }
+        warnings.print();
+
+        let unsigned = apk.add_pending_libs_and_align()?;
+
+        println!(
+            "Signing `{}` with keystore `{}`",
+            config.apk().display(),
+            signing_key.path.display()
+        );
+        let apk = match &rotation {
+            Some(rotation) => {
+                println!(
+                    "Rotating from the key in `{}` using lineage `{}`",
+                    rotation.previous.path.display(),
+                    rotation.lineage.display()
+                );
+                unsigned.sign_with_rotation(signing_key, rotation)?
+            }
+            None => unsigned.sign(signing_key)?,
+        };
+        if let Some(fingerprint) = fingerprint {
+            std::fs::write(fingerprint_path(&config), fingerprint)?;
+        }
+        Ok(apk)
+    }
+
+    /// Checks the `dir` of the metadata `key` for paths that only differ in case. Fails with
+    /// [`Error::CaseCollisions`], or with [`ApkBuilder::allow_case_collisions()`] returns a
+    /// copy in `build_dir` that only keeps the first path of each collision.
+    fn resolve_case_collisions(
+        &self,
+        key: &'static str,
+        dir: Option<PathBuf>,
+        build_dir: &Path,
+    ) -> Result<Option<PathBuf>, Error> {
+        let Some(dir) = dir else {
+            return Ok(None);
+        };
+        let collisions = case_collisions(&dir)?;
+        if collisions.is_empty() {
+            return Ok(Some(dir));
+        }
+        if !self.allow_case_collisions {
+            return Err(Error::CaseCollisions { key, collisions });
+        }
+        let mut dropped = Vec::new();
+        for paths in collisions {
+            let (kept, others) = paths.split_first().expect("collisions have several paths");
+            for other in others {
+                eprintln!(
+                    "Warning: `{key}` contains `{}` and `{}`, which only differ in case, packaging only the former",
+                    kept.display(),
+                    other.display()
+                );
+            }
+            dropped.extend_from_slice(others);
+        }
+        let staged = build_dir.join(format!("case-collisions-{key}"));
+        stage_without(&dir, &staged, &dropped)?;
+        Ok(Some(staged))
+    }
+
+    /// The directory configured by the metadata `key`, relative to `crate_path`. Fails with
+    /// [`Error::MissingMetadataDir`] when it doesn't exist, or warns and leaves it out with
+    /// [`ApkBuilder::allow_missing_dirs()`].
+    fn metadata_dir(
+        &self,
+        key: &'static str,
+        dir: &Option<PathBuf>,
+        crate_path: &Path,
+    ) -> Result<Option<PathBuf>, Error> {
+        let Some(dir) = dir else {
+            return Ok(None);
+        };
+        let path = dunce::simplified(&crate_path.join(dir)).to_owned();
+        if path.is_dir() {
+            return Ok(Some(path));
+        }
+        if !self.allow_missing_dirs {
+            return Err(Error::MissingMetadataDir { key, path });
+        }
+        eprintln!(
+            "Warning: `{key}` directory `{}` does not exist, building without it",
+            path.display()
+        );
+        Ok(None)
+    }
+
+    /// The targets that [`ApkBuilder::build()`] builds libraries for, resolved from `--target`,
+    /// `build_targets` or the ABI of the connected device.
+    pub fn build_targets(&self) -> &[Target] {
+        &self.build_targets
+    }
+
+    /// The `targetSdkVersion` of the APKs, `target_sdk_version` or else the newest platform of
+    /// the SDK.
+    pub fn target_sdk_version(&self) -> u32 {
         self.manifest
             .android_manifest
             .sdk
-            .min_sdk_version
-            .unwrap_or(23)
-            .max(23)
+            .target_sdk_version
+            .expect("set by `from_subcommand()`")
+    }
+
+    /// The package name of the APK of `artifact`: `package` or else derived from the name of
+    /// `artifact`, e.g. `rust.example.hello_world`.
+    pub fn package_id(&self, artifact: &Artifact) -> String {
+        artifact_manifest(&self.manifest.android_manifest, artifact).package
+    }
+
+    /// The directory that the APKs are built in, `target/<profile>/apk`.
+    pub fn build_dir(&self) -> &Path {
+        &self.build_dir
+    }
+
+    /// Whether [`ApkBuilder::build()`] signs with the default debug keystore, because no other
+    /// keystore is configured for the profile.
+    pub fn is_debug_signed(&self) -> Result<bool, Error> {
+        Ok(self.debug_key()?.is_some())
+    }
+
+    /// The debug keystore that [`ApkBuilder::build()`] signs with, if any.
+    fn debug_key(&self) -> Result<Option<KeystoreMeta>, Error> {
+        let crate_path = self.cmd.manifest().parent().expect("invalid manifest path");
+        let key = read_keystore_meta(
+            &self.ndk,
+            &self.manifest,
+            self.cmd.profile(),
+            crate_path,
+            *self.cmd.profile() == Profile::Dev,
+            &self.env,
+        )?;
+        Ok(is_debug_key(&self.ndk, &key, &self.env)?.then_some(key))
+    }
+
+    /// [`ensure_device()`] for the device of this builder.
+    fn ensure_device(&self) -> Result<(), Error> {
+        ensure_device(&self.ndk, self.device_serial.as_deref())
+    }
+
+    /// The ABI of the device, which the build targets default to when neither `--target` nor
+    /// `build_targets` are given. Fails with [`Error::NoDevice`] when no device is connected.
+    pub fn device_abi(&self) -> Result<Target, Error> {
+        detect_device_abi(&self.ndk, self.device_serial.as_deref())
+    }
+
+    /// Builds the APK, sets up reverse port forwarding and installs it on the device without
+    /// launching it.
+    pub fn install(&self, artifact: &Artifact, options: &InstallOptions) -> Result<Apk, Error> {
+        self.ensure_device()?;
+        let apk = self.build(artifact)?;
+        apk.reverse_port_forwarding(self.device_serial.as_deref())?;
+        self.install_tracking_debug_key(&apk, options)?;
+        Ok(apk)
+    }
+
+    /// Installs `apk` and, when it is signed with the debug keystore, remembers the
+    /// fingerprint of that keystore for the device. A failing install is explained with
+    /// [`Error::DebugKeystoreChanged`] when the fingerprint differs from the previous install,
+    /// as the device then refuses the update.
+    fn install_tracking_debug_key(&self, apk: &Apk, options: &InstallOptions) -> Result<(), Error> {
+        let result = apk.install_with_options(self.device_serial.as_deref(), options);
+        let Some(key) = self.debug_key()? else {
+            return Ok(result?);
+        };
+        let serial = match &self.device_serial {
+            Some(serial) => serial.clone(),
+            None => match self.ndk.devices()?.as_slice() {
+                [serial] => serial.clone(),
+                // Not knowing which device `adb` picked, there is nothing to compare
+                _ => return Ok(result?),
+            },
+        };
+        let fingerprint = keystore_fingerprint(&key.path)?;
+        let mut installed = InstalledDebugKeys::load(self.build_dir.join("debug-keys.json"));
+        if result.is_err() && installed.changed(&serial, apk.package_name(), &fingerprint) {
+            return Err(Error::DebugKeystoreChanged {
+                package: apk.package_name().to_owned(),
+                keystore: key.path,
+            });
+        }
+        result?;
+        installed.record(&serial, apk.package_name(), fingerprint)
+    }
+
+    /// `target/<triple>/<profile>`, which cargo builds `triple` into with the selected profile,
+    /// see [`profile_dir()`].
+    fn target_build_dir(&self, triple: &str) -> PathBuf {
+        dunce::simplified(self.cmd.target_dir())
+            .join(triple)
+            .join(profile_dir(self.cmd.profile()))
+    }
+
+    /// The path of `artifact` built for `triple`, in the [`ApkBuilder::target_build_dir()`]
+    /// rather than below the profile's name, which `cargo-subcommand` assumes also for the
+    /// `test` and `bench` profiles.
+    fn artifact_path(&self, artifact: &Artifact, triple: &str, crate_type: CrateType) -> PathBuf {
+        let path = self.cmd.artifact(artifact, Some(triple), crate_type);
+        match path.strip_prefix(self.cmd.build_dir(Some(triple))) {
+            Ok(relative) => self.target_build_dir(triple).join(relative),
+            Err(_) => path,
+        }
+    }
+
+    /// Starts `activity` on `run` instead of the `default_launch_activity` from the manifest.
+    #[must_use]
+    pub fn launch_activity(mut self, activity: Option<String>) -> Self {
+        if activity.is_some() {
+            self.manifest.default_launch_activity = activity;
+        }
+        self
+    }
+
+    /// Prints a [`LaunchInfo`] as a single line of JSON once `run` started the application,
+    /// before following its log.
+    #[must_use]
+    pub fn print_launch_info(mut self, enabled: bool) -> Self {
+        self.print_launch_info = enabled;
+        self
+    }
+
+    /// Whether the warnings of the `cargo` builds of several targets are printed once, with
+    /// the targets they occurred on, instead of once per target as cargo prints them (the
+    /// default). Errors are always printed per target. Has no effect when the cargo flags
+    /// select a `--message-format` of their own.
+    #[must_use]
+    pub fn dedup_warnings(mut self, enabled: bool) -> Self {
+        self.dedup_warnings = enabled;
+        self
+    }
+
+    /// Prints an inner `cargo` invocation that failed as a `bash` command line, with the
+    /// linker, `CC`, `AR` and other variables set for the NDK, to reproduce the failure
+    /// without `cargo-android`. Passwords are redacted.
+    #[must_use]
+    pub fn print_cargo_command(mut self, enabled: bool) -> Self {
+        self.print_cargo_command = enabled;
+        self
+    }
+
+    /// Stops following `logcat` after `duration` and returns from `run`, e.g. for smoke tests
+    /// on CI. Fails when a [`logcat_until()`](Self::logcat_until) pattern didn't match by then.
+    #[must_use]
+    pub fn logcat_duration(mut self, duration: Option<Duration>) -> Self {
+        self.logcat_duration = duration;
+        self
+    }
+
+    /// Stops following `logcat` at the first line that matches `pattern` and returns from
+    /// `run`. Fails when `logcat` ends, or the [`logcat_duration()`](Self::logcat_duration)
+    /// passes, before such a line.
+    #[must_use]
+    pub fn logcat_until(mut self, pattern: Option<Regex>) -> Self {
+        self.logcat_until = pattern;
+        self
+    }
+
+    /// Clears the log of the device (`adb logcat -c`) before starting the application, so
+    /// that `run` only prints the log of this launch.
+    #[must_use]
+    pub fn clear_logcat(mut self, enabled: bool) -> Self {
+        self.clear_logcat = enabled;
+        self
+    }
+
+    /// Appends `commands` to the `before_run` commands from the manifest.
+    #[must_use]
+    pub fn before_run(mut self, commands: Vec<String>) -> Self {
+        self.manifest.before_run.extend(commands);
+        self
+    }
+
+    /// Builds, installs and starts the APK of `artifact`, following its log unless `no_logcat`
+    /// is set. Returns what was installed once the application exited.
+    pub fn run(&self, artifact: &Artifact, no_logcat: bool) -> Result<BuiltApk, Error> {
+        self.run_with_options(artifact, no_logcat, &InstallOptions::default())
+    }
+
+    /// [`ApkBuilder::run()`], installing the APK with `options`.
+    pub fn run_with_options(
+        &self,
+        artifact: &Artifact,
+        no_logcat: bool,
+        options: &InstallOptions,
+    ) -> Result<BuiltApk, Error> {
+        let apk = self.install(artifact, options)?;
+        self.launch(&apk, no_logcat)?;
+        Ok(BuiltApk::from(&apk))
+    }
+
+    /// Runs the APK at `path`, created for `artifact` by other means than
+    /// [`ApkBuilder::build()`] such as the universal APK of a bundle, exactly like
+    /// [`ApkBuilder::run_with_options()`] runs the built one.
+    pub fn run_prebuilt(
+        &self,
+        artifact: &Artifact,
+        path: &Path,
+        no_logcat: bool,
+        options: &InstallOptions,
+    ) -> Result<BuiltApk, Error> {
+        self.ensure_device()?;
+        let apk = Apk::from_path(
+            self.ndk.clone(),
+            path.to_owned(),
+            &artifact_manifest(&self.manifest.android_manifest, artifact),
+            self.manifest.reverse_port_forward.clone(),
+        );
+        apk.reverse_port_forwarding(self.device_serial.as_deref())?;
+        self.install_tracking_debug_key(&apk, options)?;
+        self.launch(&apk, no_logcat)?;
+        Ok(BuiltApk::from(&apk))
+    }
+
+    /// Runs the `pre_run_shell` commands, starts the installed `apk` and follows its log, then
+    /// runs the `post_run_shell` commands.
+    fn launch(&self, apk: &Apk, no_logcat: bool) -> Result<(), Error> {
+        let pre_run_shell = self
+            .manifest
+            .before_run
+            .iter()
+            .chain(&self.manifest.run.pre_run_shell);
+        self.run_shell_commands("pre_run_shell", pre_run_shell, apk, false)?;
+
+        let result = self.start_and_follow_logcat(apk, no_logcat);
+        // Tear down regardless of whether the application exited cleanly
+        let post_run = self.run_shell_commands(
+            "post_run_shell",
+            &self.manifest.run.post_run_shell,
+            apk,
+            true,
+        );
+        result.and(post_run)
+    }
+
+    /// Starts the installed `apk` in the order of [`launch_steps()`], following its log unless
+    /// `no_logcat` is set.
+    fn start_and_follow_logcat(&self, apk: &Apk, no_logcat: bool) -> Result<(), Error> {
+        let bounded = self.logcat_duration.is_some() || self.logcat_until.is_some();
+        let mut uid = None;
+        let mut logcat = None;
+        for step in launch_steps(no_logcat, self.clear_logcat, self.print_launch_info) {
+            match step {
+                LaunchStep::ClearLogcat => self.clear_device_log()?,
+                LaunchStep::LookUpUid => uid = self.uidof(apk),
+                LaunchStep::SpawnLogcat => {
+                    // Colors would get in the way of matching the lines
+                    logcat = Some(Logcat::spawn(self.logcat(uid, !bounded)?)?);
+                }
+                LaunchStep::Start => {
+                    if let Err(e) = self.start(apk) {
+                        if let Some(logcat) = logcat.take() {
+                            logcat.flush_and_stop()?;
+                        }
+                        return Err(e);
+                    }
+                }
+                LaunchStep::PrintLaunchInfo => {
+                    let info = self.launch_info(apk, uid)?;
+                    println!(
+                        "{}",
+                        serde_json::to_string(&info).map_err(std::io::Error::from)?
+                    );
+                }
+                LaunchStep::FollowLogcat => {
+                    let logcat = logcat
+                        .take()
+                        .expect("`logcat` is spawned before following it");
+                    self.follow_logcat(apk, logcat, bounded)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Echoes the spawned `logcat`, until a bound of [`ApkBuilder::logcat_duration()`] or
+    /// [`ApkBuilder::logcat_until()`] when `bounded`, or until the application exited when
+    /// `post_run_shell` commands are pending.
+    fn follow_logcat(&self, apk: &Apk, logcat: Logcat, bounded: bool) -> Result<(), Error> {
+        if bounded {
+            return self.follow_bounded(logcat);
+        }
+        if self.manifest.run.post_run_shell.is_empty() {
+            for line in &logcat.lines {
+                println!("{line}");
+            }
+            return logcat.stop();
+        }
+
+        // Stop following the log once the application is gone, or when the user interrupts
+        // it, so that the `post_run_shell` commands still run
+        FOLLOWING_LOGCAT.store(true, Ordering::SeqCst);
+        let result = self.follow_until_exit(apk, logcat);
+        FOLLOWING_LOGCAT.store(false, Ordering::SeqCst);
+        result
+    }
+
+    /// Starts the [`launch_activity()`] of the installed `apk` with the intent extras of the
+    /// device profile, and waits for it to reach the foreground.
+    fn start(&self, apk: &Apk) -> Result<(), Error> {
+        let extras = self
+            .launch_extras
+            .iter()
+            .flat_map(|(key, value)| value.am_start_args(key))
+            .map(|arg| shell::quote(&arg))
+            .collect::<Vec<_>>();
+        let launch_output = match launch_activity(&self.manifest) {
+            Some(activity) => {
+                apk.start_activity(self.device_serial.as_deref(), activity, &extras)?
+            }
+            None => {
+                if !extras.is_empty() {
+                    eprintln!("Warning: the launcher activity is started without the intent extras of the device profile, set `default_launch_activity` or pass `--activity` to pass them");
+                }
+                apk.launch(self.device_serial.as_deref())?
+            }
+        };
+        self.wait_for_foreground(apk, &launch_output)
+    }
+
+    /// Looks up the UID of the installed `apk`, retrying with backoff while the package
+    /// manager of a slow device doesn't know it yet. Returns [`None`] with a warning when it
+    /// never resolves, so that the run continues with an unfiltered log.
+    fn uidof(&self, apk: &Apk) -> Option<u32> {
+        let retries = self.manifest.run.uid_retries.unwrap_or(DEFAULT_UID_RETRIES);
+        let mut delay = UID_RETRY_DELAY;
+        let mut attempt = 0;
+        loop {
+            match apk.uidof(self.device_serial.as_deref()) {
+                Ok(uid) => return Some(uid),
+                Err(e) if attempt < retries => {
+                    log::debug!("Looking up the UID of `{}` failed: {e}", apk.package_name());
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Warning: could not look up the UID of `{}`, following the unfiltered log: {e}",
+                        apk.package_name()
+                    );
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// The [`LaunchInfo`] of the started `apk`, waiting up to [`PID_TIMEOUT`] for its process.
+    fn launch_info(&self, apk: &Apk, uid: Option<u32>) -> Result<LaunchInfo, Error> {
+        let started = Instant::now();
+        let pid = loop {
+            let pid = apk.pidof(self.device_serial.as_deref())?;
+            if pid.is_some() || started.elapsed() >= PID_TIMEOUT {
+                break pid;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        };
+        let device_serial = match &self.device_serial {
+            Some(serial) => Some(serial.clone()),
+            None => {
+                let devices = self.ndk.devices()?;
+                match <[String; 1]>::try_from(devices) {
+                    Ok([serial]) => Some(serial),
+                    Err(_) => None,
+                }
+            }
+        };
+        Ok(LaunchInfo {
+            package: apk.package_name().to_owned(),
+            uid,
+            pid,
+            device_serial,
+            apk_path: apk.path().to_owned(),
+        })
+    }
+
+    /// `adb logcat` of the processes of `uid`, which stays the same across reinstalls, or of
+    /// the whole device from now on without one.
+    fn logcat(&self, uid: Option<u32>, color: bool) -> Result<Command, Error> {
+        let since = match uid {
+            Some(_) => None,
+            None => self.device_time(),
+        };
+        let mut logcat = self.ndk.adb(self.device_serial.as_deref())?;
+        logcat.args(logcat_args(uid, since.as_deref(), color));
+        Ok(logcat)
+    }
+
+    /// The current time of the device in seconds since the Unix epoch, as `logcat -T` accepts
+    /// it, or [`None`] when it can't be read.
+    fn device_time(&self) -> Option<String> {
+        let mut adb = self.ndk.adb(self.device_serial.as_deref()).ok()?;
+        let output = adb.arg("shell").arg("date").arg("+%s").output().ok()?;
+        let secs = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse::<u64>()
+            .ok()?;
+        Some(format!("{secs}.000"))
+    }
+
+    /// Clears the log of the device with `adb logcat -c`.
+    fn clear_device_log(&self) -> Result<(), Error> {
+        let mut adb = self.ndk.adb(self.device_serial.as_deref())?;
+        adb.arg("logcat").arg("-c");
+        if !adb.status()?.success() {
+            return Err(NdkError::CmdFailed(adb).into());
+        }
+        Ok(())
+    }
+
+    /// Builds, installs and starts the APK of `artifact` like [`ApkBuilder::run()`], then
+    /// rebuilds it whenever a file in the workspace (or the crate outside of one) changes,
+    /// reinstalling and, with [`WatchOptions::restart`], restarting the application when the
+    /// APK changed. Runs until interrupted.
+    ///
+    /// The log of the application is followed throughout. Failing builds, installs and starts
+    /// are printed and the next change is waited for.
+    pub fn watch(&self, artifact: &Artifact, options: &WatchOptions) -> Result<(), Error> {
+        let crate_path = self.cmd.manifest().parent().expect("invalid manifest path");
+        let root = self
+            .cmd
+            .workspace_manifest()
+            .and_then(Path::parent)
+            .unwrap_or(crate_path);
+        let root = dunce::canonicalize(root)?;
+        // Created right away, so that the writes of the first build are skipped as well
+        std::fs::create_dir_all(self.cmd.target_dir())?;
+        let skip = vec![dunce::canonicalize(self.cmd.target_dir())?];
+
+        // Before the first build, so that no change during it is missed
+        let watcher = ChangeWatcher::new(&root, skip)?;
+        let apk = self.install(artifact, &InstallOptions::default())?;
+        let mut digest = file_digest(apk.path())?;
+        if self.clear_logcat {
+            self.clear_device_log()?;
+        }
+        // Before starting the application, to capture the log of its startup
+        let mut logcat = self.logcat(self.uidof(&apk), true)?;
+        let mut following = logcat.spawn()?;
+        self.start(&apk)?;
+        println!("Watching `{}` for changes", root.display());
+
+        loop {
+            let changed = watcher.next_change(LOGCAT_CHECK_INTERVAL, options.debounce)?;
+            // E.g. the device reconnected
+            match following.try_wait() {
+                Ok(None) => {}
+                Ok(Some(_)) => match logcat.spawn() {
+                    Ok(child) => following = child,
+                    Err(err) => eprintln!("Error: {err}"),
+                },
+                Err(err) => eprintln!("Error: {err}"),
+            }
+            let Some(changed) = changed else {
+                continue;
+            };
+            println!("`{}` changed, rebuilding", changed.display());
+            match self.rebuild_changed(artifact, options, &digest) {
+                Ok(Some(new_digest)) => digest = new_digest,
+                Ok(None) => {}
+                Err(err) => eprintln!("Error: {err}"),
+            }
+        }
+    }
+
+    /// Rebuilds the APK of `artifact` for [`ApkBuilder::watch()`], and installs and restarts it
+    /// when it differs from the APK with `digest`. Returns the digest of the installed APK.
+    fn rebuild_changed(
+        &self,
+        artifact: &Artifact,
+        options: &WatchOptions,
+        digest: &str,
+    ) -> Result<Option<String>, Error> {
+        let apk = self.build(artifact)?;
+        let new_digest = file_digest(apk.path())?;
+        if new_digest == digest {
+            println!("The APK did not change");
+            return Ok(None);
+        }
+        self.install_tracking_debug_key(&apk, &InstallOptions::default())?;
+        if options.restart {
+            apk.force_stop(self.device_serial.as_deref())?;
+            self.start(&apk)?;
+        }
+        Ok(Some(new_digest))
+    }
+
+    /// Polls until an activity of `apk` is in the foreground, failing with the `launch_output`
+    /// of `am start` when it isn't within the `start_timeout`, e.g. because it crashed right
+    /// away. Doesn't wait unless a `start_timeout` is configured.
+    fn wait_for_foreground(&self, apk: &Apk, launch_output: &str) -> Result<(), Error> {
+        let timeout = match self.manifest.run.start_timeout {
+            None | Some(0) => return Ok(()),
+            Some(timeout) => timeout,
+        };
+        let started = Instant::now();
+        while !apk.is_foreground(self.device_serial.as_deref())? {
+            if started.elapsed() >= Duration::from_secs(timeout) {
+                return Err(Error::NotInForeground {
+                    package: apk.package_name().to_owned(),
+                    timeout,
+                    output: launch_output.trim_end().to_owned(),
+                });
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        Ok(())
+    }
+
+    /// Echoes `logcat` until it exits, ending it once the application process is gone.
+    fn follow_until_exit(&self, apk: &Apk, logcat: Logcat) -> Result<(), Error> {
+        let mut seen_running = false;
+        let mut checked = Instant::now();
+        loop {
+            match logcat.lines.recv_timeout(EXIT_POLL_INTERVAL) {
+                Ok(line) => println!("{line}"),
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if checked.elapsed() < EXIT_POLL_INTERVAL {
+                continue;
+            }
+            checked = Instant::now();
+            let running = apk.pidof(self.device_serial.as_deref())?.is_some();
+            if seen_running && !running {
+                println!("`{}` exited", apk.package_name());
+                break;
+            }
+            seen_running |= running;
+        }
+        logcat.stop()
+    }
+
+    /// Echoes the lines of `logcat` until the [`logcat_duration()`](Self::logcat_duration)
+    /// passed or a line matched the [`logcat_until()`](Self::logcat_until) pattern, see
+    /// [`follow_lines()`].
+    fn follow_bounded(&self, logcat: Logcat) -> Result<(), Error> {
+        let result = follow_lines(
+            &logcat.lines,
+            self.logcat_duration,
+            self.logcat_until.as_ref(),
+            |line| println!("{line}"),
+        );
+        logcat.stop()?;
+        result
+    }
+
+    /// Runs `adb shell` `commands` one by one, echoing their output prefixed with `label`.
+    ///
+    /// Stops at the first failing command, unless `keep_going` is set in which case all
+    /// commands are attempted and the first failure is returned.
+    fn run_shell_commands<'s>(
+        &self,
+        label: &str,
+        commands: impl IntoIterator<Item = &'s String>,
+        apk: &Apk,
+        keep_going: bool,
+    ) -> Result<(), Error> {
+        let mut result = Ok(());
+        for command in commands {
+            let command = command.replace("{package}", apk.package_name());
+            println!("[{label}] Running `adb shell {command}`");
+            let mut adb = self.ndk.adb(self.device_serial.as_deref())?;
+            adb.arg("shell").arg(command);
+            let output = adb.output()?;
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                println!("[{label}] {line}");
+            }
+            for line in String::from_utf8_lossy(&output.stderr).lines() {
+                eprintln!("[{label}] {line}");
+            }
+            if !output.status.success() {
+                if !keep_going {
+                    return Err(NdkError::CmdFailed(adb).into());
+                }
+                if result.is_ok() {
+                    result = Err(NdkError::CmdFailed(adb).into());
+                }
+            }
+        }
+        result
+    }
+
+    /// Builds a `[[bin]]` artifact as a plain executable for the ABI of the device, pushes it
+    /// to `/data/local/tmp` on the device and runs it through `adb shell` with `args`, skipping
+    /// APK packaging entirely. Fails with [`Error::DeviceAbiNotBuilt`] when that ABI is not
+    /// one of the build targets.
+    ///
+    /// Output is streamed back as-is. Returns the exit code of the binary on the device.
+    pub fn run_bin(&self, artifact: &Artifact, args: &[String]) -> Result<i32, Error> {
+        let target = self.device_abi()?;
+        if !self.build_targets.contains(&target) {
+            return Err(Error::DeviceAbiNotBuilt {
+                abi: target.android_abi(),
+                triple: target.rust_triple(),
+                targets: self.build_targets.iter().map(|t| t.rust_triple()).collect(),
+            });
+        }
+        self.ensure_rust_targets_installed(&[target])?;
+
+        let selection = [ArtifactSelector::from_artifact(artifact)];
+        let mut cargo = self.cargo(target, "build", &selection)?;
+        if !cargo.status()?.success() {
+            return Err(self.cargo_failed(cargo));
+        }
+        let executable = self.artifact_path(artifact, target.rust_triple(), CrateType::Bin);
+
+        let remote_path = format!("/data/local/tmp/{}", artifact.name);
+
+        let mut adb = self.ndk.adb(self.device_serial.as_deref())?;
+        adb.arg("push")
+            .arg(self.ndk.adb_path_arg(&executable)?)
+            .arg(&remote_path);
+        if !adb.status()?.success() {
+            return Err(NdkError::CmdFailed(adb).into());
+        }
+
+        let mut adb = self.ndk.adb(self.device_serial.as_deref())?;
+        adb.arg("shell").arg("chmod").arg("755").arg(&remote_path);
+        if !adb.status()?.success() {
+            return Err(NdkError::CmdFailed(adb).into());
+        }
+
+        // `adb shell` concatenates its arguments into a single command line for the remote
+        // shell, so quote everything to pass arguments through verbatim.
+        let mut command_line = format!("cd /data/local/tmp && {}", shell::quote(&remote_path));
+        for arg in args {
+            command_line.push(' ');
+            command_line.push_str(&shell::quote(arg));
+        }
+
+        let status = self
+            .ndk
+            .adb(self.device_serial.as_deref())?
+            .arg("shell")
+            .arg(command_line)
+            .status()?;
+
+        Ok(status.code().unwrap_or(1))
     }
-}
\ No newline at end of file
+
+    pub fn gdb(&self, artifact: &Artifact) -> Result<(), Error> {
+        self.ensure_device()?;
+        let apk = self.build(artifact)?;
+        apk.install(self.device_serial.as_deref())?;
+
+        let target_dir = self.build_dir.join(artifact.build_dir());
+        self.ndk.ndk_gdb(
+            target_dir,
+            "android.app.NativeActivity",
+            self.device_serial.as_deref(),
+        )?;
+        Ok(())
+    }
+
+    pub fn default(&self, cargo_cmd: &str, cargo_args: &[String]) -> Result<(), Error> {
+        self.ensure_rust_targets_installed(&self.build_targets)?;
+        self.print_parallelism();
+        for target in &self.build_targets {
+            let mut cargo = self.cargo(*target, cargo_cmd, &[])?;
+            for additional_arg in cargo_args {
+                cargo.arg(additional_arg);
+            }
+
+            if !cargo.status()?.success() {
+                return Err(self.cargo_failed(cargo));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `minSdkVersion` for use in compiler target selection:
+    /// <https://developer.android.com/ndk/guides/sdk-versions#minsdkversion>
+    ///
+    /// Has a lower bound of `23` to retain backwards compatibility with
+    /// the previous default.
+    pub fn min_sdk_version(&self) -> u32 {
+        self.manifest
+            .android_manifest
+            .sdk
+            .min_sdk_version
+            .unwrap_or(23)
+            .max(23)
+    }
+
+    /// The API level that C/C++ dependencies and the final link of `target` are built for:
+    /// the [`Self::min_sdk_version()`], unless the NDK has no platform libraries that new.
+    ///
+    /// Clang would otherwise quietly link against the newest ones it has, while the build
+    /// appears to target the `min_sdk_version`. Builds against those instead with a warning,
+    /// or fails with `strict_min_sdk_version`.
+    fn native_api_level(&self, target: Target) -> Result<u32, Error> {
+        let min_sdk_version = self.min_sdk_version();
+        let platforms = match self.ndk.sysroot_platforms(target) {
+            Ok(platforms) => platforms,
+            Err(e) => {
+                log::debug!("Not checking `min_sdk_version` against the NDK sysroot: {e}");
+                return Ok(min_sdk_version);
+            }
+        };
+        let api_level = clamp_api_level(
+            min_sdk_version,
+            &platforms,
+            target,
+            self.manifest.strict_min_sdk_version,
+        )?;
+        if api_level != min_sdk_version {
+            eprintln!(
+                "Warning: the NDK has no `{}` platform libraries for `min_sdk_version` {min_sdk_version}, building against API level {api_level} instead (set `strict_min_sdk_version` to fail)",
+                target.android_abi()
+            );
+        }
+        Ok(api_level)
+    }
+}
+
+/// `min_sdk_version`, or the newest of the sysroot `platforms` when it is above all of them
+/// and not `strict`.
+fn clamp_api_level(
+    min_sdk_version: u32,
+    platforms: &[u32],
+    target: Target,
+    strict: bool,
+) -> Result<u32, Error> {
+    match platforms.iter().max() {
+        Some(&newest) if newest < min_sdk_version => {
+            if strict {
+                Err(Error::MinSdkAboveNdk {
+                    min_sdk_version,
+                    newest,
+                    abi: target.android_abi(),
+                })
+            } else {
+                Ok(newest)
+            }
+        }
+        _ => Ok(min_sdk_version),
+    }
+}
+
+/// Fails when `min_sdk_version` is below the oldest of the sysroot `platforms`, as the
+/// libraries couldn't be loaded on those devices.
+fn check_min_sdk_floor(
+    min_sdk_version: u32,
+    platforms: &[u32],
+    target: Target,
+) -> Result<(), Error> {
+    match platforms.iter().min() {
+        Some(&oldest) if min_sdk_version < oldest => Err(Error::MinSdkBelowNdk {
+            min_sdk_version,
+            oldest,
+            abi: target.android_abi(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Where the [`ApkBuilder::input_fingerprint()`] of the libraries in the APK of `config` is
+/// kept.
+fn fingerprint_path(config: &ApkConfig) -> PathBuf {
+    config
+        .build_dir
+        .join(format!("{}.inputs-fingerprint", config.apk_name))
+}
+
+/// Feeds the paths relative to `root` and the contents of the files in `dir` to `hasher`, in
+/// a stable order, not descending into hidden directories and the directories in `skip`.
+fn hash_tree(hasher: &mut Sha256, root: &Path, dir: &Path, skip: &[PathBuf]) -> Result<(), Error> {
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(|e| NdkError::IoPathError(dir.to_owned(), e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| NdkError::IoPathError(dir.to_owned(), e))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') || skip.contains(&path) {
+            continue;
+        }
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if entry.file_type()?.is_dir() {
+            hash_tree(hasher, root, &path, skip)?;
+        } else {
+            let contents =
+                std::fs::read(&path).map_err(|e| NdkError::IoPathError(path.clone(), e))?;
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update((contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+    }
+    Ok(())
+}
+
+/// A hash of the contents of the file at `path`, to tell whether a rebuild changed it.
+fn file_digest(path: &Path) -> Result<String, Error> {
+    let contents = std::fs::read(path).map_err(|e| NdkError::IoPathError(path.to_owned(), e))?;
+    Ok(format!("{:x}", Sha256::digest(&contents)))
+}
+
+/// Passes the `lines` of a log to `echo` until `duration` passed or a line matched `until`,
+/// failing with [`Error::LogcatUntil`] when the lines end or the duration passes before
+/// `until` matched.
+fn follow_lines(
+    lines: &mpsc::Receiver<String>,
+    duration: Option<Duration>,
+    until: Option<&Regex>,
+    mut echo: impl FnMut(&str),
+) -> Result<(), Error> {
+    let deadline = duration.map(|duration| Instant::now() + duration);
+    loop {
+        let line = match deadline {
+            Some(deadline) => {
+                lines.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => lines.recv().map_err(RecvTimeoutError::from),
+        };
+        match line {
+            Ok(line) => {
+                echo(&line);
+                if until.is_some_and(|until| until.is_match(&line)) {
+                    return Ok(());
+                }
+            }
+            Err(reason) => {
+                return match until {
+                    Some(until) => Err(Error::LogcatUntil {
+                        pattern: until.to_string(),
+                        reason: match reason {
+                            RecvTimeoutError::Timeout => "within `--logcat-duration`",
+                            RecvTimeoutError::Disconnected => "before `logcat` ended",
+                        },
+                    }),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// The environment variables explicitly set on `cmd`.
+fn command_env(cmd: &Command) -> BTreeMap<String, String> {
+    cmd.get_envs()
+        .filter_map(|(key, value)| Some((key.to_str()?.to_owned(), value?.to_str()?.to_owned())))
+        .collect()
+}
+
+/// The rustflags set by the `--config` entries, which are either `KEY=VALUE` pairs or paths to
+/// TOML files. Like cargo, `target.<triple>.rustflags` is used when any entry sets it and
+/// `build.rustflags` otherwise, with the flags of several entries concatenated.
+///
+/// Cargo ignores these once `CARGO_ENCODED_RUSTFLAGS` is set, which
+/// [`cargo_ndk_with_vars()`] always does, so they have to be appended to it instead.
+fn config_rustflags(configs: &[String], triple: &str) -> Vec<String> {
+    let mut build = None;
+    let mut target = None;
+    for config in configs {
+        let contents = match std::fs::read_to_string(config) {
+            Ok(contents) => contents,
+            Err(_) => config.clone(),
+        };
+        let Ok(table) = toml::from_str::<toml::Table>(&contents) else {
+            log::debug!("Ignoring the rustflags of unparsable `--config {config}`");
+            continue;
+        };
+        if let Some(flags) = table.get("build").and_then(|build| build.get("rustflags")) {
+            build
+                .get_or_insert_with(Vec::new)
+                .extend(rustflags_value(flags));
+        }
+        if let Some(flags) = table
+            .get("target")
+            .and_then(|target| target.get(triple))
+            .and_then(|target| target.get("rustflags"))
+        {
+            target
+                .get_or_insert_with(Vec::new)
+                .extend(rustflags_value(flags));
+        }
+    }
+    target.or(build).unwrap_or_default()
+}
+
+/// The flags of a `rustflags` value, which is a space-separated string or an array.
+fn rustflags_value(flags: &toml::Value) -> Vec<String> {
+    match flags {
+        toml::Value::String(flags) => flags.split_whitespace().map(str::to_owned).collect(),
+        toml::Value::Array(flags) => flags
+            .iter()
+            .filter_map(|f| f.as_str())
+            .map(str::to_owned)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Appends `subcommand`, the arguments added by `apply_args` and, if given, `--target <triple>`
+/// to `cargo`, in that order.
+fn append_cargo_args(
+    cargo: &mut Command,
+    subcommand: &str,
+    apply_args: impl FnOnce(&mut Command),
+    triple: Option<&str>,
+) {
+    cargo.arg(subcommand);
+    apply_args(cargo);
+    if let Some(triple) = triple {
+        cargo.arg("--target").arg(triple);
+    }
+}
+
+/// The targets to build: the `--target` given on the command line, otherwise all configured
+/// `build_targets`, and only when neither is set the ABI of the connected device (`detect`),
+/// falling back to `arm64-v8a`.
+///
+/// A configured `build_targets` is never narrowed down to the device, so that release
+/// artifacts contain the same ABIs regardless of what is connected.
+fn resolve_build_targets(
+    target: Option<&str>,
+    build_targets: &[Target],
+    detect: impl FnOnce() -> Option<Target>,
+) -> Result<Vec<Target>, Error> {
+    Ok(if let Some(target) = target {
+        vec![Target::from_rust_triple(target)?]
+    } else if !build_targets.is_empty() {
+        build_targets.to_vec()
+    } else {
+        vec![detect().unwrap_or(Target::Arm64V8a)]
+    })
+}
+
+/// The `android.jar` of `platform`, failing with the platforms installed in the SDK when it is
+/// missing.
+pub(crate) fn android_jar(ndk: &Ndk, platform: u32) -> Result<PathBuf, Error> {
+    match ndk.android_jar(platform) {
+        Err(NdkError::PlatformNotFound(_)) => {
+            let mut installed = std::fs::read_dir(ndk.sdk().join("platforms"))?
+                .filter_map(|entry| {
+                    let name = entry.ok()?.file_name().into_string().ok()?;
+                    name.strip_prefix("android-")?.parse().ok()
+                })
+                .collect::<Vec<u32>>();
+            installed.sort_unstable();
+            Err(Error::PlatformNotInstalled {
+                platform,
+                installed,
+            })
+        }
+        result => Ok(result?),
+    }
+}
+
+/// The activity that `run` starts: `--activity`, the `default_launch_activity`, the first
+/// enabled `activity_aliases` entry with a `MAIN` action, or the Rust activity unless it has
+/// `no_launcher`. `None` leaves the choice to the launcher of the device, for a manifest whose
+/// launchable activities are declared by hand.
+fn launch_activity(manifest: &Manifest) -> Option<&str> {
+    let application = &manifest.android_manifest.application;
+    let launcher_alias = application
+        .activity_aliases
+        .iter()
+        .find(|alias| alias.enabled != Some(false) && has_main_action(&alias.intent_filter));
+    manifest
+        .default_launch_activity
+        .as_deref()
+        .or(launcher_alias.map(|alias| &*alias.name))
+        .or_else(|| (!manifest.no_launcher).then_some(&*application.activity.name))
+}
+
+/// The permission to open network sockets, which connections to reverse port forwards need
+const INTERNET_PERMISSION: &str = "android.permission.INTERNET";
+
+/// Adds the [`INTERNET_PERMISSION`] when `requires_internet` is set. Otherwise, returns a
+/// warning when it's missing while `reverse_port_forward` entries are configured, as the app
+/// can't connect to them without it.
+fn check_internet_permission(manifest: &mut Manifest) -> Option<String> {
+    let permissions = &mut manifest.android_manifest.uses_permission;
+    if permissions.iter().any(|p| p.name == INTERNET_PERMISSION) {
+        return None;
+    }
+    if manifest.requires_internet {
+        permissions.push(Permission {
+            name: INTERNET_PERMISSION.to_owned(),
+            max_sdk_version: None,
+        });
+        return None;
+    }
+    (!manifest.reverse_port_forward.is_empty()).then(|| {
+        format!("`reverse_port_forward` is configured, but `{INTERNET_PERMISSION}` is missing from `uses_permission` so the app can't connect to it, add it or set `requires_internet = true`")
+    })
+}
+
+/// The 64-bit ABI that Google Play requires next to the 32-bit `target`, [`None`] for 64-bit
+/// targets.
+fn counterpart_64_bit(target: Target) -> Option<Target> {
+    match target {
+        Target::ArmV7a => Some(Target::Arm64V8a),
+        Target::X86 => Some(Target::X86_64),
+        Target::Arm64V8a | Target::X86_64 => None,
+    }
+}
+
+/// Warns, or fails with [`Error::Missing64Bit`] when `strict`, about 32-bit `targets` without
+/// their 64-bit counterpart, as Google Play rejects apps whose native code is 32-bit only.
+pub(crate) fn check_64_bit(targets: &[Target], strict: bool) -> Result<(), Error> {
+    let missing = targets
+        .iter()
+        .filter_map(|target| {
+            let counterpart = counterpart_64_bit(*target)?;
+            (!targets.contains(&counterpart)).then(|| {
+                format!(
+                    "`{}` (next to `{}`)",
+                    counterpart.android_abi(),
+                    target.android_abi()
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let error = Error::Missing64Bit(missing.join(", "));
+    if strict {
+        return Err(error);
+    }
+    eprintln!("Warning: {error}");
+    Ok(())
+}
+
+/// The first API level that honors the `<profileable>` element
+const PROFILEABLE_API_LEVEL: u32 = 29;
+
+/// Returns a warning when `profileable` is set while the `min_sdk_version` includes devices
+/// that ignore it, where profiling still requires a `debuggable` build.
+fn check_profileable(manifest: &AndroidManifest) -> Option<String> {
+    manifest.application.profileable.as_ref()?;
+    let min_sdk_version = manifest.sdk.min_sdk_version?;
+    (min_sdk_version < PROFILEABLE_API_LEVEL).then(|| {
+        format!("`profileable` is ignored below Android 10 (API {PROFILEABLE_API_LEVEL}), but `min_sdk_version` is {min_sdk_version}, so the app can only be profiled on those devices when it is `debuggable`")
+    })
+}
+
+/// Makes the Rust activity launchable, unless the user configured this by hand, put the
+/// launcher on an `activity_aliases` entry, or set `no_launcher`. Points aliases without a
+/// `target_activity` at the Rust activity.
+fn add_launcher_defaults(manifest: &mut Manifest, target_sdk_version: u32) {
+    let application = &mut manifest.android_manifest.application;
+    for alias in &mut application.activity_aliases {
+        alias
+            .target_activity
+            .get_or_insert_with(|| application.activity.name.clone());
+        // Like the Rust activity below, as a launcher alias won't start on S+ otherwise
+        if target_sdk_version >= 31 && has_main_action(&alias.intent_filter) {
+            alias.exported.get_or_insert(true);
+        }
+    }
+    let launcher_alias = application
+        .activity_aliases
+        .iter()
+        .any(|alias| has_main_action(&alias.intent_filter));
+    if manifest.no_launcher || launcher_alias {
+        return;
+    }
+    let activity = &mut application.activity;
+
+    // Add a default `MAIN` action to launch the activity, if the user didn't supply it by hand.
+    if !has_main_action(&activity.intent_filter) {
+        activity.intent_filter.push(IntentFilter {
+            actions: vec!["android.intent.action.MAIN".to_string()],
+            categories: vec!["android.intent.category.LAUNCHER".to_string()],
+            data: vec![],
+        });
+    }
+
+    // Export the sole Rust activity on Android S and up, if the user didn't explicitly do so.
+    // Without this, apps won't start on S+.
+    // https://developer.android.com/about/versions/12/behavior-changes-12#exported
+    if target_sdk_version >= 31 {
+        activity.exported.get_or_insert(true);
+    }
+}
+
+fn has_main_action(intent_filters: &[IntentFilter]) -> bool {
+    intent_filters
+        .iter()
+        .any(|i| i.actions.iter().any(|a| a == "android.intent.action.MAIN"))
+}
+
+/// The name of `profile` as used in `[profile.<name>]` and
+/// `[package.metadata.android.signing.<name>]`.
+pub(crate) fn profile_name(profile: &Profile) -> &str {
+    match profile {
+        Profile::Dev => "dev",
+        Profile::Release => "release",
+        Profile::Custom(c) => c.as_str(),
+    }
+}
+
+/// The directory below `target/` (and `target/<triple>/`) that cargo writes the artifacts of
+/// `profile` to. Unlike the `AsRef<Path>` of [`Profile`], this maps the built-in `test` and
+/// `bench` profiles to `debug` and `release` like cargo does, other custom profiles use their
+/// name.
+pub(crate) fn profile_dir(profile: &Profile) -> &Path {
+    Path::new(match profile_name(profile) {
+        "dev" | "test" => "debug",
+        "release" | "bench" => "release",
+        custom => custom,
+    })
+}
+
+/// The directory APKs built with `profile` are written to (below the artifact's
+/// [`Artifact::build_dir()`]), shared with `AabBuilder` which picks them up from there.
+pub(crate) fn apk_dir(target_dir: &Path, profile: &Profile) -> PathBuf {
+    dunce::simplified(target_dir)
+        .join(profile_dir(profile))
+        .join("apk")
+}
+
+/// The `cargo_android.*` application meta-data entries added by `embed_build_info`.
+///
+/// The git revision is left empty (with a warning) when `crate_path` is not in a git
+/// repository or `git` is not installed, and the build time is taken from
+/// `SOURCE_DATE_EPOCH` in `env` when set, for reproducible builds.
+fn build_info(profile: &Profile, crate_path: &Path, env: &Env) -> Vec<MetaData> {
+    let git_rev = match Command::new("git")
+        .arg("rev-parse")
+        .arg("--short")
+        .arg("HEAD")
+        .current_dir(crate_path)
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_owned()
+        }
+        _ => {
+            eprintln!("Warning: failed to determine the git revision for `embed_build_info`");
+            String::new()
+        }
+    };
+    let build_time = env.var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string()
+    });
+
+    [
+        ("cargo_android.profile", profile_name(profile).to_owned()),
+        ("cargo_android.git_rev", git_rev),
+        ("cargo_android.build_time", build_time),
+    ]
+    .into_iter()
+    .map(|(name, value)| MetaData {
+        name: name.to_owned(),
+        value,
+        resource: None,
+    })
+    .collect()
+}
+
+/// Clones `manifest` with the artifact specific default values of `artifact` filled in.
+///
+/// `self.manifest` is shared by all artifacts of an invocation, so it must not be modified
+/// per artifact. An `android.app.lib_name` that was declared explicitly is kept as-is, and
+/// `{applicationId}` in the authorities of providers is replaced with the resolved package.
+pub(crate) fn artifact_manifest(
+    manifest: &AndroidManifest,
+    artifact: &Artifact,
+) -> AndroidManifest {
+    let mut manifest = manifest.clone();
+
+    if manifest.package.is_empty() {
+        let name = artifact.name.replace('-', "_");
+        manifest.package = match artifact.r#type {
+            ArtifactType::Lib | ArtifactType::Bin => format!("rust.{name}"),
+            ArtifactType::Example => format!("rust.example.{name}"),
+        };
+    }
+
+    if manifest.application.label.is_empty() {
+        manifest.application.label = artifact.name.to_string();
+    }
+
+    for provider in &mut manifest.application.provider {
+        provider.authorities = provider
+            .authorities
+            .replace("{applicationId}", &manifest.package);
+    }
+
+    let meta_data = &mut manifest.application.activity.meta_data;
+    if !meta_data.iter().any(|m| m.name == "android.app.lib_name") {
+        meta_data.push(MetaData {
+            name: "android.app.lib_name".to_string(),
+            value: artifact.name.replace('-', "_"),
+            resource: None,
+        });
+    }
+
+    manifest
+}
+
+/// Replaces the `versionCode` of `manifest` with the one that `version_code_abi_scheme`
+/// derives for the split APK of `target`.
+fn apply_abi_version_code(
+    manifest: &mut AndroidManifest,
+    config: &Manifest,
+    target: Target,
+) -> Result<(), Error> {
+    let scheme = config
+        .version_code_abi_scheme
+        .as_ref()
+        .ok_or(Error::MissingVersionCodeAbiScheme)?;
+    if let Some(version_code) = &mut manifest.version_code {
+        *version_code = scheme.version_code(*version_code, target)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndk_build::manifest::{Profileable, Provider};
+
+    /// Parses a test crate `name` with `metadata` in its `[package.metadata.android]`.
+    fn parse_metadata(name: &str, metadata: &str) -> Manifest {
+        let path = crate::manifest::tests::write_manifest(
+            name,
+            &format!("[package]\nversion = \"1.0.0\"\n[package.metadata.android]\n{metadata}"),
+        );
+        Manifest::parse_from_toml(&path).unwrap()
+    }
+
+    #[test]
+    fn cargo_flags_are_forwarded() {
+        let mut cargo = Command::new("cargo");
+        CargoFlags {
+            locked: true,
+            frozen: true,
+            offline: true,
+            jobs: Some(4),
+            ..Default::default()
+        }
+        .apply(&mut cargo);
+        assert_eq!(
+            cargo.get_args().collect::<Vec<_>>(),
+            ["--locked", "--frozen", "--offline", "--jobs", "4"]
+        );
+
+        let mut cargo = Command::new("cargo");
+        CargoFlags::default().apply(&mut cargo);
+        assert_eq!(cargo.get_args().count(), 0);
+    }
+
+    #[test]
+    fn cargo_flags_precede_target() {
+        let flags = CargoFlags {
+            config: vec!["profile.release.lto=true".to_string()],
+            unstable_flags: vec!["build-std=std,panic_abort".to_string()],
+            cargo_flags: vec!["--timings".to_string()],
+            ..Default::default()
+        };
+        // `check`, `build` and arbitrary subcommands through `cargo android apk -- <cmd>`
+        for subcommand in ["check", "build", "doc"] {
+            let mut cargo = Command::new("cargo");
+            append_cargo_args(
+                &mut cargo,
+                subcommand,
+                |cargo| flags.apply(cargo),
+                Some("aarch64-linux-android"),
+            );
+            assert_eq!(
+                cargo.get_args().collect::<Vec<_>>(),
+                [
+                    subcommand,
+                    "--config",
+                    "profile.release.lto=true",
+                    "-Z",
+                    "build-std=std,panic_abort",
+                    "--timings",
+                    "--target",
+                    "aarch64-linux-android",
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn rustflags_from_config() {
+        let configs = [
+            "build.rustflags=[\"-Cforce-frame-pointers=yes\"]".to_owned(),
+            "target.aarch64-linux-android.rustflags=\"-Ctarget-cpu=cortex-a76 -Copt-level=2\""
+                .to_owned(),
+            "target.x86_64-linux-android.rustflags=[\"-Ctarget-feature=+avx2\"]".to_owned(),
+            "profile.release.lto=true".to_owned(),
+        ];
+        // The flags of the target replace those of `build`, as in cargo
+        assert_eq!(
+            config_rustflags(&configs, "aarch64-linux-android"),
+            ["-Ctarget-cpu=cortex-a76", "-Copt-level=2"]
+        );
+        assert_eq!(
+            config_rustflags(&configs, "x86_64-linux-android"),
+            ["-Ctarget-feature=+avx2"]
+        );
+        assert_eq!(
+            config_rustflags(&configs, "armv7-linux-androideabi"),
+            ["-Cforce-frame-pointers=yes"]
+        );
+        // Several entries for the same key are concatenated
+        let configs = [
+            "build.rustflags=[\"-Cforce-frame-pointers=yes\"]".to_owned(),
+            "build.rustflags=\"-Cdebuginfo=1\"".to_owned(),
+        ];
+        assert_eq!(
+            config_rustflags(&configs, "aarch64-linux-android"),
+            ["-Cforce-frame-pointers=yes", "-Cdebuginfo=1"]
+        );
+
+        let file = std::env::temp_dir().join("cargo-android-rustflags-config.toml");
+        std::fs::write(&file, "[build]\nrustflags = [\"-Cdebuginfo=1\"]\n").unwrap();
+        assert_eq!(
+            config_rustflags(&[file.display().to_string()], "i686-linux-android"),
+            ["-Cdebuginfo=1"]
+        );
+    }
+
+    fn lib_names(manifest: &AndroidManifest) -> Vec<&str> {
+        manifest
+            .application
+            .activity
+            .meta_data
+            .iter()
+            .filter(|m| m.name == "android.app.lib_name")
+            .map(|m| m.value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn lib_name_per_artifact() {
+        let mut shared = AndroidManifest::default();
+        shared.application.activity.meta_data.push(MetaData {
+            name: "com.example.key".to_string(),
+            value: "value".to_string(),
+            resource: None,
+        });
+        let artifacts = ["demo-lib", "other"].map(|name| Artifact {
+            name: name.to_string(),
+            path: PathBuf::from("src/lib.rs"),
+            r#type: ArtifactType::Lib,
+        });
+
+        // Building artifacts in sequence must not accumulate entries on the shared manifest
+        for (artifact, lib_name) in artifacts.iter().zip(["demo_lib", "other"]) {
+            let manifest = artifact_manifest(&shared, artifact);
+            assert_eq!(lib_names(&manifest), [lib_name]);
+            assert!(manifest
+                .application
+                .activity
+                .meta_data
+                .iter()
+                .any(|m| m.name == "com.example.key"));
+        }
+        assert!(lib_names(&shared).is_empty());
+
+        shared.application.activity.meta_data.push(MetaData {
+            name: "android.app.lib_name".to_string(),
+            value: "custom".to_string(),
+            resource: None,
+        });
+        assert_eq!(
+            lib_names(&artifact_manifest(&shared, &artifacts[0])),
+            ["custom"]
+        );
+    }
+
+    #[test]
+    fn provider_authority_from_package() {
+        let mut shared = AndroidManifest::default();
+        shared.application.provider.push(Provider {
+            name: "androidx.core.content.FileProvider".to_string(),
+            authorities: "{applicationId}.fileprovider;com.example.fixed".to_string(),
+            ..Default::default()
+        });
+        let artifact = Artifact {
+            name: "demo".to_string(),
+            path: PathBuf::from("examples/demo.rs"),
+            r#type: ArtifactType::Example,
+        };
+        let manifest = artifact_manifest(&shared, &artifact);
+        assert_eq!(
+            manifest.application.provider[0].authorities,
+            "rust.example.demo.fileprovider;com.example.fixed"
+        );
+
+        shared.package = "com.example.app".to_string();
+        let manifest = artifact_manifest(&shared, &artifact);
+        assert_eq!(
+            manifest.application.provider[0].authorities,
+            "com.example.app.fileprovider;com.example.fixed"
+        );
+    }
+
+    #[test]
+    fn fingerprint_outside_assets() {
+        let dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("fingerprint");
+        let _ = std::fs::remove_dir_all(&dir);
+        for path in [
+            "src/lib.rs",
+            "assets/level.json",
+            "target/debug/lib.so",
+            ".git/HEAD",
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let skip = [dir.join("assets"), dir.join("target")];
+        let fingerprint = || {
+            let mut hasher = Sha256::new();
+            hash_tree(&mut hasher, &dir, &dir, &skip).unwrap();
+            format!("{:x}", hasher.finalize())
+        };
+        let built = fingerprint();
+
+        for path in ["assets/level.json", "target/debug/lib.so", ".git/HEAD"] {
+            std::fs::write(dir.join(path), "changed").unwrap();
+        }
+        assert_eq!(fingerprint(), built);
+
+        // Content changes count even when the modification time is restored, e.g. by a
+        // checkout or an archive
+        let lib = dir.join("src/lib.rs");
+        let modified = std::fs::metadata(&lib).unwrap().modified().unwrap();
+        std::fs::write(&lib, "fn main() {}").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&lib)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_ne!(fingerprint(), built);
+
+        // Touching without changing contents doesn't
+        std::fs::write(&lib, "").unwrap();
+        assert_eq!(fingerprint(), built);
+        std::fs::rename(&lib, dir.join("src/main.rs")).unwrap();
+        assert_ne!(fingerprint(), built);
+    }
+
+    #[test]
+    fn api_level_above_ndk_platforms() {
+        let platforms = [21, 22, 33, 34];
+        assert_eq!(
+            clamp_api_level(30, &platforms, Target::Arm64V8a, true).unwrap(),
+            30
+        );
+        assert_eq!(
+            clamp_api_level(35, &platforms, Target::Arm64V8a, false).unwrap(),
+            34
+        );
+        assert!(matches!(
+            clamp_api_level(35, &platforms, Target::X86_64, true),
+            Err(Error::MinSdkAboveNdk {
+                min_sdk_version: 35,
+                newest: 34,
+                abi: "x86_64"
+            })
+        ));
+        // Nothing to compare against
+        assert_eq!(
+            clamp_api_level(35, &[], Target::Arm64V8a, true).unwrap(),
+            35
+        );
+    }
+
+    #[test]
+    fn min_sdk_below_ndk_platforms() {
+        let platforms = [21, 22, 33, 34];
+        assert!(check_min_sdk_floor(21, &platforms, Target::Arm64V8a).is_ok());
+        assert!(check_min_sdk_floor(35, &platforms, Target::Arm64V8a).is_ok());
+        assert!(matches!(
+            check_min_sdk_floor(19, &platforms, Target::ArmV7a),
+            Err(Error::MinSdkBelowNdk {
+                min_sdk_version: 19,
+                oldest: 21,
+                abi: "armeabi-v7a"
+            })
+        ));
+        assert!(check_min_sdk_floor(19, &[], Target::ArmV7a).is_ok());
+    }
+
+    #[test]
+    fn configured_build_targets_are_authoritative() {
+        let configured = [Target::ArmV7a, Target::Arm64V8a, Target::X86_64];
+        let targets = resolve_build_targets(None, &configured, || {
+            panic!("device detection must not run when `build_targets` is configured")
+        })
+        .unwrap();
+        assert_eq!(targets, configured);
+
+        assert_eq!(
+            resolve_build_targets(None, &[], || Some(Target::X86_64)).unwrap(),
+            [Target::X86_64]
+        );
+        assert_eq!(
+            resolve_build_targets(None, &[], || None).unwrap(),
+            [Target::Arm64V8a]
+        );
+        assert_eq!(
+            resolve_build_targets(Some("aarch64-linux-android"), &configured, || None).unwrap(),
+            [Target::Arm64V8a]
+        );
+    }
+
+    #[test]
+    fn target_env_file() {
+        let env = BTreeMap::from([
+            (
+                "CARGO_ENCODED_RUSTFLAGS".to_owned(),
+                "-Cdebuginfo=2\x1f-Clink-arg=--target=aarch64-linux-android23".to_owned(),
+            ),
+            (
+                "CARGO_TARGET_AARCH64_LINUX_ANDROID_LINKER".to_owned(),
+                "/ndk/bin/clang".to_owned(),
+            ),
+        ]);
+        let target_env = TargetEnv::new(Target::Arm64V8a, 23, env);
+        assert_eq!(target_env.triple, "aarch64-linux-android");
+        assert_eq!(
+            target_env.rustflags,
+            [
+                "-Cdebuginfo=2",
+                "-Clink-arg=--target=aarch64-linux-android23"
+            ]
+        );
+        assert_eq!(target_env.linker.as_deref(), Some("/ndk/bin/clang"));
+
+        let json = serde_json::to_string(&target_env).unwrap();
+        assert_eq!(
+            serde_json::from_str::<TargetEnv>(&json).unwrap(),
+            target_env
+        );
+    }
+
+    #[test]
+    fn no_launcher() {
+        let parse = |no_launcher: bool| {
+            let mut manifest =
+                parse_metadata("no_launcher", &format!("no_launcher = {no_launcher}\n"));
+            add_launcher_defaults(&mut manifest, 33);
+            manifest.android_manifest.application.activity
+        };
+
+        let activity = parse(false);
+        assert_eq!(
+            activity.intent_filter[0].actions,
+            ["android.intent.action.MAIN"]
+        );
+        assert_eq!(activity.exported, Some(true));
+
+        let activity = parse(true);
+        assert!(activity.intent_filter.is_empty());
+        assert_eq!(activity.exported, None);
+    }
+
+    #[test]
+    fn custom_profile_dirs() {
+        let custom = Profile::Custom("staging".to_owned());
+        assert_eq!(profile_dir(&Profile::Dev), Path::new("debug"));
+        assert_eq!(profile_dir(&custom), Path::new("staging"));
+        // Built-in profiles that share the directory of the one they inherit from
+        assert_eq!(
+            profile_dir(&Profile::Custom("test".to_owned())),
+            Path::new("debug")
+        );
+        assert_eq!(
+            profile_dir(&Profile::Custom("bench".to_owned())),
+            Path::new("release")
+        );
+
+        // Libraries linked by build scripts are found below the custom profile's directory
+        let target_dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("custom_profile");
+        let out_dir = target_dir
+            .join("aarch64-linux-android")
+            .join("staging")
+            .join("build")
+            .join("native-sys-0123");
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::fs::write(
+            out_dir.join("output"),
+            "cargo:rustc-link-search=native=/opt/native/lib\n",
+        )
+        .unwrap();
+        let paths =
+            get_libs_search_paths(&target_dir, "aarch64-linux-android", profile_dir(&custom))
+                .unwrap();
+        assert_eq!(paths, [PathBuf::from("/opt/native/lib")]);
+        assert_eq!(
+            apk_dir(&target_dir, &custom),
+            target_dir.join("staging").join("apk")
+        );
+    }
+
+    #[test]
+    fn missing_64_bit_targets() {
+        assert!(check_64_bit(&[Target::Arm64V8a], true).is_ok());
+        assert!(check_64_bit(&[Target::ArmV7a, Target::Arm64V8a, Target::X86_64], true).is_ok());
+        // Only warns
+        assert!(check_64_bit(&[Target::ArmV7a], false).is_ok());
+
+        let error = check_64_bit(&[Target::ArmV7a, Target::X86, Target::X86_64], true)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("add `arm64-v8a` (next to `armeabi-v7a`) to `build_targets`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn profileable_min_sdk() {
+        let mut manifest = AndroidManifest::default();
+        manifest.sdk.min_sdk_version = Some(26);
+        assert_eq!(check_profileable(&manifest), None);
+
+        manifest.application.profileable = Some(Profileable {
+            shell: Some(true),
+            enabled: None,
+        });
+        let warning = check_profileable(&manifest).unwrap();
+        assert!(warning.contains("`min_sdk_version` is 26"), "{warning}");
+
+        manifest.sdk.min_sdk_version = Some(29);
+        assert_eq!(check_profileable(&manifest), None);
+    }
+
+    #[test]
+    fn internet_permission() {
+        let parse = |metadata: &str| parse_metadata("internet_permission", metadata);
+        let internet = |manifest: &Manifest| {
+            manifest
+                .android_manifest
+                .uses_permission
+                .iter()
+                .filter(|p| p.name == INTERNET_PERMISSION)
+                .count()
+        };
+
+        let mut manifest = parse("");
+        assert_eq!(check_internet_permission(&mut manifest), None);
+        assert_eq!(internet(&manifest), 0);
+
+        let forward = "reverse_port_forward = { \"tcp:8080\" = \"tcp:8080\" }\n";
+        let mut manifest = parse(forward);
+        let warning = check_internet_permission(&mut manifest).unwrap();
+        assert!(
+            warning.contains("`android.permission.INTERNET`"),
+            "{warning}"
+        );
+        assert_eq!(internet(&manifest), 0);
+
+        let mut manifest = parse(&format!("requires_internet = true\n{forward}"));
+        assert_eq!(check_internet_permission(&mut manifest), None);
+        assert_eq!(internet(&manifest), 1);
+        // Not added twice
+        let mut manifest = parse(&format!(
+            "requires_internet = true\nuses_permission = [{{ name = \"{INTERNET_PERMISSION}\" }}]\n{forward}"
+        ));
+        assert_eq!(check_internet_permission(&mut manifest), None);
+        assert_eq!(internet(&manifest), 1);
+    }
+
+    #[test]
+    fn launch_activities() {
+        let parse = |metadata: &str| parse_metadata("launch_activity", metadata);
+
+        let manifest = parse("");
+        assert_eq!(
+            launch_activity(&manifest),
+            Some("android.app.NativeActivity")
+        );
+        let manifest = parse("application.activity.name = \".MainActivity\"\n");
+        assert_eq!(launch_activity(&manifest), Some(".MainActivity"));
+        // Nothing launchable that cargo-android knows of
+        let manifest = parse("no_launcher = true\n");
+        assert_eq!(launch_activity(&manifest), None);
+
+        let manifest = parse("no_launcher = true\ndefault_launch_activity = \".Settings\"\n");
+        assert_eq!(launch_activity(&manifest), Some(".Settings"));
+    }
+
+    #[test]
+    fn launcher_on_activity_alias() {
+        let mut manifest = parse_metadata(
+            "activity_aliases",
+            "[[package.metadata.android.application.activity_aliases]]\nname = \".WinterIcon\"\nenabled = false\nicon = \"@mipmap/winter\"\nintent_filter = [{ actions = [\"android.intent.action.MAIN\"], categories = [\"android.intent.category.LAUNCHER\"] }]\n[[package.metadata.android.application.activity_aliases]]\nname = \".DefaultIcon\"\nintent_filter = [{ actions = [\"android.intent.action.MAIN\"], categories = [\"android.intent.category.LAUNCHER\"] }]\n",
+        );
+        add_launcher_defaults(&mut manifest, 33);
+
+        let application = &manifest.android_manifest.application;
+        // The launcher is on the aliases instead of the Rust activity
+        assert!(application.activity.intent_filter.is_empty());
+        assert_eq!(application.activity.exported, None);
+        for alias in &application.activity_aliases {
+            assert_eq!(
+                alias.target_activity.as_deref(),
+                Some("android.app.NativeActivity")
+            );
+            assert_eq!(alias.exported, Some(true));
+        }
+        // The first one is disabled until the app enables it
+        assert_eq!(launch_activity(&manifest), Some(".DefaultIcon"));
+    }
+
+    #[test]
+    fn logcat_spawns_before_start() {
+        use LaunchStep::*;
+        assert_eq!(
+            launch_steps(false, false, false),
+            [LookUpUid, SpawnLogcat, Start, FollowLogcat]
+        );
+        assert_eq!(
+            launch_steps(false, true, true),
+            [
+                ClearLogcat,
+                LookUpUid,
+                SpawnLogcat,
+                Start,
+                PrintLaunchInfo,
+                FollowLogcat
+            ]
+        );
+        assert_eq!(
+            launch_steps(true, true, false),
+            [ClearLogcat, LookUpUid, Start]
+        );
+    }
+
+    #[test]
+    fn logcat_filter() {
+        assert_eq!(
+            logcat_args(Some(10123), Some("1700000000.000"), true),
+            ["logcat", "-v", "color", "--uid", "10123"]
+        );
+        assert_eq!(
+            logcat_args(None, Some("1700000000.000"), false),
+            ["logcat", "-T", "1700000000.000"]
+        );
+        assert_eq!(logcat_args(None, None, false), ["logcat"]);
+    }
+
+    #[test]
+    fn per_abi_version_code() {
+        let config = parse_metadata(
+            "per_abi_version_code",
+            "version_code = 7\nversion_code_abi_scheme = \"suffix\"\n",
+        );
+        let artifact = Artifact {
+            name: "demo".to_string(),
+            path: PathBuf::from("src/lib.rs"),
+            r#type: ArtifactType::Lib,
+        };
+        let split = |target| {
+            let mut manifest = artifact_manifest(&config.android_manifest, &artifact);
+            manifest.version_code = Some(7);
+            apply_abi_version_code(&mut manifest, &config, target).unwrap();
+            manifest.version_code
+        };
+        assert_eq!(split(Target::Arm64V8a), Some(72));
+        assert_eq!(split(Target::X86_64), Some(74));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_bin_needs_device_target() {
+        use clap::Parser;
+
+        let sdk = crate::test_support::FakeSdk::new("run_bin_abi");
+        let manifest = sdk.fixture_crate(
+            "run-bin-abi",
+            "build_targets = [\"aarch64-linux-android\"]\ncompile_sdk_version = 34\n",
+        );
+        let cmd = Subcommand::new(cargo_subcommand::Args::parse_from([
+            "cargo".as_ref(),
+            "--manifest-path".as_ref(),
+            manifest.as_os_str(),
+        ]))
+        .unwrap();
+        let builder = ApkBuilder::from_subcommand_with_ndk(
+            &cmd,
+            Some("emulator-5554".to_owned()),
+            sdk.ndk.clone(),
+            Env::default(),
+        )
+        .unwrap();
+        let artifact = Artifact {
+            name: "tool".to_string(),
+            path: PathBuf::from("src/main.rs"),
+            r#type: ArtifactType::Bin,
+        };
+
+        sdk.respond("adb", "x86_64\n", 0);
+        assert!(matches!(
+            builder.run_bin(&artifact, &[]),
+            Err(Error::DeviceAbiNotBuilt { abi: "x86_64", triple: "x86_64-linux-android", targets })
+                if targets == ["aarch64-linux-android"]
+        ));
+        // Nothing was built or pushed for another ABI
+        assert_eq!(sdk.calls("adb").len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apk_name_collisions() {
+        use clap::Parser;
+
+        let sdk = crate::test_support::FakeSdk::new("apk_names");
+        let manifest = sdk.fixture_crate(
+            "apk-names",
+            "apk_name = \"app\"\nbuild_targets = [\"aarch64-linux-android\"]\ncompile_sdk_version = 34\n",
+        );
+        let cmd = Subcommand::new(cargo_subcommand::Args::parse_from([
+            "cargo".as_ref(),
+            "--manifest-path".as_ref(),
+            manifest.as_os_str(),
+        ]))
+        .unwrap();
+        let artifact = |name: &str, r#type| Artifact {
+            name: name.to_owned(),
+            path: PathBuf::from(format!("{name}.rs")),
+            r#type,
+        };
+        let lib = artifact("apk_names", ArtifactType::Lib);
+        let one = artifact("one", ArtifactType::Example);
+        let two = artifact("two", ArtifactType::Example);
+        let artifacts = [lib.clone(), one.clone(), two.clone()];
+        let mut builder =
+            ApkBuilder::from_subcommand_with_ndk(&cmd, None, sdk.ndk.clone(), Env::default())
+                .unwrap();
+
+        // Only the examples share a directory, and thereby a path
+        assert!(matches!(
+            builder.resolve_apk_names(&artifacts, true),
+            Err(Error::ApkNameCollision { path, artifacts })
+                if path.ends_with("examples/app.apk") && artifacts == ["one", "two"]
+        ));
+        assert!(builder.apk_path(&one).ends_with("examples/app.apk"));
+
+        builder.resolve_apk_names(&artifacts, false).unwrap();
+        assert!(builder.apk_path(&one).ends_with("examples/app-one.apk"));
+        assert!(builder.apk_path(&two).ends_with("examples/app-two.apk"));
+        assert!(builder.apk_path(&lib).ends_with("apk/app.apk"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hermetic_env() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use clap::Parser;
+
+        let sdk = crate::test_support::FakeSdk::new("hermetic_env");
+        let manifest = sdk.fixture_crate(
+            "hermetic",
+            "build_targets = [\"aarch64-linux-android\"]\ncompile_sdk_version = 34\n",
+        );
+        let cmd = Subcommand::new(cargo_subcommand::Args::parse_from([
+            "cargo".as_ref(),
+            "--manifest-path".as_ref(),
+            manifest.as_os_str(),
+        ]))
+        .unwrap();
+        // A `rustc` whose sysroot has no targets installed
+        let rustc = sdk.root().join("rustc");
+        std::fs::write(
+            &rustc,
+            format!(
+                "#!/bin/sh\ncase \"$2\" in\n  sysroot) echo '{}' ;;\n  target-list) echo aarch64-linux-android ;;\nesac\n",
+                sdk.root().join("sysroot").display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&rustc, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let env = |vars: &[(&str, &Path)]| {
+            Env::hermetic(
+                vars.iter()
+                    .map(|(name, value)| (name.to_string(), value.display().to_string()))
+                    .collect(),
+            )
+        };
+        let builder =
+            |env| ApkBuilder::from_subcommand_with_ndk(&cmd, None, sdk.ndk.clone(), env).unwrap();
+
+        let rustc_only = builder(env(&[("RUSTC", &rustc)]));
+        assert!(matches!(
+            rustc_only.ensure_rust_targets_installed(&rustc_only.build_targets),
+            Err(Error::RustTargetNotInstalled(triple)) if triple == "aarch64-linux-android"
+        ));
+        let build_std = builder(env(&[
+            ("RUSTC", &rustc),
+            ("CARGO_UNSTABLE_BUILD_STD", Path::new("std")),
+        ]));
+        build_std
+            .ensure_rust_targets_installed(&build_std.build_targets)
+            .unwrap();
+
+        let build_time = |env: &Env| {
+            build_info(&Profile::Release, sdk.root(), env)
+                .into_iter()
+                .find(|entry| entry.name == "cargo_android.build_time")
+                .unwrap()
+                .value
+        };
+        let reproducible = env(&[("SOURCE_DATE_EPOCH", Path::new("1700000000"))]);
+        assert_eq!(build_time(&reproducible), "1700000000");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hermetic_env_ignores_process() {
+        use clap::Parser;
+
+        let sdk = crate::test_support::FakeSdk::new("hermetic_vars");
+        let manifest = sdk.fixture_crate(
+            "hermetic",
+            "build_targets = [\"aarch64-linux-android\"]\ncompile_sdk_version = 34\n",
+        );
+        let cmd = Subcommand::new(cargo_subcommand::Args::parse_from([
+            "cargo".as_ref(),
+            "--manifest-path".as_ref(),
+            manifest.as_os_str(),
+        ]))
+        .unwrap();
+        let keytool = sdk.root().join("jdk/bin/keytool");
+        std::fs::create_dir_all(keytool.parent().unwrap()).unwrap();
+        std::fs::write(&keytool, "").unwrap();
+
+        // Conflicting installations in the process environment, which other tests may see too
+        // and therefore do not exist
+        let process = sdk.root().join("process");
+        let vars = [
+            "ANDROID_HOME",
+            "ANDROID_NDK_ROOT",
+            "ANDROID_USER_HOME",
+            "JAVA_HOME",
+            "RUSTFLAGS",
+        ];
+        for name in vars {
+            std::env::set_var(name, process.join(name));
+        }
+        let path = |path: &Path| path.display().to_string();
+        let user_home = sdk.root().join("home/.android");
+        let env = Env::hermetic(HashMap::from([
+            ("ANDROID_HOME".to_owned(), path(sdk.ndk.sdk())),
+            ("ANDROID_NDK_ROOT".to_owned(), path(sdk.ndk.ndk())),
+            ("ANDROID_USER_HOME".to_owned(), path(&user_home)),
+            ("JAVA_HOME".to_owned(), path(&sdk.root().join("jdk"))),
+            ("RUSTFLAGS".to_owned(), "-Cenv-flag".to_owned()),
+        ]))
+        .ndk_cache(false);
+        let builder = ApkBuilder::from_subcommand_with_env(&cmd, None, env);
+        for name in vars {
+            std::env::remove_var(name);
+        }
+
+        let builder = builder.unwrap();
+        assert_eq!(builder.ndk.sdk(), sdk.ndk.sdk());
+        assert_eq!(builder.ndk.ndk(), sdk.ndk.ndk());
+        assert_eq!(builder.ndk.android_user_home().unwrap(), user_home);
+        assert_eq!(builder.ndk.keytool().unwrap().get_program(), keytool);
+        let cargo = builder
+            .cargo_for_api_level(Target::Arm64V8a, 23, "build", &[])
+            .unwrap();
+        let rustflags = &command_env(&cargo)["CARGO_ENCODED_RUSTFLAGS"];
+        assert!(rustflags.starts_with("-Cenv-flag\x1f"), "{rustflags}");
+        assert!(!rustflags.contains("process"), "{rustflags}");
+    }
+
+    /// The lines that [`follow_lines()`] echoed, and its result.
+    fn follow(
+        lines: &mpsc::Receiver<String>,
+        duration: Option<Duration>,
+        until: Option<&Regex>,
+    ) -> (Vec<String>, Result<(), Error>) {
+        let mut echoed = Vec::new();
+        let result = follow_lines(lines, duration, until, |line| echoed.push(line.to_owned()));
+        (echoed, result)
+    }
+
+    #[test]
+    fn follow_lines_until_match() {
+        let (send, lines) = mpsc::channel();
+        for line in ["starting", "I/app: ready on port 8080", "after"] {
+            send.send(line.to_owned()).unwrap();
+        }
+        let until = Regex::new("ready on port \\d+").unwrap();
+        let (echoed, result) = follow(&lines, None, Some(&until));
+        result.unwrap();
+        // Stops at the matching line, which is echoed
+        assert_eq!(echoed, ["starting", "I/app: ready on port 8080"]);
+        assert_eq!(lines.try_recv().unwrap(), "after");
+
+        // The log ended first
+        drop(send);
+        let (_, result) = follow(&lines, None, Some(&until));
+        assert!(matches!(
+            result,
+            Err(Error::LogcatUntil { reason, .. }) if reason == "before `logcat` ended"
+        ));
+        // Without a pattern, the end of the log is the end of following it
+        follow(&lines, None, None).1.unwrap();
+    }
+
+    #[test]
+    fn follow_lines_deadline() {
+        let (send, lines) = mpsc::channel();
+        send.send("starting".to_owned()).unwrap();
+        let duration = Some(Duration::from_millis(100));
+        let until = Regex::new("ready").unwrap();
+
+        let started = Instant::now();
+        let (echoed, result) = follow(&lines, duration, Some(&until));
+        assert!(matches!(
+            result,
+            Err(Error::LogcatUntil { reason, .. }) if reason == "within `--logcat-duration`"
+        ));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(echoed, ["starting"]);
+
+        // Only a duration succeeds once it passed, with the log still open
+        follow(&lines, duration, None).1.unwrap();
+        drop(send);
+    }
+}
@@ -0,0 +1,161 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use cargo_subcommand::{Args, Artifact, ArtifactType};
+
+/// Selects an artifact of the package to build, so that [`crate::ApkBuilder`] can be driven
+/// from code without going through command line parsing.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ArtifactSelector {
+    /// The `[lib]` target
+    Lib,
+    /// The `[[bin]]` target with the given name
+    Bin(String),
+    /// The `[[example]]` target with the given name
+    Example(String),
+}
+
+impl ArtifactSelector {
+    /// Selects `artifact`, such as one that `cargo-subcommand` resolved from the command line.
+    pub fn from_artifact(artifact: &Artifact) -> Self {
+        match artifact.r#type {
+            ArtifactType::Lib => Self::Lib,
+            ArtifactType::Bin => Self::Bin(artifact.name.clone()),
+            ArtifactType::Example => Self::Example(artifact.name.clone()),
+        }
+    }
+
+    /// Whether `artifact` is the selected one.
+    pub(crate) fn matches(&self, artifact: &Artifact) -> bool {
+        match self {
+            Self::Lib => artifact.r#type == ArtifactType::Lib,
+            Self::Bin(name) => artifact.r#type == ArtifactType::Bin && artifact.name == *name,
+            Self::Example(name) => {
+                artifact.r#type == ArtifactType::Example && artifact.name == *name
+            }
+        }
+    }
+
+    /// Creates the selected artifact of a package whose library is called `lib_name`, with
+    /// the source path that cargo infers by default.
+    pub(crate) fn to_artifact(&self, lib_name: &str) -> Artifact {
+        let (name, path, r#type) = match self {
+            Self::Lib => (lib_name, PathBuf::from("src/lib.rs"), ArtifactType::Lib),
+            Self::Bin(name) => (
+                name.as_str(),
+                PathBuf::from(format!("src/bin/{name}.rs")),
+                ArtifactType::Bin,
+            ),
+            Self::Example(name) => (
+                name.as_str(),
+                PathBuf::from(format!("examples/{name}.rs")),
+                ArtifactType::Example,
+            ),
+        };
+        Artifact {
+            name: name.to_owned(),
+            path,
+            r#type,
+        }
+    }
+
+    /// The arguments that make `cargo` build the selected artifact.
+    pub(crate) fn cargo_args(&self) -> Vec<String> {
+        match self {
+            Self::Lib => vec!["--lib".to_owned()],
+            Self::Bin(name) => vec!["--bin".to_owned(), name.clone()],
+            Self::Example(name) => vec!["--example".to_owned(), name.clone()],
+        }
+    }
+}
+
+/// Applies `args` to `cargo`, with their `--lib`, `--bin(s)` and `--example(s)` replaced by
+/// the artifacts in `selection` unless it is empty, so that `cargo` only builds what is
+/// packaged instead of e.g. every helper binary of the package along with the library.
+pub(crate) fn apply_args(cargo: &mut Command, args: &Args, selection: &[ArtifactSelector]) {
+    if selection.is_empty() {
+        args.apply(cargo);
+        return;
+    }
+    Args {
+        lib: false,
+        bin: Vec::new(),
+        bins: false,
+        example: Vec::new(),
+        examples: false,
+        ..args.clone()
+    }
+    .apply(cargo);
+    let mut selected = Vec::new();
+    for selector in selection {
+        if !selected.contains(selector) {
+            cargo.args(selector.cargo_args());
+            selected.push(selector.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn args(args: &[&str]) -> Args {
+        Args::parse_from(["cargo"].iter().chain(args))
+    }
+
+    #[test]
+    fn resolve_and_select() {
+        let example = ArtifactSelector::Example("demo".to_owned());
+        let artifact = example.to_artifact("my_lib");
+        assert_eq!(artifact.name, "demo");
+        assert_eq!(artifact.r#type, ArtifactType::Example);
+        assert!(example.matches(&artifact));
+        assert!(!ArtifactSelector::Lib.matches(&artifact));
+        assert_eq!(ArtifactSelector::from_artifact(&artifact), example);
+        assert_eq!(ArtifactSelector::Lib.to_artifact("my_lib").name, "my_lib");
+
+        assert_eq!(example.cargo_args(), ["--example", "demo"]);
+        assert_eq!(ArtifactSelector::Lib.cargo_args(), ["--lib"]);
+        assert_eq!(
+            ArtifactSelector::Bin("tool".to_owned()).cargo_args(),
+            ["--bin", "tool"]
+        );
+    }
+
+    #[test]
+    fn only_selected_artifacts_are_built() {
+        let command_line = |args: &Args, selection: &[ArtifactSelector]| {
+            let mut cargo = Command::new("cargo");
+            apply_args(&mut cargo, args, selection);
+            cargo
+                .get_args()
+                .map(|arg| arg.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        let helpers = args(&["--lib", "--bins", "--example", "demo"]);
+
+        let command_line_args = command_line(&helpers, &[ArtifactSelector::Lib]);
+        assert!(command_line_args.contains(&"--lib".to_owned()));
+        for unrelated in ["--bins", "--bin", "--example", "demo"] {
+            assert!(
+                !command_line_args.iter().any(|arg| arg == unrelated),
+                "{command_line_args:?}"
+            );
+        }
+        assert_eq!(
+            command_line(
+                &args(&[]),
+                &[
+                    ArtifactSelector::Lib,
+                    ArtifactSelector::Example("demo".to_owned()),
+                    ArtifactSelector::Lib,
+                ]
+            )
+            .into_iter()
+            .filter(|arg| arg.starts_with("--"))
+            .collect::<Vec<_>>(),
+            ["--lib", "--example"]
+        );
+    }
+}
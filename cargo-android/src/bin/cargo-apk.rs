@@ -0,0 +1,4 @@
+//! `cargo apk`, for projects migrating from cargo-apk: the same command line as `cargo android`,
+//! which accepts both invocations.
+
+include!("../main.rs");
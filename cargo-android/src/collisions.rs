@@ -0,0 +1,139 @@
+//! Detects paths in `assets` and `resources` that only differ in case, of which a checkout on a
+//! case-insensitive file system (macOS, Windows) keeps only one, so that the APK would differ
+//! between hosts.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use ndk_build::error::NdkError;
+
+/// The entries of `dir` sorted by file name, independent of the order of `read_dir()`.
+fn sorted_entries(dir: &Path) -> Result<Vec<(String, PathBuf)>, NdkError> {
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(|e| NdkError::IoPathError(dir.to_owned(), e))?
+        .map(|entry| {
+            let entry = entry.map_err(|e| NdkError::IoPathError(dir.to_owned(), e))?;
+            Ok((
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.path(),
+            ))
+        })
+        .collect::<Result<Vec<_>, NdkError>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+/// The groups of paths below `dir` that only differ in case, relative to `dir`, each sorted
+/// and in the order of a depth-first walk. Directories that collide count as one path each.
+pub(crate) fn case_collisions(dir: &Path) -> Result<Vec<Vec<PathBuf>>, NdkError> {
+    let mut collisions = Vec::new();
+    collect_case_collisions(dir, Path::new(""), &mut collisions)?;
+    Ok(collisions)
+}
+
+fn collect_case_collisions(
+    dir: &Path,
+    relative: &Path,
+    collisions: &mut Vec<Vec<PathBuf>>,
+) -> Result<(), NdkError> {
+    let entries = sorted_entries(dir)?;
+    let mut by_folded_name = BTreeMap::<String, Vec<PathBuf>>::new();
+    for (name, _) in &entries {
+        by_folded_name
+            .entry(name.to_lowercase())
+            .or_default()
+            .push(relative.join(name));
+    }
+    collisions.extend(by_folded_name.into_values().filter(|paths| paths.len() > 1));
+    for (name, path) in entries {
+        if path.is_dir() {
+            collect_case_collisions(&path, &relative.join(name), collisions)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies `dir` to `staged`, leaving out the `dropped` paths (relative to `dir`) and
+/// everything below them, replacing the previous copy.
+pub(crate) fn stage_without(
+    dir: &Path,
+    staged: &Path,
+    dropped: &[PathBuf],
+) -> Result<(), NdkError> {
+    if staged.exists() {
+        std::fs::remove_dir_all(staged).map_err(|e| NdkError::IoPathError(staged.to_owned(), e))?;
+    }
+    copy_without(dir, staged, Path::new(""), dropped)
+}
+
+fn copy_without(
+    from: &Path,
+    to: &Path,
+    relative: &Path,
+    dropped: &[PathBuf],
+) -> Result<(), NdkError> {
+    std::fs::create_dir_all(to).map_err(|e| NdkError::IoPathError(to.to_owned(), e))?;
+    for (name, path) in sorted_entries(from)? {
+        let relative = relative.join(&name);
+        if dropped.contains(&relative) {
+            continue;
+        }
+        let target = to.join(&name);
+        if path.is_dir() {
+            copy_without(&path, &target, &relative, dropped)?;
+        } else {
+            std::fs::copy(&path, &target).map_err(|e| NdkError::IoPathError(path, e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_collisions_are_detected_and_dropped() {
+        let dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("case_collisions");
+        let _ = std::fs::remove_dir_all(&dir);
+        let assets = dir.join("assets");
+        for path in [
+            "icon.png",
+            "Icon.png",
+            "fonts/Regular.ttf",
+            "Fonts/bold.ttf",
+            "sounds/click.ogg",
+        ] {
+            let path = assets.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        if std::fs::read_dir(&assets).unwrap().count() < 5 {
+            // Case-insensitive file system, which can't have collisions to begin with
+            return;
+        }
+
+        let collisions = case_collisions(&assets).unwrap();
+        assert_eq!(
+            collisions,
+            [
+                vec![PathBuf::from("Fonts"), PathBuf::from("fonts")],
+                vec![PathBuf::from("Icon.png"), PathBuf::from("icon.png")],
+            ]
+        );
+
+        let dropped = collisions
+            .iter()
+            .flat_map(|paths| paths[1..].iter().cloned())
+            .collect::<Vec<_>>();
+        let staged = dir.join("staged");
+        stage_without(&assets, &staged, &dropped).unwrap();
+        assert!(case_collisions(&staged).unwrap().is_empty());
+        for kept in ["Icon.png", "Fonts/bold.ttf", "sounds/click.ogg"] {
+            assert!(staged.join(kept).is_file(), "{kept} was not staged");
+        }
+        assert!(!staged.join("fonts").exists());
+    }
+}
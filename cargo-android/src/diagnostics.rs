@@ -0,0 +1,312 @@
+//! Deduplicates the warnings of the inner `cargo` invocations, which build the same code once
+//! per target and would otherwise repeat every warning for each of them.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::process::{Command, ExitStatus, Stdio};
+
+use ndk_build::target::Target;
+use serde::Deserialize;
+
+/// A warning of the inner `cargo` builds, printed once for all targets it occurred on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct CargoWarning {
+    /// The warning as rustc renders it
+    rendered: String,
+    /// The targets whose build emitted the warning, in build order
+    targets: Vec<Target>,
+}
+
+/// A line of `cargo --message-format json` output.
+#[derive(Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum Message {
+    CompilerMessage {
+        message: Diagnostic,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct Diagnostic {
+    message: String,
+    level: String,
+    rendered: Option<String>,
+    #[serde(default)]
+    spans: Vec<Span>,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: u32,
+    column_start: u32,
+    is_primary: bool,
+}
+
+impl Diagnostic {
+    /// Whether this is the count rustc prints after the warnings of a crate, such as
+    /// `2 warnings emitted`, which no longer holds once the warnings are deduplicated.
+    fn is_warning_count(&self) -> bool {
+        self.spans.is_empty()
+            && self
+                .message
+                .strip_suffix(" warnings emitted")
+                .or_else(|| self.message.strip_suffix(" warning emitted"))
+                .is_some_and(|count| count.parse::<u32>().is_ok())
+    }
+
+    /// Identifies the same warning across targets by its message and primary span.
+    fn fingerprint(&self) -> String {
+        match self.spans.iter().find(|span| span.is_primary) {
+            Some(span) => format!(
+                "{}@{}:{}:{}",
+                self.message, span.file_name, span.line_start, span.column_start
+            ),
+            None => self.message.clone(),
+        }
+    }
+}
+
+/// Collects the warnings of the per-target builds, see [`Warnings::run()`].
+#[derive(Debug, Default)]
+pub(crate) struct Warnings {
+    warnings: Vec<CargoWarning>,
+    /// Index into `warnings` by [`Diagnostic::fingerprint()`]
+    fingerprints: HashMap<String, usize>,
+}
+
+/// Whether the arguments of `cargo` already select a `--message-format`, which the JSON
+/// messages that the deduplication relies on would conflict with.
+pub(crate) fn has_message_format(cargo: &Command) -> bool {
+    cargo.get_args().any(|arg| {
+        let arg = arg.to_string_lossy();
+        arg == "--message-format" || arg.starts_with("--message-format=")
+    })
+}
+
+/// A copy of `cargo` with the `--message-format` that [`Warnings::run()`] parses, with the
+/// rendered diagnostics colored when `ansi` is set. `cargo` itself keeps the arguments of the
+/// user, to print it when it fails.
+fn with_json_message_format(cargo: &Command, ansi: bool) -> Command {
+    let format = if ansi {
+        "json-diagnostic-rendered-ansi"
+    } else {
+        "json"
+    };
+    let mut json = Command::new(cargo.get_program());
+    json.args(cargo.get_args())
+        .arg("--message-format")
+        .arg(format);
+    for (key, value) in cargo.get_envs() {
+        match value {
+            Some(value) => json.env(key, value),
+            None => json.env_remove(key),
+        };
+    }
+    if let Some(dir) = cargo.get_current_dir() {
+        json.current_dir(dir);
+    }
+    json
+}
+
+impl Warnings {
+    /// Runs `cargo`, which builds `target`, with JSON messages: errors and other diagnostics
+    /// are printed right away, warnings are collected for [`Warnings::print()`].
+    ///
+    /// `cargo` must not select a `--message-format` of its own, see
+    /// [`has_message_format()`].
+    pub(crate) fn run(&mut self, cargo: &Command, target: Target) -> std::io::Result<ExitStatus> {
+        let mut json = with_json_message_format(cargo, std::io::stderr().is_terminal());
+        let mut child = json.stdout(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        for line in BufReader::new(stdout).lines() {
+            self.handle_line(&line?, target);
+        }
+        child.wait()
+    }
+
+    /// Handles one line of the JSON messages of the build of `target`.
+    fn handle_line(&mut self, line: &str, target: Target) {
+        let diagnostic = match serde_json::from_str(line) {
+            Ok(Message::CompilerMessage { message }) => message,
+            Ok(Message::Other) => return,
+            Err(e) => {
+                log::debug!("Not a cargo message: {e}: {line}");
+                return;
+            }
+        };
+        let Some(rendered) = diagnostic.rendered.as_deref() else {
+            return;
+        };
+        if diagnostic.level != "warning" {
+            eprint!("{rendered}");
+            return;
+        }
+        if diagnostic.is_warning_count() {
+            // Counted again over the deduplicated warnings by `print()`
+            return;
+        }
+
+        let index = *self
+            .fingerprints
+            .entry(diagnostic.fingerprint())
+            .or_insert_with(|| {
+                self.warnings.push(CargoWarning {
+                    rendered: rendered.to_owned(),
+                    targets: Vec::new(),
+                });
+                self.warnings.len() - 1
+            });
+        let targets = &mut self.warnings[index].targets;
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+
+    /// Prints every collected warning once, annotated with the targets it occurred on,
+    /// followed by their count.
+    pub(crate) fn print(&self) {
+        for warning in &self.warnings {
+            let targets = warning
+                .targets
+                .iter()
+                .map(|target| target.android_abi())
+                .collect::<Vec<_>>();
+            eprint!("{}", warning.rendered);
+            eprintln!("  = on {}\n", targets.join(", "));
+        }
+        match self.warnings.len() {
+            0 => {}
+            1 => eprintln!("warning: 1 warning emitted\n"),
+            count => eprintln!("warning: {count} warnings emitted\n"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cargo: &Command) -> Vec<String> {
+        cargo
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn message_format_arguments() {
+        let mut cargo = Command::new("cargo");
+        cargo
+            .current_dir("/path/to/app")
+            .env("CARGO_ENCODED_RUSTFLAGS", "-Copt-level=s")
+            .env_remove("RUSTFLAGS")
+            .args(["build", "--target", "aarch64-linux-android"]);
+        assert!(!has_message_format(&cargo));
+        let json = with_json_message_format(&cargo, false);
+        assert_eq!(
+            args(&json),
+            [
+                "build",
+                "--target",
+                "aarch64-linux-android",
+                "--message-format",
+                "json"
+            ]
+        );
+        // Set now, and not added twice by a caller that checks
+        assert!(has_message_format(&json));
+        assert_eq!(json.get_current_dir(), cargo.get_current_dir());
+        assert_eq!(
+            json.get_envs().collect::<Vec<_>>(),
+            cargo.get_envs().collect::<Vec<_>>()
+        );
+        // The command of the user is printed when it fails
+        assert_eq!(args(&cargo), ["build", "--target", "aarch64-linux-android"]);
+
+        let json = with_json_message_format(&Command::new("cargo"), true);
+        assert_eq!(
+            args(&json),
+            ["--message-format", "json-diagnostic-rendered-ansi"]
+        );
+
+        for user_args in [
+            ["build", "--message-format", "short"],
+            ["build", "--message-format=short", "--release"],
+        ] {
+            let mut cargo = Command::new("cargo");
+            cargo.args(user_args);
+            assert!(has_message_format(&cargo), "{user_args:?}");
+        }
+        let mut cargo = Command::new("cargo");
+        cargo.args(["build", "--features", "message-format"]);
+        assert!(!has_message_format(&cargo));
+    }
+
+    fn compiler_message(level: &str, message: &str, line: u32) -> String {
+        serde_json::json!({
+            "reason": "compiler-message",
+            "package_id": "app 0.1.0",
+            "target": { "name": "app" },
+            "message": {
+                "message": message,
+                "level": level,
+                "rendered": format!("{level}: {message}\n"),
+                "spans": [{
+                    "file_name": "src/lib.rs",
+                    "line_start": line,
+                    "column_start": 5,
+                    "is_primary": true,
+                }],
+            },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn warnings_are_deduplicated_across_targets() {
+        let unused = compiler_message("warning", "unused variable: `x`", 3);
+        let mut warnings = Warnings::default();
+        for target in [Target::Arm64V8a, Target::X86_64] {
+            warnings.handle_line(&unused, target);
+            warnings.handle_line(r#"{"reason":"build-finished","success":true}"#, target);
+            warnings.handle_line("not json", target);
+        }
+        // Same message elsewhere
+        warnings.handle_line(
+            &compiler_message("warning", "unused variable: `x`", 7),
+            Target::X86_64,
+        );
+        warnings.handle_line(
+            &compiler_message("error", "mismatched types", 9),
+            Target::X86_64,
+        );
+        // The per-target count of rustc is dropped
+        let count = serde_json::json!({
+            "reason": "compiler-message",
+            "message": {
+                "message": "2 warnings emitted",
+                "level": "warning",
+                "rendered": "warning: 2 warnings emitted\n\n",
+                "spans": [],
+            },
+        });
+        warnings.handle_line(&count.to_string(), Target::X86_64);
+
+        assert_eq!(
+            warnings.warnings,
+            [
+                CargoWarning {
+                    rendered: "warning: unused variable: `x`\n".to_owned(),
+                    targets: vec![Target::Arm64V8a, Target::X86_64],
+                },
+                CargoWarning {
+                    rendered: "warning: unused variable: `x`\n".to_owned(),
+                    targets: vec![Target::X86_64],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! The environment that [`crate::ApkBuilder`] and [`crate::AabBuilder`] read `JAVA_HOME`,
+//! `ANDROID_HOME` and the other SDK and NDK variables, `RUSTFLAGS`, the `CARGO_ANDROID_*`
+//! signing variables, `RUSTC`, `CARGO_UNSTABLE_BUILD_STD` and `SOURCE_DATE_EPOCH` from, which
+//! hermetic build systems and tests can provide instead of the environment of the process.
+
+use std::collections::HashMap;
+use std::env::VarError;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use ndk_build::secret::Secret;
+
+use crate::error::Error;
+
+/// Environment variables for the builders: the environment of the process by default,
+/// [`Env::with_overrides()`] on top of it, or only the given variables with [`Env::hermetic()`].
+///
+/// [`Debug`] only prints the names of the variables, as the values include passwords.
+#[derive(Clone)]
+pub struct Env {
+    vars: HashMap<String, String>,
+    /// Whether variables that are not in `vars` are read from the process
+    inherit: bool,
+    /// Secrets read from the file descriptors in the variables of these names, see
+    /// [`Env::fd_secret()`]
+    fd_secrets: Arc<Mutex<HashMap<String, Secret>>>,
+    /// See [`Env::keystore_check()`]
+    keystore_check: bool,
+    /// See [`Env::signing_config()`]
+    signing_config: Option<PathBuf>,
+    /// See [`Env::ndk_cache()`]
+    ndk_cache: bool,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::with_overrides(HashMap::new())
+    }
+}
+
+impl Env {
+    /// `vars`, which take precedence over the environment of the process.
+    pub fn with_overrides(vars: HashMap<String, String>) -> Self {
+        Self {
+            vars,
+            inherit: true,
+            fd_secrets: Default::default(),
+            keystore_check: true,
+            signing_config: None,
+            ndk_cache: true,
+        }
+    }
+
+    /// Only `vars`, without ever reading the environment of the process.
+    pub fn hermetic(vars: HashMap<String, String>) -> Self {
+        Self {
+            vars,
+            inherit: false,
+            fd_secrets: Default::default(),
+            keystore_check: true,
+            signing_config: None,
+            ndk_cache: true,
+        }
+    }
+
+    /// Whether the builders reuse the SDK and NDK probe results that are cached in the target
+    /// directory (the default), or probe the SDK and NDK every time.
+    #[must_use]
+    pub fn ndk_cache(mut self, enabled: bool) -> Self {
+        self.ndk_cache = enabled;
+        self
+    }
+
+    pub(crate) fn uses_ndk_cache(&self) -> bool {
+        self.ndk_cache
+    }
+
+    /// Whether keystores are opened with `keytool` before building to check their password and
+    /// key alias (the default), or only when signing, e.g. for keystores on slow hardware
+    /// tokens.
+    #[must_use]
+    pub fn keystore_check(mut self, enabled: bool) -> Self {
+        self.keystore_check = enabled;
+        self
+    }
+
+    pub(crate) fn checks_keystore(&self) -> bool {
+        self.keystore_check
+    }
+
+    /// Reads the signing configuration from the file at `path` instead of the one that the
+    /// `CARGO_ANDROID_SIGNING_CONFIG` variable points to, if any.
+    ///
+    /// The file is a JSON (`.json`) or TOML file with a table per profile in the shape of
+    /// `[package.metadata.android.signing.<profile>]`, such as one that CI injects. Its profiles
+    /// replace those of `Cargo.toml`, while the `CARGO_ANDROID_<PROFILE>_*` variables still
+    /// take precedence over both.
+    #[must_use]
+    pub fn signing_config(mut self, path: Option<PathBuf>) -> Self {
+        self.signing_config = path;
+        self
+    }
+
+    pub(crate) fn signing_config_path(&self) -> Option<&Path> {
+        self.signing_config.as_deref()
+    }
+
+    /// The variable `name` like [`std::env::var_os()`] reads it.
+    pub fn var_os(&self, name: &str) -> Option<OsString> {
+        match self.vars.get(name) {
+            Some(value) => Some(value.into()),
+            None if self.inherit => std::env::var_os(name),
+            None => None,
+        }
+    }
+
+    /// The variable `name` like [`std::env::var()`] reads it.
+    pub fn var(&self, name: &str) -> Result<String, VarError> {
+        match self.vars.get(name) {
+            Some(value) => Ok(value.clone()),
+            None if self.inherit => std::env::var(name),
+            None => Err(VarError::NotPresent),
+        }
+    }
+
+    /// The secret from the file descriptor in the variable `fd_var`, which `read` reads only
+    /// the first time: a descriptor such as a pipe can only be read once, but keystores are
+    /// resolved for every artifact. The secret lives as long as this environment and its
+    /// clones.
+    pub(crate) fn fd_secret(
+        &self,
+        fd_var: &str,
+        read: impl FnOnce() -> Result<Secret, Error>,
+    ) -> Result<Secret, Error> {
+        let mut secrets = self.fd_secrets.lock().unwrap();
+        if let Some(secret) = secrets.get(fd_var) {
+            return Ok(secret.clone());
+        }
+        let secret = read()?;
+        secrets.insert(fd_var.to_owned(), secret.clone());
+        Ok(secret)
+    }
+}
+
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = self.vars.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_struct("Env")
+            .field("vars", &names)
+            .field("inherit", &self.inherit)
+            .field("keystore_check", &self.keystore_check)
+            .field("signing_config", &self.signing_config)
+            .field("ndk_cache", &self.ndk_cache)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_and_hermetic() {
+        std::env::set_var("CARGO_ANDROID_ENV_TEST_INHERITED", "process");
+        std::env::set_var("CARGO_ANDROID_ENV_TEST_OVERRIDDEN", "process");
+        let vars = HashMap::from([(
+            "CARGO_ANDROID_ENV_TEST_OVERRIDDEN".to_owned(),
+            "s3cret".to_owned(),
+        )]);
+
+        let env = Env::with_overrides(vars.clone());
+        assert_eq!(
+            env.var("CARGO_ANDROID_ENV_TEST_OVERRIDDEN").unwrap(),
+            "s3cret"
+        );
+        assert_eq!(
+            env.var("CARGO_ANDROID_ENV_TEST_INHERITED").unwrap(),
+            "process"
+        );
+
+        let env = Env::hermetic(vars);
+        assert_eq!(
+            env.var("CARGO_ANDROID_ENV_TEST_OVERRIDDEN").unwrap(),
+            "s3cret"
+        );
+        assert!(env.var_os("CARGO_ANDROID_ENV_TEST_INHERITED").is_none());
+        assert_eq!(
+            env.var("CARGO_ANDROID_ENV_TEST_INHERITED"),
+            Err(VarError::NotPresent)
+        );
+
+        let debug = format!("{env:?}");
+        assert!(!debug.contains("s3cret"), "{debug}");
+        assert!(
+            debug.contains("CARGO_ANDROID_ENV_TEST_OVERRIDDEN"),
+            "{debug}"
+        );
+    }
+}
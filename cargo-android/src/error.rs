@@ -1,6 +1,7 @@
 use cargo_subcommand::Error as SubcommandError;
 use ndk_build::error::NdkError;
 use std::io::Error as IoError;
+use std::path::PathBuf;
 use thiserror::Error;
 use toml::de::Error as TomlError;
 
@@ -16,12 +17,154 @@ pub enum Error {
     Io(#[from] IoError),
     #[error("Configure a release keystore via `[package.metadata.android.signing.{0}]`")]
     MissingReleaseKey(String),
+    #[error("`{key}` directory `{path:?}` does not exist, fix the path in `[package.metadata.android]` or pass `--allow-missing-dirs` to build without it")]
+    MissingMetadataDir { key: &'static str, path: PathBuf },
+    #[error("`{key}` contains paths that only differ in case, of which a checkout on a case-insensitive file system keeps only one:\n{}\nRename them, or pass `--allow-case-collisions` to only package the first path of each", .collisions.iter().map(|paths| paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")).collect::<Vec<_>>().join("\n"))]
+    CaseCollisions {
+        key: &'static str,
+        collisions: Vec<Vec<PathBuf>>,
+    },
+    #[error(
+        "Google Play requires a 64-bit version of every native library, add {0} to `build_targets`"
+    )]
+    Missing64Bit(String),
+    #[error("No line of `logcat` matched `{pattern}` {reason}")]
+    LogcatUntil {
+        pattern: String,
+        reason: &'static str,
+    },
+    #[error("Keystore `{0:?}` does not exist")]
+    KeystoreNotFound(PathBuf),
+    #[error("Keystore password for `{0:?}` is incorrect")]
+    KeystoreWrongPassword(PathBuf),
+    #[error("Failed to read the signing config `{path:?}`: {message}")]
+    SigningConfig { path: PathBuf, message: String },
+    #[error(
+        "Key alias `{alias}` does not exist in keystore `{keystore:?}`, it contains {available:?}"
+    )]
+    KeyAliasNotFound {
+        alias: String,
+        keystore: PathBuf,
+        available: Vec<String>,
+    },
+    #[error("Failed to read keystore `{0:?}`: {1}")]
+    KeystoreUnreadable(PathBuf, String),
+    #[error("Signing key lineage `{0:?}` does not exist, create it with `apksigner rotate`")]
+    LineageNotFound(PathBuf),
+    #[error("Environment variable `{0}` is not valid unicode")]
+    EnvVarNotUnicode(String),
+    #[error("`{package}` did not reach the foreground within {timeout}s of launching it, the launch printed:\n{output}")]
+    NotInForeground {
+        package: String,
+        timeout: u64,
+        output: String,
+    },
+    #[error("Environment variable `{0}` must be a file descriptor above 2, not `{1}`")]
+    InvalidSecretFd(String, String),
+    #[error("Failed to read the secret of `{var}` from file descriptor {fd}")]
+    SecretFd {
+        var: String,
+        fd: i32,
+        #[source]
+        source: IoError,
+    },
+    #[error("Rust target `{0}` is not installed, add it with `rustup target add {0}`")]
+    RustTargetNotInstalled(String),
+    #[error("Rust target `{0}` is not supported by this `rustc`, update it with `rustup update`")]
+    RustTargetUnsupported(String),
+    #[error("Artifacts {artifacts:?} would all be written to `{path:?}`, include `{{name}}` in `apk_name`")]
+    ApkNameCollision {
+        path: PathBuf,
+        artifacts: Vec<String>,
+    },
+    #[error("Package `{package}` has no `[package.metadata.android]` table, select one of the Android packages in the workspace with `-p`: {candidates:?}")]
+    NotAnAndroidPackage {
+        package: String,
+        candidates: Vec<String>,
+    },
+    #[error(
+        "Example `{example}` requires the features {features:?}, enable them with `--features`"
+    )]
+    MissingRequiredFeatures {
+        example: String,
+        features: Vec<String>,
+    },
+    #[error("Example `{example}` requires the features {features:?}, which are not declared in `[features]`")]
+    UndeclaredRequiredFeatures {
+        example: String,
+        features: Vec<String>,
+    },
+    #[error("Refusing to serve `{0:?}`, which is signed with the debug keystore, pass `--allow-debug-signing` to serve it anyway")]
+    ServeDebugSigned(PathBuf),
+    #[error("Refusing to build `{profile}` artifacts that are {reasons}, pass `--allow-debug-signing` or set `allow_debuggable_release = true` to build them anyway")]
+    DebugRelease { profile: String, reasons: String },
+    #[error("Refusing to create an aab of the `{0}` profile with `test_only = true`, as Google Play rejects `android:testOnly` apps")]
+    TestOnlyRelease(String),
+    #[error("`{0}` has no effect on aabs, which package the libraries of the last built apk, pass it to `build` instead")]
+    AabStripFlag(&'static str),
+    #[error("`splash.background` must be a color like `#101010`, got {0:?}")]
+    InvalidSplashColor(String),
+    #[error("Invalid `network_security`: {0}")]
+    InvalidNetworkSecurity(String),
+    #[error("Both `network_security_config` ({0:?}) and `[package.metadata.android.network_security]` are set, remove one of them")]
+    NetworkSecurityConfigConflict(String),
+    #[error("Unknown ABI `{abi}` in `{key}`, expected one of {accepted:?}")]
+    UnknownAbi {
+        key: &'static str,
+        abi: String,
+        accepted: Vec<&'static str>,
+    },
+    #[error("The device runs `{abi}`, which is not among the build targets {targets:?}, add `{triple}` to `build_targets` or pass `--target {triple}`")]
+    DeviceAbiNotBuilt {
+        abi: &'static str,
+        triple: &'static str,
+        targets: Vec<&'static str>,
+    },
+    #[error("`version_code_abi_scheme` has no offset for `{0}`")]
+    MissingVersionCodeAbiOffset(String),
+    #[error("Splitting the apk per ABI requires a `version_code_abi_scheme` in `[package.metadata.android]`, as every split needs a distinct versionCode")]
+    MissingVersionCodeAbiScheme,
+    #[error("The `{abi}` versionCode derived from {base} exceeds the maximum versionCode")]
+    VersionCodeOverflow { abi: &'static str, base: u32 },
+    #[error("SDK versions must satisfy `compile_sdk_version` ({compile}) >= `target_sdk_version` ({target}) >= `min_sdk_version` ({min})")]
+    InvalidSdkVersions { min: u32, target: u32, compile: u32 },
+    #[error("Platform `android-{platform}` is not installed, install it with `sdkmanager \"platforms;android-{platform}\"` or pick one of the installed platforms: {installed:?}")]
+    PlatformNotInstalled { platform: u32, installed: Vec<u32> },
+    #[error("`min_sdk_version` {min_sdk_version} is above the newest platform {newest} that the NDK has `{abi}` libraries for, update the NDK or lower `min_sdk_version`")]
+    MinSdkAboveNdk {
+        min_sdk_version: u32,
+        newest: u32,
+        abi: &'static str,
+    },
+    #[error("`min_sdk_version` {min_sdk_version} is below the oldest platform {oldest} that the NDK supports for `{abi}`, raise `min_sdk_version` or use an older NDK")]
+    MinSdkBelowNdk {
+        min_sdk_version: u32,
+        oldest: u32,
+        abi: &'static str,
+    },
+    #[error("`{package}` is installed signed with another debug keystore than `{keystore:?}`, which changed since (was it regenerated or replaced?). Pass `--reinstall` to uninstall and install it again, which removes its data, or set `CARGO_ANDROID_DEBUG_KEYSTORE` to the previous keystore")]
+    DebugKeystoreChanged { package: String, keystore: PathBuf },
+    #[error("Device profile `{name}` is not configured in `[package.metadata.android.devices]`, configured are {configured:?}")]
+    UnknownDeviceProfile {
+        name: String,
+        configured: Vec<String>,
+    },
+    #[error("`{key}` must be a valid file name without path separators, got {name:?}")]
+    InvalidFileName { key: &'static str, name: String },
+    #[error("No device found, connect one or start an emulator (see `adb devices`)")]
+    NoDevice,
+    #[error("No device found. Inside WSL, devices attached to Windows are only visible through the adb server on the Windows host: run `adb kill-server` and `adb -a nodaemon server start` on Windows, then set `ADB_SERVER_SOCKET=tcp:<windows host ip>:5037`")]
+    NoDeviceInWsl,
     #[error("`workspace=false` is unsupported")]
     InheritedFalse,
     #[error("`workspace=true` requires a workspace")]
     InheritanceMissingWorkspace,
     #[error("Failed to inherit field: `workspace.{0}` was not defined in workspace root manifest")]
     WorkspaceMissingInheritedField(&'static str),
+    #[error("Failed to watch for changes: {0}")]
+    Watch(#[from] notify::Error),
+    #[error("Stopped receiving changes of the watched files")]
+    WatcherStopped,
 }
 
 impl Error {
@@ -0,0 +1,830 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env::VarError;
+use std::path::{Path, PathBuf};
+
+use cargo_subcommand::Profile;
+use ndk_build::apk::KeyRotation;
+use ndk_build::error::NdkError;
+use ndk_build::ndk::{KeystoreMeta, Ndk};
+use ndk_build::secret::Secret;
+use sha2::{Digest, Sha256};
+
+use crate::apk::profile_name;
+use crate::env::Env;
+use crate::error::Error;
+use crate::manifest::{Manifest, Signing};
+
+/// Replaces the `[package.metadata.android.signing.<profile>]` tables of `manifest` by the
+/// profiles of the signing config file, see [`Env::signing_config()`].
+pub(crate) fn apply_signing_config(manifest: &mut Manifest, env: &Env) -> Result<(), Error> {
+    let path = match env.signing_config_path() {
+        Some(path) => path.to_owned(),
+        None => match env_var(env, "CARGO_ANDROID_SIGNING_CONFIG")? {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(()),
+        },
+    };
+    for (profile, signing) in read_signing_config(&path)? {
+        log::debug!("Using the `{profile}` keystore from `{}`", path.display());
+        manifest.signing.insert(profile, signing);
+    }
+    Ok(())
+}
+
+/// The [`Signing`] per profile in the signing config file at `path`, with their paths
+/// resolved relative to the file.
+fn read_signing_config(path: &Path) -> Result<HashMap<String, Signing>, Error> {
+    let path = dunce::canonicalize(path).map_err(|e| NdkError::IoPathError(path.to_owned(), e))?;
+    let contents =
+        std::fs::read_to_string(&path).map_err(|e| NdkError::IoPathError(path.clone(), e))?;
+    let profiles: Result<HashMap<String, Signing>, String> = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&contents).map_err(|e| e.to_string())
+    };
+    let mut profiles = profiles.map_err(|message| Error::SigningConfig {
+        path: path.clone(),
+        message,
+    })?;
+
+    let dir = path.parent().expect("file has a parent directory");
+    for signing in profiles.values_mut() {
+        signing.store_path = dir.join(&signing.store_path);
+        if let Some(rotation) = &mut signing.rotation {
+            rotation.store_path = dir.join(&rotation.store_path);
+            rotation.lineage = dir.join(&rotation.lineage);
+        }
+    }
+    Ok(profiles)
+}
+
+/// Resolves the keystore used to sign artifacts built with `profile`.
+///
+/// `CARGO_ANDROID_<PROFILE>_*` environment variables take precedence over the signing config
+/// file of [`Env::signing_config()`], which takes precedence over the
+/// `[package.metadata.android.signing.<profile>]` table. When neither is present, the
+/// [`debug_keystore_path()`] is used (and generated) if `is_debug_profile` is set.
+pub(crate) fn read_keystore_meta(
+    ndk: &Ndk,
+    manifest: &Manifest,
+    profile: &Profile,
+    crate_path: &Path,
+    is_debug_profile: bool,
+    env: &Env,
+) -> Result<KeystoreMeta, Error> {
+    let profile_name = profile_name(profile);
+
+    if let Some(signing_key) = keystore_from_env(env, profile_name, is_debug_profile)? {
+        validate_keystore(ndk, &signing_key, env)?;
+        return Ok(signing_key);
+    }
+
+    let env_profile_name = env_profile_name(profile_name);
+
+    if let Some(signing) = manifest.signing.get(profile_name) {
+        log::debug!("Using keystore from `[package.metadata.android.signing.{profile_name}]`");
+        let store_path = crate_path.join(&signing.store_path);
+        let signing_key = KeystoreMeta::single(store_path, signing.store_password.clone());
+
+        let signing_key = match (&signing.key_alias, &signing.key_password) {
+            (Some(key_alias), Some(key_password)) => signing_key
+                .alias(key_alias.clone())
+                .key_pass(key_password.clone()),
+            (Some(key_alias), None) => {
+                eprintln!("`{key_alias}` was specified via `[package.metadata.android.signing.{profile_name}]`, but `key_password` was not specified");
+                return Err(Error::MissingReleaseKey(env_profile_name));
+            }
+            (None, _) => signing_key,
+        };
+        validate_keystore(ndk, &signing_key, env)?;
+        return Ok(signing_key);
+    }
+
+    if is_debug_profile {
+        let path = debug_keystore_path(ndk, env)?;
+        log::debug!("Using the debug keystore `{}`", path.display());
+        Ok(ndk.debug_key_at(&path)?)
+    } else {
+        Err(Error::MissingReleaseKey(env_profile_name))
+    }
+}
+
+/// Resolves the `[package.metadata.android.signing.<profile>.rotation]` table, if any, used
+/// to sign APKs with both the previous and the current key.
+pub(crate) fn read_key_rotation(
+    ndk: &Ndk,
+    manifest: &Manifest,
+    profile: &Profile,
+    crate_path: &Path,
+    env: &Env,
+) -> Result<Option<KeyRotation>, Error> {
+    let profile_name = profile_name(profile);
+    let Some(rotation) = manifest
+        .signing
+        .get(profile_name)
+        .and_then(|signing| signing.rotation.as_ref())
+    else {
+        return Ok(None);
+    };
+    let env_store_path = format!(
+        "CARGO_ANDROID_{}_STORE_PATH",
+        env_profile_name(profile_name)
+    );
+    if env.var_os(&env_store_path).is_some() {
+        eprintln!("Warning: rotating from the key in `[package.metadata.android.signing.{profile_name}.rotation]` to the keystore from the environment");
+    }
+
+    let mut previous = KeystoreMeta::single(
+        crate_path.join(&rotation.store_path),
+        rotation.store_password.clone(),
+    );
+    previous.alias = rotation.key_alias.clone();
+    previous.key_pass = rotation.key_password.clone();
+    validate_keystore(ndk, &previous, env)?;
+
+    let lineage = crate_path.join(&rotation.lineage);
+    if !lineage.is_file() {
+        return Err(Error::LineageNotFound(lineage));
+    }
+    Ok(Some(KeyRotation { previous, lineage }))
+}
+
+/// The `jarsigner -sigalg` and `-digestalg` for signing with `key`, as configured in
+/// `[package.metadata.android.signing.<profile>]`, or otherwise derived from the algorithm of
+/// the key as reported by `keytool`.
+///
+/// `None` leaves the choice to `jarsigner`, when the key algorithm could not be determined.
+pub(crate) fn jarsigner_algorithms(
+    ndk: &Ndk,
+    manifest: &Manifest,
+    profile: &Profile,
+    key: &KeystoreMeta,
+) -> (Option<String>, Option<String>) {
+    let signing = manifest.signing.get(profile_name(profile));
+    let configured_sig_alg = signing.and_then(|signing| signing.sig_alg.clone());
+    let configured_digest_alg = signing.and_then(|signing| signing.digest_alg.clone());
+    let sig_alg = configured_sig_alg.or_else(|| {
+        let sig_alg = jarsigner_sig_alg(&key_algorithm(ndk, key)?)?;
+        log::debug!("Using `{sig_alg}` to sign with `{}`", key.path.display());
+        Some(sig_alg.to_owned())
+    });
+    let digest_alg =
+        configured_digest_alg.or_else(|| sig_alg.is_some().then(|| "SHA-256".to_owned()));
+    (sig_alg, digest_alg)
+}
+
+/// The `jarsigner -sigalg` for a key of `algorithm`.
+fn jarsigner_sig_alg(algorithm: &str) -> Option<&'static str> {
+    match algorithm {
+        "RSA" => Some("SHA256withRSA"),
+        "EC" => Some("SHA256withECDSA"),
+        "DSA" => Some("SHA256withDSA"),
+        _ => None,
+    }
+}
+
+/// The public key algorithm of `key`, e.g. `RSA` or `EC`, from `keytool -list -v`.
+fn key_algorithm(ndk: &Ndk, key: &KeystoreMeta) -> Option<String> {
+    let mut keytool = ndk.keytool().ok()?;
+    keytool
+        .arg("-list")
+        .arg("-v")
+        .arg("-keystore")
+        .arg(&key.path)
+        .arg("-storepass")
+        .arg(key.store_pass.expose());
+    if let Some(alias) = &key.alias {
+        keytool.arg("-alias").arg(alias);
+    }
+    let output = keytool.stdin(std::process::Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_key_algorithm(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Extracts the algorithm from a line like `Subject Public Key Algorithm: 256-bit EC
+/// (secp256r1) key` in the output of `keytool -list -v`.
+fn parse_key_algorithm(keytool_output: &str) -> Option<String> {
+    let description = keytool_output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Subject Public Key Algorithm:"))?;
+    description
+        .split_whitespace()
+        .find(|word| !word.ends_with("-bit"))
+        .map(str::to_owned)
+}
+
+/// Pins a project-specific debug keystore instead of the `debug.keystore` that every tool
+/// shares in the Android user home.
+const DEBUG_KEYSTORE_ENV: &str = "CARGO_ANDROID_DEBUG_KEYSTORE";
+
+/// The debug keystore that `dev` builds are signed with when no other keystore is configured:
+/// `CARGO_ANDROID_DEBUG_KEYSTORE`, or the `debug.keystore` from the Android user home.
+pub(crate) fn debug_keystore_path(ndk: &Ndk, env: &Env) -> Result<PathBuf, Error> {
+    match env.var_os(DEBUG_KEYSTORE_ENV) {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => Ok(ndk.android_user_home()?.join("debug.keystore")),
+    }
+}
+
+/// Whether `key` is the [`debug_keystore_path()`] or the `debug.keystore` from the Android
+/// user home, without generating it like [`Ndk::debug_key()`] does.
+pub(crate) fn is_debug_key(ndk: &Ndk, key: &KeystoreMeta, env: &Env) -> Result<bool, Error> {
+    let canonicalize = |path: &Path| dunce::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let key_path = canonicalize(&key.path);
+    Ok([
+        debug_keystore_path(ndk, env)?,
+        ndk.android_user_home()?.join("debug.keystore"),
+    ]
+    .iter()
+    .any(|debug_key| key_path == canonicalize(debug_key)))
+}
+
+/// The SHA-256 of the contents of the keystore at `path`, which changes when the keystore is
+/// regenerated or replaced, and is stable across Rust versions unlike [`std::hash::Hash`].
+pub(crate) fn keystore_fingerprint(path: &Path) -> Result<String, Error> {
+    let contents = std::fs::read(path).map_err(|e| NdkError::IoPathError(path.to_owned(), e))?;
+    Ok(format!("{:x}", Sha256::digest(contents)))
+}
+
+/// The [`keystore_fingerprint()`]s of the debug keystores that packages were last installed
+/// with, by device serial and package name, stored in the build directory so that a changed
+/// debug keystore can be told apart from other reasons for a failing install.
+pub(crate) struct InstalledDebugKeys {
+    path: PathBuf,
+    fingerprints: BTreeMap<String, String>,
+}
+
+impl InstalledDebugKeys {
+    /// Reads the fingerprints from `path`, starting over when it is missing or unreadable.
+    pub fn load(path: PathBuf) -> Self {
+        let fingerprints = std::fs::read(&path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default();
+        Self { path, fingerprints }
+    }
+
+    /// Whether `package` was last installed on `serial` with a debug keystore other than the
+    /// one with `fingerprint`.
+    pub fn changed(&self, serial: &str, package: &str, fingerprint: &str) -> bool {
+        self.fingerprints
+            .get(&format!("{serial}/{package}"))
+            .is_some_and(|installed| installed != fingerprint)
+    }
+
+    /// Records that `package` was installed on `serial` with the debug keystore with
+    /// `fingerprint`.
+    pub fn record(
+        &mut self,
+        serial: &str,
+        package: &str,
+        fingerprint: String,
+    ) -> Result<(), Error> {
+        self.fingerprints
+            .insert(format!("{serial}/{package}"), fingerprint);
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_vec_pretty(&self.fingerprints).map_err(std::io::Error::from)?;
+        std::fs::write(&self.path, json)?;
+        Ok(())
+    }
+}
+
+/// Guards against distributing artifacts of a non-`dev` profile that are signed with the
+/// debug keystore (e.g. because `CARGO_ANDROID_<PROFILE>_STORE_PATH` points at it) or that
+/// are `debuggable`. Warns either way, but only proceeds when `allow` is set.
+pub(crate) fn check_release_signing(
+    profile: &Profile,
+    is_debug_key: bool,
+    debuggable: bool,
+    allow: bool,
+) -> Result<(), Error> {
+    if *profile == Profile::Dev {
+        return Ok(());
+    }
+    let reasons = [
+        (is_debug_key, "signed with the debug keystore"),
+        (debuggable, "`debuggable`"),
+    ]
+    .into_iter()
+    .filter_map(|(applies, reason)| applies.then_some(reason))
+    .collect::<Vec<_>>();
+    if reasons.is_empty() {
+        return Ok(());
+    }
+
+    let profile_name = profile_name(profile);
+    for reason in &reasons {
+        eprintln!("Warning: the `{profile_name}` build is {reason}, it must not be distributed!");
+    }
+    if allow {
+        return Ok(());
+    }
+    Err(Error::DebugRelease {
+        profile: profile_name.to_owned(),
+        reasons: reasons.join(" and "),
+    })
+}
+
+/// Verifies that the keystore can be opened with its password and contains the configured
+/// key alias, so that a misconfigured key fails the build before compiling instead of only
+/// when signing the final artifact.
+///
+/// The default debug keystore is generated on demand and not validated, nor is any keystore
+/// beyond its existence when disabled with [`Env::keystore_check()`].
+fn validate_keystore(ndk: &Ndk, key: &KeystoreMeta, env: &Env) -> Result<(), Error> {
+    if !key.path.is_file() {
+        return Err(Error::KeystoreNotFound(key.path.clone()));
+    }
+    if !env.checks_keystore() {
+        return Ok(());
+    }
+
+    let mut keytool = match ndk.keytool() {
+        Ok(keytool) => keytool,
+        Err(_) => {
+            eprintln!(
+                "Warning: `keytool` not found, skipping validation of keystore `{}`",
+                key.path.display()
+            );
+            return Ok(());
+        }
+    };
+    keytool
+        .arg("-list")
+        .arg("-keystore")
+        .arg(&key.path)
+        .arg("-storepass")
+        .arg(key.store_pass.expose());
+    if let Some(alias) = &key.alias {
+        keytool.arg("-alias").arg(alias);
+    }
+    let output = keytool.stdin(std::process::Stdio::null()).output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    // keytool reports errors on stdout
+    let message = String::from_utf8_lossy(&output.stdout) + String::from_utf8_lossy(&output.stderr);
+    if message.contains("password was incorrect") {
+        Err(Error::KeystoreWrongPassword(key.path.clone()))
+    } else if let Some(alias) = key
+        .alias
+        .as_ref()
+        .filter(|_| message.contains("does not exist"))
+    {
+        Err(Error::KeyAliasNotFound {
+            alias: alias.clone(),
+            keystore: key.path.clone(),
+            available: keystore_aliases(ndk, key),
+        })
+    } else {
+        Err(Error::KeystoreUnreadable(
+            key.path.clone(),
+            message.trim().to_owned(),
+        ))
+    }
+}
+
+/// The aliases in the keystore of `key`, empty when `keytool` can't list them.
+fn keystore_aliases(ndk: &Ndk, key: &KeystoreMeta) -> Vec<String> {
+    let Ok(mut keytool) = ndk.keytool() else {
+        return Vec::new();
+    };
+    keytool
+        .arg("-list")
+        .arg("-v")
+        .arg("-keystore")
+        .arg(&key.path)
+        .arg("-storepass")
+        .arg(key.store_pass.expose())
+        .stdin(std::process::Stdio::null());
+    match keytool.output() {
+        Ok(output) if output.status.success() => {
+            parse_aliases(&String::from_utf8_lossy(&output.stdout))
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The `Alias name:` entries of `keytool -list -v` output.
+fn parse_aliases(keytool_output: &str) -> Vec<String> {
+    keytool_output
+        .lines()
+        .filter_map(|line| line.strip_prefix("Alias name:"))
+        .map(|alias| alias.trim().to_owned())
+        .collect()
+}
+
+/// The profile portion of the `CARGO_ANDROID_<PROFILE>_*` environment variables.
+fn env_profile_name(profile_name: &str) -> String {
+    profile_name.to_uppercase().replace('-', "_")
+}
+
+/// Resolves the keystore from the `CARGO_ANDROID_<PROFILE>_*` environment variables, if
+/// `CARGO_ANDROID_<PROFILE>_STORE_PATH` is set.
+///
+/// Empty values are accepted as deliberate (e.g. an empty password), whereas values that
+/// are not valid unicode are rejected instead of being treated as unset.
+fn keystore_from_env(
+    env: &Env,
+    profile_name: &str,
+    is_debug_profile: bool,
+) -> Result<Option<KeystoreMeta>, Error> {
+    let profile_name = env_profile_name(profile_name);
+
+    let env_store_path = format!("CARGO_ANDROID_{profile_name}_STORE_PATH");
+    let env_store_password = format!("CARGO_ANDROID_{profile_name}_STORE_PASSWORD");
+    let env_key_alias = format!("CARGO_ANDROID_{profile_name}_KEY_ALIAS");
+    let env_key_password = format!("CARGO_ANDROID_{profile_name}_KEY_PASSWORD");
+
+    let Some(store_path) = env.var_os(&env_store_path).map(PathBuf::from) else {
+        log::debug!("`{env_store_path}` not set, not reading the keystore from the environment");
+        return Ok(None);
+    };
+    log::debug!("Using keystore path from `{env_store_path}`");
+
+    let signing_key = match secret_var(env, &env_store_password)? {
+        Some(store_password) => KeystoreMeta::single(store_path, store_password),
+        None if is_debug_profile => {
+            println!("{env_store_password} not specified, falling back to default password");
+            KeystoreMeta::single(
+                store_path,
+                ndk_build::ndk::DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned(),
+            )
+        }
+        None => {
+            eprintln!("`{}` was specified via `{env_store_path}`, but `{env_store_password}` was not specified, both or neither must be present for profiles other than `dev`", store_path.to_string_lossy());
+            return Err(Error::MissingReleaseKey(profile_name));
+        }
+    };
+
+    match env_var(env, &env_key_alias)? {
+        Some(key_alias) => match secret_var(env, &env_key_password)? {
+            Some(key_password) => Ok(Some(signing_key.alias(key_alias).key_pass(key_password))),
+            None => {
+                eprintln!("`{key_alias}` was specified via `{env_key_alias}`, but `{env_key_password}` was not specified");
+                Err(Error::MissingReleaseKey(profile_name))
+            }
+        },
+        None => Ok(Some(signing_key)),
+    }
+}
+
+/// Reads the password `name` from the file descriptor given by the `<name>_FD` environment
+/// variable, which keeps it out of the environment, or else from `name` itself.
+///
+/// A single trailing newline is stripped from secrets read by file descriptor, as written by
+/// `echo`. Descriptors 0 to 2 are rejected.
+fn secret_var(env: &Env, name: &str) -> Result<Option<Secret>, Error> {
+    let fd_name = format!("{name}_FD");
+    let Some(value) = env_var(env, &fd_name)? else {
+        return Ok(env_var(env, name)?.map(Secret::from));
+    };
+    let fd = value
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .filter(|fd| *fd > 2)
+        .ok_or_else(|| Error::InvalidSecretFd(fd_name.clone(), value))?;
+    if env.var_os(name).is_some() {
+        log::debug!("Both `{fd_name}` and `{name}` are set, using `{fd_name}`");
+    }
+
+    let secret = env.fd_secret(&fd_name, || {
+        let mut secret = read_fd(fd).map_err(|source| Error::SecretFd {
+            var: fd_name.clone(),
+            fd,
+            source,
+        })?;
+        if secret.ends_with('\n') {
+            secret.pop();
+            if secret.ends_with('\r') {
+                secret.pop();
+            }
+        }
+        log::debug!("Read `{name}` from file descriptor {fd}");
+        Ok(Secret::from(secret))
+    })?;
+    Ok(Some(secret))
+}
+
+/// Reads the file descriptor `fd` to its end through `/dev/fd`, which fails when it isn't open
+/// and leaves it open for its owner to close.
+#[cfg(unix)]
+fn read_fd(fd: i32) -> std::io::Result<String> {
+    std::fs::read_to_string(format!("/dev/fd/{fd}"))
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: i32) -> std::io::Result<String> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Reads the environment variable `name`, distinguishing an unset variable (`None`) from one
+/// that is set to a value which is not valid unicode (an error).
+fn env_var(env: &Env, name: &str) -> Result<Option<String>, Error> {
+    match env.var(name) {
+        Ok(value) => {
+            log::debug!("Read `{name}` from the environment");
+            Ok(Some(value))
+        }
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(Error::EnvVarNotUnicode(name.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndk_build::secret::Secret;
+    use std::ffi::OsString;
+
+    fn set_env(name: &str, value: impl Into<OsString>) {
+        std::env::set_var(name, value.into());
+    }
+
+    // Every test uses its own profile name, and thus its own set of environment variables,
+    // as tests run concurrently within the same process.
+
+    #[test]
+    fn unset_store_path() {
+        assert!(keystore_from_env(&Env::default(), "env-unset", false)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn hermetic_env() {
+        set_env("CARGO_ANDROID_ENV_HERMETIC_STORE_PATH", "process.keystore");
+        let env = Env::hermetic(HashMap::from([(
+            "CARGO_ANDROID_ENV_HERMETIC_STORE_PASSWORD".to_owned(),
+            "s3cret".to_owned(),
+        )]));
+        assert!(keystore_from_env(&env, "env-hermetic", false)
+            .unwrap()
+            .is_none());
+
+        let env = Env::with_overrides(HashMap::from([(
+            "CARGO_ANDROID_ENV_HERMETIC_STORE_PASSWORD".to_owned(),
+            "s3cret".to_owned(),
+        )]));
+        let key = keystore_from_env(&env, "env-hermetic", false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.path, Path::new("process.keystore"));
+        assert_eq!(key.store_pass.expose(), "s3cret");
+    }
+
+    #[test]
+    fn empty_password() {
+        set_env("CARGO_ANDROID_ENV_EMPTY_STORE_PATH", "release.keystore");
+        set_env("CARGO_ANDROID_ENV_EMPTY_STORE_PASSWORD", "");
+        let key = keystore_from_env(&Env::default(), "env-empty", false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.path, Path::new("release.keystore"));
+        assert_eq!(key.store_pass.expose(), "");
+        assert!(key.alias.is_none());
+    }
+
+    #[test]
+    fn missing_password() {
+        set_env("CARGO_ANDROID_ENV_MISSING_STORE_PATH", "release.keystore");
+        assert!(matches!(
+            keystore_from_env(&Env::default(), "env-missing", false),
+            Err(Error::MissingReleaseKey(profile)) if profile == "ENV_MISSING"
+        ));
+        let key = keystore_from_env(&Env::default(), "env-missing", true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            key.store_pass.expose(),
+            ndk_build::ndk::DEFAULT_DEV_KEYSTORE_PASSWORD
+        );
+    }
+
+    #[test]
+    fn alias_requires_key_password() {
+        set_env("CARGO_ANDROID_ENV_ALIAS_STORE_PATH", "release.keystore");
+        set_env("CARGO_ANDROID_ENV_ALIAS_STORE_PASSWORD", "store");
+        set_env("CARGO_ANDROID_ENV_ALIAS_KEY_ALIAS", "upload");
+        assert!(matches!(
+            keystore_from_env(&Env::default(), "env-alias", false),
+            Err(Error::MissingReleaseKey(_))
+        ));
+        set_env("CARGO_ANDROID_ENV_ALIAS_KEY_PASSWORD", "");
+        let key = keystore_from_env(&Env::default(), "env-alias", false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.alias.as_deref(), Some("upload"));
+        assert_eq!(key.key_pass.as_ref().map(Secret::expose), Some(""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_unicode_password() {
+        use std::os::unix::ffi::OsStringExt;
+
+        set_env("CARGO_ANDROID_ENV_UTF8_STORE_PATH", "release.keystore");
+        set_env(
+            "CARGO_ANDROID_ENV_UTF8_STORE_PASSWORD",
+            OsString::from_vec(vec![0x66, 0x6f, 0x80]),
+        );
+        assert!(matches!(
+            keystore_from_env(&Env::default(), "env-utf8", false),
+            Err(Error::EnvVarNotUnicode(name)) if name == "CARGO_ANDROID_ENV_UTF8_STORE_PASSWORD"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn password_from_fd() {
+        use std::os::fd::AsRawFd;
+
+        let dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("password_fd");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("secret");
+        std::fs::write(&path, "from-fd\n").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let fd = file.as_raw_fd();
+
+        set_env("CARGO_ANDROID_ENV_FD_STORE_PATH", "release.keystore");
+        set_env("CARGO_ANDROID_ENV_FD_STORE_PASSWORD", "from-env");
+        let key = keystore_from_env(&Env::default(), "env-fd", false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.store_pass.expose(), "from-env");
+
+        set_env("CARGO_ANDROID_ENV_FD_STORE_PASSWORD_FD", fd.to_string());
+        let env = Env::default();
+        let key = keystore_from_env(&env, "env-fd", false).unwrap().unwrap();
+        assert_eq!(key.store_pass.expose(), "from-fd");
+        // Left open for its owner, and remembered by the environment rather than read again
+        std::fs::write(&path, "changed\n").unwrap();
+        let key = keystore_from_env(&env, "env-fd", false).unwrap().unwrap();
+        assert_eq!(key.store_pass.expose(), "from-fd");
+        drop(file);
+        assert!(matches!(
+            keystore_from_env(&Env::default(), "env-fd", false),
+            Err(Error::SecretFd { fd: closed, .. }) if closed == fd
+        ));
+
+        set_env("CARGO_ANDROID_ENV_FD_STORE_PASSWORD_FD", "1");
+        assert!(matches!(
+            keystore_from_env(&Env::default(), "env-fd", false),
+            Err(Error::InvalidSecretFd(name, value))
+                if name == "CARGO_ANDROID_ENV_FD_STORE_PASSWORD_FD" && value == "1"
+        ));
+    }
+
+    #[test]
+    fn key_algorithm_from_keytool() {
+        let ec = "Alias name: upload\nEntry type: PrivateKeyEntry\n\
+            Signature algorithm name: SHA256withECDSA\n\
+            Subject Public Key Algorithm: 256-bit EC (secp256r1) key\nVersion: 3\n";
+        let algorithm = parse_key_algorithm(ec).unwrap();
+        assert_eq!(algorithm, "EC");
+        assert_eq!(jarsigner_sig_alg(&algorithm), Some("SHA256withECDSA"));
+
+        let rsa = "Subject Public Key Algorithm: 2048-bit RSA key\n";
+        assert_eq!(parse_key_algorithm(rsa).as_deref(), Some("RSA"));
+        assert_eq!(parse_key_algorithm("Keystore type: PKCS12\n"), None);
+    }
+
+    #[test]
+    fn aliases_from_keytool() {
+        let output = "Keystore type: PKCS12\nKeystore provider: SUN\n\n\
+            Your keystore contains 2 entries\n\n\
+            Alias name: upload\nEntry type: PrivateKeyEntry\n\n\
+            Alias name: release key\nEntry type: PrivateKeyEntry\n";
+        assert_eq!(parse_aliases(output), ["upload", "release key"]);
+        assert!(parse_aliases("Your keystore contains 0 entries\n").is_empty());
+    }
+
+    #[test]
+    fn signing_config_file() {
+        let dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("signing-config");
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml = dir.join("signing.toml");
+        std::fs::write(
+            &toml,
+            "[release]\nstore_path = \"keys/release.jks\"\nstore_password = \"store\"\n\
+            key_alias = \"upload\"\nkey_password = \"key\"\n",
+        )
+        .unwrap();
+        let json = dir.join("signing.json");
+        std::fs::write(
+            &json,
+            r#"{"beta": {"store_path": "/keys/beta.jks", "store_password": "beta"}}"#,
+        )
+        .unwrap();
+
+        let profiles = read_signing_config(&toml).unwrap();
+        let release = &profiles["release"];
+        let dir = dunce::canonicalize(&dir).unwrap();
+        assert_eq!(release.store_path, dir.join("keys/release.jks"));
+        assert_eq!(release.store_password.expose(), "store");
+        assert_eq!(release.key_alias.as_deref(), Some("upload"));
+
+        let profiles = read_signing_config(&json).unwrap();
+        assert_eq!(profiles["beta"].store_path, Path::new("/keys/beta.jks"));
+        assert!(profiles["beta"].key_alias.is_none());
+
+        // `Env::signing_config()` takes precedence over `CARGO_ANDROID_SIGNING_CONFIG`
+        let cargo_toml = dir.join("Cargo.toml");
+        std::fs::write(
+            &cargo_toml,
+            "[package]\nname = \"signing\"\nversion = \"1.0.0\"\n",
+        )
+        .unwrap();
+        let env = Env::hermetic(HashMap::from([(
+            "CARGO_ANDROID_SIGNING_CONFIG".to_owned(),
+            json.to_string_lossy().into_owned(),
+        )]));
+        let mut manifest = Manifest::parse_from_toml(&cargo_toml).unwrap();
+        apply_signing_config(&mut manifest, &env).unwrap();
+        assert!(manifest.signing.contains_key("beta"));
+        let mut manifest = Manifest::parse_from_toml(&cargo_toml).unwrap();
+        apply_signing_config(&mut manifest, &env.signing_config(Some(toml))).unwrap();
+        assert!(manifest.signing.contains_key("release"));
+        assert!(!manifest.signing.contains_key("beta"));
+
+        std::fs::write(&json, r#"{"beta": {"store_password": "beta"}}"#).unwrap();
+        assert!(matches!(
+            read_signing_config(&json),
+            Err(Error::SigningConfig { message, .. }) if message.contains("store_path")
+        ));
+    }
+
+    #[test]
+    fn release_signing_guard() {
+        let release = Profile::Release;
+        let custom = Profile::Custom("staging".to_owned());
+        for (is_debug_key, debuggable) in
+            [(false, false), (true, false), (false, true), (true, true)]
+        {
+            let flagged = is_debug_key || debuggable;
+            // `dev` builds are meant for debugging
+            assert!(check_release_signing(&Profile::Dev, is_debug_key, debuggable, false).is_ok());
+            for profile in [&release, &custom] {
+                let result = check_release_signing(profile, is_debug_key, debuggable, false);
+                assert_eq!(
+                    result.is_err(),
+                    flagged,
+                    "{profile:?} {is_debug_key} {debuggable}"
+                );
+                assert!(check_release_signing(profile, is_debug_key, debuggable, true).is_ok());
+            }
+        }
+
+        assert!(matches!(
+            check_release_signing(&release, true, true, false),
+            Err(Error::DebugRelease { profile, reasons })
+                if profile == "release" && reasons == "signed with the debug keystore and `debuggable`"
+        ));
+    }
+
+    #[test]
+    fn installed_debug_key_changes() {
+        let dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("debug-keys");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let keystore = dir.join("debug.keystore");
+        std::fs::write(&keystore, "first").unwrap();
+        let first = keystore_fingerprint(&keystore).unwrap();
+        assert_eq!(
+            first,
+            "a7937b64b8caa58f03721bb6bacf5c78cb235febe0e70b1b84cd99541461a08e"
+        );
+        std::fs::write(&keystore, "regenerated").unwrap();
+        let regenerated = keystore_fingerprint(&keystore).unwrap();
+        assert_ne!(first, regenerated);
+
+        let state = dir.join("apk").join("debug-keys.json");
+        let mut installed = InstalledDebugKeys::load(state.clone());
+        // Nothing to compare against yet
+        assert!(!installed.changed("emulator-5554", "com.example", &first));
+        installed
+            .record("emulator-5554", "com.example", first.clone())
+            .unwrap();
+
+        let installed = InstalledDebugKeys::load(state);
+        assert!(!installed.changed("emulator-5554", "com.example", &first));
+        assert!(installed.changed("emulator-5554", "com.example", &regenerated));
+        assert!(!installed.changed("emulator-5556", "com.example", &regenerated));
+        assert!(!installed.changed("emulator-5554", "com.example.other", &regenerated));
+    }
+}
@@ -1,8 +1,32 @@
 mod aab;
 mod apk;
+mod artifact;
+mod collisions;
+mod diagnostics;
+mod env;
 mod error;
+mod keystore;
+pub mod lint;
 mod manifest;
+pub mod manifest_diff;
+mod network_security;
+mod serve;
+mod splash;
+#[cfg(all(test, unix))]
+mod test_support;
+mod watch;
 
 pub use aab::AabBuilder;
-pub use apk::ApkBuilder;
+pub use apk::{
+    device_abi, is_following_logcat, ApkBuilder, BuiltApk, CargoFlags, LaunchInfo, TargetEnv,
+    WatchOptions,
+};
+pub use artifact::ArtifactSelector;
+pub use env::Env;
 pub use error::Error;
+pub use manifest::{
+    compute_version_code, compute_version_name, CertificatePins, DeviceProfile, Inheritable,
+    IntentExtra, Manifest, NetworkSecurity, Root, RunConfig, Signing, SigningRotation, Splash, Strip,
+    VersionCodeAbiScheme,
+};
+pub use serve::{serve, ServeOptions};
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ndk_build::manifest::{AndroidManifest, IntentFilter};
+use serde::{Deserialize, Serialize};
+
+/// Configuration of the manifest lint, read from `[package.metadata.android.lint]`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LintConfig {
+    /// Lowest `minSdkVersion` that is accepted by the `MinSdkTooLow` rule
+    pub min_sdk_floor: Option<u32>,
+    /// Overrides the severity of individual rules by their id, e.g. `ExportedActivity = "ignore"`
+    #[serde(default)]
+    pub rules: HashMap<String, RuleSeverity>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleSeverity {
+    Ignore,
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Warning => f.write_str("warning"),
+            Self::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// A single issue found in the manifest. The `id`s follow the naming of the corresponding
+/// Android lint checks where one exists.
+#[derive(Clone, Debug, Serialize)]
+pub struct Finding {
+    pub id: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} [{}]", self.severity, self.message, self.id)
+    }
+}
+
+/// Checks `manifest` against the built-in rule set. `is_release` is set for builds of any
+/// profile other than `dev`.
+pub fn lint_manifest(
+    manifest: &AndroidManifest,
+    is_release: bool,
+    config: &LintConfig,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut report = |id: &'static str, default: Severity, message: String| {
+        let severity = match config.rules.get(id) {
+            Some(RuleSeverity::Ignore) => return,
+            Some(RuleSeverity::Warning) => Severity::Warning,
+            Some(RuleSeverity::Error) => Severity::Error,
+            None => default,
+        };
+        findings.push(Finding {
+            id,
+            severity,
+            message,
+        });
+    };
+
+    let application = &manifest.application;
+    let activities = std::iter::once((
+        "Activity",
+        &application.activity.name,
+        application.activity.exported,
+        &application.activity.permission,
+        &application.activity.intent_filter,
+    ))
+    .chain(application.activity_aliases.iter().map(|alias| {
+        (
+            "Activity alias",
+            &alias.name,
+            alias.exported,
+            &alias.permission,
+            &alias.intent_filter,
+        )
+    }));
+    for (kind, name, exported, permission, intent_filter) in activities {
+        if exported == Some(true) && permission.is_none() && !is_launcher(intent_filter) {
+            report(
+                "ExportedActivity",
+                Severity::Warning,
+                format!("{kind} `{name}` is exported without requiring a permission"),
+            );
+        }
+    }
+    for provider in &application.provider {
+        // `<provider>` has no `permission` attribute in `AndroidManifest`, any export is open
+        if provider.exported == Some(true) {
+            report(
+                "ExportedContentProvider",
+                Severity::Warning,
+                format!(
+                    "Content provider `{}` is exported without requiring a permission",
+                    provider.name
+                ),
+            );
+        }
+    }
+
+    if is_release && application.debuggable == Some(true) {
+        report(
+            "HardcodedDebugMode",
+            Severity::Error,
+            "Release build is marked `debuggable`".to_string(),
+        );
+    }
+
+    if application.uses_cleartext_traffic == Some(true)
+        && application.network_security_config.is_none()
+    {
+        report(
+            "UsesCleartextTraffic",
+            Severity::Warning,
+            "Cleartext traffic is allowed for all domains without a `network_security_config`"
+                .to_string(),
+        );
+    }
+
+    if let (Some(floor), Some(min_sdk_version)) =
+        (config.min_sdk_floor, manifest.sdk.min_sdk_version)
+    {
+        if min_sdk_version < floor {
+            report(
+                "MinSdkTooLow",
+                Severity::Error,
+                format!("`min_sdk_version` {min_sdk_version} is below the required {floor}"),
+            );
+        }
+    }
+
+    findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+    findings
+}
+
+/// Whether `intent_filter` makes the component an entry point of the app, which has to be
+/// exported.
+fn is_launcher(intent_filter: &[IntentFilter]) -> bool {
+    intent_filter
+        .iter()
+        .any(|i| i.actions.iter().any(|a| a == "android.intent.action.MAIN"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndk_build::manifest::{ActivityAlias, Provider};
+
+    #[test]
+    fn release_rules() {
+        let mut manifest = AndroidManifest::default();
+        manifest.application.debuggable = Some(true);
+        manifest.application.uses_cleartext_traffic = Some(true);
+        manifest.sdk.min_sdk_version = Some(21);
+
+        let mut config = LintConfig {
+            min_sdk_floor: Some(24),
+            ..Default::default()
+        };
+        assert_eq!(lint_manifest(&manifest, false, &config).len(), 2);
+
+        let findings = lint_manifest(&manifest, true, &config);
+        let ids = findings.iter().map(|f| f.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            ["HardcodedDebugMode", "MinSdkTooLow", "UsesCleartextTraffic"]
+        );
+
+        config
+            .rules
+            .insert("HardcodedDebugMode".to_string(), RuleSeverity::Ignore);
+        config
+            .rules
+            .insert("UsesCleartextTraffic".to_string(), RuleSeverity::Error);
+        let findings = lint_manifest(&manifest, true, &config);
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|f| f.severity == Severity::Error));
+    }
+
+    #[test]
+    fn exported_activity() {
+        let mut manifest = AndroidManifest::default();
+        manifest.application.activity.exported = Some(true);
+        let findings = lint_manifest(&manifest, false, &LintConfig::default());
+        assert_eq!(findings[0].id, "ExportedActivity");
+
+        manifest.application.activity.permission = Some("com.example.PERMISSION".to_string());
+        assert!(lint_manifest(&manifest, false, &LintConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn exported_components() {
+        let mut manifest = AndroidManifest::default();
+        manifest.application.activity_aliases.push(ActivityAlias {
+            name: ".Share".to_string(),
+            exported: Some(true),
+            ..Default::default()
+        });
+        manifest.application.activity_aliases.push(ActivityAlias {
+            name: ".Launcher".to_string(),
+            exported: Some(true),
+            intent_filter: vec![IntentFilter {
+                actions: vec!["android.intent.action.MAIN".to_string()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        manifest.application.provider.push(Provider {
+            name: ".Files".to_string(),
+            exported: Some(true),
+            ..Default::default()
+        });
+        let messages = lint_manifest(&manifest, false, &LintConfig::default())
+            .into_iter()
+            .map(|f| (f.id, f.message))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                (
+                    "ExportedActivity",
+                    "Activity alias `.Share` is exported without requiring a permission"
+                        .to_string()
+                ),
+                (
+                    "ExportedContentProvider",
+                    "Content provider `.Files` is exported without requiring a permission"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -1,8 +1,16 @@
 use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use cargo_android::{AabBuilder, ApkBuilder, Error};
-use cargo_subcommand::Subcommand;
+use cargo_android::lint::{Finding, Severity};
+use cargo_android::{
+    compute_version_code, compute_version_name, device_abi, serve, AabBuilder, ApkBuilder,
+    ArtifactSelector, CargoFlags, Env, Error, Manifest, Root, ServeOptions, WatchOptions,
+};
+use cargo_subcommand::{Artifact, ArtifactType, Subcommand};
 use clap::{CommandFactory, FromArgMatches, Parser};
+use ndk_build::apk::InstallOptions;
 
 #[derive(Parser)]
 struct Cmd {
@@ -21,6 +29,11 @@ enum ApkCmd {
         #[clap(subcommand)]
         cmd: AabSubCmd,
     },
+    /// Print the `versionCode` and `versionName` that a build of the current package would produce
+    VersionCode {
+        #[clap(flatten)]
+        args: Args,
+    },
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Parser)]
@@ -31,6 +44,61 @@ struct Args {
     /// Use device with the given serial (see `adb devices`)
     #[clap(short, long)]
     device: Option<String>,
+    /// Use the serial, ABI and launch extras of `[package.metadata.android.devices.<NAME>]`
+    #[clap(long, value_name = "NAME")]
+    device_profile: Option<String>,
+    #[clap(flatten)]
+    cargo_flags: CargoFlags,
+    /// Build non-`dev` profiles even when they are signed with the debug keystore or
+    /// `debuggable`, and `serve` apks signed with the debug keystore
+    #[clap(long)]
+    allow_debug_signing: bool,
+    /// Probe the SDK and NDK again instead of using the results cached in the target directory
+    #[clap(long)]
+    no_cache: bool,
+    /// Don't strip debug symbols from the libraries, regardless of the `strip` configuration
+    #[clap(long)]
+    no_strip: bool,
+    /// Strip the debug symbols of the libraries to `.debug` files next to them in the build
+    /// directory, for symbolicating crashes later
+    #[clap(long, conflicts_with = "no_strip")]
+    strip_debug: bool,
+    /// Only repackage the apk with the `assets` when nothing else changed since the previous
+    /// build, reusing its libraries instead of running cargo
+    #[clap(long)]
+    assets_only_rebuild: bool,
+    /// Fail when aapt, aapt2 or apktool print warnings about the manifest or resources
+    #[clap(long)]
+    deny_warnings: bool,
+    /// Fail when the packaged libraries have problems, and release builds and bundles that
+    /// ship a 32-bit ABI without its 64-bit counterpart, which Google Play rejects, instead of
+    /// warning
+    #[clap(long)]
+    strict: bool,
+    /// Build without the `assets`, `resources` or `runtime_libs` directories that don't exist,
+    /// with a warning, instead of failing
+    #[clap(long)]
+    allow_missing_dirs: bool,
+    /// Package only the first of the `assets` and `resources` paths that only differ in case,
+    /// with a warning, instead of failing
+    #[clap(long)]
+    allow_case_collisions: bool,
+    /// Don't open the keystore with `keytool` before building to check its password and key
+    /// alias, e.g. for keystores on slow hardware tokens
+    #[clap(long)]
+    skip_keystore_check: bool,
+    /// JSON or TOML file with the keystore of each profile, in the shape of
+    /// `[package.metadata.android.signing.<profile>]`, instead of `CARGO_ANDROID_SIGNING_CONFIG`
+    #[clap(long, value_name = "FILE")]
+    signing_config: Option<PathBuf>,
+    /// Print the warnings of the builds of several targets once with the targets they occurred
+    /// on, instead of once per target as cargo prints them. Ignored with `--message-format`
+    #[clap(long)]
+    dedup_warnings: bool,
+    /// Print an inner cargo invocation that failed as a shell command with the environment
+    /// configured for the NDK, to reproduce the failure without cargo-android
+    #[clap(long)]
+    print_cargo_command: bool,
 }
 
 #[derive(clap::Subcommand)]
@@ -40,7 +108,95 @@ enum AabSubCmd {
     Build {
         #[clap(flatten)]
         args: Args,
+        /// Also write an APK with binary-format (non-proto) linked resources next to the aab,
+        /// for tools that cannot read the proto format
+        #[clap(long)]
+        binary_resources_apk: bool,
+        /// Keep the intermediate files of apktool, aapt2 and bundletool and print their paths,
+        /// instead of removing them after a successful build
+        #[clap(long)]
+        keep_intermediates: bool,
     },
+    /// Create an aab like `build` does and extract a universal apk from it, which went through
+    /// the aab's resource pipeline, to compare its behavior with the regular apk
+    UniversalApk {
+        #[clap(flatten)]
+        args: Args,
+        /// Keep the intermediate files and print their paths, like `aab build` does
+        #[clap(long)]
+        keep_intermediates: bool,
+    },
+    /// Create a universal apk like `universal-apk` does, and install and run it like
+    /// `apk run` runs the regular apk
+    Run {
+        #[clap(flatten)]
+        args: Args,
+        /// Keep the intermediate files and print their paths, like `aab build` does
+        #[clap(long)]
+        keep_intermediates: bool,
+        /// Do not print or follow `logcat` after running the app
+        #[clap(short, long)]
+        no_logcat: bool,
+        /// Activity to start instead of the `default_launch_activity` or the Rust activity
+        #[clap(long)]
+        activity: Option<String>,
+        #[clap(flatten)]
+        install: InstallArgs,
+        /// Print the package, UID, PID, device serial and apk path of the started app as a
+        /// single line of JSON, before following `logcat`
+        #[clap(long)]
+        print_launch_info: bool,
+        #[clap(flatten)]
+        logcat: LogcatArgs,
+    },
+}
+
+/// How `run` follows `logcat`, e.g. bounded for one-shot tests on CI.
+#[derive(clap::Args)]
+struct LogcatArgs {
+    /// Clear the log of the device before starting the app, to only print the log of this
+    /// launch
+    #[clap(long)]
+    clear_logcat: bool,
+    /// Stop following `logcat` after this many seconds and exit
+    #[clap(long, value_name = "SECS")]
+    logcat_duration: Option<u64>,
+    /// Stop following `logcat` at the first line matching this regex and exit, failing when
+    /// `logcat` ends or `--logcat-duration` passes first
+    #[clap(long, value_name = "REGEX")]
+    logcat_until: Option<regex::Regex>,
+}
+
+/// How `adb install` transfers the apk, for large apks and flaky connections such as Wi-Fi adb.
+#[derive(clap::Args)]
+struct InstallArgs {
+    /// Uninstall the app and install it again when the device refuses the update because the
+    /// installed apk is signed with a different key, removing its data
+    #[clap(long)]
+    reinstall: bool,
+    /// Stop an `adb install` attempt after this many seconds, retrying it
+    #[clap(long, value_name = "SECS")]
+    install_timeout: Option<u64>,
+    /// How often `adb install` is retried when transferring the apk failed, waiting longer
+    /// before each retry. Rejections of the apk (`INSTALL_FAILED_*`) are not retried
+    #[clap(long, value_name = "N", default_value_t = 2)]
+    install_retries: u32,
+    /// Prefer incremental installation, which makes retries cheaper and starts the app before
+    /// the whole apk is transferred, falling back to a regular installation when unsupported
+    #[clap(long)]
+    incremental: bool,
+}
+
+impl InstallArgs {
+    fn options(self) -> InstallOptions {
+        InstallOptions {
+            reinstall: self.reinstall,
+            incremental: self.incremental,
+            retries: self.install_retries,
+            timeout: self.install_timeout.map(Duration::from_secs),
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(clap::Subcommand)]
@@ -56,6 +212,14 @@ enum ApkSubCmd {
     Build {
         #[clap(flatten)]
         args: Args,
+        /// Fail instead of appending the artifact name when several artifacts resolve to the
+        /// same apk path
+        #[clap(long)]
+        strict_apk_names: bool,
+        /// Create an apk per build target, each with the versionCode that
+        /// `version_code_abi_scheme` derives for its ABI, instead of a single apk
+        #[clap(long)]
+        split_per_abi: bool,
     },
     /// Invoke `cargo` under the detected NDK environment
     #[clap(name = "--")]
@@ -78,16 +242,138 @@ enum ApkSubCmd {
         /// Do not print or follow `logcat` after running the app
         #[clap(short, long)]
         no_logcat: bool,
+        /// `adb shell` command to run after installing and before launching the app, may be
+        /// given multiple times. `{package}` is replaced by the package name
+        #[clap(long)]
+        before_run: Vec<String>,
+        /// Activity to start instead of the `default_launch_activity` or the Rust activity,
+        /// e.g. `.SettingsActivity`
+        #[clap(long)]
+        activity: Option<String>,
+        #[clap(flatten)]
+        install: InstallArgs,
+        /// Print the package, UID, PID, device serial and apk path of the started app as a
+        /// single line of JSON, before following `logcat`
+        #[clap(long)]
+        print_launch_info: bool,
+        #[clap(flatten)]
+        logcat: LogcatArgs,
+        /// Arguments passed to the binary when running a `[[bin]]` target directly on the device
+        #[clap(last = true)]
+        bin_args: Vec<String>,
+    },
+    /// Build and install an apk of the local package, without launching it
+    #[clap(visible_alias = "i")]
+    Install {
+        #[clap(flatten)]
+        args: Args,
+        /// Grant all runtime permissions listed in the manifest
+        #[clap(short, long)]
+        grant_permissions: bool,
+        /// Install for the given user only
+        #[clap(long)]
+        user: Option<String>,
+        /// Force streamed installation
+        #[clap(long, conflicts_with = "no_streaming")]
+        streaming: bool,
+        /// Disable streamed installation
+        #[clap(long)]
+        no_streaming: bool,
+        #[clap(flatten)]
+        install: InstallArgs,
+    },
+    /// Build an apk and serve it over HTTP on the local network for sideloading without adb
+    Serve {
+        #[clap(flatten)]
+        args: Args,
+        /// Port to listen on, a free one is picked by default
+        #[clap(long, default_value_t = 0)]
+        port: u16,
+        /// Stop serving after this many seconds
+        #[clap(long, default_value_t = 600)]
+        timeout: u64,
+        /// Stop serving after this many downloads
+        #[clap(long)]
+        downloads: Option<u32>,
+    },
+    /// Run the app and rebuild, reinstall and restart it whenever a source file changes
+    Watch {
+        #[clap(flatten)]
+        args: Args,
+        /// Only reinstall a changed apk, without restarting the app
+        #[clap(long)]
+        no_restart: bool,
+        /// Milliseconds without further changes before rebuilding
+        #[clap(long, default_value_t = 300)]
+        debounce: u64,
     },
     /// Start a gdb session attached to an adb device with symbols loaded
     Gdb {
         #[clap(flatten)]
         args: Args,
     },
+    /// Check the resolved AndroidManifest.xml for common issues
+    Lint {
+        #[clap(flatten)]
+        args: Args,
+        /// Print findings as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Compare the manifest of a previously built apk or aab, e.g. the last release, with the
+    /// last built apk of the current package
+    ManifestDiff {
+        #[clap(flatten)]
+        args: Args,
+        /// The previously built apk or aab
+        old: PathBuf,
+        /// The apk or aab to compare with, instead of the last built apk
+        #[clap(long)]
+        new: Option<PathBuf>,
+        /// Print the differences as JSON
+        #[clap(long)]
+        json: bool,
+        /// Exit with a nonzero code when the new manifest requests permissions the old one didn't
+        #[clap(long, value_parser = ["permissions"])]
+        deny: Vec<String>,
+    },
+    /// Print the `versionCode` and `versionName` that a build of the current package would produce
+    VersionCode {
+        #[clap(flatten)]
+        args: Args,
+    },
+    /// Print the ABI of the connected device, e.g. `arm64-v8a`, without building anything
+    PrintAbi {
+        #[clap(flatten)]
+        args: Args,
+    },
     /// Print the version of cargo-android
     Version,
 }
 
+/// Maps the arguments of `cargo android ...` onto those of `cargo apk ...`, which [`Cmd`] parses:
+/// cargo passes the subcommand name as the first argument, so `cargo android run` becomes
+/// `apk run`, while `cargo android apk run` and `cargo android aab build` drop the `android`.
+fn normalize_invocation(mut args: Vec<OsString>) -> Vec<OsString> {
+    if args.get(1).is_some_and(|arg| arg == "android") {
+        let keep_next = match args.get(2) {
+            Some(next) => {
+                ["apk", "aab", "version-code", "help"]
+                    .iter()
+                    .any(|cmd| next == cmd)
+                    || next.to_string_lossy().starts_with('-')
+            }
+            None => true,
+        };
+        if keep_next {
+            args.remove(1);
+        } else {
+            args[1] = "apk".into();
+        }
+    }
+    args
+}
+
 fn split_apk_and_cargo_args(input: Vec<String>) -> (Args, Vec<String>) {
     // Clap doesn't support parsing unknown args properly:
     // https://github.com/clap-rs/clap/issues/1404
@@ -143,6 +429,84 @@ fn split_apk_and_cargo_args(input: Vec<String>) -> (Args, Vec<String>) {
     (args, split_args.cargo_args)
 }
 
+/// `cargo android aab run`: creates the aab from the last built apk and runs its universal
+/// apk with the same `ApkBuilder` setup as `apk run`.
+fn run_universal_apk(
+    args: Args,
+    keep_intermediates: bool,
+    no_logcat: bool,
+    activity: Option<String>,
+    options: InstallOptions,
+    print_launch_info: bool,
+    logcat: LogcatArgs,
+) -> anyhow::Result<()> {
+    let mut builder = aab_builder(&args)?;
+    builder.keep_intermediates = keep_intermediates;
+    let apk_builder = apk_builder(&builder.cmd, &args)?
+        .launch_activity(activity)
+        .print_launch_info(print_launch_info)
+        .logcat_duration(logcat.logcat_duration.map(Duration::from_secs))
+        .logcat_until(logcat.logcat_until)
+        .clear_logcat(logcat.clear_logcat);
+    let aab = builder.create_from_apk()?;
+    builder.run_universal_apk(&aab, &apk_builder, no_logcat, &options)
+}
+
+/// The [`Env`] of the builders, with the settings in `args` applied.
+fn env(args: &Args) -> Env {
+    Env::default()
+        .ndk_cache(!args.no_cache)
+        .keystore_check(!args.skip_keystore_check)
+        .signing_config(args.signing_config.clone())
+}
+
+/// Creates the [`ApkBuilder`] for `cmd` with all settings in `args`, and the
+/// `--device-profile` applied.
+fn apk_builder<'a>(cmd: &'a Subcommand, args: &Args) -> Result<ApkBuilder<'a>, Error> {
+    let builder = ApkBuilder::from_subcommand_with_env(cmd, args.device.clone(), env(args))?
+        .cargo_flags(args.cargo_flags.clone())
+        .print_cargo_command(args.print_cargo_command)
+        .dedup_warnings(args.dedup_warnings)
+        .allow_debug_signing(args.allow_debug_signing)
+        .no_strip(args.no_strip)
+        .strip_debug(args.strip_debug)
+        .assets_only_rebuild(args.assets_only_rebuild)
+        .deny_warnings(args.deny_warnings)
+        .strict(args.strict)
+        .allow_missing_dirs(args.allow_missing_dirs)
+        .allow_case_collisions(args.allow_case_collisions);
+    match args.device_profile.as_deref() {
+        Some(name) => builder.device_profile(name),
+        None => Ok(builder),
+    }
+}
+
+/// Creates the [`AabBuilder`] for the package in `args` with the settings of `args` that
+/// apply to bundles.
+fn aab_builder(args: &Args) -> anyhow::Result<AabBuilder> {
+    if args.no_strip {
+        return Err(Error::AabStripFlag("--no-strip").into());
+    }
+    if args.strip_debug {
+        return Err(Error::AabStripFlag("--strip-debug").into());
+    }
+    let cmd = Subcommand::new(args.subcommand_args.clone())?;
+    let mut builder = AabBuilder::from_subcommand_with_env(cmd, env(args))?;
+    builder.allow_debug_signing = args.allow_debug_signing;
+    builder.deny_warnings = args.deny_warnings;
+    builder.strict = args.strict;
+    Ok(builder)
+}
+
+/// The artifacts selected on the command line, resolved through [`ArtifactSelector`] exactly
+/// like library consumers of [`ApkBuilder`] do.
+fn selected_artifacts(cmd: &Subcommand, builder: &ApkBuilder) -> Vec<Artifact> {
+    cmd.artifacts()
+        .map(ArtifactSelector::from_artifact)
+        .map(|selector| builder.artifact(&selector))
+        .collect()
+}
+
 fn iterator_single_item<T>(mut iter: impl Iterator<Item = T>) -> Option<T> {
     let first_item = iter.next()?;
     if iter.next().is_some() {
@@ -152,30 +516,130 @@ fn iterator_single_item<T>(mut iter: impl Iterator<Item = T>) -> Option<T> {
     }
 }
 
+/// Prints the `versionCode` followed by the `versionName` on a separate line, resolved
+/// exactly like `ApkBuilder` and `AabBuilder` do without building anything.
+fn print_version_code(args: Args) -> anyhow::Result<()> {
+    let cmd = Subcommand::new(args.subcommand_args)?;
+    let manifest = Manifest::parse_from_toml(cmd.manifest())?;
+    let workspace_manifest = cmd
+        .workspace_manifest()
+        .map(Root::parse_from_toml)
+        .transpose()?;
+    println!(
+        "{}",
+        compute_version_code(&manifest, workspace_manifest.as_ref())?
+    );
+    println!(
+        "{}",
+        compute_version_name(&manifest, workspace_manifest.as_ref())?
+    );
+    if let Some(scheme) = &manifest.version_code_abi_scheme {
+        let version_code = compute_version_code(&manifest, workspace_manifest.as_ref())?;
+        for target in scheme.targets() {
+            println!(
+                "{} {}",
+                target.android_abi(),
+                scheme.version_code(version_code, target)?
+            );
+        }
+    }
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     env_logger::init();
-    
-    let cmd = match Cmd::parse() {
-        Cmd { apk: ApkCmd::Aab { cmd } } => {
-            let AabSubCmd::Build { args } = cmd;
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = AabBuilder::from_subcommand(cmd)?;
-            return builder.create_from_apk();
+
+    // Don't leave half-written artifacts behind when interrupted while aligning or signing
+    ctrlc::set_handler(|| {
+        if cargo_android::is_following_logcat() {
+            // `run` continues with its `post_run_shell` commands once `logcat` was interrupted
+            return;
         }
-        Cmd { apk: ApkCmd::Apk { cmd } } => cmd,
+        ndk_build::partial::remove_all();
+        std::process::exit(130);
+    })?;
+
+    let cmd = match Cmd::parse_from(normalize_invocation(std::env::args_os().collect())) {
+        Cmd {
+            apk: ApkCmd::Aab { cmd },
+        } => {
+            let (args, binary_resources_apk, keep_intermediates, universal_apk) = match cmd {
+                AabSubCmd::Build {
+                    args,
+                    binary_resources_apk,
+                    keep_intermediates,
+                } => (args, binary_resources_apk, keep_intermediates, false),
+                AabSubCmd::UniversalApk {
+                    args,
+                    keep_intermediates,
+                } => (args, false, keep_intermediates, true),
+                AabSubCmd::Run {
+                    args,
+                    keep_intermediates,
+                    no_logcat,
+                    activity,
+                    install,
+                    print_launch_info,
+                    logcat,
+                } => {
+                    return run_universal_apk(
+                        args,
+                        keep_intermediates,
+                        no_logcat,
+                        activity,
+                        install.options(),
+                        print_launch_info,
+                        logcat,
+                    )
+                }
+            };
+            let mut builder = aab_builder(&args)?;
+            builder.binary_resources_apk = binary_resources_apk;
+            builder.keep_intermediates = keep_intermediates;
+            let aab = builder.create_from_apk()?;
+            // Printed last so that scripts can pick it up from the final line
+            if universal_apk {
+                let apk = builder.build_apks(&aab)?;
+                println!("Built APK: {}", dunce::canonicalize(apk)?.display());
+            } else {
+                println!("Built AAB: {}", dunce::canonicalize(aab)?.display());
+            }
+            return Ok(());
+        }
+        Cmd {
+            apk: ApkCmd::VersionCode { args },
+        } => return print_version_code(args),
+        Cmd {
+            apk: ApkCmd::Apk { cmd },
+        } => cmd,
     };
-    
+
     match cmd {
         ApkSubCmd::Check { args } => {
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let builder = apk_builder(&cmd, &args)?;
             builder.check()?;
         }
-        ApkSubCmd::Build { args } => {
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
-            for artifact in cmd.artifacts() {
-                builder.build(artifact)?;
+        ApkSubCmd::Build {
+            args,
+            strict_apk_names,
+            split_per_abi,
+        } => {
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let mut builder = apk_builder(&cmd, &args)?;
+            let artifacts = selected_artifacts(&cmd, &builder);
+            builder.resolve_apk_names(&artifacts, strict_apk_names)?;
+            let mut apks = Vec::new();
+            for artifact in &artifacts {
+                if split_per_abi {
+                    apks.extend(builder.build_split_per_abi(artifact)?);
+                } else {
+                    apks.push(builder.build(artifact)?);
+                }
+            }
+            // Printed last so that scripts can pick them up from the final lines
+            for apk in apks {
+                println!("Built APK: {}", dunce::canonicalize(apk.path())?.display());
             }
         }
         ApkSubCmd::Ndk {
@@ -184,21 +648,175 @@ fn main() -> anyhow::Result<()> {
         } => {
             let (args, cargo_args) = split_apk_and_cargo_args(cargo_args);
 
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let builder = apk_builder(&cmd, &args)?;
             builder.default(&cargo_cmd, &cargo_args)?;
         }
-        ApkSubCmd::Run { args, no_logcat } => {
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
-            let artifact = iterator_single_item(cmd.artifacts()).ok_or(Error::invalid_args())?;
-            builder.run(artifact, no_logcat)?;
+        ApkSubCmd::Run {
+            args,
+            no_logcat,
+            before_run,
+            activity,
+            install,
+            print_launch_info,
+            logcat,
+            bin_args,
+        } => {
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let builder = apk_builder(&cmd, &args)?
+                .before_run(before_run)
+                .launch_activity(activity)
+                .print_launch_info(print_launch_info)
+                .logcat_duration(logcat.logcat_duration.map(Duration::from_secs))
+                .logcat_until(logcat.logcat_until)
+                .clear_logcat(logcat.clear_logcat);
+            let artifact = iterator_single_item(selected_artifacts(&cmd, &builder).into_iter())
+                .ok_or(Error::invalid_args())?;
+            if matches!(artifact.r#type, ArtifactType::Bin) {
+                let code = builder.run_bin(&artifact, &bin_args)?;
+                if code != 0 {
+                    std::process::exit(code);
+                }
+            } else {
+                let options = install.options();
+                builder.run_with_options(&artifact, no_logcat, &options)?;
+            }
+        }
+        ApkSubCmd::Install {
+            args,
+            grant_permissions,
+            user,
+            streaming,
+            no_streaming,
+            install,
+        } => {
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let builder = apk_builder(&cmd, &args)?;
+            let options = InstallOptions {
+                grant_permissions,
+                user,
+                streaming: match (streaming, no_streaming) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
+                ..install.options()
+            };
+            for artifact in selected_artifacts(&cmd, &builder) {
+                builder.install(&artifact, &options)?;
+            }
+        }
+        ApkSubCmd::Serve {
+            args,
+            port,
+            timeout,
+            downloads,
+        } => {
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let builder = apk_builder(&cmd, &args)?;
+            let artifact = iterator_single_item(selected_artifacts(&cmd, &builder).into_iter())
+                .ok_or(Error::invalid_args())?;
+            // Checked up front to not build in vain
+            if !args.allow_debug_signing && builder.is_debug_signed()? {
+                return Err(Error::ServeDebugSigned(builder.apk_path(&artifact)).into());
+            }
+            let apk = builder.build(&artifact)?;
+            let options = ServeOptions {
+                port,
+                timeout: Duration::from_secs(timeout),
+                max_downloads: downloads,
+            };
+            serve(apk.path(), &options)?;
+        }
+        ApkSubCmd::Watch {
+            args,
+            no_restart,
+            debounce,
+        } => {
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let builder = apk_builder(&cmd, &args)?;
+            let artifact = iterator_single_item(selected_artifacts(&cmd, &builder).into_iter())
+                .ok_or(Error::invalid_args())?;
+            let options = WatchOptions {
+                restart: !no_restart,
+                debounce: Duration::from_millis(debounce),
+            };
+            builder.watch(&artifact, &options)?;
         }
         ApkSubCmd::Gdb { args } => {
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
-            let artifact = iterator_single_item(cmd.artifacts()).ok_or(Error::invalid_args())?;
-            builder.gdb(artifact)?;
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let builder = apk_builder(&cmd, &args)?;
+            let artifact = iterator_single_item(selected_artifacts(&cmd, &builder).into_iter())
+                .ok_or(Error::invalid_args())?;
+            builder.gdb(&artifact)?;
+        }
+        ApkSubCmd::Lint { args, json } => {
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let builder = apk_builder(&cmd, &args)?;
+            let mut findings = Vec::new();
+            for artifact in selected_artifacts(&cmd, &builder) {
+                for finding in builder.lint(&artifact) {
+                    // Artifacts mostly share their manifest, report its findings once
+                    if !findings
+                        .iter()
+                        .any(|f: &Finding| f.id == finding.id && f.message == finding.message)
+                    {
+                        findings.push(finding);
+                    }
+                }
+            }
+            findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+            if json {
+                println!("{}", serde_json::to_string_pretty(&findings)?);
+            } else {
+                for finding in &findings {
+                    println!("{finding}");
+                }
+            }
+            if findings.iter().any(|f| f.severity == Severity::Error) {
+                std::process::exit(1);
+            }
+        }
+        ApkSubCmd::ManifestDiff {
+            args,
+            old,
+            new,
+            json,
+            deny,
+        } => {
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let builder = apk_builder(&cmd, &args)?;
+            let new = match new {
+                Some(new) => new,
+                None => {
+                    let artifact =
+                        iterator_single_item(selected_artifacts(&cmd, &builder).into_iter())
+                            .ok_or(Error::invalid_args())?;
+                    builder.apk_path(&artifact)
+                }
+            };
+            let diff = builder.manifest_diff(&old, &new)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                print!("{diff}");
+            }
+            if deny.iter().any(|deny| deny == "permissions") && !diff.added_permissions.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        ApkSubCmd::VersionCode { args } => print_version_code(args)?,
+        ApkSubCmd::PrintAbi { args } => {
+            let cmd = Subcommand::new(args.subcommand_args.clone())?;
+            let device_serial = match (&args.device, &args.device_profile) {
+                (None, Some(name)) => Manifest::parse_from_toml(cmd.manifest())?
+                    .device_profile(name)?
+                    .serial
+                    .clone(),
+                _ => args.device.clone(),
+            };
+            let abi = device_abi(cmd.target_dir(), device_serial.as_deref(), &env(&args))?;
+            println!("{}", abi.android_abi());
         }
         ApkSubCmd::Version => {
             println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
@@ -324,8 +942,88 @@ fn test_split_apk_and_cargo_args() {
                     ..args_default.subcommand_args
                 },
                 device: Some("adb:test".to_string()),
+                cargo_flags: CargoFlags::default(),
+                allow_debug_signing: false,
+                no_cache: false,
+                no_strip: false,
+                strip_debug: false,
+                assets_only_rebuild: false,
+                deny_warnings: false,
+                strict: false,
+                allow_missing_dirs: false,
+                allow_case_collisions: false,
+                skip_keystore_check: false,
+                signing_config: None,
+                dedup_warnings: false,
+                print_cargo_command: false,
+                device_profile: None,
             },
             vec!["--no-deps".to_string(), "--unrecognized".to_string()]
         )
     );
-}
\ No newline at end of file
+
+    assert_eq!(
+        split_apk_and_cargo_args(vec![
+            "--locked".to_string(),
+            "--offline".to_string(),
+            "--unrecognized".to_string(),
+        ]),
+        (
+            Args {
+                subcommand_args: Args::parse_from(std::iter::empty::<&str>()).subcommand_args,
+                device: None,
+                cargo_flags: CargoFlags {
+                    locked: true,
+                    offline: true,
+                    ..Default::default()
+                },
+                allow_debug_signing: false,
+                no_cache: false,
+                no_strip: false,
+                strip_debug: false,
+                assets_only_rebuild: false,
+                deny_warnings: false,
+                strict: false,
+                allow_missing_dirs: false,
+                allow_case_collisions: false,
+                skip_keystore_check: false,
+                signing_config: None,
+                dedup_warnings: false,
+                print_cargo_command: false,
+                device_profile: None,
+            },
+            vec!["--unrecognized".to_string()]
+        )
+    );
+}
+#[test]
+fn test_normalize_invocation() {
+    let normalize = |args: &[&str]| {
+        normalize_invocation(args.iter().map(OsString::from).collect())
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        normalize(&["cargo-apk", "apk", "run"]),
+        ["cargo-apk", "apk", "run"]
+    );
+    assert_eq!(
+        normalize(&["cargo-android", "android", "run", "--release"]),
+        ["cargo-android", "apk", "run", "--release"]
+    );
+    assert_eq!(
+        normalize(&["cargo-android", "android", "apk", "run"]),
+        ["cargo-android", "apk", "run"]
+    );
+    assert_eq!(
+        normalize(&["cargo-android", "android", "aab", "build"]),
+        ["cargo-android", "aab", "build"]
+    );
+    assert_eq!(
+        normalize(&["cargo-android", "android", "--help"]),
+        ["cargo-android", "--help"]
+    );
+    assert_eq!(normalize(&["cargo-android", "android"]), ["cargo-android"]);
+}
@@ -1,38 +1,79 @@
 use crate::error::Error;
-use ndk_build::apk::StripConfig;
+use crate::lint::LintConfig;
+use cargo_subcommand::Args;
+use ndk_build::apk::{ReversePortForward, StripConfig};
+use ndk_build::cargo::VersionCode;
 use ndk_build::manifest::AndroidManifest;
+use ndk_build::secret::Secret;
 use ndk_build::target::Target;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
 };
 
-#[derive(Debug, Clone, Deserialize)]
+/// A `[package]` field that is either set or inherited from `[workspace.package]`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum Inheritable<T> {
     Value(T),
     Inherited { workspace: bool },
 }
 
+/// The `[package.metadata.android]` table of a package, together with the package fields that
+/// the build depends on.
+///
+/// Parsing does not build anything, so this can be used by other tools to inspect the Android
+/// configuration of a package. [`Manifest::resolve()`] resolves fields that are inherited from
+/// the workspace.
 pub struct Manifest {
     pub version: Inheritable<String>,
+    /// Name of the `[lib]` target, when configured
+    pub lib_name: Option<String>,
     pub apk_name: Option<String>,
+    pub aab_name: Option<String>,
+    pub aab_dir: Option<PathBuf>,
+    pub aab_scratch_dir: Option<PathBuf>,
     pub version_name: Option<String>,
     pub version_code: Option<u32>,
+    pub version_code_abi_scheme: Option<VersionCodeAbiScheme>,
+    pub compile_sdk_version: Option<u32>,
+    pub strict_min_sdk_version: bool,
     pub android_manifest: AndroidManifest,
+    pub no_launcher: bool,
+    pub requires_internet: bool,
+    pub default_launch_activity: Option<String>,
     pub build_targets: Vec<Target>,
     pub assets: Option<PathBuf>,
     pub resources: Option<PathBuf>,
     pub runtime_libs: Option<PathBuf>,
+    pub round_icon: Option<PathBuf>,
+    pub banner: Option<PathBuf>,
+    pub splash: Option<Splash>,
+    pub network_security: Option<NetworkSecurity>,
     /// Maps profiles to keystores
     pub signing: HashMap<String, Signing>,
-    pub reverse_port_forward: HashMap<String, String>,
-    pub strip: StripConfig,
+    pub allow_debuggable_release: bool,
+    pub reverse_port_forward: HashMap<String, ReversePortForward>,
+    pub before_run: Vec<String>,
+    pub run: RunConfig,
+    /// Device profiles by name, see [`DeviceProfile`]
+    pub devices: HashMap<String, DeviceProfile>,
+    pub strip: Strip,
+    pub shrink_resources: bool,
+    pub shrink_keep: Vec<String>,
+    pub embed_build_info: bool,
+    pub cargo_flags: Vec<String>,
+    pub cargo_unstable_flags: Vec<String>,
+    pub lint: LintConfig,
+    /// `required-features` of the `[[example]]` targets, by example name
+    pub example_required_features: HashMap<String, Vec<String>>,
+    /// Features of the package, including the implicit features of optional dependencies
+    pub features: Vec<String>,
 }
 
 impl Manifest {
-    pub(crate) fn parse_from_toml(path: &Path) -> Result<Self, Error> {
+    pub fn parse_from_toml(path: &Path) -> Result<Self, Error> {
         let toml = Root::parse_from_toml(path)?;
         // Unlikely to fail as cargo-subcommand should give us a `Cargo.toml` containing
         // a `[package]` table (with a matching `name` when requested by the user)
@@ -44,44 +85,374 @@ impl Manifest {
             .unwrap_or_default()
             .android
             .unwrap_or_default();
+        metadata.strip.validate()?;
+        if let Some(splash) = &metadata.splash {
+            splash.validate()?;
+        }
+        if let Some(network_security) = &metadata.network_security {
+            network_security.validate()?;
+        }
+        if let Some(scheme) = &metadata.version_code_abi_scheme {
+            scheme.validate()?;
+        }
+        validate_abis(
+            "devices",
+            metadata
+                .devices
+                .values()
+                .filter_map(|device| device.abi.as_ref()),
+        )?;
+        for (key, name) in [
+            ("apk_name", &metadata.apk_name),
+            ("aab_name", &metadata.aab_name),
+        ] {
+            if let Some(name) = name {
+                validate_file_name(key, name)?;
+            }
+        }
+        let example_required_features = toml
+            .example
+            .into_iter()
+            .map(|example| (example.name, example.required_features))
+            .collect();
+        let optional_dependencies = toml.dependencies.into_iter().filter_map(|(name, dep)| {
+            dep.get("optional")
+                .and_then(toml::Value::as_bool)
+                .unwrap_or(false)
+                .then_some(name)
+        });
+        let features = toml
+            .features
+            .into_keys()
+            .chain(optional_dependencies)
+            .collect();
         Ok(Self {
             version: package.version,
+            lib_name: toml.lib.and_then(|lib| lib.name),
             version_name: metadata.version_name,
             version_code: metadata.version_code,
+            version_code_abi_scheme: metadata.version_code_abi_scheme,
+            compile_sdk_version: metadata.compile_sdk_version,
+            strict_min_sdk_version: metadata.strict_min_sdk_version,
             apk_name: metadata.apk_name,
+            aab_name: metadata.aab_name,
+            aab_dir: metadata.aab_dir,
+            aab_scratch_dir: metadata.aab_scratch_dir,
             android_manifest: metadata.android_manifest,
+            no_launcher: metadata.no_launcher,
+            requires_internet: metadata.requires_internet,
+            default_launch_activity: metadata.default_launch_activity,
             build_targets: metadata.build_targets,
             assets: metadata.assets,
             resources: metadata.resources,
             runtime_libs: metadata.runtime_libs,
+            round_icon: metadata.round_icon,
+            banner: metadata.banner,
+            splash: metadata.splash,
+            network_security: metadata.network_security,
             signing: metadata.signing,
+            allow_debuggable_release: metadata.allow_debuggable_release,
             reverse_port_forward: metadata.reverse_port_forward,
+            before_run: metadata.before_run,
+            run: metadata.run,
+            devices: metadata.devices,
             strip: metadata.strip,
+            shrink_resources: metadata.shrink_resources,
+            shrink_keep: metadata.shrink_keep,
+            embed_build_info: metadata.embed_build_info,
+            cargo_flags: metadata.cargo_flags,
+            cargo_unstable_flags: metadata.cargo_unstable_flags,
+            lint: metadata.lint,
+            example_required_features,
+            features,
         })
     }
+
+    /// The configured `[package.metadata.android.sdk] target_sdk_version`, or `default` (the
+    /// NDK's default target platform) when unset.
+    ///
+    /// This single value drives the `AndroidManifest.xml`, the `exported` default for Android
+    /// S+, and the platform that APKs and AABs are linked against.
+    pub fn target_sdk_version(&self, default: impl FnOnce() -> u32) -> u32 {
+        self.android_manifest
+            .sdk
+            .target_sdk_version
+            .unwrap_or_else(default)
+    }
+
+    /// The device profile `name` from `[package.metadata.android.devices]`.
+    pub fn device_profile(&self, name: &str) -> Result<&DeviceProfile, Error> {
+        self.devices.get(name).ok_or_else(|| {
+            let mut configured = self.devices.keys().cloned().collect::<Vec<_>>();
+            configured.sort_unstable();
+            Error::UnknownDeviceProfile {
+                name: name.to_owned(),
+                configured,
+            }
+        })
+    }
+
+    /// The platform whose `android.jar` resources are linked against: the configured
+    /// `compile_sdk_version`, or `target_sdk_version` when unset.
+    ///
+    /// Fails unless `compile_sdk_version >= target_sdk_version >= min_sdk_version`.
+    pub fn compile_sdk_version(&self, target_sdk_version: u32) -> Result<u32, Error> {
+        let compile = self.compile_sdk_version.unwrap_or(target_sdk_version);
+        // Android defaults `minSdkVersion` to 1
+        let min = self.android_manifest.sdk.min_sdk_version.unwrap_or(1);
+        if compile < target_sdk_version || target_sdk_version < min {
+            return Err(Error::InvalidSdkVersions {
+                min,
+                target: target_sdk_version,
+                compile,
+            });
+        }
+        Ok(compile)
+    }
+
+    /// Resolves the fields that are inherited from `workspace`, the root manifest of the
+    /// package's workspace, so that [`Manifest::version`] holds the actual version.
+    pub fn resolve(mut self, workspace: Option<&Root>) -> Result<Self, Error> {
+        self.version = Inheritable::Value(self.package_version(workspace)?);
+        Ok(self)
+    }
+
+    /// Resolves `package.version`, looking it up in the `[workspace.package]` table of
+    /// `workspace` when it is inherited through `version.workspace = true`.
+    pub fn package_version(&self, workspace: Option<&Root>) -> Result<String, Error> {
+        match &self.version {
+            Inheritable::Value(v) => Ok(v.clone()),
+            Inheritable::Inherited { workspace: true } => {
+                let workspace = workspace
+                    .ok_or(Error::InheritanceMissingWorkspace)?
+                    .workspace
+                    .clone()
+                    // Unlikely to fail as cargo-subcommand should give us
+                    // a `Cargo.toml` containing a `[workspace]` table
+                    .expect("Workspace root manifest must contain a `[workspace]` table");
+
+                workspace
+                    .package
+                    .ok_or(Error::WorkspaceMissingInheritedField("package"))?
+                    .version
+                    .ok_or(Error::WorkspaceMissingInheritedField("package.version"))
+            }
+            Inheritable::Inherited { workspace: false } => Err(Error::InheritedFalse),
+        }
+    }
+}
+
+/// Computes the `versionCode` that is written into the `AndroidManifest.xml` of APKs and
+/// AABs built from `manifest`.
+///
+/// An explicit `version_code` in `[package.metadata.android]` is used as-is; otherwise the
+/// code is derived from the (possibly workspace-inherited) package version through
+/// [`VersionCode::from_semver`].
+pub fn compute_version_code(manifest: &Manifest, workspace: Option<&Root>) -> Result<u32, Error> {
+    if let Some(version_code) = manifest.version_code {
+        return Ok(version_code);
+    }
+    let package_version = manifest.package_version(workspace)?;
+    Ok(VersionCode::from_semver(&package_version)?.to_code(1))
+}
+
+/// Computes the `versionName` that is written into the `AndroidManifest.xml` of APKs and
+/// AABs built from `manifest`: the `version_name` in `[package.metadata.android]`, or the
+/// (possibly workspace-inherited) package version.
+pub fn compute_version_name(
+    manifest: &Manifest,
+    workspace: Option<&Root>,
+) -> Result<String, Error> {
+    match &manifest.version_name {
+        Some(version_name) => Ok(version_name.clone()),
+        None => manifest.package_version(workspace),
+    }
+}
+
+/// Checks that `name`, configured through the metadata `key`, can be used as a single file
+/// name component, instead of failing with an obscure IO error when writing the artifact.
+fn validate_file_name(key: &'static str, name: &str) -> Result<(), Error> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(Error::InvalidFileName {
+            key,
+            name: name.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Expands the `{name}` and `{version}` placeholders of an `apk_name` or `aab_name` template.
+pub(crate) fn expand_artifact_name(template: &str, name: &str, version: &str) -> String {
+    template
+        .replace("{name}", name)
+        .replace("{version}", version)
+}
+
+/// The features to enable through `--features` for building `example`, which cargo otherwise
+/// silently skips when its `required-features` are not enabled by `args`.
+///
+/// Fails listing the missing features when they are not declared by the package, or when
+/// `--no-default-features` was given and the features are thus left up to the user.
+pub(crate) fn example_features(
+    manifest: &Manifest,
+    example: &str,
+    args: &Args,
+) -> Result<Vec<String>, Error> {
+    let Some(required_features) = manifest.example_required_features.get(example) else {
+        return Ok(Vec::new());
+    };
+    if args.all_features {
+        return Ok(Vec::new());
+    }
+    let enabled = args
+        .features
+        .iter()
+        .flat_map(|features| features.split([',', ' ']))
+        .collect::<Vec<_>>();
+    let missing = required_features
+        .iter()
+        .filter(|feature| !enabled.contains(&feature.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(missing);
+    }
+
+    let undeclared = missing
+        .iter()
+        // `dependency/feature` is enabled through the dependency
+        .filter(|feature| !feature.contains('/') && !manifest.features.contains(feature))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !undeclared.is_empty() {
+        return Err(Error::UndeclaredRequiredFeatures {
+            example: example.to_owned(),
+            features: undeclared,
+        });
+    }
+    if args.no_default_features {
+        return Err(Error::MissingRequiredFeatures {
+            example: example.to_owned(),
+            features: missing,
+        });
+    }
+    Ok(missing)
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Root {
     pub(crate) package: Option<Package>,
     pub(crate) workspace: Option<Workspace>,
+    pub(crate) lib: Option<Lib>,
+    #[serde(default)]
+    pub(crate) example: Vec<Example>,
+    #[serde(default)]
+    pub(crate) features: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub(crate) dependencies: HashMap<String, toml::Value>,
 }
 
 impl Root {
-    pub(crate) fn parse_from_toml(path: &Path) -> Result<Self, Error> {
+    pub fn parse_from_toml(path: &Path) -> Result<Self, Error> {
         let contents = std::fs::read_to_string(path)?;
         toml::from_str(&contents).map_err(std::convert::Into::into)
     }
+
+    /// Whether this manifest has a `[package.metadata.android]` table.
+    fn has_android_metadata(&self) -> bool {
+        self.package
+            .as_ref()
+            .and_then(|package| package.metadata.as_ref())
+            .is_some_and(|metadata| metadata.android.is_some())
+    }
+
+    /// Names of the packages in the workspace of this root manifest, located in `root_dir`,
+    /// that have a `[package.metadata.android]` table. Members that fail to parse are skipped.
+    ///
+    /// Only `*` as the last path component of `workspace.members` is expanded.
+    fn android_packages(&self, root_dir: &Path) -> Vec<String> {
+        let Some(workspace) = &self.workspace else {
+            return Vec::new();
+        };
+        let mut member_dirs = vec![root_dir.to_owned()];
+        for member in &workspace.members {
+            match member.strip_suffix("/*") {
+                Some(parent) => {
+                    let Ok(entries) = std::fs::read_dir(root_dir.join(parent)) else {
+                        continue;
+                    };
+                    let mut dirs = entries
+                        .filter_map(|entry| Some(entry.ok()?.path()))
+                        .collect::<Vec<_>>();
+                    dirs.sort();
+                    member_dirs.extend(dirs);
+                }
+                None => member_dirs.push(root_dir.join(member)),
+            }
+        }
+
+        member_dirs
+            .into_iter()
+            .filter_map(|dir| Self::parse_from_toml(&dir.join("Cargo.toml")).ok())
+            .filter(Self::has_android_metadata)
+            .filter_map(|root| root.package?.name)
+            .collect()
+    }
+}
+
+/// Fails when the selected `package` (with manifest `manifest_path`) has no
+/// `[package.metadata.android]` table while other packages in its workspace do, as it is then
+/// most likely not the Android app that was meant to be built.
+///
+/// Packages without any Android metadata in their workspace are accepted, as all keys are
+/// optional.
+pub(crate) fn ensure_android_package(
+    package: &str,
+    manifest_path: &Path,
+    workspace_manifest_path: Option<&Path>,
+) -> Result<(), Error> {
+    if Root::parse_from_toml(manifest_path)?.has_android_metadata() {
+        return Ok(());
+    }
+    let Some(workspace_manifest_path) = workspace_manifest_path else {
+        return Ok(());
+    };
+    let root_dir = workspace_manifest_path.parent().unwrap();
+    let candidates = Root::parse_from_toml(workspace_manifest_path)?.android_packages(root_dir);
+    if candidates.is_empty() {
+        return Ok(());
+    }
+    Err(Error::NotAnAndroidPackage {
+        package: package.to_owned(),
+        candidates,
+    })
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub(crate) struct Package {
+    pub(crate) name: Option<String>,
     pub(crate) version: Inheritable<String>,
     pub(crate) metadata: Option<PackageMetadata>,
 }
 
+/// `[lib]`
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Lib {
+    pub(crate) name: Option<String>,
+}
+
+/// `[[example]]`
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Example {
+    pub(crate) name: String,
+    #[serde(default, rename = "required-features")]
+    pub(crate) required_features: Vec<String>,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct Workspace {
+    #[serde(default)]
+    pub(crate) members: Vec<String>,
     pub(crate) package: Option<WorkspacePackage>,
 }
 
@@ -100,29 +471,941 @@ pub(crate) struct PackageMetadata {
 #[derive(Clone, Debug, Default, Deserialize)]
 struct AndroidMetadata {
     apk_name: Option<String>,
+    /// Name for the signed `.aab` file, defaults to the name of the APK
+    aab_name: Option<String>,
+    /// Directory the signed `.aab` file is written to, relative to the crate
+    aab_dir: Option<PathBuf>,
+    /// Directory the intermediate files of the `.aab` are created in, relative to the crate
+    aab_scratch_dir: Option<PathBuf>,
     version_name: Option<String>,
     version_code: Option<u32>,
+    /// How the `versionCode` of APKs containing a single ABI derives from `version_code`
+    version_code_abi_scheme: Option<VersionCodeAbiScheme>,
+    /// Platform whose `android.jar` resources are linked against, defaults to the
+    /// `target_sdk_version`
+    compile_sdk_version: Option<u32>,
+    /// Fail instead of building against the newest platform libraries of the NDK when the
+    /// `min_sdk_version` is above them
+    #[serde(default)]
+    strict_min_sdk_version: bool,
     #[serde(flatten)]
     android_manifest: AndroidManifest,
+    /// Don't add a `MAIN`/`LAUNCHER` intent filter to the activity nor export it, for apps
+    /// without a launchable UI
+    #[serde(default)]
+    no_launcher: bool,
+    /// Add the `INTERNET` permission, unless it's already listed in `uses_permission`
+    #[serde(default)]
+    requires_internet: bool,
+    /// Activity that `run` starts when `--activity` isn't given, instead of the Rust activity
+    default_launch_activity: Option<String>,
     #[serde(default)]
     build_targets: Vec<Target>,
     assets: Option<PathBuf>,
     resources: Option<PathBuf>,
     runtime_libs: Option<PathBuf>,
+    /// Image packaged as `@mipmap/ic_launcher_round` and used as `android:roundIcon`
+    round_icon: Option<PathBuf>,
+    /// Image packaged as `@drawable/banner` and used as `android:banner` on Android TV
+    banner: Option<PathBuf>,
+    /// Splash screen shown while the main activity starts
+    splash: Option<Splash>,
+    /// Generates the `android:networkSecurityConfig`
+    network_security: Option<NetworkSecurity>,
     /// Maps profiles to keystores
     #[serde(default)]
     signing: HashMap<String, Signing>,
-    /// Set up reverse port forwarding before launching the application
+    /// Build non-`dev` profiles even when they are signed with the debug keystore or
+    /// `debuggable`, which otherwise requires `--allow-debug-signing`
+    #[serde(default)]
+    allow_debuggable_release: bool,
+    /// Set up reverse port forwarding before launching the application, either to a host
+    /// socket or a table with `to` and `required`
+    #[serde(default)]
+    reverse_port_forward: HashMap<String, ReversePortForward>,
+    /// `adb shell` commands to run after installing and before launching the application
+    #[serde(default)]
+    before_run: Vec<String>,
+    #[serde(default)]
+    run: RunConfig,
+    /// Named sets of device settings, selected with `--device-profile <name>`
+    #[serde(default)]
+    devices: HashMap<String, DeviceProfile>,
+    /// How to treat debug symbols, for all ABIs or per ABI
+    #[serde(default)]
+    strip: Strip,
+    /// Leave unreferenced resources out of the APK
+    #[serde(default)]
+    shrink_resources: bool,
+    /// Resource patterns that are never removed by `shrink_resources`
+    #[serde(default)]
+    shrink_keep: Vec<String>,
+    /// Add the build profile, git revision and build time as application meta-data
+    #[serde(default)]
+    embed_build_info: bool,
+    /// Additional raw arguments for every inner `cargo` invocation
     #[serde(default)]
-    reverse_port_forward: HashMap<String, String>,
+    cargo_flags: Vec<String>,
+    /// Unstable flags passed to every inner `cargo` invocation as `-Z <flag>`
     #[serde(default)]
-    strip: StripConfig,
+    cargo_unstable_flags: Vec<String>,
+    #[serde(default)]
+    lint: LintConfig,
 }
 
+/// `[package.metadata.android.run]`
 #[derive(Clone, Debug, Default, Deserialize)]
+pub struct RunConfig {
+    /// `adb shell` commands to run after installing and before launching the application,
+    /// following the top-level `before_run` commands
+    #[serde(default)]
+    pub pre_run_shell: Vec<String>,
+    /// `adb shell` commands to run once the application exited, even when it crashed
+    #[serde(default)]
+    pub post_run_shell: Vec<String>,
+    /// Seconds to wait for the application to reach the foreground after launching it, not
+    /// checked when unset or `0`
+    pub start_timeout: Option<u64>,
+    /// Times to retry looking up the UID of the installed application for the `logcat`
+    /// filter, waiting twice as long before every retry
+    pub uid_retries: Option<u32>,
+}
+
+/// `[package.metadata.android.devices.<name>]`, the settings of one device that are selected
+/// together with `--device-profile <name>`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct DeviceProfile {
+    /// Serial of the device (see `adb devices`), unless one is given with `--device`
+    pub serial: Option<String>,
+    /// Android ABI to build for, instead of the `build_targets` or the detected one, unless
+    /// `--target` is given
+    pub abi: Option<String>,
+    /// Extras of the intent that launches the application, by key
+    #[serde(default)]
+    pub extras: BTreeMap<String, IntentExtra>,
+}
+
+impl DeviceProfile {
+    /// The [`Target`] of [`DeviceProfile::abi`], which is validated when parsing the manifest.
+    pub fn target(&self) -> Option<Target> {
+        let abi = self.abi.as_deref()?;
+        Some(Target::from_android_abi(abi).expect("ABI is validated when parsing the manifest"))
+    }
+}
+
+/// The value of an intent extra, passed to `am start` with the flag of its type.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum IntentExtra {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl IntentExtra {
+    /// The `am start` arguments that add this value as the extra `key`.
+    pub(crate) fn am_start_args(&self, key: &str) -> [String; 3] {
+        let (flag, value) = match self {
+            Self::Bool(value) => ("--ez", value.to_string()),
+            Self::Int(value) if i32::try_from(*value).is_ok() => ("--ei", value.to_string()),
+            Self::Int(value) => ("--el", value.to_string()),
+            Self::Float(value) => ("--ef", value.to_string()),
+            Self::String(value) => ("--es", value.clone()),
+        };
+        [flag.to_owned(), key.to_owned(), value]
+    }
+}
+
+/// `splash`, the splash screen of the main activity.
+///
+/// Uses the splash screen attributes of Android 12+ and falls back to a `windowBackground`
+/// showing the same on older versions.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct Splash {
+    /// Background color, as `#RGB`, `#ARGB`, `#RRGGBB` or `#AARRGGBB`
+    pub background: String,
+    /// Image shown in the center, relative to the crate
+    pub icon: Option<PathBuf>,
+}
+
+impl Splash {
+    fn validate(&self) -> Result<(), Error> {
+        let is_color = self.background.strip_prefix('#').is_some_and(|hex| {
+            [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        });
+        if !is_color {
+            return Err(Error::InvalidSplashColor(self.background.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// `[package.metadata.android.network_security]`, generated into the
+/// `@xml/network_security_config` resource and set as `android:networkSecurityConfig`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct NetworkSecurity {
+    /// Domains that cleartext (HTTP) traffic is allowed to
+    #[serde(default)]
+    pub cleartext_domains: Vec<String>,
+    /// Domains that cleartext traffic is allowed to in `dev` builds only, e.g. `localhost`
+    #[serde(default)]
+    pub debug_cleartext_domains: Vec<String>,
+    /// Certificates trusted for all domains instead of the system ones: `system`, `user`, or a
+    /// certificate file relative to the crate
+    #[serde(default)]
+    pub trust_anchors: Vec<String>,
+    /// Certificates additionally trusted in `dev` builds only, like `trust_anchors`
+    #[serde(default)]
+    pub debug_trust_anchors: Vec<String>,
+    /// Certificate pins by domain
+    #[serde(default)]
+    pub pins: Vec<CertificatePins>,
+}
+
+/// The pins of a domain in [`NetworkSecurity::pins`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct CertificatePins {
+    pub domain: String,
+    #[serde(default)]
+    pub include_subdomains: bool,
+    /// Base64 SHA-256 digests of the public keys (`SubjectPublicKeyInfo`) of the certificates,
+    /// of which the chain must contain one
+    pub sha256: Vec<String>,
+    /// Date (`YYYY-MM-DD`) after which the pins are no longer enforced
+    pub expiration: Option<String>,
+}
+
+impl NetworkSecurity {
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: String| Err(Error::InvalidNetworkSecurity(message));
+        for pins in &self.pins {
+            let domain = &pins.domain;
+            if pins.sha256.is_empty() {
+                return invalid(format!("the pins of `{domain}` need at least one `sha256`"));
+            }
+            for pin in &pins.sha256 {
+                let is_digest = pin.len() == 44
+                    && pin.ends_with('=')
+                    && pin[..43]
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/');
+                if !is_digest {
+                    return invalid(format!(
+                        "`{pin}` of `{domain}` is not a base64 SHA-256 digest"
+                    ));
+                }
+            }
+            if let Some(date) = &pins.expiration {
+                let is_date = date.len() == 10
+                    && date.char_indices().all(|(i, c)| match i {
+                        4 | 7 => c == '-',
+                        _ => c.is_ascii_digit(),
+                    });
+                if !is_date {
+                    return invalid(format!(
+                        "the `expiration` of `{domain}` must be a date like `2027-01-01`, got `{date}`"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `strip`, either a single [`StripConfig`] for all ABIs or a table of them keyed by Android
+/// ABI, e.g. `strip = { arm64-v8a = "none", armeabi-v7a = "strip" }`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Strip {
+    All(StripConfig),
+    /// ABIs that are not listed use [`StripConfig::Default`]
+    PerAbi(HashMap<String, StripConfig>),
+}
+
+impl Default for Strip {
+    fn default() -> Self {
+        Self::All(StripConfig::default())
+    }
+}
+
+impl Strip {
+    /// The [`StripConfig`] for the libraries of `target`.
+    pub fn for_target(&self, target: Target) -> StripConfig {
+        match self {
+            Self::All(strip) => *strip,
+            Self::PerAbi(strip) => strip.get(target.android_abi()).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Rejects keys that are not Android ABIs, which would otherwise be ignored silently.
+    fn validate(&self) -> Result<(), Error> {
+        let Self::PerAbi(strip) = self else {
+            return Ok(());
+        };
+        validate_abis("strip", strip.keys())
+    }
+}
+
+/// Rejects `abis` configured through the metadata `key` that are not Android ABIs.
+fn validate_abis<'a>(
+    key: &'static str,
+    mut abis: impl Iterator<Item = &'a String>,
+) -> Result<(), Error> {
+    match abis.find(|abi| Target::from_android_abi(abi).is_err()) {
+        Some(abi) => Err(Error::UnknownAbi {
+            key,
+            abi: abi.clone(),
+            accepted: ABIS.map(Target::android_abi).to_vec(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The Android ABIs, in the order of their [`VersionCodeAbiScheme::Suffix`]
+const ABIS: [Target; 4] = [
+    Target::ArmV7a,
+    Target::Arm64V8a,
+    Target::X86,
+    Target::X86_64,
+];
+
+/// `version_code_abi_scheme`, which derives a distinct `versionCode` for each APK containing
+/// a single ABI from the base code, as Google Play requires for per-ABI APKs of a release.
+///
+/// The schemes never change for an ABI, so that the codes grow with the base code.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "RawVersionCodeAbiScheme")]
+pub enum VersionCodeAbiScheme {
+    /// `"suffix"`: `base * 10 + n` with `n` being 1 for `armeabi-v7a`, 2 for `arm64-v8a`, 3
+    /// for `x86` and 4 for `x86_64`
+    Suffix,
+    /// A table of offsets per ABI, added to the base code
+    Offsets(HashMap<String, u32>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawVersionCodeAbiScheme {
+    Name(String),
+    Offsets(HashMap<String, u32>),
+}
+
+impl TryFrom<RawVersionCodeAbiScheme> for VersionCodeAbiScheme {
+    type Error = String;
+
+    fn try_from(raw: RawVersionCodeAbiScheme) -> Result<Self, Self::Error> {
+        match raw {
+            RawVersionCodeAbiScheme::Name(name) if name == "suffix" => Ok(Self::Suffix),
+            RawVersionCodeAbiScheme::Name(name) => Err(format!(
+                "unknown `version_code_abi_scheme` {name:?}, expected \"suffix\" or a table of offsets per ABI"
+            )),
+            RawVersionCodeAbiScheme::Offsets(offsets) => Ok(Self::Offsets(offsets)),
+        }
+    }
+}
+
+impl VersionCodeAbiScheme {
+    /// The `versionCode` of an APK that only contains the libraries of `target`.
+    pub fn version_code(&self, base: u32, target: Target) -> Result<u32, Error> {
+        let abi = target.android_abi();
+        let code = match self {
+            Self::Suffix => {
+                let suffix = ABIS.iter().position(|t| *t == target).unwrap() as u32 + 1;
+                base.checked_mul(10)
+                    .and_then(|code| code.checked_add(suffix))
+            }
+            Self::Offsets(offsets) => {
+                let offset = offsets
+                    .get(abi)
+                    .ok_or_else(|| Error::MissingVersionCodeAbiOffset(abi.to_owned()))?;
+                base.checked_add(*offset)
+            }
+        };
+        // Play caps versionCodes at 2100000000
+        code.filter(|code| *code <= 2_100_000_000)
+            .ok_or(Error::VersionCodeOverflow { abi, base })
+    }
+
+    /// The ABIs this scheme has a `versionCode` for.
+    pub fn targets(&self) -> Vec<Target> {
+        match self {
+            Self::Suffix => ABIS.to_vec(),
+            Self::Offsets(offsets) => ABIS
+                .into_iter()
+                .filter(|target| offsets.contains_key(target.android_abi()))
+                .collect(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        match self {
+            Self::Suffix => Ok(()),
+            Self::Offsets(offsets) => validate_abis("version_code_abi_scheme", offsets.keys()),
+        }
+    }
+}
+
+/// `[package.metadata.android.signing.<profile>]`
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Signing {
     pub store_path: PathBuf,
-    pub store_password: String,
+    pub store_password: Secret,
+    pub key_alias: Option<String>,
+    pub key_password: Option<Secret>,
+    /// `jarsigner -sigalg` for signing AABs, derived from the key algorithm by default
+    pub sig_alg: Option<String>,
+    /// `jarsigner -digestalg` for signing AABs, `SHA-256` by default
+    pub digest_alg: Option<String>,
+    /// The key this one replaces, for APK signature scheme v3 key rotation
+    pub rotation: Option<SigningRotation>,
+}
+
+impl Signing {
+    /// A copy with all passwords, including those of the `rotation`, replaced by
+    /// [`Secret::REDACTED`], for displaying the configuration without leaking secrets.
+    #[must_use]
+    pub fn redacted(&self) -> Self {
+        let redact = |_: &Secret| Secret::from(Secret::REDACTED);
+        Self {
+            store_password: Secret::from(Secret::REDACTED),
+            key_password: self.key_password.as_ref().map(redact),
+            rotation: self.rotation.as_ref().map(|rotation| SigningRotation {
+                store_password: Secret::from(Secret::REDACTED),
+                key_password: rotation.key_password.as_ref().map(redact),
+                ..rotation.clone()
+            }),
+            ..self.clone()
+        }
+    }
+}
+
+/// `[package.metadata.android.signing.<profile>.rotation]`
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SigningRotation {
+    /// Keystore of the previous key
+    pub store_path: PathBuf,
+    pub store_password: Secret,
     pub key_alias: Option<String>,
-    pub key_password: Option<String>,
-}
\ No newline at end of file
+    pub key_password: Option<Secret>,
+    /// Lineage linking the previous key to the current one, created with `apksigner rotate`
+    pub lineage: PathBuf,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Writes `contents` to the `Cargo.toml` of a fresh test crate `name`.
+    pub(crate) fn write_manifest(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("cargo-android-tests").join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Cargo.toml");
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn version_code_from_semver() {
+        let path = write_manifest(
+            "version_code_from_semver",
+            "[package]\nversion = \"1.2.3\"\n",
+        );
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        assert_eq!(compute_version_code(&manifest, None).unwrap(), 0x01010203);
+    }
+
+    #[test]
+    fn version_code_inherited_from_workspace() {
+        let path = write_manifest(
+            "version_code_inherited",
+            "[package]\nversion.workspace = true\n",
+        );
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        assert!(matches!(
+            compute_version_code(&manifest, None),
+            Err(Error::InheritanceMissingWorkspace)
+        ));
+
+        let root = write_manifest(
+            "version_code_inherited_root",
+            "[workspace]\n[workspace.package]\nversion = \"0.4.10\"\n",
+        );
+        let root = Root::parse_from_toml(&root).unwrap();
+        assert_eq!(
+            compute_version_code(&manifest, Some(&root)).unwrap(),
+            0x0100040a
+        );
+    }
+
+    #[test]
+    fn version_name_override() {
+        let path = write_manifest("version_name_default", "[package]\nversion = \"1.2.3\"\n");
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        assert_eq!(compute_version_name(&manifest, None).unwrap(), "1.2.3");
+
+        let path = write_manifest(
+            "version_name_override",
+            "[package]\nversion = \"1.2.3\"\n[package.metadata.android]\nversion_name = \"1.2.3-beta\"\n",
+        );
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        assert_eq!(compute_version_name(&manifest, None).unwrap(), "1.2.3-beta");
+    }
+
+    #[test]
+    fn version_code_override() {
+        let path = write_manifest(
+            "version_code_override",
+            "[package]\nversion = \"1.2.3\"\n[package.metadata.android]\nversion_code = 42\n",
+        );
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        assert_eq!(compute_version_code(&manifest, None).unwrap(), 42);
+    }
+
+    #[test]
+    fn build_targets_by_abi_or_triple() {
+        let path = write_manifest(
+            "build_targets_by_abi_or_triple",
+            "[package]\nversion = \"1.2.3\"\n[package.metadata.android]\nbuild_targets = [\"arm64-v8a\", \"x86_64-linux-android\"]\n",
+        );
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        assert_eq!(manifest.build_targets, [Target::Arm64V8a, Target::X86_64]);
+
+        let path = write_manifest(
+            "build_targets_unknown",
+            "[package]\nversion = \"1.2.3\"\n[package.metadata.android]\nbuild_targets = [\"arm64\"]\n",
+        );
+        let error = Manifest::parse_from_toml(&path)
+            .err()
+            .expect("unknown target");
+        // Printed as the cause of `Error::Config`
+        let error = std::error::Error::source(&error).unwrap().to_string();
+        assert!(error.contains("unknown target `arm64`"), "{error}");
+        assert!(
+            error.contains("`armeabi-v7a`, `arm64-v8a`, `x86`, `x86_64`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn artifact_name_placeholders() {
+        assert_eq!(
+            expand_artifact_name("{name}-{version}", "myapp", "1.2.3"),
+            "myapp-1.2.3"
+        );
+        assert_eq!(expand_artifact_name("myapp", "other", "1.2.3"), "myapp");
+    }
+
+    #[test]
+    fn android_package_candidates() {
+        let root = write_manifest(
+            "android_candidates",
+            "[workspace]\nmembers = [\"apps/*\", \"tool\"]\n",
+        );
+        let root_dir = root.parent().unwrap();
+        for (member, contents) in [
+            ("apps/game", "[package]\nname = \"game\"\nversion = \"1.0.0\"\n[package.metadata.android]\napk_name = \"game\"\n"),
+            ("apps/viewer", "[package]\nname = \"viewer\"\nversion = \"1.0.0\"\n[package.metadata.android.sdk]\ntarget_sdk_version = 33\n"),
+            ("tool", "[package]\nname = \"tool\"\nversion = \"1.0.0\"\n"),
+        ] {
+            let dir = root_dir.join(member);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("Cargo.toml"), contents).unwrap();
+        }
+
+        let tool = root_dir.join("tool/Cargo.toml");
+        assert!(matches!(
+            ensure_android_package("tool", &tool, Some(&root)),
+            Err(Error::NotAnAndroidPackage { package, candidates })
+                if package == "tool" && candidates == ["game", "viewer"]
+        ));
+        let game = root_dir.join("apps/game/Cargo.toml");
+        ensure_android_package("game", &game, Some(&root)).unwrap();
+        ensure_android_package("tool", &tool, None).unwrap();
+    }
+
+    #[test]
+    fn example_required_features() {
+        use clap::Parser;
+
+        let path = write_manifest(
+            "example_required_features",
+            r#"
+[package]
+version = "1.0.0"
+
+[features]
+audio = []
+
+[dependencies]
+oboe = { version = "0.6", optional = true }
+
+[[example]]
+name = "with_audio"
+required-features = ["audio", "oboe"]
+
+[[example]]
+name = "with_typo"
+required-features = ["adio"]
+"#,
+        );
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        let args = |args: &[&str]| Args::parse_from(["cargo"].iter().chain(args));
+
+        assert_eq!(
+            example_features(&manifest, "with_audio", &args(&[])).unwrap(),
+            ["audio", "oboe"]
+        );
+        assert_eq!(
+            example_features(&manifest, "with_audio", &args(&["--features", "audio"])).unwrap(),
+            ["oboe"]
+        );
+        assert!(example_features(&manifest, "plain", &args(&[]))
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            example_features(&manifest, "with_audio", &args(&["--no-default-features"])),
+            Err(Error::MissingRequiredFeatures { features, .. }) if features == ["audio", "oboe"]
+        ));
+        assert!(matches!(
+            example_features(&manifest, "with_typo", &args(&[])),
+            Err(Error::UndeclaredRequiredFeatures { features, .. }) if features == ["adio"]
+        ));
+    }
+
+    #[test]
+    fn compile_sdk_version() {
+        let path = write_manifest(
+            "compile_sdk_version",
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android]\ncompile_sdk_version = 35\n[package.metadata.android.sdk]\nmin_sdk_version = 24\n",
+        );
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        assert_eq!(manifest.compile_sdk_version(33).unwrap(), 35);
+        assert!(matches!(
+            manifest.compile_sdk_version(36),
+            Err(Error::InvalidSdkVersions {
+                compile: 35,
+                target: 36,
+                ..
+            })
+        ));
+        assert!(matches!(
+            manifest.compile_sdk_version(23),
+            Err(Error::InvalidSdkVersions { min: 24, .. })
+        ));
+
+        let path = write_manifest("compile_sdk_default", "[package]\nversion = \"1.0.0\"\n");
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        assert_eq!(manifest.compile_sdk_version(33).unwrap(), 33);
+    }
+
+    #[test]
+    fn invalid_apk_name() {
+        // TOML string literals and the values they parse to
+        for (literal, name) in [
+            ("''", ""),
+            ("'..'", ".."),
+            ("'dist/app'", "dist/app"),
+            ("'app\\name'", "app\\name"),
+            ("\"app\\u0000\"", "app\0"),
+        ] {
+            let path = write_manifest(
+                "invalid_apk_name",
+                &format!("[package]\nversion = \"1.0.0\"\n[package.metadata.android]\napk_name = {literal}\n"),
+            );
+            assert!(matches!(
+                Manifest::parse_from_toml(&path),
+                Err(Error::InvalidFileName { key: "apk_name", name: n }) if n == name
+            ));
+        }
+        let path = write_manifest(
+            "valid_apk_name",
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android]\napk_name = \"{name}-{version}\"\n",
+        );
+        assert!(Manifest::parse_from_toml(&path).is_ok());
+    }
+
+    /// Parses `tests/fixtures/<fixture>.toml` as the `Cargo.toml` of a package.
+    fn fixture(fixture: &str) -> PathBuf {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(fixture)
+            .with_extension("toml");
+        write_manifest(
+            &format!("fixture_{fixture}"),
+            &std::fs::read_to_string(path).unwrap(),
+        )
+    }
+
+    #[test]
+    fn fixtures() {
+        let workspace = Root::parse_from_toml(&fixture("workspace-root")).unwrap();
+        for (name, version, version_name, package, sdk_versions, build_targets) in [
+            ("minimal", "0.1.0", "0.1.0", "", (Some(23), None), &[][..]),
+            (
+                "full",
+                "1.2.3",
+                "1.2.3-beta.1",
+                "com.example.full",
+                (Some(24), Some(33)),
+                &[Target::Arm64V8a, Target::X86_64][..],
+            ),
+            (
+                "workspace-member",
+                "0.4.10",
+                "0.4.10",
+                "com.example.app",
+                (Some(21), None),
+                &[Target::ArmV7a][..],
+            ),
+        ] {
+            let manifest = Manifest::parse_from_toml(&fixture(name))
+                .unwrap()
+                .resolve(Some(&workspace))
+                .unwrap();
+            assert_eq!(manifest.version, Inheritable::Value(version.to_owned()));
+            assert_eq!(
+                compute_version_name(&manifest, None).unwrap(),
+                version_name,
+                "{name}"
+            );
+            let sdk = &manifest.android_manifest.sdk;
+            assert_eq!(manifest.android_manifest.package, package, "{name}");
+            assert_eq!(
+                (sdk.min_sdk_version, sdk.target_sdk_version),
+                sdk_versions,
+                "{name}"
+            );
+            assert_eq!(manifest.build_targets, build_targets, "{name}");
+        }
+
+        let member = Manifest::parse_from_toml(&fixture("workspace-member")).unwrap();
+        assert!(matches!(
+            member.resolve(None),
+            Err(Error::InheritanceMissingWorkspace)
+        ));
+    }
+
+    #[test]
+    fn signing_round_trip() {
+        let manifest = Manifest::parse_from_toml(&fixture("full")).unwrap();
+        assert_eq!(manifest.signing.len(), 2);
+        let release = &manifest.signing["release"];
+        assert_eq!(release.store_path, Path::new("keys/release.keystore"));
+        assert_eq!(release.sig_alg.as_deref(), Some("SHA256withECDSA"));
+        assert_eq!(release.digest_alg.as_deref(), Some("SHA-384"));
+        let rotation = release.rotation.as_ref().unwrap();
+        assert_eq!(rotation.store_path, Path::new("keys/previous.keystore"));
+        assert_eq!(rotation.key_alias.as_deref(), Some("previous"));
+        let dev = &manifest.signing["dev"];
+        assert_eq!(
+            (&dev.key_alias, &dev.digest_alg, &dev.rotation),
+            (&None, &None, &None)
+        );
+        for signing in manifest.signing.values() {
+            let toml = toml::to_string(signing).unwrap();
+            assert_eq!(&toml::from_str::<Signing>(&toml).unwrap(), signing);
+        }
+        for version in [
+            Inheritable::Value("1.0.0".to_owned()),
+            Inheritable::Inherited { workspace: true },
+        ] {
+            let toml = toml::to_string(
+                &[("version", &version)]
+                    .into_iter()
+                    .collect::<HashMap<_, _>>(),
+            )
+            .unwrap();
+            let parsed: HashMap<String, Inheritable<String>> = toml::from_str(&toml).unwrap();
+            assert_eq!(parsed["version"], version);
+        }
+
+        // Passwords never show up in debug output, even without `redacted()`
+        let debug = format!("{:?}", manifest.signing);
+        for signing in manifest.signing.values() {
+            assert!(!debug.contains(signing.store_password.expose()), "{debug}");
+        }
+
+        let release = manifest.signing["release"].redacted();
+        assert_eq!(release.store_password.expose(), Secret::REDACTED);
+        assert_eq!(
+            release.key_password.as_ref().map(Secret::expose),
+            Some(Secret::REDACTED)
+        );
+        assert_eq!(release.key_alias.as_deref(), Some("upload"));
+        let rotation = release.rotation.unwrap();
+        assert_eq!(rotation.store_password.expose(), Secret::REDACTED);
+        assert_eq!(rotation.key_password, None);
+        assert_eq!(rotation.lineage, Path::new("keys/lineage"));
+    }
+
+    #[test]
+    fn strip_per_abi() {
+        let path = write_manifest(
+            "strip_per_abi",
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android]\nstrip = { arm64-v8a = \"none\", armeabi-v7a = \"strip\" }\n",
+        );
+        let strip = Manifest::parse_from_toml(&path).unwrap().strip;
+        assert_eq!(strip.for_target(Target::Arm64V8a), StripConfig::Default);
+        assert_eq!(strip.for_target(Target::ArmV7a), StripConfig::Strip);
+        assert_eq!(strip.for_target(Target::X86_64), StripConfig::Default);
+
+        let path = write_manifest(
+            "strip_all",
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android]\nstrip = \"split\"\n",
+        );
+        let strip = Manifest::parse_from_toml(&path).unwrap().strip;
+        assert_eq!(strip.for_target(Target::X86), StripConfig::Split);
+
+        let path = write_manifest(
+            "strip_modes",
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android]\nstrip = { arm64-v8a = \"debug\", x86 = \"symbols\", x86_64 = \"all\" }\n",
+        );
+        let strip = Manifest::parse_from_toml(&path).unwrap().strip;
+        assert_eq!(strip.for_target(Target::Arm64V8a), StripConfig::Strip);
+        assert_eq!(
+            strip.for_target(Target::X86).objcopy_flag(),
+            Some("--discard-all")
+        );
+        assert_eq!(
+            strip.for_target(Target::X86_64).objcopy_flag(),
+            Some("--strip-all")
+        );
+
+        let path = write_manifest(
+            "strip_unknown_abi",
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android]\nstrip = { arm64 = \"none\" }\n",
+        );
+        assert!(matches!(
+            Manifest::parse_from_toml(&path),
+            Err(Error::UnknownAbi { key: "strip", abi, accepted }) if abi == "arm64" && accepted.contains(&"arm64-v8a")
+        ));
+    }
+    #[test]
+    fn reverse_port_forward_entries() {
+        let path = write_manifest(
+            "reverse_port_forward",
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android.reverse_port_forward]\n\"tcp:1338\" = \"tcp:1338\"\n\"tcp:8080\" = { to = \"tcp:80\", required = true }\n\"tcp:9000\" = { to = \"tcp:9000\" }\n",
+        );
+        let forwards = Manifest::parse_from_toml(&path)
+            .unwrap()
+            .reverse_port_forward;
+        assert_eq!(
+            forwards["tcp:1338"],
+            ReversePortForward::To("tcp:1338".to_owned())
+        );
+        assert!(!forwards["tcp:1338"].required());
+        assert_eq!(forwards["tcp:8080"].to(), "tcp:80");
+        assert!(forwards["tcp:8080"].required());
+        assert!(!forwards["tcp:9000"].required());
+    }
+    #[test]
+    fn version_code_abi_schemes() {
+        let parse = |name, scheme| {
+            let path = write_manifest(
+                name,
+                &format!("[package]\nversion = \"1.0.0\"\n[package.metadata.android]\nversion_code = 7\nversion_code_abi_scheme = {scheme}\n"),
+            );
+            Manifest::parse_from_toml(&path)
+        };
+
+        let suffix = parse("abi_scheme_suffix", "\"suffix\"")
+            .unwrap()
+            .version_code_abi_scheme
+            .unwrap();
+        assert_eq!(suffix.version_code(7, Target::ArmV7a).unwrap(), 71);
+        assert_eq!(suffix.version_code(7, Target::Arm64V8a).unwrap(), 72);
+        assert_eq!(suffix.version_code(7, Target::X86_64).unwrap(), 74);
+        // Never decreases when the base code grows
+        assert!(
+            suffix.version_code(8, Target::ArmV7a).unwrap()
+                > suffix.version_code(7, Target::X86_64).unwrap()
+        );
+        assert!(matches!(
+            suffix.version_code(u32::MAX / 10, Target::X86),
+            Err(Error::VersionCodeOverflow { abi: "x86", .. })
+        ));
+
+        let offsets = parse("abi_scheme_offsets", "{ arm64-v8a = 2000, x86_64 = 4000 }")
+            .unwrap()
+            .version_code_abi_scheme
+            .unwrap();
+        assert_eq!(offsets.version_code(7, Target::Arm64V8a).unwrap(), 2007);
+        assert_eq!(offsets.targets(), [Target::Arm64V8a, Target::X86_64]);
+        assert!(matches!(
+            offsets.version_code(7, Target::X86),
+            Err(Error::MissingVersionCodeAbiOffset(abi)) if abi == "x86"
+        ));
+
+        assert!(matches!(
+            parse("abi_scheme_unknown_abi", "{ arm64 = 2000 }"),
+            Err(Error::UnknownAbi {
+                key: "version_code_abi_scheme",
+                ..
+            })
+        ));
+        assert!(parse("abi_scheme_unknown", "\"prefix\"").is_err());
+    }
+    #[test]
+    fn device_profiles() {
+        let path = write_manifest(
+            "device_profiles",
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android.devices.phone]\nserial = \"R58M\"\nabi = \"arm64-v8a\"\nextras = { demo = true, level = 3, scale = 1.5, name = \"it's me\", big = 5000000000 }\n[package.metadata.android.devices.ci]\n",
+        );
+        let devices = Manifest::parse_from_toml(&path).unwrap().devices;
+        let phone = &devices["phone"];
+        assert_eq!(phone.serial.as_deref(), Some("R58M"));
+        assert_eq!(phone.target(), Some(Target::Arm64V8a));
+        let args = phone
+            .extras
+            .iter()
+            .map(|(key, value)| value.am_start_args(key).join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            [
+                "--el big 5000000000",
+                "--ez demo true",
+                "--ei level 3",
+                "--es name it's me",
+                "--ef scale 1.5"
+            ]
+        );
+        assert_eq!(devices["ci"], DeviceProfile::default());
+
+        let path = write_manifest(
+            "device_profile_unknown_abi",
+            "[package]\nversion = \"1.0.0\"\n[package.metadata.android.devices.tablet]\nabi = \"x64\"\n",
+        );
+        assert!(matches!(
+            Manifest::parse_from_toml(&path),
+            Err(Error::UnknownAbi { key: "devices", abi, .. }) if abi == "x64"
+        ));
+    }
+
+    #[test]
+    fn network_security_pins_are_validated() {
+        let pins = |pin: &str, expiration: &str| {
+            format!("[package]\nversion = \"1.0.0\"\n[[package.metadata.android.network_security.pins]]\ndomain = \"example.com\"\nsha256 = [\"{pin}\"]\nexpiration = \"{expiration}\"\n")
+        };
+        let pin = "7HIpactkIAq2Y49orFOOQKurWxmmSFZhBCoQYcRhJ3Y=";
+
+        let path = write_manifest("network_security", &pins(pin, "2027-01-01"));
+        let network_security = Manifest::parse_from_toml(&path)
+            .unwrap()
+            .network_security
+            .unwrap();
+        assert_eq!(network_security.pins[0].sha256, [pin]);
+
+        for (name, pin, expiration) in [
+            ("network_security_hex", "9f86d081", "2027-01-01"),
+            ("network_security_bad_date", pin, "01/01/2027"),
+        ] {
+            let path = write_manifest(name, &pins(pin, expiration));
+            assert!(matches!(
+                Manifest::parse_from_toml(&path),
+                Err(Error::InvalidNetworkSecurity(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! Compares the `AndroidManifest.xml` of two built APKs or AABs, e.g. the previous release and
+//! the current build, to review changes such as new permissions before shipping.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+use ndk_build::error::NdkError;
+use ndk_build::ndk::Ndk;
+use serde::Serialize;
+
+use crate::error::Error;
+
+const ANDROID_NAMESPACE: &str = "http://schemas.android.com/apk/res/android:";
+
+/// Elements that declare the permissions an app requests
+const PERMISSION_ELEMENTS: &[&str] = &["uses-permission", "uses-permission-sdk-23"];
+
+/// Elements that declare the app's components
+const COMPONENT_ELEMENTS: &[&str] = &[
+    "activity",
+    "activity-alias",
+    "service",
+    "receiver",
+    "provider",
+];
+
+/// The elements of a manifest by their path, such as
+/// `manifest/application/activity[android.app.NativeActivity]`, with their attributes.
+///
+/// Elements are identified by their `android:name` where they have one, and otherwise by
+/// their position among the siblings of the same name, as in `intent-filter#2`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ManifestTree {
+    elements: BTreeMap<String, Element>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Element {
+    tag: String,
+    attributes: BTreeMap<String, String>,
+}
+
+impl ManifestTree {
+    /// Parses the output of `aapt2 dump xmltree` (or `aapt dump xmltree`), with the attribute
+    /// values and the namespace of `android:` attributes normalized, so that the manifests of
+    /// different builds compare equal when they declare the same.
+    pub fn parse_xmltree(dump: &str) -> Self {
+        // The elements with their indentation, which nests them
+        let mut nodes: Vec<(usize, Element)> = Vec::new();
+        for line in dump.lines() {
+            let indent = line.len() - line.trim_start().len();
+            let line = line.trim();
+            if let Some(tag) = line.strip_prefix("E: ") {
+                let tag = tag.split(" (line=").next().unwrap_or(tag).to_owned();
+                nodes.push((
+                    indent,
+                    Element {
+                        tag,
+                        attributes: BTreeMap::new(),
+                    },
+                ));
+            } else if let Some((_, element)) = nodes.last_mut() {
+                if let Some((name, value)) = line.strip_prefix("A: ").and_then(parse_attribute) {
+                    element.attributes.insert(name, value);
+                }
+            }
+        }
+
+        let mut tree = Self::default();
+        let mut parents: Vec<(usize, String)> = Vec::new();
+        let mut siblings: BTreeMap<String, usize> = BTreeMap::new();
+        for (indent, element) in nodes {
+            while parents.last().is_some_and(|(depth, _)| *depth >= indent) {
+                parents.pop();
+            }
+            let mut path = match &element.attributes.get("android:name") {
+                Some(name) => format!("{}[{name}]", element.tag),
+                None => element.tag.clone(),
+            };
+            if let Some((_, parent)) = parents.last() {
+                path = format!("{parent}/{path}");
+            }
+            let count = siblings.entry(path.clone()).or_default();
+            *count += 1;
+            if *count > 1 {
+                path = format!("{path}#{count}");
+            }
+            parents.push((indent, path.clone()));
+            tree.elements.insert(path, element);
+        }
+        tree
+    }
+
+    /// The `android:name`s of the elements with one of the `tags`, as `<tag> <name>`, or just
+    /// the name when `with_tag` is unset.
+    fn names(&self, tags: &[&str], with_tag: bool) -> BTreeSet<String> {
+        self.elements
+            .values()
+            .filter(|element| tags.contains(&element.tag.as_str()))
+            .filter_map(|element| {
+                let name = element.attributes.get("android:name")?;
+                Some(if with_tag {
+                    format!("{} {name}", element.tag)
+                } else {
+                    name.clone()
+                })
+            })
+            .collect()
+    }
+}
+
+/// Parses an attribute line of `aapt2 dump xmltree` (without the `A: ` prefix) into its name and
+/// value, such as `http://schemas.android.com/apk/res/android:name(0x01010003)="foo" (Raw: "foo")`
+/// into `android:name` and `foo`.
+fn parse_attribute(attribute: &str) -> Option<(String, String)> {
+    let (name, value) = attribute.split_once('=')?;
+    let name = name
+        .strip_prefix(ANDROID_NAMESPACE)
+        .map_or_else(|| name.to_owned(), |name| format!("android:{name}"));
+    // Drop the resource id of the attribute
+    let name = match name.find("(0x") {
+        Some(index) => name[..index].to_owned(),
+        None => name,
+    };
+    // `aapt` prefixes typed values with their type
+    let value = match value.strip_prefix("(type ") {
+        Some(typed) => typed.split_once(')').map_or(typed, |(_, value)| value),
+        None => value,
+    };
+    let value = match value.strip_prefix('"') {
+        Some(quoted) => quoted
+            .rsplit_once("\" (Raw: ")
+            .map_or(quoted.trim_end_matches('"'), |(value, _)| value),
+        None => value,
+    };
+    Some((name, value.to_owned()))
+}
+
+/// A changed attribute of an element that is present in both manifests.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AttributeChange {
+    pub element: String,
+    pub attribute: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// The differences between two manifests, see [`ManifestDiff::between()`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ManifestDiff {
+    pub added_permissions: Vec<String>,
+    pub removed_permissions: Vec<String>,
+    /// Components as `<tag> <name>`, e.g. `service com.example.SyncService`
+    pub added_components: Vec<String>,
+    pub removed_components: Vec<String>,
+    /// Paths of other elements, only the outermost one when whole subtrees were added
+    pub added_elements: Vec<String>,
+    pub removed_elements: Vec<String>,
+    pub changed_attributes: Vec<AttributeChange>,
+}
+
+impl ManifestDiff {
+    pub fn between(old: &ManifestTree, new: &ManifestTree) -> Self {
+        let difference = |a: &BTreeSet<String>, b: &BTreeSet<String>| {
+            a.difference(b).cloned().collect::<Vec<_>>()
+        };
+        let (old_permissions, new_permissions) = (
+            old.names(PERMISSION_ELEMENTS, false),
+            new.names(PERMISSION_ELEMENTS, false),
+        );
+        let (old_components, new_components) = (
+            old.names(COMPONENT_ELEMENTS, true),
+            new.names(COMPONENT_ELEMENTS, true),
+        );
+
+        // The outermost elements that only `tree` has, except for those reported as
+        // permissions or components
+        let elements_only_in = |tree: &ManifestTree, other: &ManifestTree| {
+            let mut outermost: Vec<String> = Vec::new();
+            for path in tree.elements.keys() {
+                if other.elements.contains_key(path)
+                    || outermost
+                        .iter()
+                        .any(|parent| path.starts_with(&format!("{parent}/")))
+                {
+                    continue;
+                }
+                outermost.push(path.clone());
+            }
+            outermost
+                .into_iter()
+                .filter(|path| {
+                    let tag = &tree.elements[path].tag;
+                    !PERMISSION_ELEMENTS.contains(&tag.as_str())
+                        && !COMPONENT_ELEMENTS.contains(&tag.as_str())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut changed_attributes = Vec::new();
+        for (path, old_element) in &old.elements {
+            let Some(new_element) = new.elements.get(path) else {
+                continue;
+            };
+            let attributes = old_element
+                .attributes
+                .keys()
+                .chain(new_element.attributes.keys())
+                .collect::<BTreeSet<_>>();
+            for attribute in attributes {
+                let (old_value, new_value) = (
+                    old_element.attributes.get(attribute),
+                    new_element.attributes.get(attribute),
+                );
+                if old_value != new_value {
+                    changed_attributes.push(AttributeChange {
+                        element: path.clone(),
+                        attribute: attribute.clone(),
+                        old: old_value.cloned(),
+                        new: new_value.cloned(),
+                    });
+                }
+            }
+        }
+
+        Self {
+            added_permissions: difference(&new_permissions, &old_permissions),
+            removed_permissions: difference(&old_permissions, &new_permissions),
+            added_components: difference(&new_components, &old_components),
+            removed_components: difference(&old_components, &new_components),
+            added_elements: elements_only_in(new, old),
+            removed_elements: elements_only_in(old, new),
+            changed_attributes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for ManifestDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "The manifests are equivalent");
+        }
+        let section =
+            |f: &mut fmt::Formatter<'_>, title: &str, added: &[String], removed: &[String]| {
+                if added.is_empty() && removed.is_empty() {
+                    return Ok(());
+                }
+                writeln!(f, "{title}:")?;
+                for line in added {
+                    writeln!(f, "  + {line}")?;
+                }
+                for line in removed {
+                    writeln!(f, "  - {line}")?;
+                }
+                Ok(())
+            };
+        section(
+            f,
+            "Permissions",
+            &self.added_permissions,
+            &self.removed_permissions,
+        )?;
+        section(
+            f,
+            "Components",
+            &self.added_components,
+            &self.removed_components,
+        )?;
+        section(f, "Elements", &self.added_elements, &self.removed_elements)?;
+        if !self.changed_attributes.is_empty() {
+            writeln!(f, "Attributes:")?;
+            for change in &self.changed_attributes {
+                let value = |value: &Option<String>| {
+                    value
+                        .as_deref()
+                        .map_or("(unset)".to_owned(), |value| format!("{value:?}"))
+                };
+                writeln!(
+                    f,
+                    "  ~ {} {}: {} -> {}",
+                    change.element,
+                    change.attribute,
+                    value(&change.old),
+                    value(&change.new)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the manifest of the APK or AAB at `path` with `aapt2 dump xmltree`. The proto-format
+/// manifest of an AAB is first repackaged into a proto APK below `scratch_dir`, which `aapt2`
+/// can read.
+pub(crate) fn dump_manifest(
+    ndk: &Ndk,
+    path: &Path,
+    scratch_dir: &Path,
+) -> Result<ManifestTree, Error> {
+    if !path.is_file() {
+        return Err(NdkError::PathNotFound(path.to_owned()).into());
+    }
+    let apk = if path.extension().is_some_and(|extension| extension == "aab") {
+        let dir = scratch_dir.join("manifest-diff");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let mut unzip = Command::new("unzip");
+        unzip
+            .arg("-o")
+            .arg("-j")
+            .arg(path)
+            .arg("base/manifest/AndroidManifest.xml")
+            .arg("base/resources.pb")
+            .arg("-d")
+            .arg(&dir);
+        if !unzip.output()?.status.success() {
+            return Err(NdkError::CmdFailed(unzip).into());
+        }
+        let apk = dir.join("proto.apk");
+        let mut jar = Command::new("jar");
+        jar.arg("cMf")
+            .arg(&apk)
+            .arg("-C")
+            .arg(&dir)
+            .arg("AndroidManifest.xml")
+            .arg("-C")
+            .arg(&dir)
+            .arg("resources.pb");
+        if !jar.output()?.status.success() {
+            return Err(NdkError::CmdFailed(jar).into());
+        }
+        apk
+    } else {
+        path.to_owned()
+    };
+
+    let mut aapt2 = ndk.build_tool(if cfg!(windows) { "aapt2.exe" } else { "aapt2" })?;
+    aapt2
+        .arg("dump")
+        .arg("xmltree")
+        .arg("--file")
+        .arg("AndroidManifest.xml")
+        .arg(&apk);
+    let output = aapt2.output()?;
+    if !output.status.success() {
+        return Err(NdkError::CmdFailed(aapt2).into());
+    }
+    Ok(ManifestTree::parse_xmltree(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = r#"N: android=http://schemas.android.com/apk/res/android (line=2)
+  E: manifest (line=2)
+    A: http://schemas.android.com/apk/res/android:versionCode(0x0101021b)=1
+    A: package="com.example" (Raw: "com.example")
+    E: uses-sdk (line=6)
+      A: http://schemas.android.com/apk/res/android:minSdkVersion(0x0101020c)=21
+    E: uses-permission (line=8)
+      A: http://schemas.android.com/apk/res/android:name(0x01010003)="android.permission.INTERNET" (Raw: "android.permission.INTERNET")
+    E: application (line=10)
+      A: http://schemas.android.com/apk/res/android:debuggable(0x0101000f)=true
+      E: activity (line=12)
+        A: http://schemas.android.com/apk/res/android:name(0x01010003)="android.app.NativeActivity" (Raw: "android.app.NativeActivity")
+        A: http://schemas.android.com/apk/res/android:exported(0x01010010)=true
+        E: intent-filter (line=14)
+          E: action (line=15)
+            A: http://schemas.android.com/apk/res/android:name(0x01010003)="android.intent.action.MAIN" (Raw: "android.intent.action.MAIN")
+        E: intent-filter (line=17)
+          E: action (line=18)
+            A: http://schemas.android.com/apk/res/android:name(0x01010003)="android.intent.action.VIEW" (Raw: "android.intent.action.VIEW")
+"#;
+
+    #[test]
+    fn parse_xmltree() {
+        let tree = ManifestTree::parse_xmltree(OLD);
+        let activity = "manifest/application/activity[android.app.NativeActivity]";
+        assert_eq!(
+            tree.elements[activity].attributes["android:exported"],
+            "true"
+        );
+        assert_eq!(
+            tree.elements["manifest"].attributes["package"],
+            "com.example"
+        );
+        assert!(tree.elements.contains_key(&format!(
+            "{activity}/intent-filter#2/action[android.intent.action.VIEW]"
+        )));
+        assert_eq!(
+            tree.names(PERMISSION_ELEMENTS, false),
+            BTreeSet::from(["android.permission.INTERNET".to_owned()])
+        );
+
+        // `aapt` prints the namespace prefix and value types differently
+        assert_eq!(
+            parse_attribute("android:versionCode(0x0101021b)=(type 0x10)0x1"),
+            Some(("android:versionCode".to_owned(), "0x1".to_owned()))
+        );
+    }
+
+    #[test]
+    fn diff_manifests() {
+        let old = ManifestTree::parse_xmltree(OLD);
+        assert!(ManifestDiff::between(&old, &old).is_empty());
+
+        let new = OLD
+            .replace("versionCode(0x0101021b)=1", "versionCode(0x0101021b)=2")
+            .replace(
+                "    E: application (line=10)\n      A: http://schemas.android.com/apk/res/android:debuggable(0x0101000f)=true\n",
+                "    E: uses-permission (line=9)\n      A: http://schemas.android.com/apk/res/android:name(0x01010003)=\"android.permission.CAMERA\" (Raw: \"android.permission.CAMERA\")\n    E: uses-feature (line=10)\n      A: http://schemas.android.com/apk/res/android:name(0x01010003)=\"android.hardware.camera\" (Raw: \"android.hardware.camera\")\n    E: application (line=11)\n      E: service (line=12)\n        A: http://schemas.android.com/apk/res/android:name(0x01010003)=\".Sync\" (Raw: \".Sync\")\n        E: intent-filter (line=13)\n",
+            );
+        let diff = ManifestDiff::between(&old, &ManifestTree::parse_xmltree(&new));
+        assert_eq!(diff.added_permissions, ["android.permission.CAMERA"]);
+        assert!(diff.removed_permissions.is_empty());
+        assert_eq!(diff.added_components, ["service .Sync"]);
+        // The components' children aren't repeated
+        assert_eq!(
+            diff.added_elements,
+            ["manifest/uses-feature[android.hardware.camera]"]
+        );
+        assert_eq!(
+            diff.changed_attributes,
+            [
+                AttributeChange {
+                    element: "manifest".to_owned(),
+                    attribute: "android:versionCode".to_owned(),
+                    old: Some("1".to_owned()),
+                    new: Some("2".to_owned()),
+                },
+                AttributeChange {
+                    element: "manifest/application".to_owned(),
+                    attribute: "android:debuggable".to_owned(),
+                    old: Some("true".to_owned()),
+                    new: None,
+                },
+            ]
+        );
+        let printed = diff.to_string();
+        assert!(
+            printed.contains("Permissions:\n  + android.permission.CAMERA\n"),
+            "{printed}"
+        );
+        assert!(
+            printed.contains("  ~ manifest/application android:debuggable: \"true\" -> (unset)\n"),
+            "{printed}"
+        );
+    }
+}
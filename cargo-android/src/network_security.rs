@@ -0,0 +1,169 @@
+//! Generates the `network_security_config` resource from
+//! `[package.metadata.android.network_security]`: the domains that cleartext traffic is allowed
+//! to, the trusted certificates and the certificate pins of domains.
+
+use std::path::Path;
+
+use ndk_build::error::NdkError;
+
+use crate::error::Error;
+use crate::manifest::NetworkSecurity;
+
+/// The generated resource, which is set as the `android:networkSecurityConfig`
+pub(crate) const CONFIG: &str = "@xml/network_security_config";
+
+/// Writes `xml/network_security_config.xml` into `res_dir`, with the certificate files of the
+/// trust anchors, relative to `crate_path`, copied into `raw/`.
+///
+/// The `debug_*` domains and trust anchors are only included in `dev` builds, when
+/// `is_debug_profile` is set, so that they never ship in a release.
+pub(crate) fn write_resources(
+    res_dir: &Path,
+    config: &NetworkSecurity,
+    crate_path: &Path,
+    is_debug_profile: bool,
+) -> Result<(), Error> {
+    let mut certificates = 0;
+    let mut trust_anchors = |anchors: &[String], indent: &str| -> Result<String, Error> {
+        let mut lines = Vec::new();
+        for anchor in anchors {
+            let src = match anchor.as_str() {
+                "system" | "user" => anchor.clone(),
+                path => {
+                    let path = crate_path.join(path);
+                    let name = format!("network_security_ca_{certificates}");
+                    certificates += 1;
+                    let dir = res_dir.join("raw");
+                    std::fs::create_dir_all(&dir)?;
+                    std::fs::copy(&path, dir.join(&name))
+                        .map_err(|e| NdkError::IoPathError(path, e))?;
+                    format!("@raw/{name}")
+                }
+            };
+            lines.push(format!("{indent}    <certificates src=\"{src}\" />"));
+        }
+        Ok(format!(
+            "{indent}<trust-anchors>\n{}\n{indent}</trust-anchors>",
+            lines.join("\n")
+        ))
+    };
+
+    let mut elements = Vec::new();
+    if !config.trust_anchors.is_empty() {
+        elements.push(format!(
+            "    <base-config>\n{}\n    </base-config>",
+            trust_anchors(&config.trust_anchors, "        ")?
+        ));
+    }
+
+    let mut cleartext_domains = config.cleartext_domains.clone();
+    if is_debug_profile {
+        cleartext_domains.extend(config.debug_cleartext_domains.iter().cloned());
+    }
+    if !cleartext_domains.is_empty() {
+        let domains = cleartext_domains
+            .iter()
+            .map(|domain| format!("        <domain>{}</domain>", escape(domain)))
+            .collect::<Vec<_>>();
+        elements.push(format!(
+            "    <domain-config cleartextTrafficPermitted=\"true\">\n{}\n    </domain-config>",
+            domains.join("\n")
+        ));
+    }
+
+    for pins in &config.pins {
+        let expiration = pins
+            .expiration
+            .as_ref()
+            .map(|date| format!(" expiration=\"{}\"", escape(date)))
+            .unwrap_or_default();
+        let digests = pins
+            .sha256
+            .iter()
+            .map(|pin| format!("            <pin digest=\"SHA-256\">{pin}</pin>"))
+            .collect::<Vec<_>>();
+        elements.push(format!(
+            "    <domain-config>\n        <domain includeSubdomains=\"{}\">{}</domain>\n        <pin-set{expiration}>\n{}\n        </pin-set>\n    </domain-config>",
+            pins.include_subdomains,
+            escape(&pins.domain),
+            digests.join("\n")
+        ));
+    }
+
+    if is_debug_profile && !config.debug_trust_anchors.is_empty() {
+        elements.push(format!(
+            "    <debug-overrides>\n{}\n    </debug-overrides>",
+            trust_anchors(&config.debug_trust_anchors, "        ")?
+        ));
+    }
+
+    let path = res_dir.join("xml").join("network_security_config.xml");
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(
+        &path,
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<network-security-config>\n{}\n</network-security-config>\n",
+            elements.join("\n")
+        ),
+    )
+    .map_err(|e| NdkError::IoPathError(path, e))?;
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::CertificatePins;
+
+    #[test]
+    fn network_security_config() {
+        let dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("network_security");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("debug_ca.pem"), "").unwrap();
+        let config = NetworkSecurity {
+            cleartext_domains: vec!["example.org".to_owned()],
+            debug_cleartext_domains: vec!["localhost".to_owned()],
+            trust_anchors: vec!["system".to_owned()],
+            debug_trust_anchors: vec!["user".to_owned(), "debug_ca.pem".to_owned()],
+            pins: vec![CertificatePins {
+                domain: "api.example.com".to_owned(),
+                include_subdomains: true,
+                sha256: vec!["7HIpactkIAq2Y49orFOOQKurWxmmSFZhBCoQYcRhJ3Y=".to_owned()],
+                expiration: Some("2027-01-01".to_owned()),
+            }],
+        };
+
+        let res_dir = dir.join("res-dev");
+        write_resources(&res_dir, &config, &dir, true).unwrap();
+        let xml = std::fs::read_to_string(res_dir.join("xml/network_security_config.xml")).unwrap();
+        assert!(xml.contains("<domain>example.org</domain>"), "{xml}");
+        assert!(xml.contains("<domain>localhost</domain>"), "{xml}");
+        assert!(xml.contains(r#"<domain includeSubdomains="true">api.example.com</domain>"#));
+        assert!(
+            xml.contains(r#"<pin-set expiration="2027-01-01">"#),
+            "{xml}"
+        );
+        assert!(xml.contains("<debug-overrides>"), "{xml}");
+        assert!(xml.contains(r#"<certificates src="@raw/network_security_ca_0" />"#));
+        assert!(res_dir.join("raw/network_security_ca_0").is_file());
+
+        // Debug-only entries don't ship in other profiles
+        let res_dir = dir.join("res-release");
+        write_resources(&res_dir, &config, &dir, false).unwrap();
+        let xml = std::fs::read_to_string(res_dir.join("xml/network_security_config.xml")).unwrap();
+        assert!(xml.contains("<domain>example.org</domain>"), "{xml}");
+        assert!(!xml.contains("localhost"), "{xml}");
+        assert!(!xml.contains("debug-overrides"), "{xml}");
+        assert!(!res_dir.join("raw").exists());
+    }
+}
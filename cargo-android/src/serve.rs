@@ -0,0 +1,248 @@
+//! Serves a signed APK over HTTP on the local network, so that it can be sideloaded onto
+//! devices without `adb`.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::types::QrError;
+use qrcode::QrCode;
+
+use crate::error::Error;
+
+/// Limits of [`serve()`].
+#[derive(Clone, Debug)]
+pub struct ServeOptions {
+    /// Port to listen on, `0` picks a free one
+    pub port: u16,
+    /// Stop serving after this long
+    pub timeout: Duration,
+    /// Stop serving after this many completed downloads
+    pub max_downloads: Option<u32>,
+}
+
+/// Serves `apk` at a random, unguessable path until `options.timeout` elapsed or
+/// `options.max_downloads` downloads completed. The URL is printed together with a QR code,
+/// and every download is logged with the client address.
+pub fn serve(apk: &Path, options: &ServeOptions) -> Result<(), Error> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, options.port))?;
+    listener.set_nonblocking(true)?;
+    let port = listener.local_addr()?.port();
+    let host = lan_address().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let file_name = apk
+        .file_name()
+        .expect("APK path has a file name")
+        .to_string_lossy();
+    let path = format!("/{}/{file_name}", random_token());
+    let url = format!("http://{host}:{port}{path}");
+
+    println!("Serving `{}` at {url}", apk.display());
+    print_qr_code(&url);
+
+    let deadline = Instant::now() + options.timeout;
+    let mut downloads = 0;
+    while Instant::now() < deadline && options.max_downloads != Some(downloads) {
+        match listener.accept() {
+            Ok((stream, client)) => match respond(stream, &path, apk) {
+                Ok(true) => {
+                    downloads += 1;
+                    println!("Downloaded by {client} ({downloads} so far)");
+                }
+                Ok(false) => log::debug!("Rejected request from {client}"),
+                Err(e) => eprintln!("Warning: failed to serve {client}: {e}"),
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    println!("Stopped serving after {downloads} download(s)");
+    Ok(())
+}
+
+/// Answers a single request on `stream`, returning whether `apk` was downloaded.
+fn respond(stream: TcpStream, path: &str, apk: &Path) -> std::io::Result<bool> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    // A client that stops reading would otherwise block serving everyone else
+    stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut stream = reader.into_inner();
+
+    let method = match parse_request_line(&request_line) {
+        Some((method @ ("GET" | "HEAD"), target)) if target == path => method,
+        _ => {
+            stream.write_all(
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )?;
+            return Ok(false);
+        }
+    };
+
+    let mut file = std::fs::File::open(apk)?;
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: application/vnd.android.package-archive\r\n\
+         Content-Length: {}\r\n\
+         Content-Disposition: attachment; filename=\"{file_name}\"\r\n\
+         Connection: close\r\n\r\n",
+        file.metadata()?.len()
+    )?;
+    if method == "HEAD" {
+        return Ok(false);
+    }
+    std::io::copy(&mut file, &mut stream)?;
+    stream.flush()?;
+    Ok(true)
+}
+
+/// Splits an HTTP request line into its method and target.
+fn parse_request_line(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?;
+    let target = parts.next()?;
+    parts
+        .next()
+        .filter(|version| version.starts_with("HTTP/"))?;
+    Some((method, target))
+}
+
+/// The address of the interface that routes to the internet, which is typically the one other
+/// devices on the LAN can reach. No packets are sent to determine it.
+fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket
+        .connect(SocketAddr::from(([192, 0, 2, 1], 80)))
+        .ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified() && !ip.is_loopback()).then_some(ip)
+}
+
+/// A random hexadecimal token, seeded from the per-process random keys of [`RandomState`].
+fn random_token() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+fn print_qr_code(url: &str) {
+    match qr_code(url) {
+        Ok(qr_code) => println!("{qr_code}"),
+        Err(e) => eprintln!("Warning: failed to render a QR code of the URL: {e}"),
+    }
+}
+
+/// `text` as a QR code of half-height block characters, with light modules drawn as blocks
+/// so that it scans on terminals with a dark background.
+fn qr_code(text: &str) -> Result<String, QrError> {
+    Ok(QrCode::new(text)?
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qr_code_lines() {
+        let qr_code = qr_code("http://192.168.1.2:8080/0123456789abcdef/app.apk").unwrap();
+        let lines = qr_code.lines().collect::<Vec<_>>();
+        // Two modules per line, including the quiet zone of 4 modules on every side
+        let width = lines[0].chars().count();
+        assert!(width > 21 + 8, "{qr_code}");
+        assert_eq!(lines.len(), width.div_ceil(2));
+        assert!(lines.iter().all(|line| line.chars().count() == width));
+    }
+
+    #[test]
+    fn request_line() {
+        assert_eq!(
+            parse_request_line("GET /0123abcd/app.apk HTTP/1.1\r\n"),
+            Some(("GET", "/0123abcd/app.apk"))
+        );
+        assert_eq!(parse_request_line("GET /\r\n"), None);
+        assert_eq!(parse_request_line("GET / SSH-2.0\r\n"), None);
+    }
+
+    /// Sends `request` to [`respond()`], returning whether it counted a download and the
+    /// response.
+    fn request(apk: &Path, request: &'static str) -> (bool, String) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let downloaded = respond(stream, "/0123abcd/app.apk", apk).unwrap();
+        (downloaded, client.join().unwrap())
+    }
+
+    #[test]
+    fn responses() {
+        let dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("serve");
+        std::fs::create_dir_all(&dir).unwrap();
+        let apk = dir.join("app.apk");
+        std::fs::write(&apk, "PK apk").unwrap();
+
+        let (downloaded, response) = request(
+            &apk,
+            "GET /0123abcd/app.apk HTTP/1.1\r\nHost: 192.0.2.1\r\n\r\n",
+        );
+        assert!(downloaded);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("Content-Length: 6\r\n"));
+        assert!(response.contains("filename=\"app.apk\""));
+        assert!(response.ends_with("\r\n\r\nPK apk"));
+
+        // Only the headers, which is not a download
+        let (downloaded, response) = request(&apk, "HEAD /0123abcd/app.apk HTTP/1.1\r\n\r\n");
+        assert!(!downloaded);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        for rejected in [
+            "GET /app.apk HTTP/1.1\r\n\r\n",
+            "POST /0123abcd/app.apk HTTP/1.1\r\n\r\n",
+            "SSH-2.0-OpenSSH\r\n\r\n",
+        ] {
+            let (downloaded, response) = request(&apk, rejected);
+            assert!(!downloaded);
+            assert!(
+                response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+                "{rejected}"
+            );
+        }
+    }
+
+    #[test]
+    fn tokens_differ() {
+        let token = random_token();
+        assert_eq!(token.len(), 16);
+        assert_ne!(token, random_token());
+    }
+}
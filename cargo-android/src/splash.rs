@@ -0,0 +1,154 @@
+//! Generates the resources of the `splash` screen: a theme for the main activity that uses the
+//! splash screen attributes of Android 12+, and a `windowBackground` showing the same
+//! background color and icon on older versions.
+
+use std::path::Path;
+
+use ndk_build::error::NdkError;
+
+use crate::error::Error;
+use crate::manifest::Splash;
+
+/// The generated theme, which is set as the `android:theme` of the main activity
+pub(crate) const THEME: &str = "@style/CargoAndroidSplash";
+
+/// The first platform with the `android:windowSplashScreen*` attributes
+const SPLASH_SCREEN_SDK_VERSION: u32 = 31;
+
+/// Writes the splash screen resources into `res_dir`, with the theme inheriting from `parent`.
+///
+/// `icon` is the image to copy into the resources. The Android 12+ theme is only generated when
+/// compiling against a platform that knows its attributes.
+pub(crate) fn write_resources(
+    res_dir: &Path,
+    splash: &Splash,
+    icon: Option<&Path>,
+    parent: &str,
+    compile_sdk_version: u32,
+) -> Result<(), Error> {
+    let write = |path: &str, contents: String| -> Result<(), Error> {
+        let path = res_dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, contents).map_err(|e| NdkError::IoPathError(path, e))?;
+        Ok(())
+    };
+
+    write(
+        "values/splash_colors.xml",
+        resources(&format!(
+            r#"<color name="splash_background">{}</color>"#,
+            splash.background
+        )),
+    )?;
+
+    let mut layers = vec![r#"<item android:drawable="@color/splash_background" />"#];
+    let mut splash_items = vec![
+        r#"<item name="android:windowSplashScreenBackground">@color/splash_background</item>"#,
+    ];
+    if let Some(icon) = icon {
+        let extension = icon
+            .extension()
+            .map_or("png".into(), |e| e.to_string_lossy());
+        let dir = res_dir.join("drawable");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::copy(icon, dir.join(format!("splash_icon.{extension}")))
+            .map_err(|e| NdkError::IoPathError(icon.to_owned(), e))?;
+        layers
+            .push(r#"<item android:gravity="center" android:drawable="@drawable/splash_icon" />"#);
+        splash_items.push(
+            r#"<item name="android:windowSplashScreenAnimatedIcon">@drawable/splash_icon</item>"#,
+        );
+    }
+    write(
+        "drawable/splash_window_background.xml",
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <layer-list xmlns:android=\"http://schemas.android.com/apk/res/android\">\n    {}\n</layer-list>\n",
+            layers.join("\n    ")
+        ),
+    )?;
+
+    write(
+        "values/splash_theme.xml",
+        resources(&style(
+            parent,
+            &[r#"<item name="android:windowBackground">@drawable/splash_window_background</item>"#],
+        )),
+    )?;
+    if compile_sdk_version >= SPLASH_SCREEN_SDK_VERSION {
+        write(
+            &format!("values-v{SPLASH_SCREEN_SDK_VERSION}/splash_theme.xml"),
+            resources(&style(parent, &splash_items)),
+        )?;
+    } else {
+        eprintln!("Warning: `splash` needs a `compile_sdk_version` of at least {SPLASH_SCREEN_SDK_VERSION} for the Android 12+ splash screen, only showing it as the window background");
+    }
+    Ok(())
+}
+
+fn style(parent: &str, items: &[&str]) -> String {
+    let name = THEME.trim_start_matches("@style/");
+    format!(
+        "<style name=\"{name}\" parent=\"{parent}\">\n        {}\n    </style>",
+        items.join("\n        ")
+    )
+}
+
+fn resources(contents: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<resources>\n    {contents}\n</resources>\n"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splash_resources() {
+        let dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("splash");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let icon = dir.join("logo.png");
+        std::fs::write(&icon, "").unwrap();
+        let splash = Splash {
+            background: "#101010".to_owned(),
+            icon: Some(icon.clone()),
+        };
+
+        let res_dir = dir.join("res");
+        write_resources(
+            &res_dir,
+            &splash,
+            Some(&icon),
+            "@android:style/Theme.Material",
+            34,
+        )
+        .unwrap();
+        let read = |path: &str| std::fs::read_to_string(res_dir.join(path)).unwrap();
+        assert!(read("values/splash_colors.xml").contains(">#101010</color>"));
+        assert!(res_dir.join("drawable/splash_icon.png").is_file());
+        assert!(read("drawable/splash_window_background.xml").contains("@drawable/splash_icon"));
+        let theme = read("values/splash_theme.xml");
+        assert!(theme.contains(
+            r#"<style name="CargoAndroidSplash" parent="@android:style/Theme.Material">"#
+        ));
+        assert!(theme.contains("android:windowBackground"));
+        assert!(
+            read("values-v31/splash_theme.xml").contains("android:windowSplashScreenAnimatedIcon")
+        );
+
+        // Older platforms only get the fallback
+        let res_dir = dir.join("res-30");
+        let splash = Splash {
+            icon: None,
+            ..splash
+        };
+        write_resources(&res_dir, &splash, None, "@android:style/Theme.Material", 30).unwrap();
+        assert!(res_dir.join("values/splash_theme.xml").is_file());
+        assert!(!res_dir.join("values-v31").exists());
+        assert!(!res_dir.join("drawable/splash_icon.png").exists());
+    }
+}
@@ -0,0 +1,424 @@
+//! A fake SDK and NDK whose tools are stub scripts that record their arguments, and fixture
+//! crates, for testing the logic that drives the Android toolchain without installing it or
+//! attaching a device.
+//!
+//! [`Ndk::from_paths()`] is used instead of the environment, which tests running concurrently
+//! cannot change without affecting each other.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use ndk_build::ndk::Ndk;
+
+/// The build tools version of the fake SDK
+pub(crate) const BUILD_TOOLS_VERSION: &str = "34.0.0";
+/// The platforms installed in the fake SDK
+pub(crate) const PLATFORMS: [u32; 2] = [33, 34];
+/// The build tag in the `Pkg.Revision` of the fake NDK
+pub(crate) const BUILD_TAG: u32 = 10909125;
+/// The API levels that the sysroot of the fake NDK has `arm64-v8a` platform libraries for
+pub(crate) const SYSROOT_PLATFORMS: [u32; 3] = [21, 24, 34];
+
+const BUILD_TOOLS: [&str; 4] = ["aapt", "aapt2", "apksigner", "zipalign"];
+const TOOLCHAIN: [&str; 3] = ["clang", "clang++", "llvm-ar"];
+
+pub(crate) struct FakeSdk {
+    root: PathBuf,
+    pub ndk: Ndk,
+}
+
+impl FakeSdk {
+    /// Creates a fake SDK, NDK and Android user home in a fresh temporary directory `name`.
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("fake-sdk")
+            .join(name);
+        let _ = std::fs::remove_dir_all(&root);
+        let sdk = root.join("sdk");
+        let ndk = root.join("ndk");
+        let user_home = root.join("home").join(".android");
+
+        let build_tools = sdk.join("build-tools").join(BUILD_TOOLS_VERSION);
+        for tool in BUILD_TOOLS {
+            write_stub(&root, &build_tools, tool);
+        }
+        write_stub(&root, &sdk.join("platform-tools"), "adb");
+        for platform in PLATFORMS {
+            write_file(
+                &sdk.join(format!("platforms/android-{platform}/android.jar")),
+                "",
+            );
+        }
+
+        write_file(
+            &ndk.join("source.properties"),
+            &format!("Pkg.Desc = Android NDK\nPkg.Revision = 26.1.{BUILD_TAG}\n"),
+        );
+        // Parsed line by line, without a trailing newline
+        write_file(
+            &ndk.join("build/core/platforms.mk"),
+            "NDK_MIN_PLATFORM_LEVEL := 21\nNDK_MAX_PLATFORM_LEVEL := 34",
+        );
+        let host = if cfg!(target_os = "macos") {
+            "darwin"
+        } else {
+            "linux"
+        };
+        let toolchain = ndk.join(format!("toolchains/llvm/prebuilt/{host}-x86_64"));
+        for tool in TOOLCHAIN {
+            write_stub(&root, &toolchain.join("bin"), tool);
+        }
+        let sysroot = toolchain.join("sysroot/usr/lib/aarch64-linux-android");
+        for platform in SYSROOT_PLATFORMS {
+            write_file(&sysroot.join(format!("{platform}/crtbegin_so.o")), "");
+        }
+        write_file(&sysroot.join("libc++_shared.so"), "");
+
+        // Present already, so that no `keytool` is needed to create it
+        write_file(&user_home.join("debug.keystore"), "");
+
+        let ndk = Ndk::from_paths(sdk, user_home, ndk).unwrap();
+        Self { root, ndk }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Makes the stub `tool` print `stdout` and exit with `status` from now on.
+    pub fn respond(&self, tool: &str, stdout: &str, status: i32) {
+        write_file(&self.root.join(format!("responses/{tool}.stdout")), stdout);
+        write_file(
+            &self.root.join(format!("responses/{tool}.status")),
+            &status.to_string(),
+        );
+    }
+
+    /// The arguments of every invocation of the stub `tool` so far.
+    pub fn calls(&self, tool: &str) -> Vec<Vec<String>> {
+        std::fs::read_to_string(self.root.join(format!("calls/{tool}")))
+            .unwrap_or_default()
+            .lines()
+            .map(|call| call.split_terminator('\x1f').map(str::to_owned).collect())
+            .collect()
+    }
+
+    /// Writes a crate called `name` with `metadata` as its `[package.metadata.android]` table
+    /// and returns the path to its `Cargo.toml`.
+    pub fn fixture_crate(&self, name: &str, metadata: &str) -> PathBuf {
+        let dir = self.root.join("crates").join(name);
+        write_file(&dir.join("src/lib.rs"), "");
+        let manifest = dir.join("Cargo.toml");
+        write_file(
+            &manifest,
+            &format!(
+                "[package]\nname = \"{name}\"\nversion = \"1.2.3\"\n\n[lib]\ncrate-type = [\"cdylib\"]\n\n[package.metadata.android]\n{metadata}"
+            ),
+        );
+        manifest
+    }
+}
+
+fn write_file(path: &Path, contents: &str) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+/// Writes an executable `dir/tool` that appends its arguments, separated by `\x1f`, as a line
+/// to `calls/<tool>` in `root`, and answers as configured through [`FakeSdk::respond()`].
+fn write_stub(root: &Path, dir: &Path, tool: &str) {
+    let calls = root.join("calls").join(tool);
+    let responses = root.join("responses").join(tool);
+    std::fs::create_dir_all(calls.parent().unwrap()).unwrap();
+    let path = dir.join(tool);
+    write_file(
+        &path,
+        &format!(
+            "#!/bin/sh\n\
+             for arg in \"$@\"; do printf '%s\\037' \"$arg\"; done >> '{calls}'\n\
+             echo >> '{calls}'\n\
+             [ -f '{responses}.stdout' ] && cat '{responses}.stdout'\n\
+             exit \"$(cat '{responses}.status' 2>/dev/null || echo 0)\"\n",
+            calls = calls.display(),
+            responses = responses.display(),
+        ),
+    );
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ffi::OsStr;
+
+    use cargo_subcommand::{Profile, Subcommand};
+    use clap::Parser;
+    use ndk_build::apk::{Apk, ApkConfig, InstallOptions, ReversePortForward};
+    use ndk_build::error::NdkError;
+    use ndk_build::target::Target;
+
+    use super::*;
+    use crate::apk::{android_jar, detect_device_abi, ApkBuilder};
+    use crate::env::Env;
+    use crate::error::Error;
+    use crate::keystore::{is_debug_key, read_keystore_meta};
+    use crate::manifest::Manifest;
+
+    /// The [`ApkConfig`] of the library of the crate at `manifest`, from an [`ApkBuilder`] on
+    /// the fake SDK.
+    fn apk_config(sdk: &FakeSdk, manifest: &Path) -> ApkConfig {
+        let cmd = Subcommand::new(cargo_subcommand::Args::parse_from([
+            "cargo".as_ref(),
+            "--manifest-path".as_ref(),
+            manifest.as_os_str(),
+        ]))
+        .unwrap();
+        let builder =
+            ApkBuilder::from_subcommand_with_ndk(&cmd, None, sdk.ndk.clone(), Env::default())
+                .unwrap();
+        let artifact = cmd.artifacts().next().unwrap();
+        builder
+            .apk_config(
+                artifact,
+                builder.artifact_manifest(artifact),
+                manifest.parent().unwrap(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn probe_fake_sdk() {
+        let sdk = FakeSdk::new("probe");
+        assert_eq!(sdk.ndk.build_tools_version(), BUILD_TOOLS_VERSION);
+        assert_eq!(sdk.ndk.build_tag(), BUILD_TAG);
+        let mut platforms = sdk.ndk.platforms().to_vec();
+        platforms.sort_unstable();
+        assert_eq!(platforms, PLATFORMS);
+        assert!(android_jar(&sdk.ndk, 34).is_ok());
+        // The Linux `adb` takes paths as they are
+        assert_eq!(
+            sdk.ndk.adb_path_arg(Path::new("/work/app.apk")).unwrap(),
+            "/work/app.apk"
+        );
+        assert!(matches!(
+            android_jar(&sdk.ndk, 30),
+            Err(Error::PlatformNotInstalled { platform: 30, installed }) if installed == PLATFORMS
+        ));
+    }
+
+    #[test]
+    fn sysroot_platform_levels() {
+        let sdk = FakeSdk::new("sysroot");
+        assert_eq!(
+            sdk.ndk.sysroot_platforms(Target::Arm64V8a).unwrap(),
+            SYSROOT_PLATFORMS
+        );
+        assert!(matches!(
+            sdk.ndk.sysroot_platforms(Target::X86),
+            Err(NdkError::PathNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn debug_keystore_resolution() {
+        let sdk = FakeSdk::new("keystore");
+        let manifest = Manifest::parse_from_toml(&sdk.fixture_crate("keystore", "")).unwrap();
+        let key = read_keystore_meta(
+            &sdk.ndk,
+            &manifest,
+            &Profile::Dev,
+            sdk.root(),
+            true,
+            &Env::default(),
+        )
+        .unwrap();
+        assert!(key.path.ends_with("home/.android/debug.keystore"));
+        assert!(is_debug_key(&sdk.ndk, &key, &Env::default()).unwrap());
+        assert!(matches!(
+            read_keystore_meta(&sdk.ndk, &manifest, &Profile::Release, sdk.root(), false, &Env::default()),
+            Err(Error::MissingReleaseKey(profile)) if profile == "RELEASE"
+        ));
+    }
+
+    #[test]
+    fn cargo_toolchain_env() {
+        let sdk = FakeSdk::new("cargo_env");
+        let cargo =
+            ndk_build::cargo::cargo_ndk(&sdk.ndk, Target::Arm64V8a, 23, sdk.root().join("target"))
+                .unwrap();
+        let envs = cargo
+            .get_envs()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .collect::<HashMap<_, _>>();
+        let env = |key: &str| Path::new(envs[OsStr::new(key)]);
+        assert!(env("CC_aarch64-linux-android").ends_with("bin/clang"));
+        assert!(env("CARGO_TARGET_AARCH64_LINUX_ANDROID_LINKER").ends_with("bin/clang"));
+        assert!(env("AR_aarch64-linux-android").ends_with("bin/llvm-ar"));
+        assert_eq!(
+            envs[OsStr::new("CFLAGS_aarch64-linux-android")],
+            "--target=aarch64-linux-android23"
+        );
+    }
+
+    #[test]
+    fn manifest_and_aapt_invocation() {
+        let sdk = FakeSdk::new("aapt");
+        let manifest = sdk.fixture_crate(
+            "aapt",
+            "package = \"com.example.fixture\"\ncompile_sdk_version = 34\n\n[[package.metadata.android.application.provider]]\nname = \"androidx.core.content.FileProvider\"\nauthorities = \"{applicationId}.files\"\n",
+        );
+        let config = apk_config(&sdk, &manifest);
+        config.create_apk().unwrap();
+
+        let xml = std::fs::read_to_string(config.build_dir.join("AndroidManifest.xml")).unwrap();
+        assert!(xml.contains("com.example.fixture.files"));
+        assert!(!xml.contains("{applicationId}"));
+
+        let calls = sdk.calls("aapt");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0][..3], ["package", "-f", "-F"]);
+        let jar = calls[0]
+            .iter()
+            .skip_while(|arg| *arg != "-I")
+            .nth(1)
+            .unwrap();
+        assert!(jar.ends_with("platforms/android-34/android.jar"));
+    }
+
+    #[test]
+    fn device_abi_from_getprop() {
+        let sdk = FakeSdk::new("device_abi");
+        for (abi, target) in [
+            ("arm64-v8a\n", Target::Arm64V8a),
+            ("armeabi-v7a\n", Target::ArmV7a),
+            ("x86\n", Target::X86),
+            ("x86_64\n", Target::X86_64),
+        ] {
+            sdk.respond("adb", abi, 0);
+            assert_eq!(
+                detect_device_abi(&sdk.ndk, Some("emulator-5554")).unwrap(),
+                target
+            );
+        }
+        assert_eq!(
+            sdk.calls("adb")[0],
+            [
+                "-s",
+                "emulator-5554",
+                "shell",
+                "getprop",
+                "ro.product.cpu.abi"
+            ]
+        );
+
+        sdk.respond("adb", "mips\n", 0);
+        assert!(matches!(
+            detect_device_abi(&sdk.ndk, Some("emulator-5554")),
+            Err(Error::Ndk(NdkError::UnsupportedTarget))
+        ));
+        // Without a serial, a device has to be attached
+        sdk.respond("adb", "List of devices attached\n\n", 0);
+        assert!(matches!(
+            detect_device_abi(&sdk.ndk, None),
+            Err(Error::NoDevice)
+        ));
+    }
+
+    #[test]
+    fn reverse_port_forward_by_api_level() {
+        let sdk = FakeSdk::new("reverse");
+        let mut config = apk_config(
+            &sdk,
+            &sdk.fixture_crate(
+                "reverse",
+                "build_targets = [\"aarch64-linux-android\"]\ncompile_sdk_version = 34\n\n[package.metadata.android.reverse_port_forward]\n\"tcp:1338\" = \"tcp:1338\"\n",
+            ),
+        );
+
+        // Skipped with a warning on devices that cannot `adb reverse`
+        sdk.respond("adb", "19\n", 0);
+        let apk = Apk::from_config(&config);
+        apk.reverse_port_forwarding(Some("emulator-5554")).unwrap();
+        assert_eq!(
+            sdk.calls("adb"),
+            [[
+                "-s",
+                "emulator-5554",
+                "shell",
+                "getprop",
+                "ro.build.version.sdk"
+            ]]
+        );
+
+        config.reverse_port_forward.insert(
+            "tcp:8080".to_owned(),
+            ReversePortForward::Table {
+                to: "tcp:80".to_owned(),
+                required: true,
+            },
+        );
+        let apk = Apk::from_config(&config);
+        assert!(matches!(
+            apk.reverse_port_forwarding(None),
+            Err(NdkError::ReversePortForwardUnsupported(19))
+        ));
+
+        sdk.respond("adb", "30\n", 0);
+        apk.reverse_port_forwarding(None).unwrap();
+        let reverse = sdk
+            .calls("adb")
+            .into_iter()
+            .filter(|call| call[0] == "reverse")
+            .collect::<Vec<_>>();
+        assert_eq!(reverse.len(), 2);
+        assert!(reverse.contains(&vec![
+            "reverse".to_owned(),
+            "tcp:8080".to_owned(),
+            "tcp:80".to_owned()
+        ]));
+    }
+
+    #[test]
+    fn reinstall_on_signature_mismatch() {
+        let sdk = FakeSdk::new("reinstall");
+        let manifest = sdk.fixture_crate(
+            "reinstall",
+            "build_targets = [\"aarch64-linux-android\"]\ncompile_sdk_version = 34\n",
+        );
+        let apk = Apk::from_config(&apk_config(&sdk, &manifest));
+        sdk.respond(
+            "adb",
+            "adb: failed to install fixture.apk: Failure [INSTALL_FAILED_UPDATE_INCOMPATIBLE: Existing package rust.fixture signatures do not match newer version; ignoring!]\n",
+            1,
+        );
+        let options = InstallOptions {
+            retries: 0,
+            ..Default::default()
+        };
+        let commands = |sdk: &FakeSdk| {
+            sdk.calls("adb")
+                .into_iter()
+                .map(|call| call[0].clone())
+                .collect::<Vec<_>>()
+        };
+
+        // Never uninstalls without `reinstall`
+        assert!(matches!(
+            apk.install_with_options(None, &options),
+            Err(NdkError::InstallFailed { .. })
+        ));
+        assert_eq!(commands(&sdk), ["install"]);
+
+        let options = InstallOptions {
+            reinstall: true,
+            ..options
+        };
+        assert!(apk.install_with_options(None, &options).is_err());
+        assert_eq!(
+            commands(&sdk),
+            ["install", "install", "uninstall", "install"]
+        );
+        assert_eq!(sdk.calls("adb")[2], ["uninstall", "rust.reinstall"]);
+    }
+}
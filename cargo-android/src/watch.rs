@@ -0,0 +1,161 @@
+//! Notices changes to the sources of a crate for `watch`, through the file system
+//! notifications of the host instead of polling the tree.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::error::Error;
+
+/// Watches a directory recursively, except for hidden directories and the directories in
+/// `skip` (e.g. the target directory that builds write to).
+pub(crate) struct ChangeWatcher {
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    changes: Receiver<PathBuf>,
+}
+
+impl ChangeWatcher {
+    pub(crate) fn new(root: &Path, skip: Vec<PathBuf>) -> Result<Self, Error> {
+        let (sender, changes) = mpsc::channel();
+        let filter_root = root.to_owned();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        eprintln!("Warning: failed to watch for changes: {e}");
+                        return;
+                    }
+                };
+                if !matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) {
+                    return;
+                }
+                for path in event.paths {
+                    if is_watched(&path, &filter_root, &skip) {
+                        // The receiver is gone once `watch` returned
+                        let _ = sender.send(path);
+                    }
+                }
+            })?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Waits up to `timeout` for a changed file, and then for a burst of changes to settle,
+    /// see [`settle()`].
+    pub(crate) fn next_change(
+        &self,
+        timeout: Duration,
+        debounce: Duration,
+    ) -> Result<Option<PathBuf>, Error> {
+        match self.changes.recv_timeout(timeout) {
+            Ok(changed) => {
+                settle(&self.changes, debounce);
+                Ok(Some(changed))
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::WatcherStopped),
+        }
+    }
+}
+
+/// Whether a change to `path` below `root` is a change to the sources, and not within a
+/// hidden directory (such as `.git`) or a directory in `skip`.
+fn is_watched(path: &Path, root: &Path, skip: &[PathBuf]) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    !skip.iter().any(|skip| path.starts_with(skip))
+        && !relative
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// Drops the changes from `changes` until none arrived for `debounce`, so that saving several
+/// files at once triggers a single build.
+fn settle(changes: &Receiver<PathBuf>, debounce: Duration) {
+    while changes.recv_timeout(debounce).is_ok() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn watched_paths() {
+        let root = Path::new("/work/app");
+        let skip = [root.join("target")];
+        assert!(is_watched(&root.join("src/lib.rs"), root, &skip));
+        assert!(is_watched(&root.join("assets/level.json"), root, &skip));
+        assert!(!is_watched(&root.join("target/debug/lib.so"), root, &skip));
+        assert!(!is_watched(&root.join(".git/HEAD"), root, &skip));
+        assert!(!is_watched(&root.join("src/.lib.rs.swp"), root, &skip));
+        assert!(!is_watched(Path::new("/elsewhere/lib.rs"), root, &skip));
+    }
+
+    #[test]
+    fn debounce() {
+        let (sender, changes) = mpsc::channel();
+        let burst = std::thread::spawn(move || {
+            for i in 0..5 {
+                sender.send(PathBuf::from(format!("src/{i}.rs"))).unwrap();
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            sender
+        });
+        assert_eq!(changes.recv().unwrap(), PathBuf::from("src/0.rs"));
+        let started = Instant::now();
+        settle(&changes, Duration::from_millis(200));
+        // Waited for the whole burst and one quiet `debounce` after it
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        let sender = burst.join().unwrap();
+        assert!(changes.try_recv().is_err());
+
+        // A later change isn't swallowed
+        sender.send(PathBuf::from("src/lib.rs")).unwrap();
+        assert_eq!(changes.try_recv().unwrap(), PathBuf::from("src/lib.rs"));
+    }
+
+    #[test]
+    fn notices_changes() {
+        let dir = std::env::temp_dir()
+            .join("cargo-android-tests")
+            .join("watch");
+        let _ = std::fs::remove_dir_all(&dir);
+        for path in ["src", "target", ".git"] {
+            std::fs::create_dir_all(dir.join(path)).unwrap();
+        }
+        let dir = dunce::canonicalize(dir).unwrap();
+        let watcher = ChangeWatcher::new(&dir, vec![dir.join("target")]).unwrap();
+        let next_change = || {
+            watcher
+                .next_change(Duration::from_secs(2), Duration::from_millis(100))
+                .unwrap()
+        };
+
+        std::fs::write(dir.join("target/lib.so"), "").unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "").unwrap();
+        assert_eq!(next_change(), None);
+
+        std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+        assert_eq!(next_change(), Some(dir.join("src/lib.rs")));
+        // The events of the same save were settled
+        assert_eq!(
+            watcher
+                .next_change(Duration::from_millis(200), Duration::ZERO)
+                .unwrap(),
+            None
+        );
+    }
+}
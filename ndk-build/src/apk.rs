@@ -1,13 +1,17 @@
 use crate::error::NdkError;
 use crate::manifest::AndroidManifest;
 use crate::ndk::{KeystoreMeta, Ndk};
+use crate::partial::PartialFile;
 use crate::target::Target;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::fs;
+use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
 /// The options for how to treat debug symbols that are present in any `.so`
 /// files that are added to the APK.
@@ -20,9 +24,18 @@ use std::process::Command;
 #[serde(rename_all = "snake_case")]
 pub enum StripConfig {
     /// Does not treat debug symbols specially
+    #[serde(alias = "none")]
     Default,
-    /// Removes debug symbols from the library before copying it into the APK
+    /// Removes debug symbols from the library before copying it into the APK, keeping the
+    /// symbol table (`--strip-debug`)
+    #[serde(alias = "debug")]
     Strip,
+    /// Removes the local symbols from the library, keeping the debug symbols and the exported
+    /// symbols (`--discard-all`)
+    Symbols,
+    /// Removes the symbol table and debug symbols, keeping only the dynamic symbols needed
+    /// to load the library (`--strip-all`)
+    All,
     /// Splits the library into into an ELF (`.so`) and DWARF (`.dwarf`). Only the
     /// `.so` is copied into the APK
     Split,
@@ -34,16 +47,81 @@ impl Default for StripConfig {
     }
 }
 
+impl StripConfig {
+    /// The `objcopy` (or `llvm-strip`) flag that strips the library, [`None`] when it is
+    /// copied as is.
+    pub fn objcopy_flag(self) -> Option<&'static str> {
+        match self {
+            Self::Default => None,
+            Self::Strip | Self::Split => Some("--strip-debug"),
+            Self::Symbols => Some("--discard-all"),
+            Self::All => Some("--strip-all"),
+        }
+    }
+}
+
+/// The first API level that supports `adb reverse`
+pub const REVERSE_PORT_FORWARD_API_LEVEL: u32 = 21;
+
+/// The host side of an `adb reverse` port forward in [`ApkConfig::reverse_port_forward`],
+/// such as `"tcp:1338"`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ReversePortForward {
+    /// Forwards to the host socket when the device supports it, and warns otherwise
+    To(String),
+    /// Like [`ReversePortForward::To`], but fails when `required` and the device does not
+    /// support it
+    Table {
+        to: String,
+        #[serde(default)]
+        required: bool,
+    },
+}
+
+impl ReversePortForward {
+    pub fn to(&self) -> &str {
+        match self {
+            Self::To(to) | Self::Table { to, .. } => to,
+        }
+    }
+
+    pub fn required(&self) -> bool {
+        matches!(self, Self::Table { required: true, .. })
+    }
+}
+
 pub struct ApkConfig {
     pub ndk: Ndk,
     pub build_dir: PathBuf,
     pub apk_name: String,
     pub assets: Option<PathBuf>,
     pub resources: Option<PathBuf>,
+    /// Additional resource directories, such as generated ones, packaged alongside
+    /// [`ApkConfig::resources`] (which takes precedence on conflicts)
+    pub extra_resources: Vec<PathBuf>,
     pub manifest: AndroidManifest,
     pub disable_aapt_compression: bool,
     pub strip: StripConfig,
-    pub reverse_port_forward: HashMap<String, String>,
+    /// Write the debug symbols of every stripped library to a `.debug` file next to it in the
+    /// build directory, which the library is linked to with a `.gnu_debuglink` section, for
+    /// symbolicating crashes later. [`StripConfig::Split`] always does so, as `.dwarf`
+    pub debug_sidecars: bool,
+    pub reverse_port_forward: HashMap<String, ReversePortForward>,
+    /// Leave resources that are not referenced from the manifest or other resources out of
+    /// the APK, see [`crate::resources::shrink`]
+    pub shrink_resources: bool,
+    /// Resource patterns such as `@drawable/legal_*` that are never removed by
+    /// [`ApkConfig::shrink_resources`]
+    pub shrink_keep: Vec<String>,
+    /// Platform whose `android.jar` resources are compiled against, defaults to the
+    /// `target_sdk_version` of the manifest
+    pub compile_sdk_version: Option<u32>,
+    /// Fail when `aapt` prints warnings while packaging the resources, see [`deny_warnings()`]
+    pub deny_warnings: bool,
+    /// Fail with [`NdkError::InvalidLibraries`] when validating the packaged libraries finds
+    /// problems, instead of printing them as warnings
+    pub strict_libraries: bool,
 }
 
 impl ApkConfig {
@@ -69,11 +147,12 @@ impl ApkConfig {
         std::fs::create_dir_all(&self.build_dir)?;
         self.manifest.write_to(&self.build_dir)?;
 
-        let target_sdk_version = self
-            .manifest
-            .sdk
-            .target_sdk_version
-            .unwrap_or_else(|| self.ndk.default_target_platform());
+        let compile_sdk_version = self.compile_sdk_version.unwrap_or_else(|| {
+            self.manifest
+                .sdk
+                .target_sdk_version
+                .unwrap_or_else(|| self.ndk.default_target_platform())
+        });
         let mut aapt = self.build_tool(bin!("aapt"))?;
         aapt.arg("package")
             .arg("-f")
@@ -82,13 +161,32 @@ impl ApkConfig {
             .arg("-M")
             .arg("AndroidManifest.xml")
             .arg("-I")
-            .arg(self.ndk.android_jar(target_sdk_version)?);
+            .arg(self.ndk.android_jar(compile_sdk_version)?);
 
         if self.disable_aapt_compression {
             aapt.arg("-0").arg("");
         }
 
         if let Some(res) = &self.resources {
+            if self.shrink_resources {
+                let manifest_xml =
+                    std::fs::read_to_string(self.build_dir.join("AndroidManifest.xml"))?;
+                let shrunk = self.build_dir.join("res");
+                let removed =
+                    crate::resources::shrink(res, &shrunk, &manifest_xml, &self.shrink_keep)?;
+                if !removed.is_empty() {
+                    println!("Removed {} unreferenced resource(s):", removed.len());
+                    for path in &removed {
+                        println!("    {}", path.display());
+                    }
+                }
+                aapt.arg("-S").arg(shrunk);
+            } else {
+                aapt.arg("-S").arg(res);
+            }
+        }
+
+        for res in &self.extra_resources {
             aapt.arg("-S").arg(res);
         }
 
@@ -96,12 +194,22 @@ impl ApkConfig {
             aapt.arg("-A").arg(assets);
         }
 
-        if !aapt.status()?.success() {
+        if self.deny_warnings {
+            // Captured to be scanned, and forwarded so that nothing is hidden
+            let output = aapt.stdout(Stdio::inherit()).output()?;
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            eprint!("{stderr}");
+            if !output.status.success() {
+                return Err(NdkError::CmdFailed(aapt));
+            }
+            deny_warnings("aapt", &stderr)?;
+        } else if !aapt.status()?.success() {
             return Err(NdkError::CmdFailed(aapt));
         }
 
         Ok(UnalignedApk {
             config: self,
+            strip: self.strip,
             pending_libs: HashSet::default(),
         })
     }
@@ -109,6 +217,7 @@ impl ApkConfig {
 
 pub struct UnalignedApk<'a> {
     config: &'a ApkConfig,
+    strip: StripConfig,
     pending_libs: HashSet<String>,
 }
 
@@ -117,6 +226,12 @@ impl<'a> UnalignedApk<'a> {
         self.config
     }
 
+    /// Overrides [`ApkConfig::strip`] for the libraries that are added from now on, such as
+    /// those of a single target.
+    pub fn set_strip(&mut self, strip: StripConfig) {
+        self.strip = strip;
+    }
+
     pub fn add_lib(&mut self, path: &Path, target: Target) -> Result<(), NdkError> {
         if !path.exists() {
             return Err(NdkError::PathNotFound(path.into()));
@@ -126,16 +241,16 @@ impl<'a> UnalignedApk<'a> {
         let out = self.config.build_dir.join(&lib_path);
         std::fs::create_dir_all(out.parent().unwrap())?;
 
-        match self.config.strip {
-            StripConfig::Default => {
+        match self.strip.objcopy_flag() {
+            None => {
                 std::fs::copy(path, out)?;
             }
-            StripConfig::Strip | StripConfig::Split => {
+            Some(flag) => {
                 let obj_copy = self.config.ndk.toolchain_bin("objcopy", target)?;
 
                 {
                     let mut cmd = Command::new(&obj_copy);
-                    cmd.arg("--strip-debug");
+                    cmd.arg(flag);
                     cmd.arg(path);
                     cmd.arg(&out);
 
@@ -144,14 +259,19 @@ impl<'a> UnalignedApk<'a> {
                     }
                 }
 
-                if self.config.strip == StripConfig::Split {
-                    let dwarf_path = out.with_extension("dwarf");
+                let sidecar = match self.strip {
+                    StripConfig::Split => Some("dwarf"),
+                    _ if self.config.debug_sidecars => Some("debug"),
+                    _ => None,
+                };
+                if let Some(extension) = sidecar {
+                    let debug_path = out.with_extension(extension);
 
                     {
                         let mut cmd = Command::new(&obj_copy);
                         cmd.arg("--only-keep-debug");
                         cmd.arg(path);
-                        cmd.arg(&dwarf_path);
+                        cmd.arg(&debug_path);
 
                         if !cmd.status()?.success() {
                             return Err(NdkError::CmdFailed(cmd));
@@ -159,7 +279,7 @@ impl<'a> UnalignedApk<'a> {
                     }
 
                     let mut cmd = Command::new(obj_copy);
-                    cmd.arg(format!("--add-gnu-debuglink={}", dwarf_path.display()));
+                    cmd.arg(format!("--add-gnu-debuglink={}", debug_path.display()));
                     cmd.arg(out);
 
                     if !cmd.status()?.success() {
@@ -179,6 +299,24 @@ impl<'a> UnalignedApk<'a> {
         Ok(())
     }
 
+    /// Adds the libraries of `target` that a previous build copied (and possibly stripped) into
+    /// the build directory, without building or copying them again.
+    pub fn add_built_libs(&mut self, target: Target) -> Result<(), NdkError> {
+        let lib_dir = Path::new("lib").join(target.android_abi());
+        let dir = self.config.build_dir.join(&lib_dir);
+        for entry in fs::read_dir(&dir).map_err(|e| NdkError::IoPathError(dir.clone(), e))? {
+            let name = entry?.file_name();
+            if Path::new(&name).extension() == Some(OsStr::new("so")) {
+                let lib_path_unix = lib_dir.join(name).to_str().unwrap().replace('\\', "/");
+                self.pending_libs.insert(lib_path_unix);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the `.so` files in the `path/<android_abi>` subdirectory matching `target`.
+    /// Subdirectories for other ABIs are ignored, and a warning is printed when no library
+    /// exists for `target`.
     pub fn add_runtime_libs(
         &mut self,
         path: &Path,
@@ -186,13 +324,29 @@ impl<'a> UnalignedApk<'a> {
         search_paths: &[&Path],
     ) -> Result<(), NdkError> {
         let abi_dir = path.join(target.android_abi());
-        for entry in fs::read_dir(&abi_dir).map_err(|e| NdkError::IoPathError(abi_dir, e))? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.extension() == Some(OsStr::new("so")) {
-                self.add_lib_recursively(&path, target, search_paths)?;
+        let mut libs = Vec::new();
+        if abi_dir.is_dir() {
+            for entry in
+                fs::read_dir(&abi_dir).map_err(|e| NdkError::IoPathError(abi_dir.clone(), e))?
+            {
+                let path = entry?.path();
+                if path.is_file() && path.extension() == Some(OsStr::new("so")) {
+                    libs.push(path);
+                }
             }
         }
+
+        if libs.is_empty() {
+            eprintln!(
+                "Warning: No runtime libraries for `{}` found in `{}`",
+                target.android_abi(),
+                abi_dir.display()
+            );
+        }
+
+        for lib in libs {
+            self.add_lib_recursively(&lib, target, search_paths)?;
+        }
         Ok(())
     }
 
@@ -214,54 +368,375 @@ impl<'a> UnalignedApk<'a> {
             return Err(NdkError::CmdFailed(aapt));
         }
 
+        // Align into a temporary file that only replaces the APK once it is signed
+        let partial = PartialFile::new(self.config.apk());
         let mut zipalign = self.config.build_tool(bin!("zipalign"))?;
         zipalign
             .arg("-f")
             .arg("-v")
             .arg("4")
             .arg(self.config.unaligned_apk())
-            .arg(self.config.apk());
+            .arg(partial.temp_path());
 
         if !zipalign.status()?.success() {
             return Err(NdkError::CmdFailed(zipalign));
         }
 
-        Ok(UnsignedApk(self.config))
+        Ok(UnsignedApk {
+            config: self.config,
+            partial,
+        })
     }
 }
 
-pub struct UnsignedApk<'a>(&'a ApkConfig);
+pub struct UnsignedApk<'a> {
+    config: &'a ApkConfig,
+    partial: PartialFile,
+}
 
 impl<'a> UnsignedApk<'a> {
     pub fn sign(self, key: KeystoreMeta) -> Result<Apk, NdkError> {
-        let mut apksigner = self.0.build_tool(bat!("apksigner"))?;
+        self.sign_with(&key, None)
+    }
+
+    /// Signs with `key` after the previous key of `rotation`, for APK signature scheme v3 key
+    /// rotation.
+    pub fn sign_with_rotation(
+        self,
+        key: KeystoreMeta,
+        rotation: &KeyRotation,
+    ) -> Result<Apk, NdkError> {
+        self.sign_with(&key, Some(rotation))
+    }
+
+    fn sign_with(
+        self,
+        key: &KeystoreMeta,
+        rotation: Option<&KeyRotation>,
+    ) -> Result<Apk, NdkError> {
+        let mut apksigner = self.config.build_tool(bat!("apksigner"))?;
         apksigner.arg("sign");
+        add_signer_args(&mut apksigner, key, rotation);
+        apksigner.arg(self.partial.temp_path());
+
+        if !apksigner.status()?.success() {
+            return Err(NdkError::CmdFailed(apksigner));
+        }
+
+        self.partial.commit()?;
+        Ok(Apk::from_config(self.config))
+    }
+}
+
+/// The warnings in the `stderr` of `aapt`, `aapt2` or `apktool`, which write them as
+/// `Warning: ...`, `<file>:<line>: warn: ...` and `W: ...` lines respectively.
+pub fn warning_lines(stderr: &str) -> Vec<&str> {
+    stderr
+        .lines()
+        .filter(|line| {
+            let line = line.trim_start().to_ascii_lowercase();
+            line.starts_with("w: ") || line.contains("warning:") || line.contains("warn:")
+        })
+        .collect()
+}
+
+/// Fails with [`NdkError::DeniedWarnings`] when the `stderr` of `tool` contains
+/// [`warning_lines()`].
+pub fn deny_warnings(tool: &str, stderr: &str) -> Result<(), NdkError> {
+    let warnings = warning_lines(stderr);
+    if warnings.is_empty() {
+        return Ok(());
+    }
+    Err(NdkError::DeniedWarnings {
+        tool: tool.to_owned(),
+        warnings: warnings.into_iter().map(str::to_owned).collect(),
+    })
+}
+
+/// The errors in the output of `am start` or `monkey`, such as `Error: Activity class {...} does
+/// not exist.`, a `SecurityException` for activities that aren't exported, or
+/// `** No activities found to run, monkey aborted.`.
+fn launch_errors(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            (line.starts_with("Error") && !line.starts_with("Error type"))
+                || line.contains("Exception:")
+                || line.contains("monkey aborted")
+        })
+        .collect()
+}
+
+/// The packages of the resumed activities in the output of `dumpsys activity activities`, from
+/// lines such as `mResumedActivity: ActivityRecord{3d4f1a u0 com.example/.MainActivity t12}`
+/// (`topResumedActivity=` and `ResumedActivity:` on newer versions).
+fn resumed_packages(dumpsys: &str) -> impl Iterator<Item = &str> {
+    dumpsys
+        .lines()
+        .filter(|line| line.contains("ResumedActivity"))
+        .filter_map(|line| {
+            let component = line.split_whitespace().find(|word| word.contains('/'))?;
+            component.split_once('/').map(|(package, _)| package)
+        })
+}
+
+/// A signing key rotation: the APK is signed with the `previous` key as well as the current
+/// one, and `lineage` (created with `apksigner rotate`) proves that the current key succeeds it.
+pub struct KeyRotation {
+    pub previous: KeystoreMeta,
+    pub lineage: PathBuf,
+}
+
+/// Adds the `apksigner sign` arguments for `key`, preceded by the previous key of `rotation`.
+fn add_signer_args(apksigner: &mut Command, key: &KeystoreMeta, rotation: Option<&KeyRotation>) {
+    let add_key = |apksigner: &mut Command, key: &KeystoreMeta| {
         apksigner.arg("--ks").arg(&key.path);
-        apksigner.arg("--ks-pass").arg(format!("pass:{}", &key.store_pass));
-        
+        apksigner
+            .arg("--ks-pass")
+            .arg(format!("pass:{}", key.store_pass.expose()));
         if let Some(alias) = &key.alias {
             apksigner.arg("--ks-key-alias").arg(alias);
         }
+        if let Some(pass) = &key.key_pass {
+            apksigner
+                .arg("--key-pass")
+                .arg(format!("pass:{}", pass.expose()));
+        }
+    };
 
-        if let Some(pass) = key.key_pass {
-            apksigner.arg("--key-pass").arg(format!("pass:{pass}"));
+    match rotation {
+        Some(rotation) => {
+            add_key(apksigner, &rotation.previous);
+            apksigner.arg("--next-signer");
+            add_key(apksigner, key);
+            apksigner.arg("--lineage").arg(&rotation.lineage);
         }
-        
-        apksigner.arg(self.0.apk());
-        
-        if !apksigner.status()?.success() {
-            return Err(NdkError::CmdFailed(apksigner));
+        None => add_key(apksigner, key),
+    }
+}
+
+/// How long [`Apk::install_with_options()`] waits before the first retry, doubling for each
+/// further one.
+const INSTALL_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Additional flags passed to `adb install`, and how failed transfers are retried.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InstallOptions {
+    /// Grant all runtime permissions listed in the manifest (`-g`)
+    pub grant_permissions: bool,
+    /// Install for the given user only (`--user`)
+    pub user: Option<String>,
+    /// Force (`Some(true)`) or disable (`Some(false)`) streamed installation
+    pub streaming: Option<bool>,
+    /// Uninstall the package and install it again when the device refuses the update because
+    /// the installed APK is signed with a different key, which removes its data. Keeping the
+    /// data (`adb uninstall -k`) would keep the signature of the package as well, which the
+    /// install would be refused for again
+    pub reinstall: bool,
+    /// Prefer incremental installation (`--incremental`), which lets the app start while the
+    /// APK is still being transferred. `adb` falls back to a regular installation when the
+    /// device or the APK (which needs its `.idsig`) doesn't support it
+    pub incremental: bool,
+    /// How often `adb install` is retried when the transfer failed or timed out. Rejections of
+    /// the APK by the device (`INSTALL_FAILED_*`) are never retried
+    pub retries: u32,
+    /// Stop an `adb install` attempt after this long, e.g. a transfer that stalled
+    pub timeout: Option<Duration>,
+}
+
+impl Default for InstallOptions {
+    fn default() -> Self {
+        Self {
+            grant_permissions: false,
+            user: None,
+            streaming: None,
+            reinstall: false,
+            incremental: false,
+            retries: 2,
+            timeout: None,
+        }
+    }
+}
+
+impl InstallOptions {
+    fn apply(&self, adb: &mut Command) {
+        if self.grant_permissions {
+            adb.arg("-g");
+        }
+        if let Some(user) = &self.user {
+            adb.arg("--user").arg(user);
+        }
+        match self.streaming {
+            Some(true) => {
+                adb.arg("--streaming");
+            }
+            Some(false) => {
+                adb.arg("--no-streaming");
+            }
+            None => {}
+        }
+        if self.incremental {
+            adb.arg("--incremental");
+        }
+    }
+}
+
+/// Runs `adb install`, echoing its output and killing it after `timeout`.
+fn run_install(mut adb: Command, timeout: Option<Duration>) -> Result<(), NdkError> {
+    let mut child = adb.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let (sender, lines) = mpsc::channel();
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let readers = [
+        spawn_line_reader(stdout, false, sender.clone()),
+        spawn_line_reader(stderr, true, sender),
+    ];
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut output = String::new();
+    loop {
+        let received = match deadline {
+            Some(deadline) => {
+                lines.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => lines.recv().map_err(RecvTimeoutError::from),
+        };
+        let (is_stderr, line) = match received {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                // Ignore errors from an `adb` that exited just now
+                let _ = child.kill();
+                child.wait()?;
+                return Err(NdkError::InstallTimedOut(timeout.unwrap()));
+            }
+        };
+        if is_stderr {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
+        output.push_str(&line);
+        output.push('\n');
+    }
+    for reader in readers {
+        reader.join().expect("reader thread panicked")?;
+    }
+
+    let status = child.wait()?;
+    if let Some(verdict) = install_verdict(&output) {
+        return Err(NdkError::InstallFailed { verdict, output });
+    }
+    if !status.success() {
+        if is_transfer_output(&output) {
+            return Err(NdkError::InstallTransferFailed(output));
+        }
+        return Err(NdkError::CmdFailed(adb));
+    }
+    Ok(())
+}
+
+/// Reads `pipe` on a thread, sending every line to `sender`.
+fn spawn_line_reader(
+    pipe: impl Read + Send + 'static,
+    is_stderr: bool,
+    sender: mpsc::Sender<(bool, String)>,
+) -> std::thread::JoinHandle<std::io::Result<()>> {
+    std::thread::spawn(move || {
+        for line in BufReader::new(pipe).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                // The receiver is gone after a timeout
+                let _ = sender.send((is_stderr, line));
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Whether `adb install` failed to transfer the APK or timed out, which is worth retrying.
+/// Rejections by the device and failures that aren't known to be transient are not.
+fn is_transfer_failure(e: &NdkError) -> bool {
+    matches!(
+        e,
+        NdkError::InstallTransferFailed(_) | NdkError::InstallTimedOut(_)
+    )
+}
+
+/// Whether the output of a failed `adb install` shows that the connection to the device broke
+/// while transferring the APK.
+fn is_transfer_output(output: &str) -> bool {
+    const TRANSFER_ERRORS: &[&str] = &[
+        "failed to copy",
+        "failed to read copy response",
+        "connection reset",
+        "broken pipe",
+        "protocol fault",
+        "error: closed",
+        "device offline",
+    ];
+    let output = output.to_ascii_lowercase();
+    TRANSFER_ERRORS.iter().any(|error| output.contains(error))
+}
+
+/// Runs `install` until it succeeds or fails with something other than a transfer failure,
+/// at most `retries` more times. Waits `delay` before the first retry, doubling it for each
+/// further one, after reporting the failure to `on_retry`.
+fn retry_transfers(
+    retries: u32,
+    delay: Duration,
+    mut on_retry: impl FnMut(u32, Duration, &NdkError),
+    mut install: impl FnMut() -> Result<(), NdkError>,
+) -> Result<(), NdkError> {
+    let mut retry = 0;
+    loop {
+        match install() {
+            Err(e) if retry < retries && is_transfer_failure(&e) => {
+                let delay = delay * 2u32.pow(retry);
+                retry += 1;
+                on_retry(retry, delay, &e);
+                std::thread::sleep(delay);
+            }
+            result => return result,
         }
-        
-        Ok(Apk::from_config(self.0))
     }
 }
 
+/// Whether the failed install `e` is resolved by uninstalling the package first, see
+/// [`InstallOptions::reinstall`].
+fn is_fixed_by_reinstall(e: &NdkError) -> bool {
+    matches!(e, NdkError::InstallFailed { verdict, .. } if is_signature_mismatch(verdict))
+}
+
+/// Whether `verdict` is a rejection of an update that is signed with a different key than the
+/// installed APK, which only uninstalling resolves.
+fn is_signature_mismatch(verdict: &str) -> bool {
+    matches!(
+        verdict,
+        "INSTALL_FAILED_UPDATE_INCOMPATIBLE"
+            | "INSTALL_FAILED_SHARED_USER_INCOMPATIBLE"
+            | "INSTALL_PARSE_FAILED_INCONSISTENT_CERTIFICATES"
+    )
+}
+
+/// The reason the device rejected the APK, such as `INSTALL_FAILED_INSUFFICIENT_STORAGE`, in
+/// the output of `adb install`.
+fn install_verdict(output: &str) -> Option<String> {
+    output
+        .split(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+        .find(|word| word.starts_with("INSTALL_") && word.contains("FAILED"))
+        .map(str::to_owned)
+}
+
 pub struct Apk {
     path: PathBuf,
     package_name: String,
+    version_code: Option<u32>,
     ndk: Ndk,
-    reverse_port_forward: HashMap<String, String>,
+    reverse_port_forward: HashMap<String, ReversePortForward>,
+    /// Installs need `-t`, see [`crate::manifest::Application::test_only`]
+    test_only: bool,
 }
 
 impl Apk {
@@ -270,20 +745,81 @@ impl Apk {
         Self {
             path: config.apk(),
             package_name: config.manifest.package.clone(),
+            version_code: config.manifest.version_code,
             ndk,
             reverse_port_forward: config.reverse_port_forward.clone(),
+            test_only: config.manifest.application.test_only == Some(true),
+        }
+    }
+
+    /// The APK at `path` of the package in `manifest`, created by other means than an
+    /// [`ApkConfig`], such as the universal APK of a bundle.
+    pub fn from_path(
+        ndk: Ndk,
+        path: PathBuf,
+        manifest: &AndroidManifest,
+        reverse_port_forward: HashMap<String, ReversePortForward>,
+    ) -> Self {
+        Self {
+            path,
+            package_name: manifest.package.clone(),
+            version_code: manifest.version_code,
+            ndk,
+            reverse_port_forward,
+            test_only: manifest.application.test_only == Some(true),
         }
     }
 
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn package_name(&self) -> &str {
+        &self.package_name
+    }
+
+    /// The `versionCode` of the manifest the APK was created with, if set
+    pub fn version_code(&self) -> Option<u32> {
+        self.version_code
+    }
+
+    /// Sets up the reverse port forwards. Devices before [`REVERSE_PORT_FORWARD_API_LEVEL`] and
+    /// some restricted builds do not support them, which only fails for forwards that are
+    /// [`ReversePortForward::required()`] and prints a warning for the others.
     pub fn reverse_port_forwarding(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
-        for (from, to) in &self.reverse_port_forward {
+        if self.reverse_port_forward.is_empty() {
+            return Ok(());
+        }
+        // Unknown API levels are attempted like supported ones
+        let api_level = self.ndk.device_api_level(device_serial).ok();
+        let unsupported = api_level.filter(|level| *level < REVERSE_PORT_FORWARD_API_LEVEL);
+
+        for (from, forward) in &self.reverse_port_forward {
+            let to = forward.to();
+            if let Some(api_level) = unsupported {
+                if forward.required() {
+                    return Err(NdkError::ReversePortForwardUnsupported(api_level));
+                }
+                eprintln!(
+                    "Warning: not reverse port forwarding from {from} to {to}, `adb reverse` \
+                     needs API level {REVERSE_PORT_FORWARD_API_LEVEL} but the device has {api_level}"
+                );
+                continue;
+            }
+
             println!("Reverse port forwarding from {} to {}", from, to);
             let mut adb = self.ndk.adb(device_serial)?;
 
             adb.arg("reverse").arg(from).arg(to);
 
             if !adb.status()?.success() {
-                return Err(NdkError::CmdFailed(adb));
+                if forward.required() {
+                    return Err(NdkError::CmdFailed(adb));
+                }
+                eprintln!(
+                    "Warning: reverse port forwarding from {from} to {to} failed, the device \
+                     may not support `adb reverse`; continuing as it is not `required`"
+                );
             }
         }
 
@@ -291,16 +827,84 @@ impl Apk {
     }
 
     pub fn install(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
-        let mut adb = self.ndk.adb(device_serial)?;
+        self.install_with_options(device_serial, &InstallOptions::default())
+    }
 
-        adb.arg("install").arg("-r").arg(&self.path);
-        if !adb.status()?.success() {
-            return Err(NdkError::CmdFailed(adb));
+    pub fn install_with_options(
+        &self,
+        device_serial: Option<&str>,
+        options: &InstallOptions,
+    ) -> Result<(), NdkError> {
+        match self.install_retrying(device_serial, options) {
+            Err(e) if options.reinstall && is_fixed_by_reinstall(&e) => {
+                eprintln!(
+                    "Warning: the installed `{}` is signed with a different key ({e}), uninstalling it and installing again",
+                    self.package_name,
+                );
+                self.uninstall(device_serial)?;
+                self.install_retrying(device_serial, options)
+            }
+            result => result,
         }
+    }
+
+    /// Runs `adb install`, retrying it as configured in `options`.
+    fn install_retrying(
+        &self,
+        device_serial: Option<&str>,
+        options: &InstallOptions,
+    ) -> Result<(), NdkError> {
+        retry_transfers(
+            options.retries,
+            INSTALL_RETRY_DELAY,
+            |retry, delay, e| {
+                eprintln!(
+                    "Warning: installing `{}` failed: {e}\nRetrying in {}s ({retry}/{})",
+                    self.path.display(),
+                    delay.as_secs(),
+                    options.retries
+                );
+            },
+            || {
+                let mut adb = self.ndk.adb(device_serial)?;
+                adb.arg("install").arg("-r");
+                if self.test_only {
+                    adb.arg("-t");
+                }
+                options.apply(&mut adb);
+                adb.arg(self.ndk.adb_path_arg(&self.path)?);
+                run_install(adb, options.timeout)
+            },
+        )
+    }
+
+    /// Uninstalls the package from the device, if it is installed.
+    pub fn uninstall(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("uninstall").arg(&self.package_name);
+        // Fails when the package is not installed, which is fine
+        adb.status()?;
         Ok(())
     }
 
     pub fn start(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
+        self.start_activity(device_serial, "android.app.NativeActivity", &[])?;
+        Ok(())
+    }
+
+    /// Starts `activity` of the package (relative names start with `.`) with
+    /// `am start -n <package>/<activity>`, appending `args` such as intent extras. They are
+    /// interpreted by the shell on the device, so they must be quoted where necessary.
+    ///
+    /// `am` mostly exits successfully when the activity doesn't exist or isn't exported, so its
+    /// output is checked for errors as well. Returns that output, which is also printed.
+    pub fn start_activity(
+        &self,
+        device_serial: Option<&str>,
+        activity: &str,
+        args: &[String],
+    ) -> Result<String, NdkError> {
+        let component = format!("{}/{}", self.package_name, activity);
         let mut adb = self.ndk.adb(device_serial)?;
         adb.arg("shell")
             .arg("am")
@@ -308,15 +912,95 @@ impl Apk {
             .arg("-a")
             .arg("android.intent.action.MAIN")
             .arg("-n")
-            .arg(format!("{}/android.app.NativeActivity", self.package_name));
+            .arg(&component)
+            .args(args);
+        self.launch_with(adb, component)
+    }
 
-        if !adb.status()?.success() {
+    /// Starts the launcher activity of the package like tapping its icon does, through
+    /// `monkey -p <package> -c android.intent.category.LAUNCHER 1`. Returns its output, like
+    /// [`Apk::start_activity()`].
+    pub fn launch(&self, device_serial: Option<&str>) -> Result<String, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell")
+            .arg("monkey")
+            .arg("-p")
+            .arg(&self.package_name)
+            .arg("-c")
+            .arg("android.intent.category.LAUNCHER")
+            .arg("1");
+        self.launch_with(adb, self.package_name.clone())
+    }
+
+    /// Runs the `am start` or `monkey` invocation `adb`, failing with
+    /// [`NdkError::ActivityStartFailed`] when it reports an error about `component`.
+    fn launch_with(&self, mut adb: Command, component: String) -> Result<String, NdkError> {
+        let output = adb.output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let errors = launch_errors(&stdout)
+            .into_iter()
+            .chain(launch_errors(&stderr))
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            return Err(NdkError::ActivityStartFailed {
+                component,
+                message: errors.join("\n"),
+            });
+        }
+        print!("{stdout}");
+        eprint!("{stderr}");
+        if !output.status.success() {
             return Err(NdkError::CmdFailed(adb));
         }
+        Ok(format!("{stdout}{stderr}"))
+    }
 
+    /// Whether an activity of the package is the resumed, foreground activity according to
+    /// `dumpsys activity activities`.
+    pub fn is_foreground(&self, device_serial: Option<&str>) -> Result<bool, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell")
+            .arg("dumpsys")
+            .arg("activity")
+            .arg("activities");
+        let output = adb.output()?;
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(adb));
+        }
+        let output = String::from_utf8_lossy(&output.stdout);
+        let foreground = resumed_packages(&output).any(|package| package == self.package_name);
+        Ok(foreground)
+    }
+
+    /// Stops all processes of the package with `am force-stop`.
+    pub fn force_stop(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell")
+            .arg("am")
+            .arg("force-stop")
+            .arg(&self.package_name);
+        if !adb.status()?.success() {
+            return Err(NdkError::CmdFailed(adb));
+        }
         Ok(())
     }
 
+    /// The id of the process of the application, or [`None`] while it isn't running.
+    pub fn pidof(&self, device_serial: Option<&str>) -> Result<Option<u32>, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg("pidof").arg(&self.package_name);
+        let output = adb.output()?;
+        // `pidof` fails when no process matches
+        if !output.status.success() {
+            return Ok(None);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .next()
+            .and_then(|pid| pid.parse().ok()))
+    }
+
     pub fn uidof(&self, device_serial: Option<&str>) -> Result<u32, NdkError> {
         let mut adb = self.ndk.adb(device_serial)?;
         adb.arg("shell")
@@ -349,3 +1033,262 @@ impl Apk {
             .map_err(|e| NdkError::NotAUid(e, uid.to_owned()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_warnings() {
+        let stderr = "\
+res/values/strings.xml:3: warn: removing resource without default.
+W: Could not decode attr value, using undecoded value instead
+I: Using Apktool 2.8.1
+AndroidManifest.xml:12: Warning: unknown attribute
+Note: a plain note
+";
+        assert_eq!(warning_lines(stderr).len(), 3);
+        assert!(deny_warnings("aapt2", "I: Copying assets\n").is_ok());
+        assert!(matches!(
+            deny_warnings("aapt2", stderr),
+            Err(NdkError::DeniedWarnings { tool, warnings }) if tool == "aapt2" && warnings.len() == 3
+        ));
+    }
+
+    #[test]
+    fn install_output() {
+        let rejected = "Performing Streamed Install\nadb: failed to install app.apk: Failure [INSTALL_FAILED_INSUFFICIENT_STORAGE: Failed to override installation location]\n";
+        assert_eq!(
+            install_verdict(rejected).as_deref(),
+            Some("INSTALL_FAILED_INSUFFICIENT_STORAGE")
+        );
+        assert!(!is_transfer_output(rejected));
+        assert_eq!(
+            install_verdict("Failure [INSTALL_PARSE_FAILED_NO_CERTIFICATES]").as_deref(),
+            Some("INSTALL_PARSE_FAILED_NO_CERTIFICATES")
+        );
+        assert_eq!(
+            install_verdict("adb: failed to install app.apk: connection reset by peer\n"),
+            None
+        );
+
+        // Output of `adb install` losing the device while transferring
+        for output in [
+            "adb: error: failed to copy 'app.apk' to '/data/local/tmp/app.apk': remote connection reset by peer\n",
+            "Performing Streamed Install\nadb: failed to install app.apk: Connection reset by peer\n",
+            "adb: error: failed to read copy response\n",
+            "error: closed\n",
+            "adb: error: protocol fault (couldn't read status): Success\n",
+            "error: device offline\n",
+        ] {
+            assert!(is_transfer_output(output), "{output}");
+        }
+        assert!(!is_transfer_output(
+            "adb: error: cannot stat 'app.apk': No such file or directory\n"
+        ));
+        assert!(!is_transfer_output("error: no devices/emulators found\n"));
+
+        assert!(!is_transfer_failure(&NdkError::InstallFailed {
+            verdict: "INSTALL_FAILED_VERSION_DOWNGRADE".to_owned(),
+            output: String::new(),
+        }));
+        assert!(!is_transfer_failure(&NdkError::CmdFailed(Command::new(
+            "adb"
+        ))));
+        assert!(is_transfer_failure(&NdkError::InstallTransferFailed(
+            String::new()
+        )));
+        assert!(is_transfer_failure(&NdkError::InstallTimedOut(
+            Duration::from_secs(60)
+        )));
+        assert!(is_signature_mismatch("INSTALL_FAILED_UPDATE_INCOMPATIBLE"));
+        assert!(!is_signature_mismatch("INSTALL_FAILED_VERSION_DOWNGRADE"));
+    }
+
+    #[cfg(unix)]
+    fn fake_adb(script: &str) -> Command {
+        let mut sh = Command::new("sh");
+        sh.arg("-c").arg(script);
+        sh
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn install_results() {
+        assert!(run_install(fake_adb("echo Success"), None).is_ok());
+        assert!(matches!(
+            run_install(fake_adb("echo 'Failure [INSTALL_FAILED_VERSION_DOWNGRADE]' >&2; exit 1"), None),
+            Err(NdkError::InstallFailed { verdict, .. }) if verdict == "INSTALL_FAILED_VERSION_DOWNGRADE"
+        ));
+        assert!(matches!(
+            run_install(fake_adb("echo 'adb: error: failed to copy: connection reset by peer' >&2; exit 1"), None),
+            Err(NdkError::InstallTransferFailed(output)) if output.contains("failed to copy")
+        ));
+        assert!(matches!(
+            run_install(
+                fake_adb("echo 'error: no devices/emulators found' >&2; exit 1"),
+                None
+            ),
+            Err(NdkError::CmdFailed(_))
+        ));
+
+        let start = Instant::now();
+        let timeout = Duration::from_millis(200);
+        assert!(matches!(
+            run_install(fake_adb("echo Performing Streamed Install; exec sleep 10"), Some(timeout)),
+            Err(NdkError::InstallTimedOut(t)) if t == timeout
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn install_retries() {
+        let attempt = |results: Vec<Result<(), NdkError>>, retries| {
+            let mut results = results.into_iter();
+            let mut attempts = 0;
+            let mut retried = Vec::new();
+            let result = retry_transfers(
+                retries,
+                Duration::ZERO,
+                |retry, _, _| retried.push(retry),
+                || {
+                    attempts += 1;
+                    results.next().unwrap()
+                },
+            );
+            (result.is_ok(), attempts, retried)
+        };
+        let transfer = || Err(NdkError::InstallTransferFailed(String::new()));
+        let timeout = || Err(NdkError::InstallTimedOut(Duration::from_secs(1)));
+
+        assert_eq!(attempt(vec![Ok(())], 2), (true, 1, vec![]));
+        assert_eq!(
+            attempt(vec![transfer(), timeout(), Ok(())], 2),
+            (true, 3, vec![1, 2])
+        );
+        assert_eq!(
+            attempt(vec![transfer(), transfer(), transfer()], 2),
+            (false, 3, vec![1, 2])
+        );
+        assert_eq!(attempt(vec![transfer()], 0), (false, 1, vec![]));
+        // Neither rejections nor unknown failures are retried
+        let rejected = || {
+            Err(NdkError::InstallFailed {
+                verdict: "INSTALL_FAILED_VERSION_DOWNGRADE".to_owned(),
+                output: String::new(),
+            })
+        };
+        assert_eq!(
+            attempt(vec![transfer(), rejected()], 2),
+            (false, 2, vec![1])
+        );
+        assert_eq!(
+            attempt(vec![Err(NdkError::CmdFailed(Command::new("adb")))], 2),
+            (false, 1, vec![])
+        );
+    }
+
+    #[test]
+    fn reinstall_decision() {
+        let failed = |output: &str| NdkError::InstallFailed {
+            verdict: install_verdict(output).unwrap(),
+            output: output.to_owned(),
+        };
+        assert!(is_fixed_by_reinstall(&failed(
+            "Performing Streamed Install\nadb: failed to install app.apk: Failure [INSTALL_FAILED_UPDATE_INCOMPATIBLE: Existing package com.example signatures do not match newer version; ignoring!]\n"
+        )));
+        assert!(is_fixed_by_reinstall(&failed(
+            "Failure [INSTALL_PARSE_FAILED_INCONSISTENT_CERTIFICATES]"
+        )));
+        // Uninstalling would lose the data without resolving these
+        assert!(!is_fixed_by_reinstall(&failed(
+            "Failure [INSTALL_FAILED_VERSION_DOWNGRADE]"
+        )));
+        assert!(!is_fixed_by_reinstall(&failed(
+            "Failure [INSTALL_FAILED_INSUFFICIENT_STORAGE]"
+        )));
+        assert!(!is_fixed_by_reinstall(&NdkError::InstallTimedOut(
+            Duration::from_secs(60)
+        )));
+    }
+
+    #[test]
+    fn activity_launch_errors() {
+        assert!(launch_errors(
+            "Starting: Intent { act=android.intent.action.MAIN cmp=com.example/.Main }\n"
+        )
+        .is_empty());
+        assert_eq!(
+            launch_errors(
+                "Starting: Intent { cmp=com.example/.Missing }\nError type 3\nError: Activity class {com.example/com.example.Missing} does not exist.\n"
+            ),
+            ["Error: Activity class {com.example/com.example.Missing} does not exist."]
+        );
+        assert_eq!(
+            launch_errors("Exception occurred while executing 'start':\njava.lang.SecurityException: Permission Denial: starting Intent { cmp=com.example/.Hidden } from null (pid=1, uid=2000) not exported from uid 10100\n\tat com.android.server.am.ActivityTaskSupervisor.checkStartAnyActivityPermission\n").len(),
+            1
+        );
+        assert_eq!(
+            launch_errors("  bash arg: -p\n** No activities found to run, monkey aborted.\n").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn resumed_activity_packages() {
+        let dumpsys = "\
+ACTIVITY MANAGER ACTIVITIES (dumpsys activity activities)
+Display #0 (activities from top to bottom):
+  * Task{8d1c2 #12 type=standard A=10100:com.example}
+    * Hist  #0: ActivityRecord{3d4f1a u0 com.example/.MainActivity t12}
+  mResumedActivity: ActivityRecord{3d4f1a u0 com.example/.MainActivity t12}
+  topResumedActivity=ActivityRecord{3d4f1a u0 com.example/.MainActivity t12}
+  mLastPausedActivity: ActivityRecord{77e1 u0 com.android.launcher3/.uioverrides.QuickstepLauncher t2}
+";
+        assert_eq!(
+            resumed_packages(dumpsys).collect::<Vec<_>>(),
+            ["com.example", "com.example"]
+        );
+        assert_eq!(
+            resumed_packages(
+                "  ResumedActivity: ActivityRecord{1 u0 com.android.launcher3/.Launcher t2}\n"
+            )
+            .collect::<Vec<_>>(),
+            ["com.android.launcher3"]
+        );
+        assert_eq!(resumed_packages("  mResumedActivity: null\n").count(), 0);
+    }
+
+    #[test]
+    fn rotation_signer_args() {
+        let key = KeystoreMeta::single("new.jks".into(), "new-pass".to_owned());
+        let rotation = KeyRotation {
+            previous: KeystoreMeta::single("old.jks".into(), "old-pass".to_owned())
+                .alias("old".to_owned())
+                .key_pass("old-key".to_owned()),
+            lineage: "lineage.bin".into(),
+        };
+        let mut apksigner = Command::new("apksigner");
+        add_signer_args(&mut apksigner, &key, Some(&rotation));
+        assert_eq!(
+            apksigner.get_args().collect::<Vec<_>>(),
+            [
+                "--ks",
+                "old.jks",
+                "--ks-pass",
+                "pass:old-pass",
+                "--ks-key-alias",
+                "old",
+                "--key-pass",
+                "pass:old-key",
+                "--next-signer",
+                "--ks",
+                "new.jks",
+                "--ks-pass",
+                "pass:new-pass",
+                "--lineage",
+                "lineage.bin",
+            ]
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::error::NdkError;
 use crate::ndk::Ndk;
 use crate::target::Target;
+use std::env::VarError;
 use std::path::Path;
 use std::process::Command;
 
@@ -9,6 +10,20 @@ pub fn cargo_ndk(
     target: Target,
     sdk_version: u32,
     target_dir: impl AsRef<Path>,
+) -> Result<Command, NdkError> {
+    cargo_ndk_with_vars(ndk, target, sdk_version, target_dir, |name| {
+        std::env::var(name)
+    })
+}
+
+/// Like [`cargo_ndk()`], but reads the initial `CARGO_ENCODED_RUSTFLAGS` or `RUSTFLAGS`
+/// through `var` instead of from the environment of the process.
+pub fn cargo_ndk_with_vars(
+    ndk: &Ndk,
+    target: Target,
+    sdk_version: u32,
+    target_dir: impl AsRef<Path>,
+    var: impl Fn(&str) -> Result<String, VarError>,
 ) -> Result<Command, NdkError> {
     let triple = target.rust_triple();
     let clang_target = format!("--target={}{}", target.ndk_llvm_triple(), sdk_version);
@@ -17,9 +32,9 @@ pub fn cargo_ndk(
     const SEP: &str = "\x1f";
 
     // Read initial CARGO_ENCODED_/RUSTFLAGS
-    let mut rustflags = match std::env::var("CARGO_ENCODED_RUSTFLAGS") {
+    let mut rustflags = match var("CARGO_ENCODED_RUSTFLAGS") {
         Ok(val) => {
-            if std::env::var_os("RUSTFLAGS").is_some() {
+            if !matches!(var("RUSTFLAGS"), Err(VarError::NotPresent)) {
                 panic!(
                     "Both `CARGO_ENCODED_RUSTFLAGS` and `RUSTFLAGS` were found in the environment, please clear one or the other before invoking this script"
                 );
@@ -27,11 +42,9 @@ pub fn cargo_ndk(
 
             val
         }
-        Err(std::env::VarError::NotPresent) => {
-            match std::env::var("RUSTFLAGS") {
+        Err(VarError::NotPresent) => {
+            match var("RUSTFLAGS") {
                 Ok(val) => {
-                    cargo.env_remove("RUSTFLAGS");
-
                     // Same as cargo
                     // https://github.com/rust-lang/cargo/blob/f6de921a5d807746e972d9d10a4d8e1ca21e1b1f/src/cargo/core/compiler/build_context/target_info.rs#L682-L690
                     val.split(' ')
@@ -40,13 +53,13 @@ pub fn cargo_ndk(
                         .collect::<Vec<_>>()
                         .join(SEP)
                 }
-                Err(std::env::VarError::NotPresent) => String::new(),
-                Err(std::env::VarError::NotUnicode(_)) => {
+                Err(VarError::NotPresent) => String::new(),
+                Err(VarError::NotUnicode(_)) => {
                     panic!("RUSTFLAGS environment variable contains non-unicode characters")
                 }
             }
         }
-        Err(std::env::VarError::NotUnicode(_)) => {
+        Err(VarError::NotUnicode(_)) => {
             panic!("CARGO_ENCODED_RUSTFLAGS environment variable contains non-unicode characters")
         }
     };
@@ -105,6 +118,9 @@ pub fn cargo_ndk(
         );
     }
 
+    // Replaces any `RUSTFLAGS` that the process would otherwise pass on, also when `var` does
+    // not read the environment of the process
+    cargo.env_remove("RUSTFLAGS");
     cargo.env("CARGO_ENCODED_RUSTFLAGS", rustflags);
 
     Ok(cargo)
@@ -44,8 +44,16 @@ pub enum NdkError {
     IoPathError(PathBuf, #[source] IoError),
     #[error("Invalid semver")]
     InvalidSemver,
-    #[error("Command `{}` had a non-zero exit code.", format!("{:?}", .0).replace('"', ""))]
+    #[error("Command `{}` had a non-zero exit code.", crate::secret::redacted_command(.0))]
     CmdFailed(Command),
+    #[error("Failed to start `{component}`:\n{message}")]
+    ActivityStartFailed { component: String, message: String },
+    #[error("`{tool}` printed warnings, which are denied:\n{}", .warnings.join("\n"))]
+    DeniedWarnings { tool: String, warnings: Vec<String> },
+    #[error("The libraries for `{abi}` have problems, which are denied:\n{}", .findings.join("\n"))]
+    InvalidLibraries { abi: String, findings: Vec<String> },
+    #[error("Failed to parse the ELF file `{0:?}`: {1}")]
+    InvalidElf(PathBuf, String),
     #[error(transparent)]
     Deserialize(#[from] quick_xml::de::DeError),
     #[error(transparent)]
@@ -56,4 +64,14 @@ pub enum NdkError {
     PackageNotInOutput { package: String, output: String },
     #[error("Could not find `uid:` in output `{0}`")]
     UidNotInOutput(String),
+    #[error("Could not parse an API level from output `{0}`")]
+    ApiLevelNotInOutput(String),
+    #[error("`adb reverse` is not supported by devices with API level {0}, but a reverse port forward is `required`")]
+    ReversePortForwardUnsupported(u32),
+    #[error("The device rejected the APK with `{verdict}`:\n{output}")]
+    InstallFailed { verdict: String, output: String },
+    #[error("`adb install` failed to transfer the APK:\n{0}")]
+    InstallTransferFailed(String),
+    #[error("`adb install` did not finish within {0:?}")]
+    InstallTimedOut(std::time::Duration),
 }
@@ -34,5 +34,10 @@ pub mod dylibs;
 pub mod error;
 pub mod manifest;
 pub mod ndk;
+pub mod partial;
 pub mod readelf;
+pub mod resources;
+pub mod secret;
+pub mod shell;
 pub mod target;
+pub mod wsl;
@@ -41,7 +41,15 @@ impl Default for AndroidManifest {
     fn default() -> Self {
         Self {
             ns_android: default_namespace(),
-            ..Default::default()
+            package: Default::default(),
+            shared_user_id: None,
+            version_code: None,
+            version_name: None,
+            sdk: Default::default(),
+            uses_feature: Default::default(),
+            uses_permission: Default::default(),
+            queries: None,
+            application: Default::default(),
         }
     }
 }
@@ -61,6 +69,10 @@ impl AndroidManifest {
 pub struct Application {
     #[serde(rename(serialize = "android:debuggable"))]
     pub debuggable: Option<bool>,
+    /// Marks the app as a test build that can only be installed through `adb install -t`, see
+    /// <https://developer.android.com/guide/topics/manifest/application-element#testOnly>
+    #[serde(rename(serialize = "android:testOnly"))]
+    pub test_only: Option<bool>,
     #[serde(rename(serialize = "android:theme"))]
     pub theme: Option<String>,
     #[serde(rename(serialize = "android:hasCode"))]
@@ -68,6 +80,13 @@ pub struct Application {
     pub has_code: bool,
     #[serde(rename(serialize = "android:icon"))]
     pub icon: Option<String>,
+    /// Icon shown by launchers that use round icons, see
+    /// <https://developer.android.com/guide/topics/manifest/application-element#roundIcon>
+    #[serde(rename(serialize = "android:roundIcon"))]
+    pub round_icon: Option<String>,
+    /// Banner shown on the Android TV home screen, required for leanback apps to be listed
+    #[serde(rename(serialize = "android:banner"))]
+    pub banner: Option<String>,
     #[serde(rename(serialize = "android:label"))]
     #[serde(default)]
     pub label: String,
@@ -75,12 +94,31 @@ pub struct Application {
     pub extract_native_libs: Option<bool>,
     #[serde(rename(serialize = "android:usesCleartextTraffic"))]
     pub uses_cleartext_traffic: Option<bool>,
+    #[serde(rename(serialize = "android:networkSecurityConfig"))]
+    pub network_security_config: Option<String>,
+
+    /// Lets the shell profile a non-`debuggable` build, e.g. with simpleperf, on Android 10
+    /// (API 29) and up. Given as `profileable = true` or a table of its attributes
+    #[serde(default, deserialize_with = "deserialize_profileable")]
+    pub profileable: Option<Profileable>,
 
     #[serde(rename(serialize = "meta-data"))]
     #[serde(default)]
     pub meta_data: Vec<MetaData>,
     #[serde(default)]
+    pub provider: Vec<Provider>,
+    /// Shared native libraries of the platform or vendor that the app loads, which need to be
+    /// declared on Android 12 (API 31) and up
+    #[serde(rename(serialize = "uses-native-library"))]
+    #[serde(default)]
+    pub native_libraries: Vec<NativeLibrary>,
+    #[serde(default)]
     pub activity: Activity,
+    /// Serialized after [`Application::activity`], as the target of an alias must be declared
+    /// before it
+    #[serde(rename(serialize = "activity-alias"))]
+    #[serde(default)]
+    pub activity_aliases: Vec<ActivityAlias>,
 }
 
 /// Android [activity element](https://developer.android.com/guide/topics/manifest/activity-element).
@@ -93,6 +131,8 @@ pub struct Activity {
     pub label: Option<String>,
     #[serde(rename(serialize = "android:launchMode"))]
     pub launch_mode: Option<String>,
+    #[serde(rename(serialize = "android:theme"))]
+    pub theme: Option<String>,
     #[serde(rename(serialize = "android:name"))]
     #[serde(default = "default_activity_name")]
     pub name: String,
@@ -100,6 +140,8 @@ pub struct Activity {
     pub orientation: Option<String>,
     #[serde(rename(serialize = "android:exported"))]
     pub exported: Option<bool>,
+    #[serde(rename(serialize = "android:permission"))]
+    pub permission: Option<String>,
     #[serde(rename(serialize = "android:resizeableActivity"))]
     pub resizeable_activity: Option<bool>,
     #[serde(rename(serialize = "android:alwaysRetainTaskState"))]
@@ -120,9 +162,11 @@ impl Default for Activity {
             config_changes: default_config_changes(),
             label: None,
             launch_mode: None,
+            theme: None,
             name: default_activity_name(),
             orientation: None,
             exported: None,
+            permission: None,
             resizeable_activity: None,
             always_retain_task_state: None,
             meta_data: Default::default(),
@@ -131,6 +175,36 @@ impl Default for Activity {
     }
 }
 
+/// Android [activity-alias element](https://developer.android.com/guide/topics/manifest/activity-alias-element),
+/// e.g. to switch the launcher icon at runtime by enabling one alias and disabling the others.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ActivityAlias {
+    #[serde(rename(serialize = "android:name"))]
+    pub name: String,
+    /// `cargo-android` points aliases without one at [`Application::activity`]
+    #[serde(rename(serialize = "android:targetActivity"))]
+    pub target_activity: Option<String>,
+    #[serde(rename(serialize = "android:enabled"))]
+    pub enabled: Option<bool>,
+    #[serde(rename(serialize = "android:exported"))]
+    pub exported: Option<bool>,
+    #[serde(rename(serialize = "android:icon"))]
+    pub icon: Option<String>,
+    #[serde(rename(serialize = "android:roundIcon"))]
+    pub round_icon: Option<String>,
+    #[serde(rename(serialize = "android:label"))]
+    pub label: Option<String>,
+    #[serde(rename(serialize = "android:permission"))]
+    pub permission: Option<String>,
+
+    #[serde(rename(serialize = "meta-data"))]
+    #[serde(default)]
+    pub meta_data: Vec<MetaData>,
+    #[serde(rename(serialize = "intent-filter"))]
+    #[serde(default)]
+    pub intent_filter: Vec<IntentFilter>,
+}
+
 /// Android [intent filter element](https://developer.android.com/guide/topics/manifest/intent-filter-element).
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct IntentFilter {
@@ -208,13 +282,82 @@ pub struct IntentFilterData {
     pub mime_type: Option<String>,
 }
 
+/// Android [provider element](https://developer.android.com/guide/topics/manifest/provider-element).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Provider {
+    #[serde(rename(serialize = "android:name"))]
+    pub name: String,
+    /// Authorities separated by `;`. `cargo-android` replaces `{applicationId}` with the
+    /// package name, e.g. `{applicationId}.fileprovider`.
+    #[serde(rename(serialize = "android:authorities"))]
+    pub authorities: String,
+    #[serde(rename(serialize = "android:exported"))]
+    pub exported: Option<bool>,
+    #[serde(rename(serialize = "android:grantUriPermissions"))]
+    pub grant_uri_permissions: Option<bool>,
+
+    #[serde(rename(serialize = "meta-data"))]
+    #[serde(default)]
+    pub meta_data: Vec<MetaData>,
+}
+
+/// Android [uses-native-library element](https://developer.android.com/guide/topics/manifest/uses-native-library-element).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NativeLibrary {
+    /// File name of the library, e.g. `libOpenCL.so`
+    #[serde(rename(serialize = "android:name"))]
+    pub name: String,
+    /// Whether the app cannot be installed without the library, defaults to `true` on the device
+    #[serde(rename(serialize = "android:required"))]
+    pub required: Option<bool>,
+}
+
+/// Android [profileable element](https://developer.android.com/guide/topics/manifest/profileable-element).
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Profileable {
+    /// Whether the app can be profiled by the shell user, such as `simpleperf` run through
+    /// `adb shell`
+    #[serde(rename(serialize = "android:shell"))]
+    pub shell: Option<bool>,
+    /// Whether the app can be profiled at all, defaults to `true` on the device
+    #[serde(rename(serialize = "android:enabled"))]
+    pub enabled: Option<bool>,
+}
+
+/// Deserializes `profileable = true` as profileable by the shell, `false` as no element, or
+/// a table of the [`Profileable`] attributes.
+fn deserialize_profileable<'de, D>(deserializer: D) -> Result<Option<Profileable>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Enabled(bool),
+        Table(Profileable),
+    }
+
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Enabled(true) => Some(Profileable {
+            shell: Some(true),
+            enabled: None,
+        }),
+        Value::Enabled(false) => None,
+        Value::Table(profileable) => Some(profileable),
+    })
+}
+
 /// Android [meta-data element](https://developer.android.com/guide/topics/manifest/meta-data-element).
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct MetaData {
     #[serde(rename(serialize = "android:name"))]
     pub name: String,
     #[serde(rename(serialize = "android:value"))]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub value: String,
+    /// A resource reference such as `@xml/file_paths`, as used by e.g. `FileProvider`
+    #[serde(rename(serialize = "android:resource"))]
+    pub resource: Option<String>,
 }
 
 /// Android [uses-feature element](https://developer.android.com/guide/topics/manifest/uses-feature-element).
@@ -297,16 +440,106 @@ pub struct Queries {
 }
 
 /// Android [uses-sdk element](https://developer.android.com/guide/topics/manifest/uses-sdk-element).
+///
+/// Versions are deserialized from either an API level (`31`) or a name that is looked up
+/// through [`api_level_from_name`] (`"android-12"`, `"S"`).
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Sdk {
     #[serde(rename(serialize = "android:minSdkVersion"))]
+    #[serde(default, deserialize_with = "deserialize_api_level")]
     pub min_sdk_version: Option<u32>,
     #[serde(rename(serialize = "android:targetSdkVersion"))]
+    #[serde(default, deserialize_with = "deserialize_api_level")]
     pub target_sdk_version: Option<u32>,
     #[serde(rename(serialize = "android:maxSdkVersion"))]
+    #[serde(default, deserialize_with = "deserialize_api_level")]
     pub max_sdk_version: Option<u32>,
 }
 
+/// API levels with their Android version name, codename and dessert name.
+///
+/// <https://developer.android.com/tools/releases/platforms>
+pub const API_LEVELS: &[(u32, &str, &str, &str)] = &[
+    (16, "4.1", "J", "JellyBean"),
+    (17, "4.2", "J_MR1", "JellyBean"),
+    (18, "4.3", "J_MR2", "JellyBean"),
+    (19, "4.4", "K", "KitKat"),
+    (20, "4.4W", "K_WATCH", "KitKatWatch"),
+    (21, "5.0", "L", "Lollipop"),
+    (22, "5.1", "L_MR1", "Lollipop"),
+    (23, "6.0", "M", "Marshmallow"),
+    (24, "7.0", "N", "Nougat"),
+    (25, "7.1", "N_MR1", "Nougat"),
+    (26, "8.0", "O", "Oreo"),
+    (27, "8.1", "O_MR1", "Oreo"),
+    (28, "9", "P", "Pie"),
+    (29, "10", "Q", "QuinceTart"),
+    (30, "11", "R", "RedVelvetCake"),
+    (31, "12", "S", "SnowCone"),
+    (32, "12L", "S_V2", "SnowConeV2"),
+    (33, "13", "T", "Tiramisu"),
+    (34, "14", "U", "UpsideDownCake"),
+    (35, "15", "V", "VanillaIceCream"),
+    (36, "16", "BAKLAVA", "Baklava"),
+];
+
+/// Maps an Android version name (`"android-12"`, `"12"`, `"5.1"`), codename (`"S"`,
+/// `"O_MR1"`) or dessert name (`"Tiramisu"`) to its API level. Strings consisting of only
+/// digits are API levels themselves, and so are the digits of platform names such as
+/// `"android-33"` unless they are a version name.
+///
+/// Dessert names that are shared by multiple releases resolve to the first of those.
+pub fn api_level_from_name(name: &str) -> Option<u32> {
+    if let Some(version) = name.strip_prefix("android-") {
+        return API_LEVELS
+            .iter()
+            .find(|(_, v, _, _)| {
+                v.eq_ignore_ascii_case(version) || v.strip_suffix(".0") == Some(version)
+            })
+            .map(|(level, _, _, _)| *level)
+            .or_else(|| version.parse().ok());
+    }
+    if let Ok(level) = name.parse() {
+        return Some(level);
+    }
+    API_LEVELS
+        .iter()
+        .find(|(_, version, codename, dessert)| {
+            version.eq_ignore_ascii_case(name)
+                || codename.eq_ignore_ascii_case(name)
+                || dessert.eq_ignore_ascii_case(name)
+        })
+        .map(|(level, _, _, _)| *level)
+}
+
+fn deserialize_api_level<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ApiLevel {
+        Level(u32),
+        Name(String),
+    }
+
+    match ApiLevel::deserialize(deserializer)? {
+        ApiLevel::Level(level) => Ok(Some(level)),
+        ApiLevel::Name(name) => api_level_from_name(&name).map(Some).ok_or_else(|| {
+            let valid = API_LEVELS
+                .iter()
+                .map(|(level, version, codename, _)| {
+                    format!("{level} (android-{version}, {codename})")
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            serde::de::Error::custom(format!(
+                "unknown Android version `{name}`, expected an API level or one of: {valid}"
+            ))
+        }),
+    }
+}
+
 impl Default for Sdk {
     fn default() -> Self {
         Self {
@@ -327,4 +560,119 @@ fn default_activity_name() -> String {
 
 fn default_config_changes() -> Option<String> {
     Some("orientation|keyboardHidden|screenSize".to_string())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::{Error, StrDeserializer, U32Deserializer};
+
+    #[test]
+    fn api_level_names_round_trip() {
+        for (level, version, codename, _) in API_LEVELS {
+            assert_eq!(
+                api_level_from_name(&format!("android-{version}")),
+                Some(*level)
+            );
+            // Plain numbers are API levels, not version names
+            if !version.chars().all(|c| c.is_ascii_digit()) {
+                assert_eq!(api_level_from_name(version), Some(*level));
+            }
+            assert_eq!(api_level_from_name(codename), Some(*level));
+            assert_eq!(api_level_from_name(&codename.to_lowercase()), Some(*level));
+            assert_eq!(api_level_from_name(&level.to_string()), Some(*level));
+        }
+        assert_eq!(api_level_from_name("android-6"), Some(23));
+        assert_eq!(api_level_from_name("Tiramisu"), Some(33));
+        // Platform names, as in `platforms/android-33`, unless they are version names
+        assert_eq!(api_level_from_name("android-33"), Some(33));
+        assert_eq!(api_level_from_name("android-12"), Some(31));
+        assert_eq!(api_level_from_name("android-99"), Some(99));
+        assert_eq!(api_level_from_name("android-99.1"), None);
+        assert_eq!(api_level_from_name("Zebra"), None);
+    }
+
+    #[test]
+    fn deserialize_sdk_versions() {
+        let level = |name| deserialize_api_level(StrDeserializer::<Error>::new(name));
+        assert_eq!(level("android-12").unwrap(), Some(31));
+        assert_eq!(level("S").unwrap(), Some(31));
+        assert_eq!(
+            deserialize_api_level(U32Deserializer::<Error>::new(31)).unwrap(),
+            Some(31)
+        );
+        let err = level("Zebra").unwrap_err().to_string();
+        assert!(err.contains("unknown Android version `Zebra`"));
+        assert!(err.contains("31 (android-12, S)"));
+    }
+
+    #[test]
+    fn native_libraries() {
+        let xml = |manifest: &AndroidManifest| {
+            let mut buf = String::new();
+            quick_xml::se::to_writer(&mut buf, manifest).unwrap();
+            buf
+        };
+        let mut manifest = AndroidManifest::default();
+        assert!(!xml(&manifest).contains("uses-native-library"));
+
+        manifest.application.native_libraries.push(NativeLibrary {
+            name: "libOpenCL.so".to_string(),
+            required: Some(false),
+        });
+        let xml = xml(&manifest);
+        assert_eq!(xml.matches("<uses-native-library").count(), 1);
+        assert!(xml.contains("libOpenCL.so"));
+    }
+
+    #[test]
+    fn profileable() {
+        use serde::de::value::BoolDeserializer;
+        assert_eq!(
+            deserialize_profileable(BoolDeserializer::<Error>::new(true)).unwrap(),
+            Some(Profileable {
+                shell: Some(true),
+                enabled: None,
+            })
+        );
+        assert_eq!(
+            deserialize_profileable(BoolDeserializer::<Error>::new(false)).unwrap(),
+            None
+        );
+
+        let mut manifest = AndroidManifest::default();
+        manifest.application.profileable = Some(Profileable {
+            shell: Some(true),
+            enabled: Some(true),
+        });
+        let mut xml = String::new();
+        quick_xml::se::to_writer(&mut xml, &manifest).unwrap();
+        assert_eq!(xml.matches("<profileable").count(), 1);
+        assert!(xml.contains("android:shell"));
+    }
+
+    #[test]
+    fn activity_aliases() {
+        let mut manifest = AndroidManifest::default();
+        manifest.application.activity_aliases.push(ActivityAlias {
+            name: ".WinterIcon".to_string(),
+            target_activity: Some("android.app.NativeActivity".to_string()),
+            enabled: Some(false),
+            icon: Some("@mipmap/winter".to_string()),
+            intent_filter: vec![IntentFilter {
+                actions: vec!["android.intent.action.MAIN".to_string()],
+                categories: vec!["android.intent.category.LAUNCHER".to_string()],
+                data: vec![],
+            }],
+            ..Default::default()
+        });
+        let mut xml = String::new();
+        quick_xml::se::to_writer(&mut xml, &manifest).unwrap();
+        let alias = xml.find("<activity-alias").unwrap();
+        assert!(xml.find("<activity").unwrap() < alias, "{xml}");
+        assert_eq!(xml.matches("<activity-alias").count(), 1);
+        assert!(xml[alias..].contains("android.app.NativeActivity"));
+        assert!(xml[alias..].contains("@mipmap/winter"));
+        assert!(xml[alias..].contains("android.intent.category.LAUNCHER"));
+    }
+}
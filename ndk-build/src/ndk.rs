@@ -1,9 +1,14 @@
 use std::collections::HashMap;
+use std::env::VarError;
+use std::ffi::{OsStr, OsString};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::SystemTime;
 
 use crate::error::NdkError;
+use crate::secret::Secret;
 use crate::target::Target;
+use crate::wsl;
 
 /// The default password used when creating the default `debug.keystore` via
 /// [`Ndk::debug_key`]
@@ -17,12 +22,95 @@ pub struct Ndk {
     build_tools_version: String,
     build_tag: u32,
     platforms: Vec<u32>,
+    /// Resolved once when probing for [`Ndk::from_env_cached()`], looked up on every call to
+    /// [`Ndk::toolchain_dir()`] otherwise
+    toolchain_dir: Option<PathBuf>,
+    adb_server: Option<String>,
+    /// The JDK that [`Ndk::keytool()`] prefers over the `keytool` on `PATH`
+    java_home: Option<PathBuf>,
 }
 
 impl Ndk {
     pub fn from_env() -> Result<Self, NdkError> {
+        Self::from_vars(|name| std::env::var(name))
+    }
+
+    /// Like [`Ndk::from_env()`], but reads `ANDROID_HOME`, the NDK and Android user home
+    /// variables and `JAVA_HOME` through `var` instead of from the environment of the process.
+    pub fn from_vars(var: impl Fn(&str) -> Result<String, VarError>) -> Result<Self, NdkError> {
+        let (sdk_path, user_home, ndk_path) = Self::locate(&var)?;
+        Ok(Self::probe(sdk_path, user_home, ndk_path)?.with_java_home(java_home(&var)))
+    }
+
+    /// Uses the SDK, Android user home (`.android`) and NDK at the given paths, instead of
+    /// locating them through the environment like [`Ndk::from_env()`] does. `JAVA_HOME` is
+    /// still read from the environment, unless replaced with [`Ndk::with_java_home()`].
+    pub fn from_paths(
+        sdk_path: PathBuf,
+        user_home: PathBuf,
+        ndk_path: PathBuf,
+    ) -> Result<Self, NdkError> {
+        Ok(Self::probe(sdk_path, user_home, ndk_path)?
+            .with_java_home(java_home(|name| std::env::var(name))))
+    }
+
+    /// Like [`Ndk::from_env()`], but reuses the build tools version, NDK build tag, platforms
+    /// and toolchain directory that a previous invocation probed and stored in `cache`.
+    ///
+    /// The cache is only used when it was written for the same SDK and NDK paths and the
+    /// NDK's `source.properties` as well as the SDK's `build-tools` and `platforms`
+    /// directories are unchanged. Otherwise, or when it cannot be read, the SDK and NDK are
+    /// probed again and the cache is rewritten.
+    pub fn from_env_cached(cache: &Path) -> Result<Self, NdkError> {
+        Self::from_vars_cached(cache, |name| std::env::var(name))
+    }
+
+    /// Like [`Ndk::from_env_cached()`], but reads the variables through `var` like
+    /// [`Ndk::from_vars()`] does.
+    pub fn from_vars_cached(
+        cache: &Path,
+        var: impl Fn(&str) -> Result<String, VarError>,
+    ) -> Result<Self, NdkError> {
+        let (sdk_path, user_home, ndk_path) = Self::locate(&var)?;
+        let java_home = java_home(&var);
+        let Some(key) = cache_key(&sdk_path, &ndk_path) else {
+            return Ok(Self::probe(sdk_path, user_home, ndk_path)?.with_java_home(java_home));
+        };
+        // Outdated and corrupted caches are simply replaced
+        let cached = std::fs::read_to_string(cache)
+            .ok()
+            .and_then(|contents| parse_cache(&contents, &key));
+        if let Some((build_tools_version, build_tag, platforms, toolchain_dir)) = cached {
+            return Ok(Self {
+                sdk_path,
+                user_home,
+                ndk_path,
+                build_tools_version,
+                build_tag,
+                platforms,
+                toolchain_dir: Some(toolchain_dir),
+                adb_server: None,
+                java_home,
+            });
+        }
+
+        let mut ndk = Self::probe(sdk_path, user_home, ndk_path)?.with_java_home(java_home);
+        ndk.toolchain_dir = ndk.find_toolchain_dir().ok();
+        if let Err(e) = ndk.write_cache(cache, &key) {
+            eprintln!(
+                "Warning: failed to write NDK cache `{}`: {e}",
+                cache.display()
+            );
+        }
+        Ok(ndk)
+    }
+
+    /// Resolves the SDK, Android user home and NDK paths from the variables that `var` reads.
+    fn locate(
+        var: impl Fn(&str) -> Result<String, VarError>,
+    ) -> Result<(PathBuf, PathBuf, PathBuf), NdkError> {
         let sdk_path = {
-            let sdk_path = std::env::var("ANDROID_SDK_ROOT").ok();
+            let sdk_path = var("ANDROID_SDK_ROOT").ok();
             if sdk_path.is_some() {
                 eprintln!(
                     "Warning: Environment variable ANDROID_SDK_ROOT is deprecated \
@@ -33,13 +121,13 @@ impl Ndk {
 
             PathBuf::from(
                 sdk_path
-                    .or_else(|| std::env::var("ANDROID_HOME").ok())
+                    .or_else(|| var("ANDROID_HOME").ok())
                     .ok_or(NdkError::SdkNotFound)?,
             )
         };
 
         let user_home = {
-            let user_home = std::env::var("ANDROID_SDK_HOME")
+            let user_home = var("ANDROID_SDK_HOME")
                 .map(PathBuf::from)
                 // Unlike ANDROID_USER_HOME, ANDROID_SDK_HOME points to the _parent_ directory of .android:
                 // https://developer.android.com/studio/command-line/variables#envar
@@ -56,17 +144,17 @@ impl Ndk {
 
             // Default to $HOME/.android
             user_home
-                .or_else(|| std::env::var("ANDROID_USER_HOME").map(PathBuf::from).ok())
+                .or_else(|| var("ANDROID_USER_HOME").map(PathBuf::from).ok())
                 .or_else(|| dirs::home_dir().map(|home| home.join(".android")))
                 .ok_or_else(|| NdkError::PathNotFound(PathBuf::from("$HOME")))?
         };
 
         let ndk_path = {
-            let ndk_path = std::env::var("ANDROID_NDK_ROOT")
+            let ndk_path = var("ANDROID_NDK_ROOT")
                 .ok()
-                .or_else(|| std::env::var("ANDROID_NDK_PATH").ok())
-                .or_else(|| std::env::var("ANDROID_NDK_HOME").ok())
-                .or_else(|| std::env::var("NDK_HOME").ok());
+                .or_else(|| var("ANDROID_NDK_PATH").ok())
+                .or_else(|| var("ANDROID_NDK_HOME").ok())
+                .or_else(|| var("NDK_HOME").ok());
 
             // default ndk installation path
             if ndk_path.is_none() && sdk_path.join("ndk-bundle").exists() {
@@ -76,6 +164,11 @@ impl Ndk {
             }
         };
 
+        Ok((sdk_path, user_home, ndk_path))
+    }
+
+    /// Probes the build tools, NDK version and platforms that are installed.
+    fn probe(sdk_path: PathBuf, user_home: PathBuf, ndk_path: PathBuf) -> Result<Self, NdkError> {
         let build_tools_dir = sdk_path.join("build-tools");
         let build_tools_version = std::fs::read_dir(&build_tools_dir)
             .or(Err(NdkError::PathNotFound(build_tools_dir)))?
@@ -148,9 +241,34 @@ impl Ndk {
             build_tools_version,
             build_tag,
             platforms,
+            toolchain_dir: None,
+            adb_server: None,
+            java_home: None,
         })
     }
 
+    fn write_cache(&self, cache: &Path, key: &str) -> std::io::Result<()> {
+        let Some(toolchain_dir) = self.toolchain_dir.as_deref().and_then(Path::to_str) else {
+            return Ok(());
+        };
+        let platforms = self
+            .platforms
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        if let Some(parent) = cache.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(
+            cache,
+            format!(
+                "{key}build_tools_version={}\nbuild_tag={}\nplatforms={platforms}\ntoolchain_dir={toolchain_dir}\n",
+                self.build_tools_version, self.build_tag
+            ),
+        )
+    }
+
     pub fn sdk(&self) -> &Path {
         &self.sdk_path
     }
@@ -191,8 +309,27 @@ impl Ndk {
         Ok(dunce::canonicalize(path)?)
     }
 
+    /// The `adb` of the SDK. Inside WSL, an SDK that is shared with Windows only has the
+    /// `adb.exe` of Windows, which is used when the Linux `adb` is missing. It talks to the adb
+    /// server on Windows, which sees the devices attached there.
     pub fn adb_path(&self) -> Result<PathBuf, NdkError> {
-        self.platform_tool_path(bin!("adb"))
+        match self.platform_tool_path(bin!("adb")) {
+            Err(NdkError::CmdNotFound(tool)) if cfg!(unix) && wsl::is_wsl() => self
+                .platform_tool_path("adb.exe")
+                .map_err(|_| NdkError::CmdNotFound(tool)),
+            adb => adb,
+        }
+    }
+
+    /// `path` as an argument of [`Ndk::adb()`]: translated to a Windows path when that is the
+    /// `adb.exe` of Windows, see [`Ndk::adb_path()`].
+    pub fn adb_path_arg(&self, path: &Path) -> Result<OsString, NdkError> {
+        let is_windows_adb = self.adb_path()?.extension() == Some(OsStr::new("exe"));
+        if cfg!(unix) && is_windows_adb {
+            Ok(wsl::windows_path(path))
+        } else {
+            Ok(path.into())
+        }
     }
 
     pub fn platform_tool(&self, tool: &str) -> Result<Command, NdkError> {
@@ -259,6 +396,13 @@ impl Ndk {
     }
 
     pub fn toolchain_dir(&self) -> Result<PathBuf, NdkError> {
+        match &self.toolchain_dir {
+            Some(toolchain_dir) => Ok(toolchain_dir.clone()),
+            None => self.find_toolchain_dir(),
+        }
+    }
+
+    fn find_toolchain_dir(&self) -> Result<PathBuf, NdkError> {
         let arch = Self::host_arch()?;
         let mut toolchain_dir = self
             .ndk_path
@@ -362,6 +506,10 @@ impl Ndk {
             ndk_gdb.arg("-s").arg(device_serial);
         }
 
+        if let Some(adb_server) = &self.adb_server {
+            ndk_gdb.env("ADB_SERVER_SOCKET", adb_server);
+        }
+
         ndk_gdb
             .arg("--adb")
             .arg(self.adb_path()?)
@@ -378,30 +526,40 @@ impl Ndk {
         Ok(android_user_home)
     }
 
+    /// `keytool` of the JDK in [`Ndk::java_home()`] when it has one, so that keys are read
+    /// with the same JDK as `jarsigner` signs with, or else the one on `PATH`.
     pub fn keytool(&self) -> Result<Command, NdkError> {
-        if let Ok(keytool) = which::which(bin!("keytool")) {
-            return Ok(Command::new(keytool));
-        }
-        if let Ok(java) = std::env::var("JAVA_HOME") {
-            let keytool = PathBuf::from(java).join("bin").join(bin!("keytool"));
+        if let Some(java_home) = &self.java_home {
+            let keytool = java_home.join("bin").join(bin!("keytool"));
             if keytool.exists() {
                 return Ok(Command::new(keytool));
             }
         }
+        if let Ok(keytool) = which::which(bin!("keytool")) {
+            return Ok(Command::new(keytool));
+        }
         Err(NdkError::CmdNotFound("keytool".to_string()))
     }
 
     pub fn debug_key(&self) -> Result<KeystoreMeta, NdkError> {
-        let path = self.android_user_home()?.join("debug.keystore");
+        self.debug_key_at(&self.android_user_home()?.join("debug.keystore"))
+    }
+
+    /// A debug keystore at `path` instead of the Android user home, generated like
+    /// [`Ndk::debug_key()`] when it does not exist yet.
+    pub fn debug_key_at(&self, path: &Path) -> Result<KeystoreMeta, NdkError> {
         let password = DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned();
 
         if !path.exists() {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
             let mut keytool = self.keytool()?;
             keytool
                 .arg("-genkey")
                 .arg("-v")
                 .arg("-keystore")
-                .arg(&path)
+                .arg(path)
                 .arg("-storepass")
                 .arg(&password)
                 .arg("-alias")
@@ -420,7 +578,7 @@ impl Ndk {
                 return Err(NdkError::CmdFailed(keytool));
             }
         }
-        Ok(KeystoreMeta::single(path, password))
+        Ok(KeystoreMeta::single(path.to_owned(), password))
     }
 
     pub fn sysroot_lib_dir(&self, target: Target) -> Result<PathBuf, NdkError> {
@@ -436,6 +594,24 @@ impl Ndk {
         Ok(sysroot_lib_dir)
     }
 
+    /// The API levels that the sysroot has platform libraries for when building `target`, in
+    /// ascending order.
+    pub fn sysroot_platforms(&self, target: Target) -> Result<Vec<u32>, NdkError> {
+        let sysroot_lib_dir = self.sysroot_lib_dir(target)?;
+        let mut platforms = std::fs::read_dir(&sysroot_lib_dir)
+            .map_err(|e| NdkError::IoPathError(sysroot_lib_dir, e))?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                if !entry.path().is_dir() {
+                    return None;
+                }
+                entry.file_name().to_str()?.parse::<u32>().ok()
+            })
+            .collect::<Vec<_>>();
+        platforms.sort_unstable();
+        Ok(platforms)
+    }
+
     pub fn sysroot_platform_lib_dir(
         &self,
         target: Target,
@@ -479,9 +655,75 @@ impl Ndk {
         Target::from_android_abi(abi.trim())
     }
 
+    /// The API level of the device, from its `ro.build.version.sdk` property.
+    pub fn device_api_level(&self, device_serial: Option<&str>) -> Result<u32, NdkError> {
+        let mut adb = self.adb(device_serial)?;
+        adb.arg("shell").arg("getprop").arg("ro.build.version.sdk");
+        let output = adb.output()?;
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(adb));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout
+            .trim()
+            .parse()
+            .map_err(|_| NdkError::ApiLevelNotInOutput(stdout.trim().to_owned()))
+    }
+
+    /// Points all `adb` invocations at the adb server listening on `socket`, in the
+    /// `ADB_SERVER_SOCKET` format (e.g. `tcp:172.20.160.1:5037`), instead of the local one.
+    ///
+    /// See [`crate::wsl::windows_host_adb_server()`].
+    #[must_use]
+    pub fn with_adb_server(mut self, socket: impl Into<String>) -> Self {
+        self.adb_server = Some(socket.into());
+        self
+    }
+
+    /// The adb server configured through [`Ndk::with_adb_server()`], if any.
+    pub fn adb_server(&self) -> Option<&str> {
+        self.adb_server.as_deref()
+    }
+
+    /// Uses the JDK at `java_home` for [`Ndk::keytool()`] instead of the one in the `JAVA_HOME`
+    /// that the SDK was located with, or only the `keytool` on `PATH` when `None`.
+    #[must_use]
+    pub fn with_java_home(mut self, java_home: Option<PathBuf>) -> Self {
+        self.java_home = java_home;
+        self
+    }
+
+    /// The JDK that [`Ndk::keytool()`] prefers, see [`Ndk::with_java_home()`].
+    pub fn java_home(&self) -> Option<&Path> {
+        self.java_home.as_deref()
+    }
+
+    /// Serials of the devices that are attached and authorized, as listed by `adb devices`.
+    pub fn devices(&self) -> Result<Vec<String>, NdkError> {
+        let mut adb = self.adb(None)?;
+        adb.arg("devices");
+        let output = adb.output()?;
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(adb));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut columns = line.split_whitespace();
+                let serial = columns.next()?;
+                (columns.next() == Some("device")).then(|| serial.to_owned())
+            })
+            .collect())
+    }
+
     pub fn adb(&self, device_serial: Option<&str>) -> Result<Command, NdkError> {
         let mut adb = Command::new(self.adb_path()?);
 
+        if let Some(adb_server) = &self.adb_server {
+            adb.env("ADB_SERVER_SOCKET", adb_server);
+        }
+
         if let Some(device_serial) = device_serial {
             adb.arg("-s").arg(device_serial);
         }
@@ -492,17 +734,17 @@ impl Ndk {
 
 pub struct KeystoreMeta {
     pub path: PathBuf,
-    pub store_pass: String,
+    pub store_pass: Secret,
     pub alias: Option<String>,
-    pub key_pass: Option<String>,
+    pub key_pass: Option<Secret>,
 }
 
 impl KeystoreMeta {
     #[must_use]
-    pub const fn single(path_buf: PathBuf, store_pass: String) -> Self {
+    pub fn single(path_buf: PathBuf, store_pass: impl Into<Secret>) -> Self {
         Self {
             path: path_buf,
-            store_pass,
+            store_pass: store_pass.into(),
             alias: None,
             key_pass: None,
         }
@@ -515,12 +757,65 @@ impl KeystoreMeta {
     }
 
     #[must_use]
-    pub fn key_pass(mut self, key_pass: String) -> Self {
-        self.key_pass = Some(key_pass);
+    pub fn key_pass(mut self, key_pass: impl Into<Secret>) -> Self {
+        self.key_pass = Some(key_pass.into());
         self
     }
 }
 
+/// The `JAVA_HOME` that `var` reads, if set.
+fn java_home(var: impl Fn(&str) -> Result<String, VarError>) -> Option<PathBuf> {
+    var("JAVA_HOME").ok().map(PathBuf::from)
+}
+
+/// Identifies the SDK and NDK installation that a cache was written for, as the leading lines
+/// of the cache file. `None` when it cannot be determined, in which case nothing is cached.
+fn cache_key(sdk_path: &Path, ndk_path: &Path) -> Option<String> {
+    let source_properties = std::fs::read_to_string(ndk_path.join("source.properties")).ok()?;
+    let modified = |dir: &str| {
+        std::fs::metadata(sdk_path.join(dir))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_nanos())
+    };
+    Some(format!(
+        "sdk={}\nndk={}\nsource_properties={:?}\nbuild_tools_modified={}\nplatforms_modified={}\nhost={}\n",
+        sdk_path.to_str()?,
+        ndk_path.to_str()?,
+        // Escaped onto a single line, verbatim otherwise
+        source_properties,
+        modified("build-tools"),
+        modified("platforms"),
+        Ndk::host_arch().ok()?,
+    ))
+}
+
+/// Parses the build tools version, build tag, platforms and toolchain directory from a cache
+/// file, or returns `None` when it was written for a different `key` or is corrupted.
+fn parse_cache(contents: &str, key: &str) -> Option<(String, u32, Vec<u32>, PathBuf)> {
+    let values = contents
+        .strip_prefix(key)?
+        .lines()
+        .map(|line| line.split_once('='))
+        .collect::<Option<HashMap<_, _>>>()?;
+    let platforms = values
+        .get("platforms")?
+        .split(',')
+        .map(|platform| platform.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    let toolchain_dir = PathBuf::from(values.get("toolchain_dir")?);
+    if platforms.is_empty() || !toolchain_dir.is_dir() {
+        return None;
+    }
+    Some((
+        values.get("build_tools_version")?.to_string(),
+        values.get("build_tag")?.parse().ok()?,
+        platforms,
+        toolchain_dir,
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -532,4 +827,35 @@ mod tests {
         assert_eq!(ndk.build_tools_version(), "29.0.2");
         assert_eq!(ndk.platforms(), &[29, 28]);
     }
+
+    #[test]
+    fn cache_roundtrip() {
+        let toolchain_dir = std::env::temp_dir();
+        let key = "sdk=/sdk\nndk=/sdk/ndk/26.1.10909125\nsource_properties=0123456789abcdef\n";
+        let contents = format!(
+            "{key}build_tools_version=34.0.0\nbuild_tag=10909125\nplatforms=33,34\ntoolchain_dir={}\n",
+            toolchain_dir.display()
+        );
+        assert_eq!(
+            parse_cache(&contents, key),
+            Some((
+                "34.0.0".to_owned(),
+                10909125,
+                vec![33, 34],
+                toolchain_dir.clone()
+            ))
+        );
+
+        // A different NDK invalidates the cache
+        let other_key = key.replace("0123456789abcdef", "fedcba9876543210");
+        assert_eq!(parse_cache(&contents, &other_key), None);
+
+        // Corrupted caches are probed again
+        assert_eq!(parse_cache(&contents.replace("=33,", "=3x,"), key), None);
+        assert_eq!(
+            parse_cache(&contents[..contents.find("toolchain_dir").unwrap()], key),
+            None
+        );
+        assert_eq!(parse_cache("\u{0}garbage", key), None);
+    }
 }
@@ -0,0 +1,160 @@
+//! Writes final artifacts under a temporary name and only moves them into place once they are
+//! complete, so that an interrupted build never leaves a half-written APK or AAB behind at the
+//! path a later (CI) step picks it up from.
+
+use crate::error::NdkError;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Temporary files of all [`PartialFile`]s that have not been committed or dropped yet.
+static PENDING: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Files that tools write next to the artifact, such as the `.idsig` of APK signature scheme
+/// v4, which are moved into place (or removed) together with it.
+const COMPANION_EXTENSIONS: &[&str] = &["idsig"];
+
+/// A file that is being written to a temporary path next to its final destination.
+///
+/// [`PartialFile::commit`] atomically renames it into place, replacing any previous artifact.
+/// Until then a previously existing artifact is left untouched; dropping an uncommitted
+/// [`PartialFile`] removes the temporary file.
+#[derive(Debug)]
+pub struct PartialFile {
+    path: PathBuf,
+    temp: PathBuf,
+}
+
+impl PartialFile {
+    /// Reserves a temporary path in the same directory as `path`, keeping its extension so that
+    /// tools which check it (e.g. `apksigner`) still accept the file.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut file_name = OsString::from(".");
+        file_name.push(path.file_stem().unwrap_or_default());
+        file_name.push(".partial");
+        if let Some(extension) = path.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        let temp = path.with_file_name(file_name);
+        PENDING.lock().unwrap().push(temp.clone());
+        Self { path, temp }
+    }
+
+    /// The temporary path that tools should write to.
+    pub fn temp_path(&self) -> &Path {
+        &self.temp
+    }
+
+    /// The final path of the artifact.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the temporary file, and its companion files, to the final path.
+    pub fn commit(self) -> Result<PathBuf, NdkError> {
+        for extension in COMPANION_EXTENSIONS {
+            let temp = companion(&self.temp, extension);
+            if temp.exists() {
+                rename(&temp, &companion(&self.path, extension))?;
+            }
+        }
+        rename(&self.temp, &self.path)?;
+        unregister(&self.temp);
+        Ok(self.path.clone())
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if unregister(&self.temp) {
+            remove(&self.temp);
+        }
+    }
+}
+
+/// Removes the temporary files of all uncommitted [`PartialFile`]s.
+///
+/// Meant to be called from a signal handler before exiting, as destructors don't run then.
+pub fn remove_all() {
+    let pending = std::mem::take(&mut *PENDING.lock().unwrap_or_else(|e| e.into_inner()));
+    for temp in pending {
+        remove(&temp);
+    }
+}
+
+fn companion(path: &Path, extension: &str) -> PathBuf {
+    let mut companion = path.as_os_str().to_owned();
+    companion.push(".");
+    companion.push(extension);
+    companion.into()
+}
+
+fn rename(from: &Path, to: &Path) -> Result<(), NdkError> {
+    std::fs::rename(from, to).map_err(|e| NdkError::IoPathError(to.to_owned(), e))
+}
+
+fn remove(temp: &Path) {
+    let _ = std::fs::remove_file(temp);
+    for extension in COMPANION_EXTENSIONS {
+        let _ = std::fs::remove_file(companion(temp, extension));
+    }
+}
+
+/// Returns whether `temp` was still pending.
+fn unregister(temp: &Path) -> bool {
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    match pending.iter().position(|p| p == temp) {
+        Some(i) => {
+            pending.swap_remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("ndk-build-partial").join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn commit_replaces_artifact() {
+        let dir = dir("commit");
+        let apk = dir.join("app.apk");
+        std::fs::write(&apk, "old").unwrap();
+
+        let partial = PartialFile::new(&apk);
+        assert_eq!(partial.temp_path(), dir.join(".app.partial.apk"));
+        std::fs::write(partial.temp_path(), "new").unwrap();
+        std::fs::write(companion(partial.temp_path(), "idsig"), "sig").unwrap();
+        assert_eq!(std::fs::read_to_string(&apk).unwrap(), "old");
+
+        assert_eq!(partial.commit().unwrap(), apk);
+        assert_eq!(std::fs::read_to_string(&apk).unwrap(), "new");
+        assert!(dir.join("app.apk.idsig").exists());
+        assert!(!dir.join(".app.partial.apk").exists());
+    }
+
+    #[test]
+    fn drop_keeps_previous_artifact() {
+        let dir = dir("drop");
+        let apk = dir.join("app.apk");
+        std::fs::write(&apk, "old").unwrap();
+
+        let partial = PartialFile::new(&apk);
+        std::fs::write(partial.temp_path(), "half").unwrap();
+        let temp = partial.temp_path().to_owned();
+        drop(partial);
+
+        assert!(!temp.exists());
+        assert_eq!(std::fs::read_to_string(&apk).unwrap(), "old");
+    }
+}
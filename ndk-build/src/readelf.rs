@@ -1,10 +1,11 @@
 use crate::apk::UnalignedApk;
 use crate::error::NdkError;
 use crate::target::Target;
+use object::elf::{self, FileHeader32, FileHeader64};
+use object::read::elf::{Dyn, FileHeader, SectionHeader};
+use object::{Endian, Endianness, FileKind};
 use std::collections::HashSet;
-use std::io::BufRead;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
 impl<'a> UnalignedApk<'a> {
     pub fn add_lib_recursively(
@@ -21,7 +22,6 @@ impl<'a> UnalignedApk<'a> {
             .sdk
             .min_sdk_version
             .unwrap_or(default_min_sdk);
-        let readelf_path = ndk.toolchain_bin("readelf", target)?;
 
         let android_search_paths = [
             &*ndk.sysroot_lib_dir(target)?,
@@ -37,10 +37,31 @@ impl<'a> UnalignedApk<'a> {
             }
         }
 
+        let mut findings = Vec::new();
         let mut artifacts = vec![lib.to_path_buf()];
         while let Some(artifact) = artifacts.pop() {
             self.add_lib(&artifact, target)?;
-            for need in list_needed_libs(&readelf_path, &artifact)? {
+            let name = artifact.file_name().unwrap().to_string_lossy();
+
+            let elf = read_elf(&artifact)?;
+            if elf.machine != expected_machine(target) {
+                findings.push(format!(
+                    "`{name}` is built for `{}` instead of `{}`",
+                    machine_name(elf.machine),
+                    machine_name(expected_machine(target))
+                ));
+            }
+            if let Some(api_level) = elf.api_level.filter(|level| *level > min_sdk_version) {
+                findings.push(format!(
+                    "`{name}` is built for Android API level {api_level}, above the `min_sdk_version` {min_sdk_version}, and may fail to load on older devices"
+                ));
+            }
+            if let Some(soname) = elf.soname.filter(|soname| *soname != name) {
+                findings.push(format!(
+                    "`{name}` has the SONAME `{soname}`, which libraries linked against it look up instead"
+                ));
+            }
+            for need in elf.needed {
                 // c++_shared is available in the NDK but not on-device.
                 // Must be bundled with the apk if used:
                 // https://developer.android.com/ndk/guides/cpp-support#libc
@@ -57,36 +78,128 @@ impl<'a> UnalignedApk<'a> {
                         artifacts.push(path);
                     }
                 } else {
-                    eprintln!("Shared library \"{}\" not found.", need);
+                    findings.push(format!(
+                        "`{need}` needed by `{name}` is neither packaged nor provided by Android API level {min_sdk_version}"
+                    ));
                 }
             }
         }
 
+        self.report_lib_findings(target, findings)
+    }
+
+    /// Prints the `findings` about the libraries of `target` as warnings, or fails with them
+    /// when [`ApkConfig::strict_libraries`] is set.
+    fn report_lib_findings(&self, target: Target, findings: Vec<String>) -> Result<(), NdkError> {
+        if findings.is_empty() {
+            return Ok(());
+        }
+        let abi = target.android_abi();
+        if self.config().strict_libraries {
+            return Err(NdkError::InvalidLibraries {
+                abi: abi.to_owned(),
+                findings,
+            });
+        }
+        for finding in findings {
+            eprintln!("Warning: [{abi}] {finding}");
+        }
         Ok(())
     }
 }
 
-/// List all linked shared libraries
-fn list_needed_libs(readelf_path: &Path, library_path: &Path) -> Result<HashSet<String>, NdkError> {
-    let mut readelf = Command::new(readelf_path);
-    let output = readelf.arg("-d").arg(library_path).output()?;
-    if !output.status.success() {
-        return Err(NdkError::CmdFailed(readelf));
+/// The `e_machine` of the libraries of `target`
+fn expected_machine(target: Target) -> u16 {
+    match target {
+        Target::ArmV7a => elf::EM_ARM,
+        Target::Arm64V8a => elf::EM_AARCH64,
+        Target::X86 => elf::EM_386,
+        Target::X86_64 => elf::EM_X86_64,
     }
-    let mut needed = HashSet::new();
-    for line in output.stdout.lines() {
-        let line = line?;
-        if line.contains("(NEEDED)") {
-            let lib = line
-                .split("Shared library: [")
-                .last()
-                .and_then(|line| line.split(']').next());
-            if let Some(lib) = lib {
-                needed.insert(lib.to_string());
+}
+
+/// The name of an `e_machine`, as `readelf -h` prints it
+fn machine_name(machine: u16) -> String {
+    match machine {
+        elf::EM_ARM => "ARM".to_owned(),
+        elf::EM_AARCH64 => "AArch64".to_owned(),
+        elf::EM_386 => "Intel 80386".to_owned(),
+        elf::EM_X86_64 => "Advanced Micro Devices X86-64".to_owned(),
+        machine => format!("machine {machine:#x}"),
+    }
+}
+
+/// The `type` of the `Android` ELF note that the NDK's `crtbegin_so.o` adds to every library,
+/// whose description starts with the API level it was built for
+const NT_ANDROID_TYPE_IDENT: u32 = 1;
+
+/// The machine, `SONAME`, linked shared libraries (`NEEDED`) and Android API level of a library
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ElfInfo {
+    machine: u16,
+    soname: Option<String>,
+    needed: HashSet<String>,
+    api_level: Option<u32>,
+}
+
+fn read_elf(library_path: &Path) -> Result<ElfInfo, NdkError> {
+    let data = std::fs::read(library_path)
+        .map_err(|e| NdkError::IoPathError(library_path.to_owned(), e))?;
+    let info = match FileKind::parse(&*data) {
+        Ok(FileKind::Elf32) => parse_elf::<FileHeader32<Endianness>>(&data),
+        Ok(FileKind::Elf64) => parse_elf::<FileHeader64<Endianness>>(&data),
+        Ok(kind) => {
+            return Err(NdkError::InvalidElf(
+                library_path.to_owned(),
+                format!("{kind:?} is not an ELF file"),
+            ))
+        }
+        Err(e) => Err(e),
+    };
+    info.map_err(|e| NdkError::InvalidElf(library_path.to_owned(), e.to_string()))
+}
+
+fn parse_elf<Elf: FileHeader<Endian = Endianness>>(data: &[u8]) -> object::Result<ElfInfo> {
+    let header = Elf::parse(data)?;
+    let endian = header.endian()?;
+    let sections = header.sections(endian, data)?;
+    let mut info = ElfInfo {
+        machine: header.e_machine(endian),
+        ..Default::default()
+    };
+
+    if let Some((dynamic, strings)) = sections.dynamic(endian, data)? {
+        let strings = sections.strings(endian, data, strings)?;
+        for entry in dynamic {
+            let value = || {
+                entry
+                    .string(endian, strings)
+                    .map(|value| String::from_utf8_lossy(value).into_owned())
+            };
+            match entry.tag32(endian) {
+                Some(elf::DT_NEEDED) => {
+                    info.needed.insert(value()?);
+                }
+                Some(elf::DT_SONAME) => info.soname = Some(value()?),
+                _ => {}
+            }
+        }
+    }
+
+    for section in sections.iter() {
+        let Some(mut notes) = section.notes(endian, data)? else {
+            continue;
+        };
+        while let Some(note) = notes.next()? {
+            if note.name() == b"Android" && note.n_type(endian) == NT_ANDROID_TYPE_IDENT {
+                info.api_level = note
+                    .desc()
+                    .get(..4)
+                    .map(|level| endian.read_u32_bytes(level.try_into().unwrap()));
             }
         }
     }
-    Ok(needed)
+    Ok(info)
 }
 
 /// List shared libraries
@@ -119,3 +232,103 @@ fn find_library_path<S: AsRef<Path>>(
     }
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian ELF64 shared library for `machine` with a dynamic section and an
+    /// `Android` note for `api_level`.
+    fn elf(machine: u16, soname: &str, needed: &[&str], api_level: u32) -> Vec<u8> {
+        let mut dynstr = vec![0];
+        let mut string = |value: &str| {
+            let offset = dynstr.len() as u64;
+            dynstr.extend(value.bytes().chain([0]));
+            offset
+        };
+        let mut dynamic = Vec::new();
+        for need in needed {
+            dynamic.push((elf::DT_NEEDED, string(need)));
+        }
+        dynamic.push((elf::DT_SONAME, string(soname)));
+        dynamic.push((elf::DT_NULL, 0));
+        let dynamic = dynamic
+            .into_iter()
+            .flat_map(|(tag, value)| [u64::from(tag).to_le_bytes(), value.to_le_bytes()])
+            .flatten()
+            .collect::<Vec<_>>();
+        let note = [8u32, 4, NT_ANDROID_TYPE_IDENT]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .chain(*b"Android\0")
+            .chain(api_level.to_le_bytes())
+            .collect::<Vec<_>>();
+        let shstrtab = b"\0.dynstr\0.dynamic\0.note.android.ident\0.shstrtab\0".to_vec();
+
+        // (name, type, link, alignment, entry size, contents) after the null section
+        let sections = [
+            (1, elf::SHT_STRTAB, 0, 1, 0, dynstr),
+            (9, elf::SHT_DYNAMIC, 1, 8, 16, dynamic),
+            (18, elf::SHT_NOTE, 0, 4, 0, note),
+            (39, elf::SHT_STRTAB, 0, 1, 0, shstrtab),
+        ];
+        let mut contents = Vec::new();
+        let mut headers = vec![0; 64];
+        for (name, kind, link, align, entsize, data) in sections {
+            while contents.len() % 8 != 0 {
+                contents.push(0);
+            }
+            let offset = 64 + contents.len() as u64;
+            headers.extend(u32::to_le_bytes(name));
+            headers.extend(u32::to_le_bytes(kind));
+            headers.extend([0; 16]); // flags, address
+            headers.extend(offset.to_le_bytes());
+            headers.extend((data.len() as u64).to_le_bytes());
+            headers.extend(u32::to_le_bytes(link));
+            headers.extend([0; 4]); // info
+            headers.extend(u64::to_le_bytes(align));
+            headers.extend(u64::to_le_bytes(entsize));
+            contents.extend(data);
+        }
+        while contents.len() % 8 != 0 {
+            contents.push(0);
+        }
+
+        let mut file = vec![0x7f, b'E', b'L', b'F', 2, 1, 1];
+        file.resize(16, 0);
+        file.extend(elf::ET_DYN.to_le_bytes());
+        file.extend(machine.to_le_bytes());
+        file.extend(1u32.to_le_bytes());
+        file.extend([0; 16]); // entry, program headers
+        file.extend((64 + contents.len() as u64).to_le_bytes());
+        file.extend([0; 4]); // flags
+        for half in [64u16, 56, 0, 64, 5, 4] {
+            file.extend(half.to_le_bytes());
+        }
+        file.extend(contents);
+        file.extend(headers);
+        file
+    }
+
+    #[test]
+    fn elf_header_dynamic_section_and_note() {
+        let library = elf(
+            elf::EM_AARCH64,
+            "libfoo.so.1",
+            &["libc++_shared.so", "liblog.so"],
+            26,
+        );
+        assert_eq!(
+            parse_elf::<FileHeader64<Endianness>>(&library).unwrap(),
+            ElfInfo {
+                machine: elf::EM_AARCH64,
+                soname: Some("libfoo.so.1".to_owned()),
+                needed: HashSet::from(["libc++_shared.so".to_owned(), "liblog.so".to_owned()]),
+                api_level: Some(26),
+            }
+        );
+        assert!(parse_elf::<FileHeader64<Endianness>>(&[]).is_err());
+        assert_eq!(machine_name(expected_machine(Target::X86)), "Intel 80386");
+        assert_eq!(machine_name(0xf3), "machine 0xf3");
+    }
+}
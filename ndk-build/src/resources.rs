@@ -0,0 +1,218 @@
+use crate::error::NdkError;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// A file-based resource such as `res/drawable-hdpi/icon.png`, identified by its
+/// `@drawable/icon` reference.
+struct ResourceFile {
+    path: PathBuf,
+    reference: String,
+}
+
+/// Copies the resource directory `res_dir` to `out_dir`, leaving out file-based resources
+/// (drawables, layouts, raw files, ...) that are not referenced from `manifest_xml`, from any
+/// `values*` resource, or from another kept resource.
+///
+/// Resources whose `@type/name` reference matches one of the `keep` patterns are always
+/// retained, which is required for resources that are only looked up reflectively at runtime.
+/// Patterns may contain `*` wildcards, e.g. `@drawable/legal_*`.
+///
+/// Returns the paths (relative to `res_dir`) of the resources that were left out.
+pub fn shrink(
+    res_dir: &Path,
+    out_dir: &Path,
+    manifest_xml: &str,
+    keep: &[String],
+) -> Result<Vec<PathBuf>, NdkError> {
+    let mut values = Vec::new();
+    let mut candidates = Vec::new();
+    for type_dir in
+        std::fs::read_dir(res_dir).map_err(|e| NdkError::IoPathError(res_dir.into(), e))?
+    {
+        let type_dir = type_dir?;
+        if !type_dir.file_type()?.is_dir() {
+            continue;
+        }
+        let dir_name = type_dir.file_name().to_string_lossy().into_owned();
+        let res_type = dir_name.split('-').next().unwrap().to_string();
+        for file in std::fs::read_dir(type_dir.path())? {
+            let path = file?.path();
+            if res_type == "values" {
+                values.push(path);
+                continue;
+            }
+            let file_name = path.file_name().unwrap().to_string_lossy();
+            // `icon.9.png` is referenced as `@drawable/icon`
+            let name = file_name.split('.').next().unwrap();
+            candidates.push(ResourceFile {
+                reference: format!("@{}/{}", res_type, name),
+                path,
+            });
+        }
+    }
+
+    let mut referenced = HashSet::new();
+    collect_references(manifest_xml, &mut referenced);
+    for path in &values {
+        collect_references(&std::fs::read_to_string(path)?, &mut referenced);
+    }
+
+    // Resources may reference each other (e.g. a layout using a drawable), so keep scanning
+    // newly kept resources until no new references are found.
+    let mut kept = vec![false; candidates.len()];
+    loop {
+        let mut changed = false;
+        for (resource, kept) in candidates.iter().zip(kept.iter_mut()) {
+            if *kept
+                || !(referenced.contains(&resource.reference)
+                    || keep
+                        .iter()
+                        .any(|pattern| glob_match(pattern, &resource.reference)))
+            {
+                continue;
+            }
+            *kept = true;
+            changed = true;
+            if resource.path.extension() == Some(OsStr::new("xml")) {
+                collect_references(&std::fs::read_to_string(&resource.path)?, &mut referenced);
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    if out_dir.exists() {
+        std::fs::remove_dir_all(out_dir).map_err(|e| NdkError::IoPathError(out_dir.into(), e))?;
+    }
+
+    let mut removed = Vec::new();
+    let kept_files = candidates
+        .into_iter()
+        .zip(kept)
+        .filter_map(|(resource, kept)| {
+            if kept {
+                Some(resource.path)
+            } else {
+                removed.push(resource.path.strip_prefix(res_dir).unwrap().to_owned());
+                None
+            }
+        });
+    for path in values.into_iter().chain(kept_files) {
+        let out = out_dir.join(path.strip_prefix(res_dir).unwrap());
+        std::fs::create_dir_all(out.parent().unwrap())?;
+        std::fs::copy(&path, &out).map_err(|e| NdkError::IoPathError(path, e))?;
+    }
+
+    removed.sort();
+    Ok(removed)
+}
+
+/// Collects every `@type/name` (and `@+type/name`) reference to an application resource in
+/// `contents`. References to framework resources (`@android:...`) are ignored.
+fn collect_references(contents: &str, references: &mut HashSet<String>) {
+    for reference in contents.split('@').skip(1) {
+        let reference = reference.strip_prefix('+').unwrap_or(reference);
+        let Some((res_type, rest)) = reference.split_once('/') else {
+            continue;
+        };
+        if res_type.is_empty() || !res_type.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+            continue;
+        }
+        let name = rest
+            .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .next()
+            .unwrap();
+        if !name.is_empty() {
+            references.insert(format!("@{}/{}", res_type, name));
+        }
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any (possibly empty) sequence of
+/// characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            let Some(text) = text.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=text.len())
+                .filter(|i| text.is_char_boundary(*i))
+                .any(|i| glob_match(rest, &text[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("@drawable/legal_*", "@drawable/legal_terms"));
+        assert!(glob_match("@drawable/*", "@drawable/x"));
+        assert!(glob_match("@*/icon", "@mipmap/icon"));
+        assert!(!glob_match("@drawable/legal_*", "@drawable/logo"));
+        assert!(!glob_match("@drawable/icon", "@drawable/icons"));
+    }
+
+    #[test]
+    fn references() {
+        let mut references = HashSet::new();
+        collect_references(
+            r#"<application android:icon="@mipmap/ic_launcher" android:theme="@android:style/Theme"><item>@+id/root</item><x a="@string/app.name"/></application>"#,
+            &mut references,
+        );
+        let mut references = references.into_iter().collect::<Vec<_>>();
+        references.sort();
+        assert_eq!(
+            references,
+            ["@id/root", "@mipmap/ic_launcher", "@string/app.name"]
+        );
+    }
+
+    #[test]
+    fn shrink_unreferenced() {
+        let dir = std::env::temp_dir().join("ndk-build-shrink-resources");
+        let _ = std::fs::remove_dir_all(&dir);
+        let res = dir.join("res");
+        for (path, contents) in [
+            ("mipmap-hdpi/ic_launcher.png", ""),
+            ("drawable/unused.png", ""),
+            ("drawable/legal_terms.png", ""),
+            ("drawable/background.xml", ""),
+            (
+                "layout/main.xml",
+                r#"<View android:background="@drawable/background"/>"#,
+            ),
+            (
+                "values/strings.xml",
+                r#"<string name="x">@layout/main</string>"#,
+            ),
+        ] {
+            let path = res.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let out = dir.join("out");
+        let removed = shrink(
+            &res,
+            &out,
+            r#"<application android:icon="@mipmap/ic_launcher"/>"#,
+            &["@drawable/legal_*".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(removed, [Path::new("drawable/unused.png")]);
+        assert!(out.join("mipmap-hdpi/ic_launcher.png").exists());
+        assert!(out.join("drawable/legal_terms.png").exists());
+        assert!(out.join("drawable/background.xml").exists());
+        assert!(out.join("layout/main.xml").exists());
+        assert!(out.join("values/strings.xml").exists());
+        assert!(!out.join("drawable/unused.png").exists());
+    }
+}
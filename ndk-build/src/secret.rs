@@ -0,0 +1,200 @@
+//! Keystore passwords and other secrets, which must never show up in error messages or logs.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::process::Command;
+
+use crate::shell::quote;
+
+/// A secret such as a keystore password. [`Debug`] and [`Display`](fmt::Display) print
+/// [`Secret::REDACTED`]; the value is only available through [`Secret::expose()`], to hand it
+/// to the tool that needs it.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Printed in place of a secret
+    pub const REDACTED: &'static str = "***";
+
+    /// The secret itself.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_owned())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(Self::REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(Self::REDACTED)
+    }
+}
+
+/// Flags of `keytool`, `jarsigner`, `apksigner` and `bundletool` whose value is a password,
+/// either as the next argument or after a `=`.
+const SECRET_FLAGS: [&str; 8] = [
+    "-storepass",
+    "-keypass",
+    "-srcstorepass",
+    "-srckeypass",
+    "-deststorepass",
+    "-destkeypass",
+    "--ks-pass",
+    "--key-pass",
+];
+
+/// `cmd` with its environment, program and arguments for printing it, where the values of
+/// password flags such as `-storepass` or `--ks-pass=pass:...` are replaced by
+/// [`Secret::REDACTED`].
+pub fn redacted_command(cmd: &Command) -> String {
+    let mut words = cmd
+        .get_envs()
+        .filter_map(|(key, value)| {
+            Some(format!(
+                "{}={}",
+                key.to_string_lossy(),
+                value?.to_string_lossy()
+            ))
+        })
+        .collect::<Vec<_>>();
+    words.push(cmd.get_program().to_string_lossy().into_owned());
+    words.extend(redacted_args(cmd));
+    words.join(" ")
+}
+
+/// `cmd` as a `bash` command line that reproduces it, redacted like [`redacted_command()`]:
+/// it changes to the working directory of `cmd`, unsets the variables that `cmd` removes and
+/// sets the ones it adds, with one variable or argument per line.
+pub fn redacted_shell_command(cmd: &Command) -> String {
+    let mut words = vec!["env".to_owned()];
+    for (key, value) in cmd.get_envs() {
+        if value.is_none() {
+            words.push(format!("-u {}", quote(&key.to_string_lossy())));
+        }
+    }
+    for (key, value) in cmd.get_envs() {
+        if let Some(value) = value {
+            words.push(format!(
+                "{}={}",
+                key.to_string_lossy(),
+                quote(&value.to_string_lossy())
+            ));
+        }
+    }
+    words.push(quote(&cmd.get_program().to_string_lossy()));
+    words.extend(redacted_args(cmd).iter().map(|arg| quote(arg)));
+    let command = words.join(" \\\n    ");
+    match cmd.get_current_dir() {
+        Some(dir) => format!("cd {} && \\\n{command}", quote(&dir.to_string_lossy())),
+        None => command,
+    }
+}
+
+/// The arguments of `cmd`, with the values of password flags replaced by [`Secret::REDACTED`].
+fn redacted_args(cmd: &Command) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut redact_next = false;
+    for arg in cmd.get_args() {
+        let arg = arg.to_string_lossy();
+        if std::mem::take(&mut redact_next) {
+            args.push(Secret::REDACTED.to_owned());
+            continue;
+        }
+        match arg.split_once('=') {
+            Some((flag, _)) if SECRET_FLAGS.contains(&flag) => {
+                args.push(format!("{flag}={}", Secret::REDACTED));
+            }
+            _ => {
+                redact_next = SECRET_FLAGS.contains(&&*arg);
+                args.push(arg.into_owned());
+            }
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::NdkError;
+
+    const SENTINEL: &str = "s3ntinel-pa55word";
+
+    #[test]
+    fn secrets_are_redacted() {
+        let secret = Secret::from(SENTINEL);
+        assert_eq!(secret.expose(), SENTINEL);
+        assert_eq!(format!("{secret} {secret:?}"), "*** ***");
+
+        let mut jarsigner = Command::new("jarsigner");
+        jarsigner
+            .arg("-storepass")
+            .arg(SENTINEL)
+            .arg("-keypass")
+            .arg(SENTINEL)
+            .arg("app.aab")
+            .arg("key");
+        let mut bundletool = Command::new("java");
+        bundletool
+            .arg("-jar")
+            .arg("bundletool.jar")
+            .arg(format!("--ks-pass=pass:{SENTINEL}"))
+            .arg(format!("--key-pass=pass:{SENTINEL}"));
+        let mut apksigner = Command::new("apksigner");
+        apksigner
+            .arg("--ks-pass")
+            .arg(format!("pass:{SENTINEL}"))
+            .arg("--ks")
+            .arg("release.jks");
+
+        assert_eq!(
+            redacted_command(&jarsigner),
+            "jarsigner -storepass *** -keypass *** app.aab key"
+        );
+        assert!(!redacted_shell_command(&bundletool).contains(SENTINEL));
+        for cmd in [jarsigner, bundletool, apksigner] {
+            let error = NdkError::CmdFailed(cmd).to_string();
+            assert!(!error.contains(SENTINEL), "{error}");
+        }
+    }
+
+    #[test]
+    fn shell_command() {
+        let mut cargo = Command::new("cargo");
+        cargo
+            .current_dir("/path/to/my app")
+            .env_remove("RUSTFLAGS")
+            .env("CARGO_ENCODED_RUSTFLAGS", "-Clink-arg=a\x1f-Clink-arg=it's")
+            .arg("build")
+            .arg("--target")
+            .arg("aarch64-linux-android");
+        assert_eq!(
+            redacted_shell_command(&cargo),
+            "cd '/path/to/my app' && \\\n\
+             env \\\n    \
+             -u RUSTFLAGS \\\n    \
+             CARGO_ENCODED_RUSTFLAGS=$'-Clink-arg=a\\x1f-Clink-arg=it\\'s' \\\n    \
+             cargo \\\n    \
+             build \\\n    \
+             --target \\\n    \
+             aarch64-linux-android"
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! Quoting of arguments for shell command lines, such as the commands printed to reproduce
+//! a failed tool or the command lines passed to `adb shell`.
+
+/// Quotes `word` for `bash` and the `mksh` of Android devices: words of only safe characters as they are, others in single
+/// quotes, or with `$'...'` escapes when they contain control characters such as the `\x1f`
+/// separators of `CARGO_ENCODED_RUSTFLAGS`.
+pub fn quote(word: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=,+@%^".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        return word.to_owned();
+    }
+    if !word.chars().any(char::is_control) {
+        return format!("'{}'", word.replace('\'', "'\\''"));
+    }
+    let mut quoted = "$'".to_owned();
+    for c in word.chars() {
+        match c {
+            '\'' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            quote("--target=aarch64-linux-android"),
+            "--target=aarch64-linux-android"
+        );
+        assert_eq!(quote("a b'c"), "'a b'\\''c'");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("-Ca\x1f-Cit's"), "$'-Ca\\x1f-Cit\\'s'");
+    }
+}
@@ -1,20 +1,41 @@
 use crate::error::NdkError;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+/// Deserializes from either its Rust triple (`aarch64-linux-android`) or its Android ABI
+/// (`arm64-v8a`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[repr(u8)]
 pub enum Target {
-    #[serde(rename = "armv7-linux-androideabi")]
     ArmV7a = 1,
-    #[serde(rename = "aarch64-linux-android")]
     Arm64V8a = 2,
-    #[serde(rename = "i686-linux-android")]
     X86 = 3,
-    #[serde(rename = "x86_64-linux-android")]
     X86_64 = 4,
 }
 
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::from_rust_triple(&name)
+            .or_else(|_| Self::from_android_abi(&name))
+            .map_err(|_| {
+                let accepted = |f: fn(Self) -> &'static str| {
+                    Self::ALL
+                        .map(|target| format!("`{}`", f(target)))
+                        .join(", ")
+                };
+                serde::de::Error::custom(format!(
+                    "unknown target `{name}`, expected one of the Android ABIs {} or Rust triples {}",
+                    accepted(Self::android_abi),
+                    accepted(Self::rust_triple),
+                ))
+            })
+    }
+}
+
 impl Target {
+    /// Every supported target
+    pub const ALL: [Self; 4] = [Self::ArmV7a, Self::Arm64V8a, Self::X86, Self::X86_64];
+
     /// Identifier used in the NDK to refer to the ABI
     pub fn android_abi(self) -> &'static str {
         match self {
@@ -0,0 +1,101 @@
+//! Helpers for running under the Windows Subsystem for Linux, where `adb` cannot see USB
+//! devices that are attached to the Windows host and has to talk to an adb server running
+//! there instead.
+
+use std::ffi::OsString;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// The default port of the adb server.
+const ADB_SERVER_PORT: u16 = 5037;
+
+/// Whether this process runs inside WSL, based on the kernel version string in `/proc/version`,
+/// which is only read once.
+pub fn is_wsl() -> bool {
+    static IS_WSL: OnceLock<bool> = OnceLock::new();
+    *IS_WSL.get_or_init(|| {
+        std::fs::read_to_string("/proc/version").is_ok_and(|version| is_wsl_kernel(&version))
+    })
+}
+
+fn is_wsl_kernel(version: &str) -> bool {
+    let version = version.to_ascii_lowercase();
+    version.contains("microsoft") || version.contains("wsl")
+}
+
+/// `path` in the form that Windows programs such as `adb.exe` understand, as translated by
+/// `wslpath -w`, or unchanged when it cannot be translated.
+pub fn windows_path(path: &Path) -> OsString {
+    let translated = Command::new("wslpath")
+        .arg("-w")
+        .arg(path)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    match translated {
+        Some(translated) => translated.trim_end().into(),
+        None => path.into(),
+    }
+}
+
+/// Looks for an adb server on the Windows host that accepts connections from WSL, returning
+/// it in the `ADB_SERVER_SOCKET` format (`tcp:<host>:<port>`) to use with
+/// [`Ndk::with_adb_server()`](crate::ndk::Ndk::with_adb_server).
+///
+/// The host is reachable at the nameserver address WSL2 writes into `/etc/resolv.conf`, or as
+/// `host.docker.internal` when Docker Desktop is installed. Note that the server on Windows
+/// must be started with `adb -a nodaemon server start` to listen on all interfaces.
+pub fn windows_host_adb_server() -> Option<String> {
+    let nameserver = std::fs::read_to_string("/etc/resolv.conf")
+        .ok()
+        .and_then(|resolv_conf| nameserver(&resolv_conf).map(str::to_owned));
+    nameserver
+        .into_iter()
+        .chain(Some("host.docker.internal".to_owned()))
+        .find(|host| is_reachable(host))
+        .map(|host| format!("tcp:{host}:{ADB_SERVER_PORT}"))
+}
+
+/// The first `nameserver` in `resolv_conf`.
+fn nameserver(resolv_conf: &str) -> Option<&str> {
+    resolv_conf.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("nameserver") => parts.next(),
+            _ => None,
+        }
+    })
+}
+
+fn is_reachable(host: &str) -> bool {
+    let Ok(mut addrs) = (host, ADB_SERVER_PORT).to_socket_addrs() else {
+        return false;
+    };
+    addrs.any(|addr| TcpStream::connect_timeout(&addr, Duration::from_millis(500)).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_kernel() {
+        assert!(is_wsl_kernel(
+            "Linux version 5.15.153.1-microsoft-standard-WSL2 (root@941d701f84f1)"
+        ));
+        assert!(!is_wsl_kernel(
+            "Linux version 6.8.0-45-generic (buildd@lcy02-amd64-075)"
+        ));
+    }
+
+    #[test]
+    fn resolv_conf_nameserver() {
+        let resolv_conf = "# generated by WSL\n[network]\nnameserver 172.20.160.1\nsearch lan\n";
+        assert_eq!(nameserver(resolv_conf), Some("172.20.160.1"));
+        assert_eq!(nameserver("search lan\n"), None);
+    }
+}